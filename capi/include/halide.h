@@ -0,0 +1,80 @@
+/*
+ * C API for the halide raytracer. Link against the `halide_capi` library
+ * built from the `capi` crate.
+ *
+ * Objects are opaque, created with a `_new` function and released with the
+ * matching `_free`. Functions that can fail return a HalideStatus. Images are
+ * written with rows ordered top to bottom.
+ */
+
+#ifndef HALIDE_H
+#define HALIDE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Scene HalideScene;
+typedef struct Camera HalideCamera;
+typedef struct Renderer HalideRenderer;
+
+typedef enum HalideStatus {
+    HALIDE_OK = 0,
+    /* A required pointer argument was null. */
+    HALIDE_NULL_POINTER = 1,
+    /* A material index that doesn't exist in the scene. */
+    HALIDE_INVALID_MATERIAL = 2,
+    /* The output buffer is smaller than the image. */
+    HALIDE_BUFFER_TOO_SMALL = 3,
+    /* Width or height was zero. */
+    HALIDE_INVALID_SIZE = 4,
+} HalideStatus;
+
+/* A point, direction, or color. */
+typedef struct HalideVec3 {
+    float x;
+    float y;
+    float z;
+} HalideVec3;
+
+HalideScene *halide_scene_new(void);
+void halide_scene_free(HalideScene *scene);
+/* Add a material, writing its index to `material`. */
+HalideStatus halide_scene_add_lambertian(HalideScene *scene, HalideVec3 albedo,
+                                         uint32_t *material);
+HalideStatus halide_scene_add_emissive(HalideScene *scene, HalideVec3 emission,
+                                       uint32_t *material);
+HalideStatus halide_scene_add_sphere(HalideScene *scene, HalideVec3 center, float radius,
+                                     uint32_t material);
+
+/* Returns null if width or height is zero. */
+HalideCamera *halide_camera_new(uint32_t width, uint32_t height);
+void halide_camera_free(HalideCamera *camera);
+/* `vertical_fov` is in degrees. */
+HalideStatus halide_camera_set_view(HalideCamera *camera, HalideVec3 position,
+                                    HalideVec3 direction, float vertical_fov);
+HalideStatus halide_camera_set_size(HalideCamera *camera, uint32_t width, uint32_t height);
+
+/* Zero threads uses one per core. Returns null if the threads couldn't start. */
+HalideRenderer *halide_renderer_new(uint32_t num_threads);
+void halide_renderer_free(HalideRenderer *renderer);
+/* Throw away accumulated samples, after changing the scene or camera. */
+HalideStatus halide_renderer_reset(HalideRenderer *renderer);
+/* Add `samples` samples per pixel, then copy the image to `out` as 8 bit sRGB
+ * RGBA with straight alpha. `out_len` is in bytes, at least width * height * 4. */
+HalideStatus halide_renderer_render(HalideRenderer *renderer, const HalideScene *scene,
+                                    const HalideCamera *camera, uint32_t samples,
+                                    uint8_t *out, size_t out_len);
+/* Copy the linear, premultiplied RGBA average of the samples to `out`.
+ * `out_len` counts floats, at least width * height * 4. */
+HalideStatus halide_renderer_read_hdr(const HalideRenderer *renderer, float *out,
+                                      size_t out_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* HALIDE_H */
@@ -0,0 +1,383 @@
+//! A C API for embedding the renderer in other applications. See
+//! `include/halide.h` for the declarations.
+//!
+//! Every object is created and freed through this API, and handed out as an
+//! opaque pointer. Functions accept null pointers, and report them as
+//! [`HalideStatus::NullPointer`] instead of crashing.
+
+use glam::Vec3;
+use halide_raytracer::{Camera, Material, Renderer, Scene, Sphere};
+use std::slice;
+
+/// The result of a call that can fail.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HalideStatus {
+    Ok = 0,
+    /// A required pointer argument was null.
+    NullPointer = 1,
+    /// A material index that doesn't exist in the scene.
+    InvalidMaterial = 2,
+    /// The output buffer is smaller than the image.
+    BufferTooSmall = 3,
+    /// Width or height was zero.
+    InvalidSize = 4,
+}
+
+/// A point, direction, or color.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HalideVec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl From<HalideVec3> for Vec3 {
+    fn from(v: HalideVec3) -> Self {
+        Vec3::new(v.x, v.y, v.z)
+    }
+}
+
+/// Create an empty scene. Free it with [`halide_scene_free`].
+#[no_mangle]
+pub extern "C" fn halide_scene_new() -> *mut Scene {
+    Box::into_raw(Box::default())
+}
+
+/// # Safety
+///
+/// `scene` must be null or come from [`halide_scene_new`], and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn halide_scene_free(scene: *mut Scene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+/// Add a diffuse material, writing its index to `material`.
+///
+/// # Safety
+///
+/// `scene` must be null or a live scene, and `material` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn halide_scene_add_lambertian(
+    scene: *mut Scene,
+    albedo: HalideVec3,
+    material: *mut u32,
+) -> HalideStatus {
+    add_material(
+        scene,
+        Material::Lambertian {
+            albedo: albedo.into(),
+        },
+        material,
+    )
+}
+
+/// Add a material that gives off light, writing its index to `material`.
+///
+/// # Safety
+///
+/// `scene` must be null or a live scene, and `material` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn halide_scene_add_emissive(
+    scene: *mut Scene,
+    emission: HalideVec3,
+    material: *mut u32,
+) -> HalideStatus {
+    add_material(
+        scene,
+        Material::Emissive {
+            emission: emission.into(),
+        },
+        material,
+    )
+}
+
+unsafe fn add_material(scene: *mut Scene, material: Material, index: *mut u32) -> HalideStatus {
+    let (Some(scene), Some(index)) = (scene.as_mut(), index.as_mut()) else {
+        return HalideStatus::NullPointer;
+    };
+    *index = scene.add_material(material).index() as u32;
+    HalideStatus::Ok
+}
+
+/// Add a sphere using a material index from one of the
+/// `halide_scene_add_*` functions.
+///
+/// # Safety
+///
+/// `scene` must be null or a live scene.
+#[no_mangle]
+pub unsafe extern "C" fn halide_scene_add_sphere(
+    scene: *mut Scene,
+    center: HalideVec3,
+    radius: f32,
+    material: u32,
+) -> HalideStatus {
+    let Some(scene) = scene.as_mut() else {
+        return HalideStatus::NullPointer;
+    };
+    let Some(material) = scene.material_handle(material as usize) else {
+        return HalideStatus::InvalidMaterial;
+    };
+    scene.add_hittable(Sphere {
+        center: center.into(),
+        radius,
+        material,
+    });
+    HalideStatus::Ok
+}
+
+/// Create a camera for images of the given size, or null if either is zero.
+/// Free it with [`halide_camera_free`].
+#[no_mangle]
+pub extern "C" fn halide_camera_new(width: u32, height: u32) -> *mut Camera {
+    if width == 0 || height == 0 {
+        return std::ptr::null_mut();
+    }
+    let mut camera = Camera::default();
+    camera.set_size(width, height);
+    Box::into_raw(Box::new(camera))
+}
+
+/// # Safety
+///
+/// `camera` must be null or come from [`halide_camera_new`], and not be used
+/// afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn halide_camera_free(camera: *mut Camera) {
+    if !camera.is_null() {
+        drop(Box::from_raw(camera));
+    }
+}
+
+/// Place the camera at `position`, looking along `direction`, with a
+/// vertical field of view in degrees.
+///
+/// # Safety
+///
+/// `camera` must be null or a live camera.
+#[no_mangle]
+pub unsafe extern "C" fn halide_camera_set_view(
+    camera: *mut Camera,
+    position: HalideVec3,
+    direction: HalideVec3,
+    vertical_fov: f32,
+) -> HalideStatus {
+    let Some(camera) = camera.as_mut() else {
+        return HalideStatus::NullPointer;
+    };
+    camera.set_position(position.into());
+    camera.set_look_direction(direction.into());
+    camera.set_vertical_fov(vertical_fov);
+    HalideStatus::Ok
+}
+
+/// Resize the camera's image.
+///
+/// # Safety
+///
+/// `camera` must be null or a live camera.
+#[no_mangle]
+pub unsafe extern "C" fn halide_camera_set_size(
+    camera: *mut Camera,
+    width: u32,
+    height: u32,
+) -> HalideStatus {
+    let Some(camera) = camera.as_mut() else {
+        return HalideStatus::NullPointer;
+    };
+    if width == 0 || height == 0 {
+        return HalideStatus::InvalidSize;
+    }
+    camera.set_size(width, height);
+    HalideStatus::Ok
+}
+
+/// Create a renderer that uses `num_threads` threads, or one per core if it
+/// is zero. Returns null if the threads couldn't be started. Free it with
+/// [`halide_renderer_free`].
+#[no_mangle]
+pub extern "C" fn halide_renderer_new(num_threads: u32) -> *mut Renderer {
+    let Ok(mut renderer) = Renderer::try_new(1, 1) else {
+        return std::ptr::null_mut();
+    };
+    if num_threads > 0 && renderer.try_set_num_threads(num_threads as usize).is_err() {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(renderer))
+}
+
+/// # Safety
+///
+/// `renderer` must be null or come from [`halide_renderer_new`], and not be
+/// used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn halide_renderer_free(renderer: *mut Renderer) {
+    if !renderer.is_null() {
+        drop(Box::from_raw(renderer));
+    }
+}
+
+/// Throw away the samples accumulated so far. Call this after changing the
+/// scene or camera.
+///
+/// # Safety
+///
+/// `renderer` must be null or a live renderer.
+#[no_mangle]
+pub unsafe extern "C" fn halide_renderer_reset(renderer: *mut Renderer) -> HalideStatus {
+    let Some(renderer) = renderer.as_mut() else {
+        return HalideStatus::NullPointer;
+    };
+    renderer.reset_accumulation();
+    HalideStatus::Ok
+}
+
+/// Add `samples` samples per pixel, and copy the image into `out` as 8 bit
+/// sRGB RGBA with straight alpha, rows ordered top to bottom. `out_len` is the
+/// size of `out` in bytes, which must be at least `width * height * 4` of
+/// the camera.
+///
+/// # Safety
+///
+/// Each pointer must be null or live, and `out` must be writable for
+/// `out_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn halide_renderer_render(
+    renderer: *mut Renderer,
+    scene: *const Scene,
+    camera: *const Camera,
+    samples: u32,
+    out: *mut u8,
+    out_len: usize,
+) -> HalideStatus {
+    let (Some(renderer), Some(scene), Some(camera)) =
+        (renderer.as_mut(), scene.as_ref(), camera.as_ref())
+    else {
+        return HalideStatus::NullPointer;
+    };
+    if out.is_null() {
+        return HalideStatus::NullPointer;
+    }
+    let [width, height] = camera.size();
+    if out_len < width as usize * height as usize * 4 {
+        return HalideStatus::BufferTooSmall;
+    }
+
+    renderer.resize(width, height);
+    renderer.render_accumulate(scene, camera, samples as usize);
+    let image = renderer.snapshot().to_straight_rgba8();
+    slice::from_raw_parts_mut(out, image.len()).copy_from_slice(&image);
+    HalideStatus::Ok
+}
+
+/// Copy the linear average of the samples for each pixel into `out`, as 4
+/// floats per pixel (premultiplied RGBA), rows ordered top to bottom.
+/// `out_len` counts floats, and must be at least `width * height * 4` of the
+/// last render.
+///
+/// # Safety
+///
+/// `renderer` must be null or live, and `out` must be writable for
+/// `out_len` floats.
+#[no_mangle]
+pub unsafe extern "C" fn halide_renderer_read_hdr(
+    renderer: *const Renderer,
+    out: *mut f32,
+    out_len: usize,
+) -> HalideStatus {
+    let Some(renderer) = renderer.as_ref() else {
+        return HalideStatus::NullPointer;
+    };
+    if out.is_null() {
+        return HalideStatus::NullPointer;
+    }
+    let snapshot = renderer.snapshot();
+    if out_len < snapshot.hdr.len() * 4 {
+        return HalideStatus::BufferTooSmall;
+    }
+    let out = slice::from_raw_parts_mut(out, snapshot.hdr.len() * 4);
+    let rows = snapshot.hdr.chunks_exact(snapshot.width as usize).rev();
+    for (dest, pixel) in out.chunks_exact_mut(4).zip(rows.flatten()) {
+        dest.copy_from_slice(&pixel.to_array());
+    }
+    HalideStatus::Ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        halide_camera_free, halide_camera_new, halide_renderer_free, halide_renderer_new,
+        halide_renderer_read_hdr, halide_renderer_render, halide_scene_add_emissive,
+        halide_scene_add_sphere, halide_scene_free, halide_scene_new, HalideStatus, HalideVec3,
+    };
+
+    #[test]
+    fn render_into_buffer() {
+        unsafe {
+            let scene = halide_scene_new();
+            let white = HalideVec3 {
+                x: 1.,
+                y: 1.,
+                z: 1.,
+            };
+            let origin = HalideVec3 {
+                x: 0.,
+                y: 0.,
+                z: 0.,
+            };
+            let mut light = 0;
+            assert_eq!(
+                halide_scene_add_emissive(scene, white, &mut light),
+                HalideStatus::Ok
+            );
+            assert_eq!(
+                halide_scene_add_sphere(scene, origin, 1., light),
+                HalideStatus::Ok
+            );
+            assert_eq!(
+                halide_scene_add_sphere(scene, origin, 1., 99),
+                HalideStatus::InvalidMaterial
+            );
+
+            let camera = halide_camera_new(8, 4);
+            assert!(halide_camera_new(0, 4).is_null());
+            let renderer = halide_renderer_new(1);
+            let mut image = vec![0u8; 8 * 4 * 4];
+            assert_eq!(
+                halide_renderer_render(renderer, scene, camera, 1, image.as_mut_ptr(), 16),
+                HalideStatus::BufferTooSmall
+            );
+            assert_eq!(
+                halide_renderer_render(
+                    renderer,
+                    scene,
+                    camera,
+                    1,
+                    image.as_mut_ptr(),
+                    image.len()
+                ),
+                HalideStatus::Ok
+            );
+            assert!(image.iter().any(|channel| *channel > 0));
+
+            let mut hdr = vec![0f32; 8 * 4 * 4];
+            assert_eq!(
+                halide_renderer_read_hdr(renderer, hdr.as_mut_ptr(), hdr.len()),
+                HalideStatus::Ok
+            );
+            assert_eq!(
+                halide_renderer_read_hdr(renderer, std::ptr::null_mut(), hdr.len()),
+                HalideStatus::NullPointer
+            );
+
+            halide_renderer_free(renderer);
+            halide_camera_free(camera);
+            halide_scene_free(scene);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Rendering the camera orbiting the scene, as a video or a numbered image
+//! for each frame.
+
+use crate::{setup, with_suffix, RenderArgs};
+use anyhow::{bail, Context, Result};
+use glam::Vec3;
+use halide_raytracer::{Camera, Scene, Turntable};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    io::Write,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+#[derive(clap::Args)]
+pub struct AnimationArgs {
+    /// Render this many frames of the camera orbiting the scene, once all
+    /// the way around, instead of a single image.
+    #[arg(long, value_name = "FRAMES", conflicts_with_all = ["stereo", "snapshot_every"])]
+    pub animate: Option<NonZeroUsize>,
+    /// How many frames of an animation to show each second.
+    #[arg(long, default_value_t = 30.)]
+    pub fps: f32,
+}
+
+/// Render the animation `animation` asks for, and save it to `output`.
+pub fn render(
+    scene: &Scene,
+    mut camera: Camera,
+    args: &RenderArgs,
+    animation: &AnimationArgs,
+    output: &Path,
+) -> Result<()> {
+    let frames = animation.animate.map_or(1, NonZeroUsize::get);
+    let fps = animation.fps;
+    if !(fps.is_finite() && fps > 0.) {
+        bail!("--fps must be more than 0");
+    }
+    let mut renderer = setup(&mut camera, args)?;
+    let [width, height] = camera.size();
+    let bounds = scene.bounds();
+    camera.set_turntable(Some(Turntable {
+        center: if bounds.is_empty() {
+            Vec3::ZERO
+        } else {
+            bounds.center()
+        },
+        degrees_per_second: 360. * fps / frames as f32,
+    }));
+
+    let mut sink = FrameSink::new(output, width, height, fps)?;
+    // hidden when stdout isn't a terminal, like the one for single images
+    let bar = ProgressBar::with_draw_target(Some(frames as u64), ProgressDrawTarget::stdout());
+    bar.set_style(ProgressStyle::with_template(
+        "{bar:40} {pos}/{len} frames in {elapsed}, {eta} left",
+    )?);
+    for frame in 0..frames {
+        renderer.reset_accumulation();
+        renderer.render_accumulate(scene, &camera, args.samples);
+        sink.write(frame, &renderer.as_image())?;
+        camera.update(1. / fps);
+        bar.inc(1);
+    }
+    bar.finish();
+    sink.finish()
+}
+
+/// Where an animation's frames go.
+enum FrameSink {
+    /// An image for each frame, numbered from 1 after this path, like
+    /// `image_0001.png`.
+    Images(PathBuf),
+    /// An ffmpeg process, encoding the frames piped to it as a video.
+    Video(Child),
+}
+
+impl FrameSink {
+    /// A video if `output` is named like one, and otherwise images.
+    fn new(output: &Path, width: u32, height: u32, fps: f32) -> Result<Self> {
+        let extension = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+        if !matches!(extension.as_deref(), Some("mp4" | "webm")) {
+            return Ok(Self::Images(output.to_owned()));
+        }
+        let child = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{width}x{height}"), "-r", &fps.to_string()])
+            .args(["-i", "-"])
+            // what players handle best, and what the usual codecs for both
+            // containers expect
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(output)
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Couldn't start ffmpeg, which is needed to save videos")?;
+        Ok(Self::Video(child))
+    }
+
+    fn write(&mut self, frame: usize, image: &image::RgbaImage) -> Result<()> {
+        match self {
+            Self::Images(path) => image.save(with_suffix(path, &format!("_{:04}", frame + 1)))?,
+            Self::Video(child) => {
+                let stdin = child.stdin.as_mut().context("ffmpeg's input was closed")?;
+                stdin
+                    .write_all(image.as_raw())
+                    .context("Sending a frame to ffmpeg")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the video to be written, if there is one.
+    fn finish(self) -> Result<()> {
+        if let Self::Video(mut child) = self {
+            // closing its input tells ffmpeg there are no more frames
+            drop(child.stdin.take());
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("ffmpeg failed: {status}");
+            }
+        }
+        Ok(())
+    }
+}
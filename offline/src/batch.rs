@@ -0,0 +1,129 @@
+//! Rendering a whole directory of scene files with the same settings.
+
+use crate::{read_scene, render_scene, Outputs, RenderArgs};
+use anyhow::{bail, Context, Result};
+use std::{
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+#[derive(clap::Args)]
+pub struct BatchArgs {
+    /// The directory of scene files to render, `.ron` or `.halide`.
+    dir: PathBuf,
+    /// The directory to save the images in, named after the scene files.
+    #[arg(long)]
+    out: PathBuf,
+    /// How many scenes to render at once, each in a process of its own.
+    /// Each still renders on every core, so this helps most with small
+    /// images, where a lot of the time is spent loading and saving.
+    #[arg(long, default_value = "1")]
+    jobs: NonZeroUsize,
+    /// Render just this scene from the batch, as the processes started for
+    /// `--jobs` do.
+    #[arg(long, hide = true)]
+    only: Option<PathBuf>,
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+pub fn run(args: &BatchArgs) -> Result<()> {
+    std::fs::create_dir_all(&args.out)
+        .with_context(|| format!("Creating {}", args.out.display()))?;
+    if let Some(path) = &args.only {
+        return render_file(args, path);
+    }
+
+    let files = scene_files(&args.dir)?;
+    if files.is_empty() {
+        bail!("No .ron or .halide files in {}", args.dir.display());
+    }
+    let failed = if args.jobs.get() == 1 {
+        let mut failed = 0;
+        for path in &files {
+            println!("{}", path.display());
+            if let Err(err) = render_file(args, path) {
+                eprintln!("Couldn't render {}: {err:#}", path.display());
+                failed += 1;
+            }
+        }
+        failed
+    } else {
+        render_in_processes(args, &files)?
+    };
+    if failed > 0 {
+        bail!("{failed} of {} scenes failed", files.len());
+    }
+    Ok(())
+}
+
+/// The scene files directly in `dir`, in order of name.
+fn scene_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Reading {}", dir.display()))? {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        if path.is_file() && matches!(extension, Some("ron" | "halide")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn render_file(args: &BatchArgs, path: &Path) -> Result<()> {
+    let (scene, camera) = read_scene(path)?;
+    let name = path.file_stem().unwrap_or_default();
+    let image = args.out.join(name).with_extension("png");
+    let outputs = Outputs {
+        image: &image,
+        depth: None,
+        trace_pixel: None,
+    };
+    render_scene(&scene, camera, &args.render, &outputs)
+}
+
+/// Render each file in a process of its own, `args.jobs` at a time, and
+/// return how many failed. Each process is started with the same arguments
+/// as this one, plus the file to render, so they share its settings.
+fn render_in_processes(args: &BatchArgs, files: &[PathBuf]) -> Result<usize> {
+    let exe = std::env::current_exe()?;
+    let arguments: Vec<_> = std::env::args_os().skip(1).collect();
+    let queue = Mutex::new(files.iter());
+    let failed = Mutex::new(0);
+    std::thread::scope(|scope| {
+        for _ in 0..args.jobs.get() {
+            scope.spawn(|| loop {
+                let Some(path) = queue.lock().unwrap().next() else {
+                    return;
+                };
+                // the output is captured, so the processes' progress bars
+                // don't draw over each other, and shown once each is done
+                let output = Command::new(&exe)
+                    .args(&arguments)
+                    .arg("--only")
+                    .arg(path)
+                    .output();
+                let mut failed = failed.lock().unwrap();
+                println!("{}", path.display());
+                match output {
+                    Ok(output) => {
+                        print!("{}", String::from_utf8_lossy(&output.stdout));
+                        eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                        if !output.status.success() {
+                            eprintln!("Couldn't render {}", path.display());
+                            *failed += 1;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Couldn't start a render for {}: {err}", path.display());
+                        *failed += 1;
+                    }
+                }
+            });
+        }
+    });
+    Ok(failed.into_inner().unwrap())
+}
@@ -0,0 +1,202 @@
+//! Records `tracing` spans in the Chrome trace event format, which
+//! `chrome://tracing` and <https://ui.perfetto.dev> show as a timeline of
+//! where the time went.
+
+use anyhow::Result;
+use std::{
+    fmt::{self, Write as _},
+    fs::File,
+    io::{BufWriter, Write as _},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Subscriber,
+};
+use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+/// Start recording spans from every thread, to be written to `path` when
+/// the returned guard is dropped.
+pub fn install(path: PathBuf) -> Result<ChromeTraceGuard> {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let layer = ChromeTraceLayer {
+        start: Instant::now(),
+        events: events.clone(),
+    };
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    Ok(ChromeTraceGuard { path, events })
+}
+
+/// Writes the trace out when dropped, so it's saved however `main` ends.
+pub struct ChromeTraceGuard {
+    path: PathBuf,
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl ChromeTraceGuard {
+    fn write(&self) -> Result<()> {
+        let events = self.events.lock().unwrap_or_else(|err| err.into_inner());
+        let mut out = BufWriter::new(File::create(&self.path)?);
+        writeln!(out, "{{\"traceEvents\":[")?;
+        for (idx, event) in events.iter().enumerate() {
+            let separator = if idx + 1 < events.len() { "," } else { "" };
+            writeln!(out, "{event}{separator}")?;
+        }
+        writeln!(out, "]}}")?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for ChromeTraceGuard {
+    fn drop(&mut self) {
+        match self.write() {
+            Ok(()) => println!("Wrote trace to {}", self.path.display()),
+            Err(err) => eprintln!("Couldn't write trace to {}: {err}", self.path.display()),
+        }
+    }
+}
+
+struct ChromeTraceLayer {
+    start: Instant,
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+/// The start or end of a span on one thread.
+struct Event {
+    name: &'static str,
+    target: &'static str,
+    begin: bool,
+    time: Duration,
+    thread: u64,
+    /// The span's fields, as the inside of a JSON object.
+    args: String,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            concat!(
+                "{{\"name\":{},\"cat\":{},\"ph\":\"{}\",\"ts\":{:.3},",
+                "\"pid\":1,\"tid\":{},\"args\":{{{}}}}}",
+            ),
+            json_string(self.name),
+            json_string(self.target),
+            if self.begin { 'B' } else { 'E' },
+            self.time.as_secs_f64() * 1e6,
+            self.thread,
+            self.args,
+        )
+    }
+}
+
+/// A span's fields, kept until it's entered.
+struct Args(String);
+
+impl Visit for Args {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, &value.to_string());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, &value.to_string());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, &value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, &json_string(&format!("{value:?}")));
+    }
+}
+
+impl Args {
+    fn push(&mut self, field: &Field, json: &str) {
+        if !self.0.is_empty() {
+            self.0.push(',');
+        }
+        let _ = write!(self.0, "{}:{json}", json_string(field.name()));
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A small number for the current thread, since trace viewers want
+/// integers and std's thread ids can't be turned into one.
+fn thread_number() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static NUMBER: u64 = NEXT.fetch_add(1, Ordering::Relaxed);
+    }
+    NUMBER.with(|number| *number)
+}
+
+impl ChromeTraceLayer {
+    fn push<S>(&self, id: &span::Id, ctx: &Context<'_, S>, begin: bool)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let args = match span.extensions().get::<Args>() {
+            Some(args) if begin => args.0.clone(),
+            _ => String::new(),
+        };
+        let event = Event {
+            name: span.name(),
+            target: span.metadata().target(),
+            begin,
+            time: self.start.elapsed(),
+            thread: thread_number(),
+            args,
+        };
+        self.events
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(event);
+    }
+}
+
+impl<S> Layer<S> for ChromeTraceLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let mut args = Args(String::new());
+        attrs.record(&mut args);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(args);
+        }
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.push(id, &ctx, true);
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        self.push(id, &ctx, false);
+    }
+}
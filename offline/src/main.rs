@@ -1,92 +1,420 @@
-use std::time::Instant;
+use std::{
+    num::NonZeroUsize,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
 use glam::Vec3;
-use halide_raytracer::{Camera, Material, Renderer, Scene, Sphere};
-use png_pong::PngRaster;
+use halide_raytracer::{
+    presets::Preset, AmbientOcclusion, Camera, DebugNormals, Eye, PhotonMapper, Projection,
+    RenderStats, Renderer, Scene, Stereo, ThreadPolicy,
+};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+mod animation;
+mod batch;
+mod chrome_trace;
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// The built-in scene to render.
+    #[arg(long, default_value_t = Preset::Demo)]
+    preset: Preset,
+    /// A scene file to render instead of a preset, either `.ron` or
+    /// `.halide`.
+    #[arg(long, conflicts_with = "preset")]
+    scene: Option<PathBuf>,
+    /// Where to save the image. Animations can be saved as `.mp4` or
+    /// `.webm` videos, which needs ffmpeg, or otherwise as an image for
+    /// each frame.
+    #[arg(long, default_value = "image.png")]
+    output: PathBuf,
+    /// Also write the linear color with a depth channel, as an EXR at this
+    /// path, for compositing.
+    #[arg(long)]
+    depth: Option<PathBuf>,
+    /// Record how long each stage takes as a Chrome trace at this path,
+    /// which chrome://tracing or ui.perfetto.dev can show as a timeline.
+    #[arg(long)]
+    trace_output: Option<PathBuf>,
+    /// Print every bounce of a path through this pixel, counting from the
+    /// bottom left, for debugging where its color comes from.
+    #[arg(long, num_args = 2, value_names = ["X", "Y"])]
+    trace_pixel: Option<Vec<u32>>,
+    /// Instead of saving an image, render the scene from the same seed on
+    /// one thread and then on all of them, and fail unless both come out
+    /// exactly the same.
+    #[arg(long, conflicts_with = "animate")]
+    verify_determinism: bool,
+    #[command(flatten)]
+    animation: animation::AnimationArgs,
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render every scene file in a directory, for regenerating galleries
+    /// and regression suites.
+    Batch(batch::BatchArgs),
+}
+
+/// Settings for how scenes are rendered, shared by single renders and
+/// batches.
+#[derive(clap::Args)]
+struct RenderArgs {
+    /// Where to put the camera, instead of where the scene puts it.
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    camera_pos: Option<Vec3>,
+    /// The direction for the camera to face.
+    #[arg(long, value_name = "X,Y,Z", value_parser = parse_vec3, allow_hyphen_values = true)]
+    camera_look: Option<Vec3>,
+    /// A point for the camera to face, instead of a direction.
+    #[arg(
+        long,
+        value_name = "X,Y,Z",
+        value_parser = parse_vec3,
+        allow_hyphen_values = true,
+        conflicts_with = "camera_look"
+    )]
+    look_at: Option<Vec3>,
+    /// The camera's vertical field of view, in degrees.
+    #[arg(long)]
+    fov: Option<f32>,
+    /// How many samples to take for each pixel.
+    #[arg(long, default_value_t = 64)]
+    samples: usize,
+    /// Also save the image every this many samples, with the sample count
+    /// in its name, like `image_0064.png`, to see how it converges.
+    #[arg(long)]
+    snapshot_every: Option<NonZeroUsize>,
+    /// Leave the sky out of the image, for compositing over other
+    /// backgrounds.
+    #[arg(long)]
+    transparent: bool,
+    /// Show pixels with NaN or infinite samples in magenta, instead of
+    /// leaving those samples out.
+    #[arg(long)]
+    mark_invalid: bool,
+    /// Render a view for each eye, for VR headsets and 3D displays.
+    #[arg(long, value_enum)]
+    stereo: Option<StereoOutput>,
+    /// The distance between the eyes of a stereo pair, in scene units.
+    #[arg(long, default_value_t = Stereo::default().ipd)]
+    ipd: f32,
+    /// How far in front of the camera the eyes of a stereo pair line up.
+    #[arg(long, default_value_t = Stereo::default().convergence)]
+    convergence: f32,
+    /// How the camera spreads its rays over the image. Panoramas see in
+    /// every direction, in an image twice as wide as it is tall.
+    #[arg(long, value_enum, default_value_t = ProjectionArg::Perspective)]
+    projection: ProjectionArg,
+    /// How each pixel's samples are worked out.
+    #[arg(long, value_enum, default_value_t = IntegratorArg::Path)]
+    integrator: IntegratorArg,
+    /// How far around each point the photons integrator gathers photons
+    /// from at first, in scene units. It shrinks as frames accumulate.
+    #[arg(long, default_value_t = PhotonMapper::default().radius)]
+    photon_radius: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ProjectionArg {
+    Perspective,
+    /// An equirectangular panorama, for environment maps and panorama
+    /// viewers.
+    Pano,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum IntegratorArg {
+    /// Light bouncing around the scene, as materials scatter it.
+    Path,
+    /// How open to the sky each surface is, in shades of grey, to check the
+    /// shape of a scene quickly.
+    Ao,
+    /// The direction each surface faces, as a color.
+    Normals,
+    /// Path tracing, with caustics from mirrors gathered from photons shot
+    /// from the lights, so they clear up in far fewer frames.
+    Photons,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum StereoOutput {
+    /// Both eyes in one image twice as wide, left eye first.
+    SideBySide,
+    /// Each eye in its own image, with `-left` or `-right` after the name.
+    Separate,
+}
 
 fn main() -> Result<()> {
-    let mut t0 = Instant::now();
-    let mut t1;
-    const WIDTH: u32 = 1920;
-    const HEIGHT: u32 = 1080;
-
-    let mut renderer = Renderer::new(WIDTH, HEIGHT);
-
-    let mut scene = Scene::default();
-
-    let ground_material = scene.add_material(Material::Lambertian {
-        albedo: Vec3::new(0.9, 0.2, 0.1),
-    });
-    let ball_material = scene.add_material(Material::Lambertian {
-        albedo: Vec3::new(0.7, 0.7, 0.7),
-    });
-
-    scene.add_hittable(Sphere {
-        center: Vec3::new(0., -10_000., 0.),
-        radius: 10_000.,
-        material_index: ground_material,
-    });
-
-    scene.add_hittable(Sphere {
-        center: Vec3::new(-1.1, 0.5, 0.),
-        radius: 0.5,
-        material_index: ball_material,
-    });
-    scene.add_hittable(Sphere {
-        center: Vec3::new(0., 0.5, 0.),
-        radius: 0.5,
-        material_index: ball_material,
-    });
-    scene.add_hittable(Sphere {
-        center: Vec3::new(1.1, 0.5, 0.),
-        radius: 0.5,
-        material_index: ball_material,
-    });
+    let args = Args::parse();
+    if let Some(Command::Batch(batch)) = &args.command {
+        return batch::run(batch);
+    }
+    let _trace = args.trace_output.clone().map(chrome_trace::install).transpose()?;
+    let (scene, camera) = match &args.scene {
+        Some(path) => read_scene(path)?,
+        None => (args.preset.scene(), args.preset.camera()),
+    };
+    if args.verify_determinism {
+        return verify_determinism(&scene, camera, &args.render);
+    }
+    if args.animation.animate.is_some() {
+        return animation::render(&scene, camera, &args.render, &args.animation, &args.output);
+    }
+    let outputs = Outputs {
+        image: &args.output,
+        depth: args.depth.as_deref(),
+        trace_pixel: args.trace_pixel.as_deref(),
+    };
+    render_scene(&scene, camera, &args.render, &outputs)
+}
 
+/// What to write for a render, besides the progress and stats.
+struct Outputs<'a> {
+    image: &'a Path,
+    depth: Option<&'a Path>,
+    trace_pixel: Option<&'a [u32]>,
+}
+
+/// Read a scene file, and the camera to see it with: its first bookmark,
+/// or failing that one that fits the whole scene in view.
+fn read_scene(path: &Path) -> Result<(Scene, Camera)> {
+    let scene = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("halide") => Scene::from_dsl(&std::fs::read_to_string(path)?)?,
+        _ => Scene::load(path)?,
+    };
     let mut camera = Camera::default();
-    camera.set_size(WIDTH, HEIGHT);
-    camera.set_position((0., 0.75, 4.).into());
-
-    t1 = Instant::now();
-    println!("Setup scene in {}ms", (t1 - t0).as_millis());
-    t0 = t1;
-
-    // image data is packed u32s in ABGR order, and y goes from bottom to top
-    let image_data = renderer.render_accumulate(&scene, &camera, 64);
-
-    t1 = Instant::now();
-    println!("Rendered scene {:.2}s", (t1 - t0).as_secs_f32());
-    t0 = t1;
-
-    // buffer is unpacked u8s in RGB(A) order, and y goes from top to bottom
-    let mut buffer = Vec::new();
-    buffer.resize(image_data.len() * 4, 0);
-    // Convert from u32 to u8, and also flip the y axis.
-    for (idx1, p) in image_data.iter().enumerate() {
-        let x = idx1 % (WIDTH as usize);
-        let y = (HEIGHT as usize) - (idx1 / (WIDTH as usize)) - 1;
-        let idx2 = (x + y * (WIDTH as usize)) * 4;
-        let bytes = p.to_le_bytes();
-        buffer[idx2..(4 + idx2)].copy_from_slice(&bytes[..4]);
-    }
-    // convert to a pix raster, and then from RGBA to RGB.
-    let raster = pix::Raster::<pix::rgb::SRgba8>::with_u8_buffer(WIDTH, HEIGHT, buffer);
-    let converted = pix::Raster::<pix::rgb::SRgb8>::with_raster(&raster);
-
-    // encode and output the image
-    let png_raster = PngRaster::Rgb8(converted);
-    let mut out_data = Vec::new();
-    let mut encoder = png_pong::Encoder::new(&mut out_data).into_step_enc();
-    let step = png_pong::Step {
-        raster: png_raster,
-        delay: 0,
+    if let Some(bookmark) = scene.bookmarks().first() {
+        camera.apply_bookmark(bookmark);
+    } else if !scene.bounds().is_empty() {
+        camera.frame(&scene.bounds());
+    }
+    Ok((scene, camera))
+}
+
+/// Render `scene` from `camera`, adjusted by `args`, and save the images.
+fn render_scene(
+    scene: &Scene,
+    mut camera: Camera,
+    args: &RenderArgs,
+    outputs: &Outputs,
+) -> Result<()> {
+    if args.projection == ProjectionArg::Pano && args.stereo.is_some() {
+        bail!("stereo panoramas aren't supported");
+    }
+    let mut renderer = setup(&mut camera, args)?;
+    let [width, height] = camera.size();
+    let views = match args.stereo {
+        None => vec![("", camera)],
+        Some(_) => {
+            let stereo = Stereo {
+                ipd: args.ipd,
+                convergence: args.convergence,
+            };
+            vec![
+                ("-left", camera.stereo_eye(Eye::Left, &stereo)),
+                ("-right", camera.stereo_eye(Eye::Right, &stereo)),
+            ]
+        }
     };
-    encoder.encode(&step)?;
-    std::fs::write("image.png", out_data)?;
 
-    t1 = Instant::now();
-    println!("Encoded and output image in {}ms", (t1 - t0).as_millis());
+    let output = outputs.image;
+    let mut images = Vec::new();
+    for (suffix, camera) in &views {
+        renderer.reset_accumulation();
+        let view_output = with_suffix(output, suffix);
+        images.push(render_view(&mut renderer, scene, camera, args, &view_output)?);
+        if let Some([x, y]) = outputs.trace_pixel {
+            println!("{}", renderer.trace_pixel(scene, camera, *x, *y, 0));
+        }
+        if let Some(path) = outputs.depth {
+            let depth = renderer.render_depth(scene, camera);
+            renderer
+                .snapshot()
+                .save_exr_with_depth(with_suffix(path, suffix), &depth)?;
+        }
+    }
+
+    {
+        let _span = tracing::info_span!("save image").entered();
+        match args.stereo {
+            None | Some(StereoOutput::Separate) => {
+                for ((suffix, _), image) in views.iter().zip(&images) {
+                    image.save(with_suffix(output, suffix))?;
+                }
+            }
+            Some(StereoOutput::SideBySide) => {
+                let mut both = image::RgbaImage::new(width * 2, height);
+                image::imageops::replace(&mut both, &images[0], 0, 0);
+                image::imageops::replace(&mut both, &images[1], width as i64, 0);
+                both.save(output)?;
+            }
+        }
+    }
 
     Ok(())
 }
+
+/// A renderer set up as `args` asks, and `camera` adjusted to match.
+fn setup(camera: &mut Camera, args: &RenderArgs) -> Result<Renderer> {
+    let (width, height) = match args.projection {
+        ProjectionArg::Perspective => (1920, 1080),
+        ProjectionArg::Pano => (2160, 1080),
+    };
+
+    let mut renderer = Renderer::try_new(width, height)?;
+    renderer.transparent_background = args.transparent;
+    renderer.mark_invalid_samples = args.mark_invalid;
+    match args.integrator {
+        IntegratorArg::Path => {}
+        IntegratorArg::Ao => renderer.set_integrator(AmbientOcclusion::default()),
+        IntegratorArg::Normals => renderer.set_integrator(DebugNormals),
+        IntegratorArg::Photons => {
+            let mut photons = PhotonMapper::default();
+            photons.radius = args.photon_radius;
+            renderer.set_integrator(photons);
+        }
+    }
+
+    camera.set_size(width, height);
+    if let Some(position) = args.camera_pos {
+        camera.set_position(position);
+    }
+    if let Some(look) = args.camera_look {
+        camera.set_look_direction(look);
+    }
+    if let Some(target) = args.look_at {
+        camera.set_look_direction(target - camera.position());
+    }
+    if let Some(fov) = args.fov {
+        camera.set_vertical_fov(fov);
+    }
+    if args.projection == ProjectionArg::Pano {
+        camera.set_projection(Projection::Equirectangular);
+    }
+    Ok(renderer)
+}
+
+/// Render `scene` from the same seed on one thread and then on every core,
+/// and fail unless the accumulated samples are bit for bit the same, which
+/// they wouldn't be if the order threads finish in leaked into the image.
+fn verify_determinism(scene: &Scene, camera: Camera, args: &RenderArgs) -> Result<()> {
+    const SEED: u64 = 0;
+    let many = ThreadPolicy::All.num_threads().max(2);
+    let mut results = Vec::new();
+    for threads in [1, many] {
+        let mut camera = camera.clone();
+        let mut renderer = setup(&mut camera, args)?;
+        renderer.try_set_thread_policy(ThreadPolicy::Fixed(threads))?;
+        renderer.set_seed(Some(SEED));
+        println!("Rendering on {threads} threads");
+        renderer.render_accumulate(scene, &camera, args.samples);
+        results.push(renderer.snapshot());
+    }
+
+    let [one, all] = &results[..] else {
+        unreachable!();
+    };
+    let bits = |pixel: &glam::Vec4| pixel.to_array().map(f32::to_bits);
+    let mut differing =
+        (0..one.hdr.len()).filter(|idx| bits(&one.hdr[*idx]) != bits(&all.hdr[*idx]));
+    if let Some(first) = differing.next() {
+        let count = differing.count() + 1;
+        let (x, y) = (first as u32 % one.width, first as u32 / one.width);
+        bail!("{count} pixels differ between 1 and {many} threads, the first at ({x}, {y})");
+    }
+    println!("Renders on 1 and {many} threads are identical");
+    Ok(())
+}
+
+/// Render the scene as seen by `camera`, with a progress bar while it
+/// renders, and then print how it went. Snapshots along the way are named
+/// after `output`, the path the finished image is for.
+fn render_view(
+    renderer: &mut Renderer,
+    scene: &Scene,
+    camera: &Camera,
+    args: &RenderArgs,
+    output: &Path,
+) -> Result<image::RgbaImage> {
+    let samples = args.samples;
+    // hidden when stdout isn't a terminal, so logs only get the summary
+    let bar = ProgressBar::with_draw_target(Some(samples as u64), ProgressDrawTarget::stdout());
+    bar.set_style(ProgressStyle::with_template(
+        "{bar:40} {pos}/{len} samples in {elapsed}, {msg}, {eta} left",
+    )?);
+    let t0 = Instant::now();
+    let mut stats = RenderStats::default();
+    while stats.frames < samples {
+        // stop at each snapshot to save it
+        let chunk = match args.snapshot_every {
+            Some(every) => every.get() - stats.frames % every.get(),
+            None => samples,
+        };
+        let chunk = chunk.min(samples - stats.frames);
+        let (_, chunk_stats) = renderer.render_with_progress(scene, camera, chunk, |progress| {
+            let rays = stats.rays_traced() + progress.rays_traced();
+            let rays_per_second = rays as f64 / t0.elapsed().as_secs_f64();
+            bar.set_message(format!("{:.2} Mrays/s", rays_per_second / 1e6));
+            bar.set_position((stats.frames + progress.frames) as u64);
+            ControlFlow::Continue(())
+        });
+        stats.add_frames(chunk_stats);
+        if args
+            .snapshot_every
+            .is_some_and(|every| stats.frames % every.get() == 0)
+        {
+            let path = with_suffix(output, &format!("_{:04}", stats.frames));
+            renderer.as_image().save(path)?;
+        }
+    }
+    bar.finish();
+
+    for (name, duration) in &stats.stage_times {
+        println!("  {name}: {:.2}s", duration.as_secs_f32());
+    }
+    println!(
+        "  {} rays, {:.2} average bounces, {:.2} Msamples/s, {:.2} Mrays/s",
+        stats.rays_traced(),
+        stats.average_bounces(),
+        stats.samples_per_second() / 1e6,
+        stats.rays_per_second() / 1e6
+    );
+    if stats.invalid_samples > 0 {
+        println!("  {} samples were NaN or infinite", stats.invalid_samples);
+    }
+    Ok(renderer.as_image())
+}
+
+/// Parse a vector written as `x,y,z`.
+fn parse_vec3(arg: &str) -> Result<Vec3> {
+    let parts = arg
+        .split(',')
+        .map(|part| part.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()?;
+    match parts[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => bail!("expected three numbers, like 1,2,3"),
+    }
+}
+
+/// `path` with `suffix` added to the end of the file name, before the
+/// extension.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(suffix);
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
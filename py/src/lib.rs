@@ -0,0 +1,338 @@
+//! Python bindings, for scripting scene generation and batch renders.
+//!
+//! ```python
+//! import halide
+//!
+//! scene = halide.Scene()
+//! red = scene.add_lambertian((0.9, 0.2, 0.1))
+//! scene.add_sphere((0, 0.5, 0), 0.5, red)
+//! camera = halide.Camera(320, 240, position=(0, 0.75, 4), look_at=(0, 0.5, 0))
+//! renderer = halide.Renderer()
+//! renderer.render(scene, camera, samples=64)
+//! pixels = renderer.hdr()  # float32 array shaped (240, 320, 4)
+//! ```
+
+use glam::Vec3;
+use halide_raytracer::{
+    presets::Preset, Camera, Material, MaterialHandle, Renderer, Scene, Snapshot, Sphere,
+};
+use numpy::{IntoPyArray, PyArray3};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::path::PathBuf;
+
+#[pymodule]
+fn halide(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyScene>()?;
+    m.add_class::<PyMaterial>()?;
+    m.add_class::<PyCamera>()?;
+    m.add_class::<PyRenderer>()?;
+    Ok(())
+}
+
+/// The objects and materials to render.
+#[pyclass(name = "Scene")]
+struct PyScene {
+    scene: Scene,
+}
+
+#[pymethods]
+impl PyScene {
+    #[new]
+    fn new() -> Self {
+        Self {
+            scene: Scene::default(),
+        }
+    }
+
+    /// One of the built-in scenes, by name, like `"cornell-box"`.
+    #[staticmethod]
+    fn preset(name: &str) -> PyResult<Self> {
+        let preset: Preset = name.parse()?;
+        Ok(Self {
+            scene: preset.scene(),
+        })
+    }
+
+    /// Parse a scene in the text format described by `Scene::from_dsl`.
+    #[staticmethod]
+    fn from_dsl(source: &str) -> PyResult<Self> {
+        Ok(Self {
+            scene: Scene::from_dsl(source)?,
+        })
+    }
+
+    /// Load a scene saved by the viewer.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        Ok(Self {
+            scene: Scene::load(path)?,
+        })
+    }
+
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.scene.save(path)?)
+    }
+
+    /// Add a diffuse material, and return it for use with `add_sphere`.
+    fn add_lambertian(&mut self, albedo: (f32, f32, f32)) -> PyMaterial {
+        self.add_material(Material::Lambertian {
+            albedo: albedo.into(),
+        })
+    }
+
+    /// Add a material that gives off light, and return it for use with
+    /// `add_sphere`.
+    fn add_emissive(&mut self, emission: (f32, f32, f32)) -> PyMaterial {
+        self.add_material(Material::Emissive {
+            emission: emission.into(),
+        })
+    }
+
+    /// Add a sphere to the root of the scene, returning its index.
+    fn add_sphere(
+        &mut self,
+        center: (f32, f32, f32),
+        radius: f32,
+        material: PyMaterial,
+    ) -> usize {
+        self.scene.add_hittable(Sphere {
+            center: center.into(),
+            radius,
+            material: material.handle,
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.scene.hittables().len()
+    }
+}
+
+impl PyScene {
+    fn add_material(&mut self, material: Material) -> PyMaterial {
+        PyMaterial {
+            handle: self.scene.add_material(material),
+        }
+    }
+}
+
+/// A material added to a scene. Only meaningful for the scene it came from.
+#[pyclass(name = "Material")]
+#[derive(Clone, Copy)]
+struct PyMaterial {
+    handle: MaterialHandle,
+}
+
+#[pymethods]
+impl PyMaterial {
+    fn __repr__(&self) -> String {
+        format!("Material({})", self.handle.index())
+    }
+}
+
+/// Where the scene is seen from, and the size of the image.
+#[pyclass(name = "Camera")]
+struct PyCamera {
+    camera: Camera,
+}
+
+#[pymethods]
+impl PyCamera {
+    #[new]
+    #[pyo3(signature = (width, height, position=None, look_at=None, vertical_fov=None))]
+    fn new(
+        width: u32,
+        height: u32,
+        position: Option<(f32, f32, f32)>,
+        look_at: Option<(f32, f32, f32)>,
+        vertical_fov: Option<f32>,
+    ) -> PyResult<Self> {
+        let mut camera = PyCamera {
+            camera: Camera::default(),
+        };
+        camera.set_size((width, height))?;
+        if let Some(position) = position {
+            camera.camera.set_position(position.into());
+        }
+        if let Some(target) = look_at {
+            camera.look_at(target);
+        }
+        if let Some(vertical_fov) = vertical_fov {
+            camera.camera.set_vertical_fov(vertical_fov);
+        }
+        Ok(camera)
+    }
+
+    /// The camera a built-in scene is meant to be seen from.
+    #[staticmethod]
+    fn preset(name: &str, width: u32, height: u32) -> PyResult<Self> {
+        let preset: Preset = name.parse()?;
+        let mut camera = PyCamera {
+            camera: preset.camera(),
+        };
+        camera.set_size((width, height))?;
+        Ok(camera)
+    }
+
+    /// Turn the camera to face `target`.
+    fn look_at(&mut self, target: (f32, f32, f32)) {
+        let direction = Vec3::from(target) - self.camera.position();
+        self.camera.set_look_direction(direction);
+    }
+
+    #[getter]
+    fn size(&self) -> (u32, u32) {
+        let [width, height] = self.camera.size();
+        (width, height)
+    }
+
+    #[setter]
+    fn set_size(&mut self, size: (u32, u32)) -> PyResult<()> {
+        let (width, height) = size;
+        if width == 0 || height == 0 {
+            return Err(PyValueError::new_err("camera size must not be zero"));
+        }
+        self.camera.set_size(width, height);
+        Ok(())
+    }
+
+    #[getter]
+    fn position(&self) -> (f32, f32, f32) {
+        self.camera.position().into()
+    }
+
+    #[setter]
+    fn set_position(&mut self, position: (f32, f32, f32)) {
+        self.camera.set_position(position.into());
+    }
+
+    #[getter]
+    fn look_direction(&self) -> (f32, f32, f32) {
+        self.camera.look_direction().into()
+    }
+
+    #[setter]
+    fn set_look_direction(&mut self, direction: (f32, f32, f32)) {
+        self.camera.set_look_direction(direction.into());
+    }
+
+    #[getter]
+    fn vertical_fov(&self) -> f32 {
+        self.camera.vertical_fov()
+    }
+
+    #[setter]
+    fn set_vertical_fov(&mut self, vertical_fov: f32) {
+        self.camera.set_vertical_fov(vertical_fov);
+    }
+}
+
+/// Accumulates samples of a scene. The image is resized to match the camera
+/// on each render, which starts over if the size changed.
+#[pyclass(name = "Renderer")]
+struct PyRenderer {
+    renderer: Renderer,
+}
+
+#[pymethods]
+impl PyRenderer {
+    #[new]
+    #[pyo3(signature = (threads=None))]
+    fn new(threads: Option<usize>) -> PyResult<Self> {
+        let mut renderer = Renderer::try_new(1, 1).map_err(anyhow::Error::from)?;
+        if let Some(threads) = threads {
+            renderer
+                .try_set_num_threads(threads)
+                .map_err(anyhow::Error::from)?;
+        }
+        Ok(Self { renderer })
+    }
+
+    /// Add `samples` samples per pixel to the image. Runs without holding
+    /// the GIL, so other Python threads keep going.
+    #[pyo3(signature = (scene, camera, samples=1))]
+    fn render(
+        &mut self,
+        py: Python<'_>,
+        scene: PyRef<'_, PyScene>,
+        camera: PyRef<'_, PyCamera>,
+        samples: usize,
+    ) {
+        let scene = &scene.scene;
+        let camera = &camera.camera;
+        let [width, height] = camera.size();
+        self.renderer.resize(width, height);
+        py.allow_threads(|| {
+            self.renderer.render_accumulate(scene, camera, samples);
+        });
+    }
+
+    /// Throw away the samples taken so far.
+    fn reset(&mut self) {
+        self.renderer.reset_accumulation();
+    }
+
+    /// How many samples per pixel have been taken since the last reset.
+    #[getter]
+    fn samples(&self) -> f32 {
+        self.renderer.frame_count()
+    }
+
+    /// The linear average of the samples for each pixel, as a float32 array
+    /// shaped `(height, width, 4)` with premultiplied alpha and the top row
+    /// first.
+    fn hdr<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray3<f32>> {
+        let snapshot = self.renderer.snapshot();
+        let shape = [snapshot.height as usize, snapshot.width as usize, 4];
+        hdr_top_down(&snapshot).into_pyarray(py).reshape(shape)
+    }
+
+    /// The tone-mapped image, as a uint8 array shaped `(height, width, 4)`
+    /// with straight alpha and the top row first.
+    fn image<'py>(&self, py: Python<'py>) -> PyResult<&'py PyArray3<u8>> {
+        let snapshot = self.renderer.snapshot();
+        let shape = [snapshot.height as usize, snapshot.width as usize, 4];
+        snapshot.to_straight_rgba8().into_pyarray(py).reshape(shape)
+    }
+
+    fn save_png(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.renderer.snapshot().save_png(path)?)
+    }
+}
+
+/// Flatten a snapshot's HDR pixels to RGBA floats, with rows ordered top to
+/// bottom like numpy images.
+fn hdr_top_down(snapshot: &Snapshot) -> Vec<f32> {
+    snapshot
+        .hdr
+        .chunks_exact(snapshot.width as usize)
+        .rev()
+        .flat_map(|row| row.iter().flat_map(|pixel| pixel.to_array()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hdr_top_down;
+    use glam::Vec4;
+    use halide_raytracer::Snapshot;
+
+    #[test]
+    fn hdr_rows_are_flipped() {
+        let snapshot = Snapshot {
+            width: 2,
+            height: 2,
+            frame_count: 1.,
+            image: vec![0; 4],
+            hdr: vec![
+                Vec4::splat(0.),
+                Vec4::splat(1.),
+                Vec4::splat(2.),
+                Vec4::splat(3.),
+            ],
+        };
+        let flat = hdr_top_down(&snapshot);
+        assert_eq!(flat.len(), 16);
+        assert_eq!(&flat[..4], &[2.; 4]);
+        assert_eq!(&flat[12..], &[1.; 4]);
+    }
+}
@@ -0,0 +1,100 @@
+//! How rendering time grows with the size of the scene, the image, and the
+//! thread pool. Run one group at a time by name, like
+//! `cargo bench --bench scaling -- "sphere count"`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use halide_raytracer::{
+    presets::{self, Preset},
+    Camera, Renderer, Scene,
+};
+use std::time::Duration;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+fn camera(width: u32, height: u32) -> Camera {
+    let mut camera = Preset::RandomSpheres.camera();
+    camera.set_size(width, height);
+    camera
+}
+
+/// Render one fresh frame of `scene` each iteration.
+fn render_frames(renderer: &mut Renderer, scene: &Scene, camera: &Camera) {
+    renderer.reset_accumulation();
+    black_box(renderer.render(scene, camera));
+}
+
+pub fn sphere_count(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sphere count");
+    let camera = camera(WIDTH, HEIGHT);
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.set_num_threads(1);
+    for count in [10, 100, 1_000, 10_000] {
+        let scene = presets::random_spheres(count, 0);
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| render_frames(&mut renderer, &scene, &camera))
+        });
+    }
+    group.finish();
+}
+
+pub fn resolution(c: &mut Criterion) {
+    let mut group = c.benchmark_group("resolution");
+    let scene = presets::random_spheres(100, 0);
+    for (width, height) in [(80, 60), (160, 120), (320, 240), (640, 480)] {
+        let camera = camera(width, height);
+        let mut renderer = Renderer::new(width, height);
+        renderer.set_num_threads(1);
+        group.throughput(Throughput::Elements(width as u64 * height as u64));
+        group.bench_function(
+            BenchmarkId::from_parameter(format!("{width}x{height}")),
+            |b| b.iter(|| render_frames(&mut renderer, &scene, &camera)),
+        );
+    }
+    group.finish();
+}
+
+pub fn threads(c: &mut Criterion) {
+    let mut group = c.benchmark_group("threads");
+    let scene = presets::random_spheres(100, 0);
+    let camera = camera(WIDTH * 2, HEIGHT * 2);
+    let mut renderer = Renderer::new(WIDTH * 2, HEIGHT * 2);
+    let mut counts = vec![1, 2, 4, 8];
+    counts.retain(|threads| *threads <= num_cpus::get());
+    for threads in counts {
+        renderer.set_num_threads(threads);
+        group.bench_function(BenchmarkId::from_parameter(threads), |b| {
+            b.iter(|| render_frames(&mut renderer, &scene, &camera))
+        });
+    }
+    group.finish();
+}
+
+/// Testing every sphere gets slow quickly, so this stops short of the
+/// largest scene in "sphere count".
+pub fn bvh(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bvh");
+    let camera = camera(WIDTH, HEIGHT);
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.set_num_threads(1);
+    for count in [10, 100, 1_000] {
+        let mut scene = presets::random_spheres(count, 0);
+        for use_bvh in [true, false] {
+            scene.set_use_bvh(use_bvh);
+            let name = if use_bvh { "on" } else { "off" };
+            group.bench_function(BenchmarkId::new(name, count), |b| {
+                b.iter(|| render_frames(&mut renderer, &scene, &camera))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(
+    name = benches;
+    config = Criterion::default()
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(10));
+    targets = sphere_count, resolution, threads, bvh
+);
+criterion_main!(benches);
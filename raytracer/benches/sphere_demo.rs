@@ -1,49 +1,86 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use glam::Vec3;
-use halide_raytracer::{Camera, Material, Renderer, Scene, Sphere};
+use halide_raytracer::{presets::Preset, test_fixtures, Material, Quad, Renderer, Scene, Sphere};
 use std::time::Duration;
 
+/// Run once with `--features f64` and once without to compare the two
+/// precisions; the bench names differ so both sets of results are kept.
+const PRECISION: &str = if cfg!(feature = "f64") { "f64" } else { "f32" };
+
 pub fn criterion_benchmark(c: &mut Criterion) {
     const WIDTH: u32 = 640;
     const HEIGHT: u32 = 480;
     let mut renderer = Renderer::new(WIDTH, HEIGHT);
     renderer.set_num_threads(1);
 
-    let mut scene = Scene::default();
-    let ground_material = scene.add_material(Material::Lambertian {
-        albedo: Vec3::new(0.9, 0.2, 0.1),
+    let scene = Preset::Demo.scene();
+    let mut camera = Preset::Demo.camera();
+    camera.set_size(WIDTH, HEIGHT);
+
+    c.bench_function(&format!("sphere demo ({PRECISION})"), move |b| {
+        b.iter(|| {
+            renderer.reset_accumulation();
+            black_box(renderer.render(&scene, &camera));
+        })
     });
-    let ball_material = scene.add_material(Material::Lambertian {
-        albedo: Vec3::new(0.7, 0.7, 0.7),
+
+    let mut renderer = test_fixtures::renderer();
+    let scene = test_fixtures::sphere_on_ground();
+    let camera = test_fixtures::camera();
+    c.bench_function(&format!("tiny fixture ({PRECISION})"), move |b| {
+        b.iter(|| {
+            renderer.reset_accumulation();
+            black_box(renderer.render(&scene, &camera));
+        })
     });
 
-    scene.add_hittable(Sphere {
-        center: Vec3::new(0., -10_000., 0.),
-        radius: 10_000.,
-        material_index: ground_material,
+    // Between two wide, bright planes, nearly every path bounces until it's
+    // cut off, and there's little else to test, so this mostly measures
+    // scattering.
+    let mut renderer = test_fixtures::renderer();
+    let mut scene = Scene::default();
+    let white = scene.add_material(Material::Lambertian {
+        albedo: Vec3::splat(0.9),
+    });
+    for y in [-1., 1.] {
+        scene.add_hittable(Quad {
+            corner: Vec3::new(-1000., y, -1000.),
+            u: Vec3::X * 2000.,
+            v: Vec3::Z * 2000.,
+            material: white,
+        });
+    }
+    let mut camera = test_fixtures::camera();
+    camera.set_position(Vec3::ZERO);
+    c.bench_function(&format!("diffuse bounces ({PRECISION})"), move |b| {
+        b.iter(|| {
+            renderer.reset_accumulation();
+            black_box(renderer.render(&scene, &camera));
+        })
     });
 
-    scene.add_hittable(Sphere {
-        center: Vec3::new(-1.1, 0.5, 0.),
-        radius: 0.5,
-        material_index: ball_material,
+    // A ball on a planet, far from the origin, is where the two precisions
+    // differ in what they draw as well as in speed.
+    let mut renderer = test_fixtures::renderer();
+    let mut scene = Scene::default();
+    let radius = 6.4e6;
+    let ground = scene.add_material(Material::Lambertian {
+        albedo: Vec3::splat(0.7),
     });
     scene.add_hittable(Sphere {
-        center: Vec3::new(0., 0.5, 0.),
-        radius: 0.5,
-        material_index: ball_material,
+        center: Vec3::NEG_Y * radius,
+        radius,
+        material: ground,
     });
+    let far = Vec3::X * 1e5;
     scene.add_hittable(Sphere {
-        center: Vec3::new(1.1, 0.5, 0.),
+        center: far + Vec3::Y * 0.5,
         radius: 0.5,
-        material_index: ball_material,
+        material: ground,
     });
-
-    let mut camera = Camera::default();
-    camera.set_size(WIDTH, HEIGHT);
-    camera.set_position((0., 0.75, 4.).into());
-
-    c.bench_function("sphere demo", move |b| {
+    let mut camera = test_fixtures::camera();
+    camera.set_position(far + Vec3::new(0., 0.5, 4.));
+    c.bench_function(&format!("planet ({PRECISION})"), move |b| {
         b.iter(|| {
             renderer.reset_accumulation();
             black_box(renderer.render(&scene, &camera));
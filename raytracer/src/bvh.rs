@@ -0,0 +1,455 @@
+//! Bounding volume hierarchies, for finding what a ray might hit without
+//! testing everything. Used both over the triangles of each mesh and over
+//! the hittables in a scene.
+
+use crate::{
+    geom::{from_real_vec3, real_vec3, Ray, RealVec3},
+    Transform,
+};
+use glam::Vec3;
+use std::ops::{ControlFlow, Range};
+use tracing::debug_span;
+
+/// How many buckets to sort primitives into when looking for a split.
+const BINS: usize = 12;
+
+/// Leaves can hold more primitives than this if they're all in one spot.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Deep enough for any reasonable tree, and the size of the traversal stack.
+const MAX_DEPTH: usize = 48;
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Contains nothing, and grows to fit whatever it's combined with.
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Aabb {
+        points.into_iter().fold(Aabb::EMPTY, Aabb::grow)
+    }
+
+    pub fn around(center: Vec3, radius: f32) -> Aabb {
+        Aabb {
+            min: center - radius,
+            max: center + radius,
+        }
+    }
+
+    pub fn grow(self, point: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn intersection(self, other: Aabb) -> Aabb {
+        Aabb {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        }
+    }
+
+    pub fn expand(self, amount: f32) -> Aabb {
+        Aabb {
+            min: self.min - amount,
+            max: self.max + amount,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.
+    }
+
+    /// True if the box contains no points, like [`Aabb::EMPTY`] or the
+    /// intersection of boxes that don't overlap.
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    fn surface_area(&self) -> f32 {
+        let size = (self.max - self.min).max(Vec3::ZERO);
+        2. * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    /// The box around this one after it's moved by `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        Aabb::from_points(
+            self.corners()
+                .into_iter()
+                .map(|corner| transform.transform_point(corner)),
+        )
+    }
+
+    /// Each corner of the box, with bits 0, 1, and 2 of the index choosing
+    /// the max rather than min of x, y, and z.
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|corner| {
+            let pick = |bit: usize, axis: usize| {
+                if corner & bit == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+            Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2))
+        })
+    }
+
+    /// The twelve edges of the box, as pairs of corners, for drawing it as
+    /// a wireframe.
+    pub fn edges(&self) -> [[Vec3; 2]; 12] {
+        let corners = self.corners();
+        let mut edges = [[Vec3::ZERO; 2]; 12];
+        let ends = (0..8).flat_map(|corner| {
+            [1, 2, 4]
+                .into_iter()
+                .filter(move |bit| corner & bit == 0)
+                .map(move |bit| (corner, corner | bit))
+        });
+        for (edge, (start, end)) in edges.iter_mut().zip(ends) {
+            *edge = [corners[start], corners[end]];
+        }
+        edges
+    }
+
+    /// Where a ray enters the box, if it's inside it anywhere within
+    /// `clip`. Takes the reciprocal of the ray's direction, so it can be
+    /// shared between boxes.
+    fn entry(&self, origin: RealVec3, inverse_direction: Vec3, clip: &Range<f32>) -> Option<f32> {
+        let to_min = from_real_vec3(real_vec3(self.min) - origin) * inverse_direction;
+        let to_max = from_real_vec3(real_vec3(self.max) - origin) * inverse_direction;
+        // NaNs from 0 * infinity are skipped by min and max
+        let enter = to_min.min(to_max).max_element().max(clip.start);
+        let exit = to_min.max(to_max).min_element().min(clip.end);
+        (enter <= exit).then_some(enter)
+    }
+}
+
+/// A binary tree of boxes over some primitives, identified by their indexes
+/// in the slice of bounds it was built from.
+#[derive(Clone, Debug)]
+pub(crate) struct Bvh {
+    /// In depth first order, so each interior node's first child follows it.
+    nodes: Vec<Node>,
+    /// Primitive indexes, grouped so each leaf's are together.
+    order: Vec<usize>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    /// For leaves, where their primitives start in `order`. For interior
+    /// nodes, the index of their second child.
+    start: usize,
+    /// How many primitives a leaf has, or zero for interior nodes.
+    count: usize,
+}
+
+impl Bvh {
+    /// Builds a tree over primitives with the given bounds, splitting where
+    /// the surface area heuristic expects rays to test the fewest of them.
+    pub fn build(bounds: &[Aabb]) -> Bvh {
+        let _span = debug_span!("build bvh", primitives = bounds.len()).entered();
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            order: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let mut order = std::mem::take(&mut bvh.order);
+            bvh.build_node(bounds, &mut order, 0, 0);
+            bvh.order = order;
+        }
+        bvh
+    }
+
+    fn build_node(&mut self, bounds: &[Aabb], order: &mut [usize], start: usize, depth: usize) {
+        let idx = self.nodes.len();
+        let node_bounds = order
+            .iter()
+            .fold(Aabb::EMPTY, |total, &primitive| total.union(bounds[primitive]));
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            start,
+            count: order.len(),
+        });
+        if order.len() == 1 || depth >= MAX_DEPTH {
+            return;
+        }
+
+        let centers = Aabb::from_points(order.iter().map(|&primitive| bounds[primitive].center()));
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0. {
+            // everything is in one spot, so no split would help
+            return;
+        }
+        let bin_of = |primitive: usize| {
+            let offset = (bounds[primitive].center()[axis] - centers.min[axis]) / extent[axis];
+            ((offset * BINS as f32) as usize).min(BINS - 1)
+        };
+
+        let mut bins = [(0, Aabb::EMPTY); BINS];
+        for &primitive in order.iter() {
+            let bin = &mut bins[bin_of(primitive)];
+            bin.0 += 1;
+            bin.1 = bin.1.union(bounds[primitive]);
+        }
+        // the cost of splitting after each bin, relative to the node's area
+        let mut best = (f32::INFINITY, 0);
+        for split in 1..BINS {
+            let side = |bins: &[(usize, Aabb)]| {
+                bins.iter().fold((0, Aabb::EMPTY), |(count, total), (n, bin)| {
+                    (count + n, total.union(*bin))
+                })
+            };
+            let (left_count, left) = side(&bins[..split]);
+            let (right_count, right) = side(&bins[split..]);
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+            let cost = left.surface_area() * left_count as f32
+                + right.surface_area() * right_count as f32;
+            if cost < best.0 {
+                best = (cost, split);
+            }
+        }
+        let leaf_cost = node_bounds.surface_area() * order.len() as f32;
+        if best.0 >= leaf_cost && order.len() <= MAX_LEAF_SIZE {
+            return;
+        }
+
+        // the ends of the centers' range are in the first and last bins, so
+        // both sides get something
+        let mut middle = 0;
+        for i in 0..order.len() {
+            if bin_of(order[i]) < best.1 {
+                order.swap(i, middle);
+                middle += 1;
+            }
+        }
+
+        self.nodes[idx].count = 0;
+        let (left, right) = order.split_at_mut(middle);
+        self.build_node(bounds, left, start, depth + 1);
+        self.nodes[idx].start = self.nodes.len();
+        self.build_node(bounds, right, start + middle, depth + 1);
+    }
+
+    /// Fits the boxes to primitives that have moved, keeping the tree's
+    /// shape. Much faster than building a new tree, but the tree gets less
+    /// efficient the further things move.
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        let _span = debug_span!("refit bvh", primitives = bounds.len()).entered();
+        assert_eq!(bounds.len(), self.order.len(), "refit a BVH with a different primitive count");
+        for idx in (0..self.nodes.len()).rev() {
+            let Node { start, count, .. } = self.nodes[idx];
+            self.nodes[idx].bounds = if count > 0 {
+                self.order[start..start + count]
+                    .iter()
+                    .fold(Aabb::EMPTY, |total, &primitive| total.union(bounds[primitive]))
+            } else {
+                self.nodes[idx + 1].bounds.union(self.nodes[start].bounds)
+            };
+        }
+    }
+
+    /// The box around everything in the tree.
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |root| root.bounds)
+    }
+
+    /// Primitive indexes in the order the tree keeps them, so that each
+    /// leaf's are next to each other.
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Calls `visit` with each primitive whose box `ray` passes through
+    /// within `clip`, roughly nearest first. `visit` can shrink `clip` as it
+    /// finds hits, so farther boxes are skipped, or stop the search early.
+    pub fn traverse<B>(
+        &self,
+        ray: &Ray,
+        clip: &mut Range<f32>,
+        mut visit: impl FnMut(usize, &mut Range<f32>) -> ControlFlow<B>,
+    ) -> Option<B> {
+        self.traverse_leaves(ray, clip, |slots, clip| {
+            for &primitive in &self.order[slots] {
+                if let ControlFlow::Break(value) = visit(primitive, clip) {
+                    return ControlFlow::Break(value);
+                }
+            }
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Like [`Bvh::traverse`], but calls `visit` once for each leaf, with
+    /// where its primitives are in [`Bvh::order`], so they can be tested
+    /// together.
+    pub fn traverse_leaves<B>(
+        &self,
+        ray: &Ray,
+        clip: &mut Range<f32>,
+        mut visit: impl FnMut(Range<usize>, &mut Range<f32>) -> ControlFlow<B>,
+    ) -> Option<B> {
+        let inverse_direction = ray.direction.recip();
+        let entry = |node: usize, clip: &Range<f32>| {
+            self.nodes[node]
+                .bounds
+                .entry(ray.origin, inverse_direction, clip)
+        };
+
+        let mut stack = [0; MAX_DEPTH + 2];
+        let mut len = 0;
+        if !self.nodes.is_empty() && entry(0, clip).is_some() {
+            len = 1;
+        }
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len]];
+            if node.count > 0 {
+                if let ControlFlow::Break(value) = visit(node.start..node.start + node.count, clip)
+                {
+                    return Some(value);
+                }
+                continue;
+            }
+            let (first, second) = (stack[len] + 1, node.start);
+            match (entry(first, clip), entry(second, clip)) {
+                (Some(a), Some(b)) => {
+                    // visit the nearer child first, so hits there can skip the other
+                    let (near, far) = if a <= b { (first, second) } else { (second, first) };
+                    stack[len] = far;
+                    stack[len + 1] = near;
+                    len += 2;
+                }
+                (Some(_), None) => {
+                    stack[len] = first;
+                    len += 1;
+                }
+                (None, Some(_)) => {
+                    stack[len] = second;
+                    len += 1;
+                }
+                (None, None) => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Aabb, Bvh};
+    use crate::Ray;
+    use glam::Vec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::ops::ControlFlow;
+
+    fn random_boxes(rng: &mut StdRng, count: usize) -> Vec<Aabb> {
+        (0..count)
+            .map(|_| {
+                let center = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 20. - 10.;
+                Aabb::around(center, rng.gen_range(0.1..1.))
+            })
+            .collect()
+    }
+
+    /// The primitives each ray's box test passes, from the tree and by
+    /// checking every box.
+    fn compare(bvh: &Bvh, boxes: &[Aabb], rng: &mut StdRng) {
+        for _ in 0..200 {
+            let origin = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 30. - 15.;
+            let direction = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 2. - 1.;
+            let ray = Ray::new(origin, direction);
+            let clip = 0.0..100.;
+            let mut found = Vec::new();
+            bvh.traverse(&ray, &mut clip.clone(), |primitive, _| {
+                found.push(primitive);
+                ControlFlow::<()>::Continue(())
+            });
+            found.sort_unstable();
+            let inverse = ray.direction.recip();
+            let expected: Vec<_> = (0..boxes.len())
+                .filter(|&i| boxes[i].entry(ray.origin, inverse, &clip).is_some())
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn edges_join_neighboring_corners() {
+        let aabb = Aabb {
+            min: Vec3::ZERO,
+            max: Vec3::new(1., 2., 3.),
+        };
+        let edges = aabb.edges();
+        for [start, end] in edges {
+            // each edge runs along one axis
+            let along = (end - start).abs();
+            assert_eq!(along.cmpgt(Vec3::ZERO).bitmask().count_ones(), 1, "{start} {end}");
+        }
+        let total: f32 = edges.iter().map(|[start, end]| start.distance(*end)).sum();
+        assert_eq!(total, 4. * (1. + 2. + 3.));
+    }
+
+    #[test]
+    fn finds_every_box_a_ray_passes_through() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let boxes = random_boxes(&mut rng, 500);
+        let bvh = Bvh::build(&boxes);
+        compare(&bvh, &boxes, &mut rng);
+        assert_eq!(bvh.bounds(), boxes.iter().fold(Aabb::EMPTY, |a, b| a.union(*b)));
+    }
+
+    #[test]
+    fn refitting_follows_moved_boxes() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let boxes = random_boxes(&mut rng, 300);
+        let mut bvh = Bvh::build(&boxes);
+        let moved: Vec<_> = boxes
+            .iter()
+            .map(|b| Aabb {
+                min: b.min * 0.5 + Vec3::X * 3.,
+                max: b.max * 0.5 + Vec3::X * 3.,
+            })
+            .collect();
+        bvh.refit(&moved);
+        compare(&bvh, &moved, &mut rng);
+    }
+
+    #[test]
+    fn stops_early() {
+        let boxes: Vec<_> = (0..10)
+            .map(|i| Aabb::around(Vec3::Z * i as f32 * 3., 1.))
+            .collect();
+        let bvh = Bvh::build(&boxes);
+        let ray = Ray::new(Vec3::NEG_Z * 5., Vec3::Z);
+        let first = bvh.traverse(&ray, &mut (0.0..100.), |primitive, _| {
+            ControlFlow::Break(primitive)
+        });
+        assert_eq!(first, Some(0));
+    }
+}
@@ -1,53 +1,333 @@
-use glam::{Mat4, Quat, Vec2, Vec3, Vec4Swizzles};
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4Swizzles};
 use parking_lot::RwLock;
 use std::ops::Range;
-use crate::halton::{Halton, Halton2};
+use crate::{
+    bvh::Aabb,
+    geom::{from_real_vec3, real, real_vec3, Ray, RealVec3},
+    halton::{Halton, Halton2},
+};
 
 pub struct Camera {
-    position: Vec3,
+    /// In [`Real`](crate::Real) precision, so the camera can move in small
+    /// steps, and its rays start where it is, far from the origin.
+    position: RealVec3,
     look_direction: Vec3,
     right_direction: Vec3,
     up_direction: Vec3,
     vertical_fov: f32,
+    projection: Projection,
     width: u32,
     height: u32,
     look_clip: Range<f32>,
+    /// How far the image slides sideways, in clip space, for the views of a
+    /// stereo pair.
+    projection_shift: f32,
+    /// Kept up to date by everything that moves the camera.
+    view_inverse: Mat4,
+    /// Kept up to date by everything that changes the field of view, size
+    /// or clip range.
+    projection_inverse: Mat4,
     jitter: RwLock<Halton2>,
+    animation: Option<Animation>,
+    turntable: Option<Turntable>,
 }
 
 impl Default for Camera {
     fn default() -> Self {
-        Self {
-            position: Vec3::Z * 3.,
+        let mut camera = Self {
+            position: real_vec3(Vec3::Z * 3.),
             look_direction: Vec3::NEG_Z,
             right_direction: Vec3::X,
             up_direction: Vec3::Y,
             vertical_fov: 25.,
+            projection: Projection::Perspective,
             width: 640,
             height: 480,
             look_clip: 0.01..100.0,
+            projection_shift: 0.,
+            view_inverse: Mat4::IDENTITY,
+            projection_inverse: Mat4::IDENTITY,
             jitter: RwLock::new(Halton::two_d((2, 3))),
+            animation: None,
+            turntable: None,
+        };
+        camera.recalculate_view();
+        camera.recalculate_projection();
+        camera
+    }
+}
+
+impl Clone for Camera {
+    fn clone(&self) -> Self {
+        Self {
+            look_clip: self.look_clip.clone(),
+            jitter: RwLock::new(self.jitter.read().clone()),
+            ..*self
+        }
+    }
+}
+
+/// A saved camera view that can be returned to later.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraBookmark {
+    pub name: String,
+    pub position: Vec3,
+    pub look_direction: Vec3,
+    pub vertical_fov: f32,
+    /// In degrees, as from [`Camera::roll`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub roll: f32,
+}
+
+/// Automatically orbits the camera around a vertical axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Turntable {
+    /// The point the axis passes through.
+    pub center: Vec3,
+    /// Orbit speed. Positive values orbit counter-clockwise seen from above.
+    pub degrees_per_second: f32,
+}
+
+/// A common lens, for choosing a field of view the way photographers do,
+/// with [`Camera::set_focal_length`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensPreset {
+    pub name: &'static str,
+    /// In millimeters.
+    pub focal_length: f32,
+    /// The height of the sensor or film the lens is used with, in
+    /// millimeters.
+    pub sensor_height: f32,
+}
+
+impl LensPreset {
+    /// The height of a full frame sensor, or of 35mm film.
+    pub const FULL_FRAME: f32 = 24.;
+    pub const APS_C: f32 = 15.6;
+    pub const MICRO_FOUR_THIRDS: f32 = 13.;
+
+    pub const ALL: [LensPreset; 9] = [
+        Self::full_frame("14mm full frame", 14.),
+        Self::full_frame("24mm full frame", 24.),
+        Self::full_frame("35mm full frame", 35.),
+        Self::full_frame("50mm full frame", 50.),
+        Self::full_frame("85mm full frame", 85.),
+        Self::full_frame("135mm full frame", 135.),
+        Self::full_frame("200mm full frame", 200.),
+        LensPreset {
+            name: "35mm APS-C",
+            focal_length: 35.,
+            sensor_height: Self::APS_C,
+        },
+        LensPreset {
+            name: "25mm Micro Four Thirds",
+            focal_length: 25.,
+            sensor_height: Self::MICRO_FOUR_THIRDS,
+        },
+    ];
+
+    const fn full_frame(name: &'static str, focal_length: f32) -> Self {
+        LensPreset {
+            name,
+            focal_length,
+            sensor_height: Self::FULL_FRAME,
+        }
+    }
+
+    /// The vertical field of view the lens gives, in degrees.
+    pub fn vertical_fov(&self) -> f32 {
+        Camera::fov_for_focal_length(self.focal_length, self.sensor_height)
+    }
+}
+
+/// How a [`Camera`] spreads its rays over the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Projection {
+    /// Like a pinhole camera, seeing as much as the field of view allows.
+    #[default]
+    Perspective,
+    /// Every direction at once, with longitude across the image and
+    /// latitude up it, for environment maps and panorama viewers. The look
+    /// direction is in the middle. Images should be twice as wide as they
+    /// are tall, and the field of view is ignored.
+    Equirectangular,
+}
+
+/// One side of a stereo pair, from [`Camera::stereo_eye`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Eye {
+    Left,
+    Right,
+}
+
+/// How a stereo pair of views is set up, for VR headsets and 3D displays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stereo {
+    /// The distance between the eyes, in scene units. People's are about
+    /// 0.064 meters apart.
+    pub ipd: f32,
+    /// How far in front of the camera the two views line up. Nearer things
+    /// seem to stand out in front of the screen, and farther things seem
+    /// to sit behind it.
+    pub convergence: f32,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            ipd: 0.064,
+            convergence: 3.,
         }
     }
 }
 
+/// An in-progress move started by [`Camera::animate_to`].
+#[derive(Clone, Copy, Debug)]
+struct Animation {
+    from_position: RealVec3,
+    to_position: RealVec3,
+    from_fov: f32,
+    to_fov: f32,
+    from_orientation: [Vec3; 3],
+    /// Rotation from the starting orientation to the target.
+    rotation: Quat,
+    elapsed: f32,
+    duration: f32,
+}
+
 impl Camera {
+    /// Capture the current view.
+    pub fn bookmark<S: Into<String>>(&self, name: S) -> CameraBookmark {
+        CameraBookmark {
+            name: name.into(),
+            position: self.position(),
+            look_direction: self.look_direction,
+            vertical_fov: self.vertical_fov,
+            roll: self.roll(),
+        }
+    }
+
+    /// Return to a view captured with [`Camera::bookmark`].
+    pub fn apply_bookmark(&mut self, bookmark: &CameraBookmark) {
+        self.set_position(bookmark.position);
+        self.set_look_direction(bookmark.look_direction);
+        self.set_roll(bookmark.roll);
+        self.set_vertical_fov(bookmark.vertical_fov);
+    }
+
+    /// Move smoothly to `target` over `duration` seconds, as time is
+    /// advanced by [`Camera::update`]. Position is eased in and out, and
+    /// orientation is spherically interpolated.
+    pub fn animate_to(&mut self, target: &CameraBookmark, duration: f32) {
+        let to_direction = target
+            .look_direction
+            .try_normalize()
+            .unwrap_or(self.look_direction);
+        let from = self.rotation();
+        let [_, to_right, to_up] = self.level_orientation(to_direction, target.roll);
+        let to = Quat::from_mat3(&Mat3::from_cols(to_right, to_up, -to_direction));
+        self.animation = Some(Animation {
+            from_position: self.position,
+            to_position: real_vec3(target.position),
+            from_fov: self.vertical_fov,
+            to_fov: target.vertical_fov,
+            from_orientation: [self.look_direction, self.right_direction, self.up_direction],
+            rotation: (to * from.inverse()).normalize(),
+            elapsed: 0.,
+            duration: duration.max(0.),
+        });
+    }
+
+    pub fn is_animating(&self) -> bool {
+        self.animation.is_some()
+    }
+
+    /// Stop any animation started with [`Camera::animate_to`], leaving the
+    /// camera where it is.
+    pub fn cancel_animation(&mut self) {
+        self.animation = None;
+    }
+
+    pub fn turntable(&self) -> Option<Turntable> {
+        self.turntable
+    }
+
+    pub fn set_turntable(&mut self, turntable: Option<Turntable>) {
+        self.turntable = turntable;
+    }
+
+    /// Advance animations by `ts` seconds. Returns true if the camera moved.
+    /// An animation started by [`Camera::animate_to`] takes priority over
+    /// the turntable.
+    pub fn update(&mut self, ts: f32) -> bool {
+        if let Some(mut animation) = self.animation.take() {
+            animation.elapsed += ts;
+            let t = if animation.duration > 0. {
+                (animation.elapsed / animation.duration).min(1.)
+            } else {
+                1.
+            };
+            let eased = t * t * (3. - 2. * t);
+
+            self.position = animation
+                .from_position
+                .lerp(animation.to_position, real(eased));
+            self.vertical_fov =
+                animation.from_fov + (animation.to_fov - animation.from_fov) * eased;
+            let q = Quat::IDENTITY.slerp(animation.rotation, eased);
+            let [look, right, up] = animation.from_orientation;
+            self.look_direction = (q * look).normalize();
+            self.right_direction = (q * right).normalize();
+            self.up_direction = (q * up).normalize();
+            self.orthonormalize();
+
+            if t < 1. {
+                self.animation = Some(animation);
+            }
+            self.recalculate_view();
+            self.recalculate_projection();
+            true
+        } else if let Some(turntable) = self.turntable {
+            if turntable.degrees_per_second == 0. {
+                return false;
+            }
+            let q = Quat::from_rotation_y((turntable.degrees_per_second * ts).to_radians());
+            let center = real_vec3(turntable.center);
+            self.position = center + real_vec3(q * from_real_vec3(self.position - center));
+            self.look_direction = q * self.look_direction;
+            self.right_direction = q * self.right_direction;
+            self.up_direction = q * self.up_direction;
+            self.orthonormalize();
+            self.recalculate_view();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Rounded to `f32`, even with the `f64` feature.
     pub fn position(&self) -> Vec3 {
-        self.position
+        from_real_vec3(self.position)
     }
 
     pub fn set_position(&mut self, position: Vec3) {
-        self.position = position;
+        let position = real_vec3(position);
+        if self.position != position {
+            self.position = position;
+            self.recalculate_view();
+        }
     }
 
     /// Move the cameras origin. `offset` is mapped to the coordinate system of
     /// the view, with X being to the right, Y being up, and Z being backwards.
-    pub fn relative_move(&mut self, offset: Vec3, ts: f32) -> &Vec3 {
+    pub fn relative_move(&mut self, offset: Vec3, ts: f32) -> &RealVec3 {
         const MOVE_SPEED: f32 = 2.;
         let rotated = offset.x * self.right_direction
             + offset.y * self.up_direction
             + offset.z * self.look_direction;
-        self.position += MOVE_SPEED * rotated * ts;
+        self.position += real_vec3(MOVE_SPEED * rotated * ts);
+        self.recalculate_view();
         &self.position
     }
 
@@ -57,24 +337,70 @@ impl Camera {
         let q = Quat::from_axis_angle(self.right_direction, pitch * scale)
             * Quat::from_axis_angle(self.up_direction, yaw * scale).normalize();
 
-        self.look_direction = q * self.look_direction;
+        // turning shouldn't roll the camera, even though pitching and
+        // yawing in turn would
+        let roll = self.roll();
+        [self.look_direction, self.right_direction, self.up_direction] =
+            self.level_orientation((q * self.look_direction).normalize(), roll);
+        self.recalculate_view();
+        &self.look_direction
+    }
+
+    /// Turn the camera around its look direction. Positive `roll` turns it
+    /// clockwise, tipping its top to the right.
+    pub fn relative_roll(&mut self, roll: f32, ts: f32) -> &Vec3 {
+        const ROLL_SPEED: f32 = 1.5;
+        let q = Quat::from_axis_angle(self.look_direction, ROLL_SPEED * roll * ts);
         self.right_direction = q * self.right_direction;
         self.up_direction = q * self.up_direction;
-        &self.look_direction
+        self.orthonormalize();
+        self.recalculate_view();
+        &self.up_direction
     }
 
     pub fn look_direction(&self) -> Vec3 {
         self.look_direction
     }
 
+    /// Turn to face along `look_direction`, keeping the same roll.
     pub fn set_look_direction(&mut self, look_direction: Vec3) {
         if let Some(normalized) = look_direction.try_normalize() {
             if normalized != self.look_direction {
-                self.look_direction = normalized;
+                [self.look_direction, self.right_direction, self.up_direction] =
+                    self.level_orientation(normalized, self.roll());
+                self.recalculate_view();
             }
         }
     }
 
+    /// The camera's up direction, at right angles to its look direction.
+    pub fn up_direction(&self) -> Vec3 {
+        self.up_direction
+    }
+
+    /// How far the camera is turned around its look direction, in degrees
+    /// clockwise from level with the horizon. Looking straight up or down
+    /// there's no horizon to measure from, so the roll is 0 and can't be
+    /// set, though [`Camera::relative_roll`] still turns the camera.
+    pub fn roll(&self) -> f32 {
+        let Some(level_right) = self.look_direction.cross(Vec3::Y).try_normalize() else {
+            return 0.;
+        };
+        let level_up = level_right.cross(self.look_direction);
+        self.up_direction
+            .dot(level_right)
+            .atan2(self.up_direction.dot(level_up))
+            .to_degrees()
+    }
+
+    pub fn set_roll(&mut self, roll: f32) {
+        if self.roll() != roll {
+            [self.look_direction, self.right_direction, self.up_direction] =
+                self.level_orientation(self.look_direction, roll);
+            self.recalculate_view();
+        }
+    }
+
     pub fn vertical_fov(&self) -> f32 {
         self.vertical_fov
     }
@@ -82,9 +408,43 @@ impl Camera {
     pub fn set_vertical_fov(&mut self, vertical_fov: f32) {
         if self.vertical_fov != vertical_fov {
             self.vertical_fov = vertical_fov;
+            self.recalculate_projection();
         }
     }
 
+    /// The focal length of a lens that would give a sensor
+    /// `sensor_height` millimeters tall the same vertical field of view as
+    /// the camera, in millimeters.
+    pub fn focal_length(&self, sensor_height: f32) -> f32 {
+        Self::focal_length_for_fov(self.vertical_fov, sensor_height)
+    }
+
+    /// Set the field of view to what a lens `focal_length` millimeters
+    /// long gives a sensor `sensor_height` millimeters tall, such as
+    /// [`LensPreset::FULL_FRAME`].
+    pub fn set_focal_length(&mut self, focal_length: f32, sensor_height: f32) {
+        self.set_vertical_fov(Self::fov_for_focal_length(focal_length, sensor_height));
+    }
+
+    /// The vertical field of view in degrees that a lens `focal_length`
+    /// millimeters long gives a sensor `sensor_height` millimeters tall.
+    pub fn fov_for_focal_length(focal_length: f32, sensor_height: f32) -> f32 {
+        (2. * (sensor_height / (2. * focal_length)).atan()).to_degrees()
+    }
+
+    /// The inverse of [`Camera::fov_for_focal_length`].
+    pub fn focal_length_for_fov(vertical_fov: f32, sensor_height: f32) -> f32 {
+        sensor_height / (2. * (vertical_fov.to_radians() / 2.).tan())
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
     pub fn size(&self) -> [u32; 2] {
         [self.width, self.height]
     }
@@ -93,6 +453,7 @@ impl Camera {
         if self.width != width || self.height != height {
             self.width = width;
             self.height = height;
+            self.recalculate_projection();
         }
     }
 
@@ -102,45 +463,519 @@ impl Camera {
 
     pub fn set_look_clip(&mut self, look_clip: Range<f32>) {
         self.look_clip = look_clip;
+        self.recalculate_projection();
     }
 
     pub fn aspect_ratio(&self) -> f32 {
         self.width as f32 / self.height as f32
     }
 
+    /// The view from one eye of a stereo pair centered on this camera.
+    /// Rather than turning in to meet, which would skew the two images
+    /// differently, the eyes look straight ahead with their images slid
+    /// sideways so they line up at `stereo.convergence`.
+    pub fn stereo_eye(&self, eye: Eye, stereo: &Stereo) -> Camera {
+        let offset = match eye {
+            Eye::Left => -stereo.ipd / 2.,
+            Eye::Right => stereo.ipd / 2.,
+        };
+        let half_width = (self.vertical_fov.to_radians() / 2.).tan()
+            * self.aspect_ratio()
+            * stereo.convergence;
+        let mut camera = self.clone();
+        camera.position = self.position + real_vec3(self.right_direction * offset);
+        camera.recalculate_view();
+        camera.projection_shift = self.projection_shift + offset / half_width;
+        camera.recalculate_projection();
+        camera
+    }
+
+    /// Move back along the look direction until all of `bounds` is in view,
+    /// centered, pushing the far end of the clip range out if it has to.
+    /// Does nothing if `bounds` is empty.
+    pub fn frame(&mut self, bounds: &Aabb) {
+        if bounds.is_empty() {
+            return;
+        }
+        // fitting the sphere around the box keeps it in view from any angle
+        let radius = (bounds.max - bounds.min).length() / 2.;
+        let half_vertical = self.vertical_fov.to_radians() / 2.;
+        let half_horizontal = (half_vertical.tan() * self.aspect_ratio()).atan();
+        let distance = radius / half_vertical.min(half_horizontal).sin();
+        self.set_position(bounds.center() - self.look_direction * distance);
+        if self.look_clip.end < distance + radius {
+            self.set_look_clip(self.look_clip.start..distance + radius);
+        }
+    }
+
+    /// The direction of the ray through each pixel, with rows ordered
+    /// bottom to top. Renderers should use [`Camera::ray_for_pixel`]
+    /// instead, which doesn't need the whole image's worth of directions at
+    /// once.
     pub fn get_ray_directions(&self) -> Vec<Vec3> {
-        const V_UP: Vec3 = Vec3::new(0., 1., 0.);
+        let rays = self.rays();
+        (0..rays.len()).map(|pixel| rays.ray(pixel).direction).collect()
+    }
 
-        let view = Mat4::look_to_rh(self.position, self.look_direction, V_UP);
-        let view_inverse = view.inverse();
+    /// The ray through a pixel, counting from the bottom left. `jitter`
+    /// moves it away from the pixel's center, by up to half a pixel in
+    /// each direction.
+    pub fn ray_for_pixel(&self, x: u32, y: u32, jitter: Vec2) -> Ray {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        // screen uv coordinate with x and y in [-1,1]
+        let coord = (Vec2::new(x as f32, y as f32) + jitter) / size * 2. - Vec2::ONE;
 
+        let view_direction = match self.projection {
+            Projection::Perspective => {
+                let target = self.projection_inverse * coord.extend(1.).extend(1.);
+                (target.xyz() / target.w).normalize()
+            }
+            Projection::Equirectangular => {
+                let longitude = coord.x * std::f32::consts::PI;
+                let latitude = coord.y * std::f32::consts::FRAC_PI_2;
+                Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                )
+            }
+        };
+        let direction = self.view_inverse * view_direction.extend(0.);
+        Ray {
+            origin: self.position,
+            direction: direction.xyz(),
+        }
+    }
+
+    /// Where `point` appears on the image, in pixels from the bottom left,
+    /// or `None` if it's behind the camera. The inverse of
+    /// [`Camera::ray_for_pixel`], so a pixel's center is at its
+    /// coordinates.
+    pub fn project(&self, point: Vec3) -> Option<Vec2> {
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let coord = match self.projection {
+            Projection::Perspective => {
+                let clip = self.view_projection() * point.extend(1.);
+                if clip.w <= 0. {
+                    return None;
+                }
+                clip.xy() / clip.w
+            }
+            Projection::Equirectangular => {
+                let offset = from_real_vec3(real_vec3(point) - self.position);
+                let view = self.view_inverse.inverse().transform_vector3(offset);
+                let view = view.try_normalize()?;
+                Vec2::new(
+                    view.x.atan2(-view.z) / std::f32::consts::PI,
+                    view.y.asin() / std::f32::consts::FRAC_PI_2,
+                )
+            }
+        };
+        Some((coord + Vec2::ONE) / 2. * size)
+    }
+
+    /// The line from `start` to `end` as it appears on the image, in pixels
+    /// from the bottom left, cut off where it goes behind the near end of
+    /// the clip range. Panoramas wrap around at their left and right edges,
+    /// and lines crossing there are left out.
+    pub fn project_segment(&self, start: Vec3, end: Vec3) -> Option<[Vec2; 2]> {
+        match self.projection {
+            Projection::Perspective => {
+                // how far in front of the near plane each end is
+                let near = self.look_clip.start;
+                let depth = |point: Vec3| {
+                    from_real_vec3(real_vec3(point) - self.position).dot(self.look_direction) - near
+                };
+                let (start_depth, end_depth) = (depth(start), depth(end));
+                if start_depth < 0. && end_depth < 0. {
+                    return None;
+                }
+                let cut = |behind: Vec3, front: Vec3, behind_depth: f32, front_depth: f32| {
+                    behind.lerp(front, behind_depth / (behind_depth - front_depth))
+                };
+                let (start, end) = if start_depth < 0. {
+                    (cut(start, end, start_depth, end_depth), end)
+                } else if end_depth < 0. {
+                    (start, cut(end, start, end_depth, start_depth))
+                } else {
+                    (start, end)
+                };
+                Some([self.project(start)?, self.project(end)?])
+            }
+            Projection::Equirectangular => {
+                let [start, end] = [self.project(start)?, self.project(end)?];
+                ((start.x - end.x).abs() < self.width as f32 / 2.).then_some([start, end])
+            }
+        }
+    }
+
+    /// Takes points in world space to clip space. Only meaningful for
+    /// [`Projection::Perspective`].
+    pub(crate) fn view_projection(&self) -> Mat4 {
+        (self.view_inverse * self.projection_inverse).inverse()
+    }
+
+    /// Set up the rays for the next frame, moving on to the next jitter
+    /// offset so that accumulated frames sample the whole of each pixel.
+    pub(crate) fn rays(&self) -> CameraRays<'_> {
+        let (jx, jy) = self.jitter.write().next().unwrap_or_default();
+        self.rays_with_jitter(Vec2::new(jx, jy) - 0.5)
+    }
+
+    /// The rays for a frame that uses a jitter offset chosen before, such
+    /// as to trace the same rays again.
+    pub(crate) fn rays_with_jitter(&self, jitter: Vec2) -> CameraRays<'_> {
+        CameraRays {
+            camera: self,
+            jitter,
+        }
+    }
+
+    /// The look, right, and up directions for facing along `look`, which
+    /// must be normalized, turned by `roll` degrees from level.
+    fn level_orientation(&self, look: Vec3, roll: f32) -> [Vec3; 3] {
+        let Some(level_right) = look.cross(Vec3::Y).try_normalize() else {
+            // straight up or down there's no level to start from, so turn
+            // the shortest way from where the camera faces now instead
+            let q = Quat::from_rotation_arc(self.look_direction, look);
+            let right = (q * self.right_direction)
+                .reject_from(look)
+                .try_normalize()
+                .unwrap_or(Vec3::X);
+            return [look, right, right.cross(look)];
+        };
+        let level_up = level_right.cross(look);
+        let q = Quat::from_axis_angle(look, roll.to_radians());
+        [look, q * level_right, q * level_up]
+    }
+
+    /// The rotation from looking down -Z, with Y up, to the camera's
+    /// orientation.
+    fn rotation(&self) -> Quat {
+        let basis = Mat3::from_cols(
+            self.right_direction,
+            self.up_direction,
+            -self.look_direction,
+        );
+        Quat::from_mat3(&basis).normalize()
+    }
+
+    /// Turns are only as precise as floats allow, so the directions slowly
+    /// drift apart from right angles as they build up. Squaring them up
+    /// again after each one keeps the view from skewing.
+    fn orthonormalize(&mut self) {
+        self.look_direction = self.look_direction.normalize();
+        self.right_direction = self
+            .look_direction
+            .cross(self.up_direction)
+            .try_normalize()
+            .unwrap_or(self.right_direction);
+        self.up_direction = self.right_direction.cross(self.look_direction);
+    }
+
+    fn recalculate_view(&mut self) {
+        self.view_inverse =
+            Mat4::look_to_rh(self.position(), self.look_direction, self.up_direction).inverse();
+    }
+
+    fn recalculate_projection(&mut self) {
         let projection = Mat4::perspective_rh(
             self.vertical_fov.to_radians(),
             self.aspect_ratio(),
             self.look_clip.start,
             self.look_clip.end,
         );
-        let projection_inverse = projection.inverse();
+        self.projection_inverse =
+            (Mat4::from_translation(Vec3::X * self.projection_shift) * projection).inverse();
+    }
+}
 
-        let mut ray_directions = Vec::with_capacity(self.width as usize * self.height as usize);
+/// The camera rays for one frame, made a pixel at a time where they're
+/// traced, instead of being collected into a buffer up front.
+pub(crate) struct CameraRays<'a> {
+    camera: &'a Camera,
+    jitter: Vec2,
+}
 
-        let (jx, jy) = self.jitter.write().next().unwrap_or_default();
-        let wp = self.width as f32;
-        let hp = self.height as f32;
-        for y in 0..self.height {
-            let yp = y as f32 + jy - 0.5;
-            for x in 0..self.width {
-                let xp = x as f32 + jx - 0.5;
-                // screen uv coordinate with x and y in [-1,1]
-                let coord = Vec2::new(xp / wp, yp / hp) * 2. - Vec2::ONE;
-
-                let target = projection_inverse * coord.extend(1.).extend(1.);
-                let direction = view_inverse * (target.xyz() / target.w).normalize().extend(0.);
-                ray_directions.push(direction.xyz());
+impl CameraRays<'_> {
+    pub fn jitter(&self) -> Vec2 {
+        self.jitter
+    }
+
+    pub fn len(&self) -> usize {
+        let [width, height] = self.camera.size();
+        width as usize * height as usize
+    }
+
+    /// The ray through a pixel, counting along rows from the bottom left.
+    pub fn ray(&self, pixel: usize) -> Ray {
+        let width = self.camera.width as usize;
+        let (x, y) = ((pixel % width) as u32, (pixel / width) as u32);
+        self.camera.ray_for_pixel(x, y, self.jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Camera, Eye, LensPreset, Projection, Stereo, Turntable};
+    use crate::{
+        bvh::Aabb,
+        geom::{from_real_vec3, real_vec3},
+    };
+    use glam::{Vec2, Vec3, Vec4Swizzles};
+
+    #[test]
+    fn pixel_rays_follow_the_camera() {
+        let mut camera = Camera::default();
+        camera.set_size(100, 50);
+        let center = camera.ray_for_pixel(50, 25, Vec2::ZERO);
+        assert_eq!(from_real_vec3(center.origin), camera.position());
+        assert!(center.direction.distance(Vec3::NEG_Z) < 1e-5);
+        // the bottom row is half the field of view down
+        let bottom = camera.ray_for_pixel(50, 0, Vec2::ZERO);
+        let angle = bottom.direction.angle_between(Vec3::NEG_Z).to_degrees();
+        assert!((angle - 12.5).abs() < 1e-3);
+        assert!(bottom.direction.y < 0.);
+
+        camera.set_position(Vec3::new(1., 2., 3.));
+        camera.set_look_direction(Vec3::X);
+        camera.set_vertical_fov(90.);
+        let center = camera.ray_for_pixel(50, 25, Vec2::ZERO);
+        assert_eq!(center.origin, real_vec3(Vec3::new(1., 2., 3.)));
+        assert!(center.direction.distance(Vec3::X) < 1e-5);
+        let bottom = camera.ray_for_pixel(50, 0, Vec2::ZERO);
+        assert!((bottom.direction.angle_between(Vec3::X).to_degrees() - 45.).abs() < 1e-3);
+    }
+
+    #[cfg(feature = "f64")]
+    #[test]
+    fn small_moves_add_up_far_from_the_origin() {
+        let mut camera = Camera::default();
+        camera.set_size(100, 50);
+        camera.set_position(Vec3::X * 1e7);
+        camera.set_look_direction(Vec3::NEG_Z);
+        // each step is 2mm, far below the spacing of f32s out here
+        for _ in 0..100 {
+            camera.relative_move(Vec3::X, 0.001);
+        }
+        let center = camera.ray_for_pixel(50, 25, Vec2::ZERO);
+        assert!((center.origin.x - 1e7 - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn roll_turns_the_view() {
+        let mut camera = Camera::default();
+        camera.set_size(100, 50);
+        camera.set_roll(90.);
+        assert!((camera.roll() - 90.).abs() < 1e-3);
+        assert!(camera.up_direction().distance(Vec3::X) < 1e-5);
+        // the top of the image now looks off to the right
+        let top = camera.ray_for_pixel(50, 49, Vec2::ZERO);
+        assert!(top.direction.x > 0.);
+        assert!(top.direction.y.abs() < 1e-5);
+
+        // turning keeps the roll, and so do bookmarks
+        camera.relative_turn([0.5, 2.], 1.);
+        assert!((camera.roll() - 90.).abs() < 1e-3);
+        let mut other = Camera::default();
+        other.apply_bookmark(&camera.bookmark("rolled"));
+        assert!(other.up_direction().distance(camera.up_direction()) < 1e-5);
+
+        camera.relative_roll(-1., 1.);
+        assert!((camera.roll() - 90. + 1.5_f32.to_degrees()).abs() < 1e-3);
+        let [look, up] = [camera.look_direction(), camera.up_direction()];
+        assert!(look.dot(up).abs() < 1e-6);
+        assert!(up.is_normalized());
+    }
+
+    #[test]
+    fn looking_straight_up() {
+        let mut camera = Camera::default();
+        camera.set_look_direction(Vec3::Y);
+        assert_eq!(camera.roll(), 0.);
+        assert!(camera.up_direction().distance(Vec3::Z) < 1e-5);
+        let center = camera.ray_for_pixel(320, 240, Vec2::ZERO);
+        assert!(center.direction.distance(Vec3::Y) < 1e-3);
+        assert!(camera.get_ray_directions().iter().all(|dir| dir.is_finite()));
+    }
+
+    #[test]
+    fn focal_length_round_trip() {
+        // a normal lens on 35mm film sees about 27 degrees vertically
+        let fov = Camera::fov_for_focal_length(50., LensPreset::FULL_FRAME);
+        assert!((fov - 26.991).abs() < 1e-3, "{fov}");
+        let mut camera = Camera::default();
+        camera.set_focal_length(85., LensPreset::FULL_FRAME);
+        assert!((camera.focal_length(LensPreset::FULL_FRAME) - 85.).abs() < 1e-3);
+        // the same field of view needs a shorter lens on a smaller sensor
+        assert!(camera.focal_length(LensPreset::APS_C) < 85.);
+        for preset in LensPreset::ALL {
+            let fov = preset.vertical_fov();
+            assert!(fov > 0. && fov < 180., "{}: {fov}", preset.name);
+        }
+    }
+
+    #[test]
+    fn stereo_eyes_line_up_at_the_convergence_distance() {
+        let mut camera = Camera::default();
+        camera.set_size(100, 50);
+        let stereo = Stereo {
+            ipd: 0.5,
+            convergence: 4.,
+        };
+        let left = camera.stereo_eye(Eye::Left, &stereo);
+        let right = camera.stereo_eye(Eye::Right, &stereo);
+        assert_eq!(left.position(), camera.position() - Vec3::X * 0.25);
+        assert_eq!(right.position(), camera.position() + Vec3::X * 0.25);
+        assert_eq!(left.look_direction(), camera.look_direction());
+
+        // the same pixel of each eye meets on the plane they converge on
+        let plane_z = camera.position().z - stereo.convergence;
+        for (x, y) in [(50, 25), (10, 40), (90, 5)] {
+            let meet = |eye: &Camera| {
+                let ray = eye.ray_for_pixel(x, y, Vec2::ZERO);
+                let origin = from_real_vec3(ray.origin);
+                origin + ray.direction * (plane_z - origin.z) / ray.direction.z
+            };
+            assert!(meet(&left).distance(meet(&right)) < 1e-4);
+        }
+    }
+
+    #[test]
+    fn equirectangular_sees_all_around() {
+        let mut camera = Camera::default();
+        camera.set_size(200, 100);
+        camera.set_projection(Projection::Equirectangular);
+        camera.set_look_direction(Vec3::X);
+        let direction = |x, y| camera.ray_for_pixel(x, y, Vec2::ZERO).direction;
+        assert!(direction(100, 50).distance(Vec3::X) < 1e-5);
+        // a quarter turn to the right, and straight behind at the edges
+        assert!(direction(150, 50).distance(Vec3::Z) < 1e-5);
+        assert!(direction(0, 50).distance(Vec3::NEG_X) < 1e-5);
+        // the top row looks almost straight up
+        assert!(direction(100, 99).y > 0.999);
+        assert!(camera.get_ray_directions().iter().all(|d| d.is_normalized()));
+    }
+
+    #[test]
+    fn project_finds_pixels() {
+        let mut camera = Camera::default();
+        camera.set_size(100, 50);
+        camera.set_look_direction(Vec3::new(1., -0.5, -2.));
+        for projection in [Projection::Perspective, Projection::Equirectangular] {
+            camera.set_projection(projection);
+            for (x, y) in [(50, 25), (10, 40), (90, 5)] {
+                let ray = camera.ray_for_pixel(x, y, Vec2::ZERO);
+                let pixel = camera.project(from_real_vec3(ray.at(3.))).unwrap();
+                assert!(pixel.distance(Vec2::new(x as f32, y as f32)) < 1e-2, "{pixel}");
             }
         }
 
-        ray_directions
+        // lines are cut off where they go behind the camera
+        camera.set_projection(Projection::Perspective);
+        let ahead = camera.position() + camera.look_direction() * 2.;
+        let behind = camera.position() - camera.look_direction() * 2.;
+        assert!(camera.project(behind).is_none());
+        let [start, end] = camera.project_segment(behind, ahead).unwrap();
+        assert!(start.is_finite());
+        assert!(end.distance(Vec2::new(50., 25.)) < 1e-3);
+        assert!(camera.project_segment(behind, behind * 2.).is_none());
+    }
+
+    #[test]
+    fn bookmark_round_trip() {
+        let mut camera = Camera::default();
+        camera.set_position(Vec3::new(1., 2., 3.));
+        camera.set_look_direction(Vec3::NEG_X);
+        camera.set_vertical_fov(40.);
+        let bookmark = camera.bookmark("side");
+
+        let mut other = Camera::default();
+        other.apply_bookmark(&bookmark);
+        assert_eq!(other.position(), Vec3::new(1., 2., 3.));
+        assert_eq!(other.look_direction(), Vec3::NEG_X);
+        assert_eq!(other.vertical_fov(), 40.);
+        assert_eq!(other.bookmark("side"), bookmark);
+    }
+
+    #[test]
+    fn animate_to() {
+        let mut camera = Camera::default();
+        let mut target = Camera::default();
+        target.set_position(Vec3::new(4., 0., 0.));
+        target.relative_turn([0., 100.], 1.);
+        target.set_roll(30.);
+        target.set_vertical_fov(45.);
+        let bookmark = target.bookmark("target");
+        let start = camera.position();
+
+        camera.animate_to(&bookmark, 2.);
+        assert!(camera.update(1.));
+        assert!(camera.is_animating());
+        // easing is symmetric, so halfway through in time is halfway there
+        assert!((camera.position() - (start + bookmark.position) / 2.).length() < 1e-5);
+
+        assert!(camera.update(1.5));
+        assert!(!camera.is_animating());
+        assert_eq!(camera.position(), bookmark.position);
+        assert_eq!(camera.vertical_fov(), 45.);
+        assert!(camera.look_direction().distance(bookmark.look_direction) < 1e-5);
+        assert!((camera.roll() - 30.).abs() < 1e-3);
+        assert!(!camera.update(1.));
+    }
+
+    #[test]
+    fn turntable_orbits() {
+        let mut camera = Camera::default();
+        camera.set_position(Vec3::new(0., 1., 3.));
+        let center = Vec3::new(0., 0.5, 0.);
+        camera.set_turntable(Some(Turntable {
+            center,
+            degrees_per_second: 90.,
+        }));
+
+        assert!(camera.update(1.));
+        assert!(camera.position().distance(Vec3::new(3., 1., 0.)) < 1e-5);
+        assert!(camera.look_direction().distance(Vec3::NEG_X) < 1e-5);
+        assert!(camera.update(3.));
+        assert!(camera.position().distance(Vec3::new(0., 1., 3.)) < 1e-5);
+    }
+
+    #[test]
+    fn frame_fits_the_bounds_in_view() {
+        let bounds = Aabb {
+            min: Vec3::new(-30., 2., -5.),
+            max: Vec3::new(10., 4., 1.),
+        };
+        for (width, height) in [(400, 100), (100, 400)] {
+            let mut camera = Camera::default();
+            camera.set_size(width, height);
+            camera.set_look_direction(Vec3::new(1., -1., -2.));
+            let look_direction = camera.look_direction();
+            camera.frame(&bounds);
+            assert_eq!(camera.look_direction(), look_direction);
+            let center = camera.ray_for_pixel(width / 2, height / 2, Vec2::ZERO);
+            let to_center = bounds.center() - from_real_vec3(center.origin);
+            assert!(to_center.normalize().distance(center.direction) < 1e-4);
+            let view_projection = camera.view_projection();
+            for corner in 0..8 {
+                let pick = |bit, axis: usize| {
+                    if corner & bit == 0 {
+                        bounds.min[axis]
+                    } else {
+                        bounds.max[axis]
+                    }
+                };
+                let point = Vec3::new(pick(1, 0), pick(2, 1), pick(4, 2));
+                let clip = view_projection * point.extend(1.);
+                let ndc = clip.xyz() / clip.w;
+                assert!(clip.w > 0.);
+                assert!(ndc.x.abs() <= 1. && ndc.y.abs() <= 1. && ndc.z.abs() <= 1.);
+            }
+        }
 
+        let mut camera = Camera::default();
+        camera.frame(&Aabb::EMPTY);
+        assert_eq!(camera.position(), Camera::default().position());
     }
 }
@@ -0,0 +1,349 @@
+//! Where the samples for each pixel are gathered up and averaged.
+
+use crate::{
+    camera::Projection, geom::from_real_vec3, parallel::*, util::CompensatedSum, Camera,
+};
+use glam::{Vec2, Vec4, Vec4Swizzles};
+use std::ops::AddAssign;
+use tracing::info_span;
+
+/// The samples taken for each pixel of an image since it was last reset,
+/// and how far away the surface each pixel's latest camera ray hit was.
+/// Rows are ordered bottom to top, like [`Snapshot::hdr`](crate::Snapshot::hdr).
+/// Each pixel keeps its own count of samples, so pixels can be traced
+/// different numbers of times.
+pub struct Film {
+    width: u32,
+    height: u32,
+    pub(crate) accumulation: Vec<PixelSum>,
+    /// Or infinity for the sky, for [`Film::reproject`].
+    pub(crate) depth: Vec<f32>,
+    /// How many frames have been rendered since the last reset. Pixels
+    /// keep their own counts, which can differ from this.
+    pub(crate) frame_count: f32,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = width as usize * height as usize;
+        Self {
+            width,
+            height,
+            accumulation: vec![PixelSum::default(); len],
+            depth: vec![f32::INFINITY; len],
+            frame_count: 0.,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline(always)]
+    pub(crate) fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Change the size, which resets the film if it's different.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if (self.width, self.height) != (width, height) {
+            *self = Self::new(width, height);
+        }
+    }
+
+    /// Forget every sample. Depths are kept for reprojecting.
+    pub fn reset(&mut self) {
+        self.accumulation.truncate(0);
+        self.accumulation.resize(self.len(), PixelSum::default());
+        self.depth.resize(self.len(), f32::INFINITY);
+        self.frame_count = 0.;
+    }
+
+    /// How many frames have been rendered since the last reset.
+    pub fn frame_count(&self) -> f32 {
+        self.frame_count
+    }
+
+    /// Add a sample, as premultiplied RGBA, to the pixel at `x` and `y`,
+    /// counting from the bottom left.
+    pub fn add_sample(&mut self, x: u32, y: u32, sample: Vec4) {
+        let pixel = self.pixel(x, y);
+        self.accumulation[pixel] += sample;
+    }
+
+    /// How many samples the pixel at `x` and `y` has, counting from the
+    /// bottom left. This needn't be whole, since blending frames and
+    /// reprojecting scale samples down.
+    pub fn sample_count(&self, x: u32, y: u32) -> f32 {
+        self.accumulation[self.pixel(x, y)].count
+    }
+
+    /// The average of the samples for the pixel at `x` and `y`, counting
+    /// from the bottom left, or of its neighbours if it has none yet.
+    pub fn average(&self, x: u32, y: u32) -> Vec4 {
+        self.averages().get(self.pixel(x, y))
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "({x}, {y}) is off the film");
+        y as usize * self.width as usize + x as usize
+    }
+
+    /// The averages of the samples for each pixel.
+    pub(crate) fn averages(&self) -> Averages<'_> {
+        Averages {
+            accumulation: &self.accumulation,
+            width: self.width as usize,
+            height: self.height as usize,
+        }
+    }
+
+    /// Start from an existing HDR image, as if it were the average of
+    /// `weight` frames, so rendering continues refining it instead of
+    /// starting over. `hdr` must match the film's size, with premultiplied
+    /// alpha and rows ordered bottom to top, like
+    /// [`Snapshot::hdr`](crate::Snapshot::hdr).
+    pub fn warm_start(&mut self, hdr: &[Vec4], weight: f32) -> anyhow::Result<()> {
+        if hdr.len() != self.len() {
+            anyhow::bail!(
+                "warm start image has {} pixels, but the renderer is {}x{}",
+                hdr.len(),
+                self.width,
+                self.height
+            );
+        }
+        if !weight.is_finite() || weight < 0. {
+            anyhow::bail!("warm start weight must be a non-negative number, got {weight}");
+        }
+
+        self.accumulation.clear();
+        self.accumulation.extend(hdr.iter().map(|c| PixelSum::from_average(*c, weight)));
+        self.frame_count = weight;
+        Ok(())
+    }
+
+    /// When blending frames, scale down the pixels `traces` picks, which
+    /// are about to be traced, to `1 / weight - 1` samples' worth, so that
+    /// the next sample makes up `weight` of their average.
+    pub(crate) fn make_room_for_samples<F>(&mut self, pool: &ThreadPool, weight: f32, traces: F)
+    where
+        F: Fn(usize) -> bool + Send + Sync,
+    {
+        let keep = 1. / weight - 1.;
+        let len = self.len();
+        let accumulation = &mut self.accumulation;
+        pool.install(|| {
+            (accumulation, 0..len)
+                .into_par_iter()
+                .for_each(|(acc, pixel)| {
+                    if traces(pixel) && acc.count > keep {
+                        acc.scale_to(keep);
+                    }
+                })
+        });
+    }
+
+    /// Carry the samples over to a new view when only the camera has moved,
+    /// instead of starting over. Each pixel is moved to where its surface
+    /// appears from `to`, found from the depth of the latest frame, and the
+    /// nearest wins where several land on the same pixel. Pixels nothing
+    /// lands on, like surfaces that were hidden before, are left without
+    /// samples, so they're traced afresh and shown as their neighbours'
+    /// average until then.
+    ///
+    /// Moved pixels keep at most a few samples' worth of history, since
+    /// they're only approximately where they belong. If either camera isn't
+    /// the size of the film, or isn't a [`Projection::Perspective`] one,
+    /// this resets the film instead, and returns false.
+    pub fn reproject(&mut self, from: &Camera, to: &Camera) -> bool {
+        /// How many frames' worth of samples are kept.
+        const MAX_HISTORY: f32 = 4.;
+        /// How many pixels away one pixel can be spread, for surfaces seen
+        /// nearly edge on.
+        const MAX_SPREAD: f32 = 4.;
+        let _span = info_span!("reproject").entered();
+        let size = [self.width, self.height];
+        let len = self.len();
+        let perspective = [from, to]
+            .iter()
+            .all(|camera| camera.projection() == Projection::Perspective);
+        if from.size() != size || to.size() != size || !perspective || self.frame_count == 0. {
+            self.reset();
+            return false;
+        }
+
+        let width = self.width as usize;
+        let pixel_size = Vec2::new(self.width as f32, self.height as f32);
+        let to_clip = to.view_projection();
+        let averages = self.averages();
+        // Where the point `distance` along the ray through a spot on the old
+        // image lands on the new one, in pixels, and how far it is from the
+        // new camera. The sky is infinitely far away, so only the direction
+        // matters for it.
+        let project = |x: u32, y: u32, offset: Vec2, distance: f32| {
+            let ray = from.ray_for_pixel(x, y, offset);
+            let (clip, new_distance) = if distance.is_finite() {
+                let point = from_real_vec3(ray.at(distance));
+                (to_clip * point.extend(1.), point.distance(to.position()))
+            } else {
+                (to_clip * ray.direction.extend(0.), f32::INFINITY)
+            };
+            // points behind the new camera aren't seen
+            let position = (clip.xy() / clip.w + Vec2::ONE) / 2. * pixel_size;
+            (clip.w > 0.).then_some((position, new_distance))
+        };
+
+        let mut accumulation = vec![PixelSum::default(); len];
+        let mut depth = vec![f32::INFINITY; len];
+        let mut filled = vec![false; len];
+        for (pixel, &distance) in self.depth.iter().enumerate() {
+            if self.accumulation[pixel].count == 0. {
+                // nothing to move, and no depth to move it by
+                continue;
+            }
+            let (x, y) = ((pixel % width) as u32, (pixel / width) as u32);
+            let Some((center, new_distance)) = project(x, y, Vec2::ZERO, distance) else {
+                continue;
+            };
+            // Cover as many pixels as the old one spreads over, so that
+            // surfaces coming closer don't crack apart.
+            let spread = [Vec2::X, Vec2::Y]
+                .into_iter()
+                .filter_map(|offset| project(x, y, offset, distance))
+                .fold(Vec2::ZERO, |spread, (side, _)| spread + (side - center).abs());
+            let reach = (spread / 2.).min(Vec2::splat(MAX_SPREAD));
+            // the pixels whose centers it covers, and at least the nearest
+            let nearest = center.round();
+            let first = (center - reach).ceil().min(nearest).max(Vec2::ZERO);
+            let last = (center + reach).floor().max(nearest).min(pixel_size - 1.);
+            if first.cmpgt(last).any() {
+                // off the edge of the new view
+                continue;
+            }
+            let history = self.accumulation[pixel].count.clamp(1., MAX_HISTORY);
+            let moved = PixelSum::from_average(averages.get(pixel), history);
+            for ty in first.y as usize..=last.y as usize {
+                for tx in first.x as usize..=last.x as usize {
+                    let target = ty * width + tx;
+                    if filled[target] && depth[target] <= new_distance {
+                        continue;
+                    }
+                    accumulation[target] = moved;
+                    depth[target] = new_distance;
+                    filled[target] = true;
+                }
+            }
+        }
+
+        self.frame_count = self.frame_count.min(MAX_HISTORY);
+        self.accumulation = accumulation;
+        self.depth = depth;
+        true
+    }
+}
+
+/// A pixel's samples, added up.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct PixelSum {
+    /// The sum of premultiplied RGBA samples, where alpha is how much of the
+    /// pixel the scene covers. It's compensated, so renders that run
+    /// overnight don't lose precision.
+    pub color: CompensatedSum,
+    /// How many samples are in `color`. This needn't be whole, since
+    /// blending frames and reprojecting scale the samples down.
+    pub count: f32,
+}
+
+impl PixelSum {
+    /// `count` samples' worth of `average`.
+    fn from_average(average: Vec4, count: f32) -> Self {
+        Self {
+            color: CompensatedSum::from(average * count),
+            count,
+        }
+    }
+
+    /// The average, unless there are no samples.
+    fn average(&self) -> Option<Vec4> {
+        (self.count > 0.).then(|| self.color.value() / self.count)
+    }
+
+    /// Keep the average, but count it as `count` samples.
+    fn scale_to(&mut self, count: f32) {
+        if let Some(average) = self.average() {
+            *self = Self::from_average(average, count);
+        }
+    }
+}
+
+impl AddAssign<Vec4> for PixelSum {
+    fn add_assign(&mut self, sample: Vec4) {
+        self.color += sample;
+        self.count += 1.;
+    }
+}
+
+impl AddAssign for PixelSum {
+    fn add_assign(&mut self, other: PixelSum) {
+        self.color += other.color.value();
+        self.count += other.count;
+    }
+}
+
+/// Averages of the accumulated samples, filling in pixels that haven't
+/// been traced yet, like those an interleaved frame skipped, from their
+/// neighbours.
+pub(crate) struct Averages<'a> {
+    accumulation: &'a [PixelSum],
+    width: usize,
+    height: usize,
+}
+
+impl Averages<'_> {
+    /// The average for a pixel, or for its neighbours that have been
+    /// traced, if it hasn't been yet.
+    pub fn get(&self, pixel: usize) -> Vec4 {
+        if let Some(average) = self.accumulation[pixel].average() {
+            return average;
+        }
+        let width = self.width;
+        let (x, y) = (pixel % width, pixel / width);
+        let (sum, traced) = (y.saturating_sub(1)..(y + 2).min(self.height))
+            .flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(width)).map(move |nx| ny * width + nx))
+            .filter_map(|neighbour| self.accumulation[neighbour].average())
+            .fold((Vec4::ZERO, 0), |(sum, traced), average| (sum + average, traced + 1));
+        if traced > 0 {
+            sum / traced as f32
+        } else {
+            Vec4::ZERO
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Film;
+    use glam::Vec4;
+
+    #[test]
+    fn pixels_average_their_own_samples() {
+        let mut film = Film::new(3, 2);
+        film.add_sample(0, 0, Vec4::ONE);
+        film.add_sample(0, 0, Vec4::ZERO);
+        film.add_sample(2, 1, Vec4::W);
+        assert_eq!(film.sample_count(0, 0), 2.);
+        assert_eq!(film.average(0, 0), Vec4::ONE / 2.);
+        assert_eq!(film.average(2, 1), Vec4::W);
+
+        // pixels with no samples borrow their neighbours'
+        assert_eq!(film.sample_count(1, 0), 0.);
+        assert_eq!(film.average(1, 0), (Vec4::ONE / 2. + Vec4::W) / 2.);
+
+        film.reset();
+        assert_eq!(film.average(0, 0), Vec4::ZERO);
+    }
+}
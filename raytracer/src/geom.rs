@@ -1,11 +1,109 @@
-use glam::Vec3;
+use glam::{Quat, Vec3};
+#[cfg(feature = "f64")]
+use glam::{DQuat, DVec3};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Ray {
-    pub origin: Vec3,
+    /// Where the ray starts, in [`Real`] precision, so that rays leaving
+    /// surfaces far from the origin start where the surface really is.
+    pub origin: RealVec3,
     pub direction: Vec3,
 }
 
+/// The precision positions along rays are worked out in. Scenes are
+/// stored in `f32`, but finding where a ray meets a large object subtracts
+/// big numbers that are nearly equal, and with planet sized scenes the
+/// rounding shows up as acne and banding. The `f64` feature carries ray
+/// origins, hit positions, the camera's position and the move into each
+/// [`Transform`]ed shape's own space in `f64` instead, at some cost in speed. Directions,
+/// distances along rays and everything stored in scenes stay `f32`.
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+/// A vector of [`Real`]s.
+#[cfg(not(feature = "f64"))]
+pub type RealVec3 = Vec3;
+#[cfg(feature = "f64")]
+pub type RealVec3 = DVec3;
+
+#[cfg(not(feature = "f64"))]
+type RealQuat = Quat;
+#[cfg(feature = "f64")]
+type RealQuat = DQuat;
+
+#[allow(clippy::useless_conversion)]
+pub(crate) fn real(x: f32) -> Real {
+    x.into()
+}
+
+#[allow(clippy::unnecessary_cast)]
+pub(crate) fn from_real(x: Real) -> f32 {
+    x as f32
+}
+
+pub(crate) fn real_vec3(v: Vec3) -> RealVec3 {
+    #[cfg(feature = "f64")]
+    return v.as_dvec3();
+    #[cfg(not(feature = "f64"))]
+    return v;
+}
+
+pub(crate) fn from_real_vec3(v: RealVec3) -> Vec3 {
+    #[cfg(feature = "f64")]
+    return v.as_vec3();
+    #[cfg(not(feature = "f64"))]
+    return v;
+}
+
+fn real_quat(q: Quat) -> RealQuat {
+    #[cfg(feature = "f64")]
+    return q.as_f64();
+    #[cfg(not(feature = "f64"))]
+    return q;
+}
+
+/// How far rays leaving a surface start from it, near the origin.
+const SPAWN_OFFSET: Real = 1e-4;
+
+/// How much the nudge off a surface grows with the size of the coordinates
+/// there, relative to [`SPAWN_OFFSET`]. Positions in `f64` round far less
+/// than the `f32` math inside some shapes, so the nudge barely grows.
+#[cfg(not(feature = "f64"))]
+const SPAWN_GROWTH: Real = 1.;
+#[cfg(feature = "f64")]
+const SPAWN_GROWTH: Real = 1e-4;
+
+impl Ray {
+    /// A ray starting at a point given in `f32`.
+    pub fn new(origin: Vec3, direction: Vec3) -> Ray {
+        Ray {
+            origin: real_vec3(origin),
+            direction,
+        }
+    }
+
+    /// The point `t` lengths of the direction along the ray.
+    pub fn at(&self, t: f32) -> RealVec3 {
+        self.origin + real_vec3(self.direction) * real(t)
+    }
+
+    /// A ray leaving a surface at `position`, which has `normal`. Hits are
+    /// only as precise as floats allow, so the origin is nudged off the
+    /// surface on the side the ray leaves from, far enough that rounding
+    /// can't put it back behind the surface. Rounding error grows with the
+    /// size of the coordinates, so the nudge does too.
+    pub fn spawn(position: RealVec3, normal: Vec3, direction: Vec3) -> Ray {
+        let offset = SPAWN_OFFSET * (1. + position.abs().max_element() * SPAWN_GROWTH);
+        let side = if direction.dot(normal) < 0. { -1. } else { 1. };
+        Ray {
+            origin: position + real_vec3(normal) * (offset * side),
+            direction,
+        }
+    }
+}
+
 impl Default for Ray {
     fn default() -> Self {
         Self {
@@ -14,3 +112,113 @@ impl Default for Ray {
         }
     }
 }
+
+/// A rotation, uniform scale, and translation, applied in that order.
+///
+/// Scale is uniform so that spheres stay spheres.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: 1.0,
+    };
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        self.rotation * (point * self.scale) + self.translation
+    }
+
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.rotation * (vector * self.scale)
+    }
+
+    /// Undoes [`Transform::transform_point`], in [`Real`] precision, for
+    /// taking ray origins into a shape's own space.
+    pub fn inverse_transform_point(&self, point: RealVec3) -> RealVec3 {
+        real_quat(self.rotation.inverse()) * (point - real_vec3(self.translation))
+            / real(self.scale)
+    }
+
+    /// Undoes [`Transform::transform_vector`].
+    pub fn inverse_transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.rotation.inverse() * vector / self.scale
+    }
+
+    /// The transform that applies `child` first, then `self`.
+    pub fn mul_transform(&self, child: &Transform) -> Transform {
+        Transform {
+            translation: self.transform_point(child.translation),
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_real_vec3, real_vec3, Ray, Transform};
+    use float_eq::assert_float_eq;
+    use glam::{Quat, Vec3};
+
+    #[test]
+    fn spawned_rays_leave_the_surface() {
+        let normal = Vec3::Y;
+        let surface = real_vec3(Vec3::ZERO);
+        let out = Ray::spawn(surface, normal, Vec3::new(1., 0.1, 0.).normalize());
+        assert!(out.origin.y > 0.);
+        let through = Ray::spawn(surface, normal, Vec3::new(1., -0.1, 0.).normalize());
+        assert!(through.origin.y < 0.);
+
+        // further from the origin, floats are coarser, so the nudge grows
+        let far = Ray::spawn(real_vec3(Vec3::X * 1e6), normal, Vec3::Y);
+        assert!(far.origin.y > out.origin.y * 100.);
+    }
+
+    #[test]
+    fn compose() {
+        let parent = Transform {
+            translation: Vec3::X,
+            rotation: Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            scale: 2.0,
+        };
+        let child = Transform::from_translation(Vec3::Z);
+        let point = Vec3::new(0.0, 1.0, 0.0);
+        let composed = parent.mul_transform(&child).transform_point(point);
+        let nested = parent.transform_point(child.transform_point(point));
+        assert_float_eq!(composed.to_array(), nested.to_array(), abs <= [0.0001; 3]);
+        assert_float_eq!(composed.to_array(), [3.0, 2.0, 0.0], abs <= [0.0001; 3]);
+    }
+
+    #[test]
+    fn inverse_transform_point() {
+        let transform = Transform {
+            translation: Vec3::new(1.0, -2.0, 0.5),
+            rotation: Quat::from_rotation_x(0.3),
+            scale: 1.5,
+        };
+        let point = Vec3::new(0.25, 4.0, -3.0);
+        let world = real_vec3(transform.transform_point(point));
+        let back = from_real_vec3(transform.inverse_transform_point(world));
+        assert_float_eq!(back.to_array(), point.to_array(), abs <= [0.0001; 3]);
+    }
+}
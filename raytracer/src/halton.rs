@@ -1,3 +1,4 @@
+#[derive(Clone)]
 pub struct Halton {
     base: u32,
     index: u32,
@@ -45,6 +46,7 @@ impl Iterator for Halton {
     }
 }
 
+#[derive(Clone)]
 pub struct Halton2(Halton, Halton);
 
 impl Iterator for Halton2 {
@@ -1,89 +1,363 @@
-use glam::Vec3;
-use std::ops::Range;
+use glam::{Vec2, Vec3};
+use std::{f32::consts::PI, ops::Range};
 
-use crate::{geom::Ray, Sphere};
+use crate::{
+    bvh::Aabb,
+    geom::{from_real, from_real_vec3, real, real_vec3, Ray, Real, RealVec3, Transform},
+    plugin::CustomShape,
+    Cone, Csg, CsgOperation, Curve, Cylinder, MaterialHandle, Mesh, Quad, Sdf, Sphere, Torus,
+};
 
+mod axial;
+mod csg;
+mod curve;
+mod mesh;
+mod quartic;
+mod sdf;
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Hittable {
     Sphere(Sphere),
+    Quad(Quad),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Torus(Torus),
+    Sdf(Sdf),
+    Curve(Curve),
+    Mesh(Mesh),
+    Csg(Csg),
+    /// A shape from outside the crate.
+    Custom(CustomShape),
 }
 
-#[derive(Eq, PartialEq, Copy, Clone)]
+#[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum FaceSide {
     Front,
     Back,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, PartialEq)]
 pub enum HitPayload {
     Hit {
         /// The proportion along the ray, not a world distance.
         hit_distance: f32,
         world_normal: Vec3,
-        world_position: Vec3,
-        material_index: usize,
+        world_position: RealVec3,
+        material: MaterialHandle,
         side: FaceSide,
+        /// Surface coordinates for looking up textures, each from 0 to 1.
+        uv: Vec2,
+        /// Along the surface in the direction of increasing `uv.x`.
+        tangent: Vec3,
     },
     Miss,
-    Inside,
+}
+
+/// Where a ray met a surface, from [`Scene::raycast`](crate::Scene::raycast).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hit {
+    /// How far along the ray the hit is, in lengths of its direction.
+    pub distance: f32,
+    /// Rounded to `f32`, even with the `f64` feature.
+    pub position: Vec3,
+    /// Faces against the ray, so it points out of the back of a surface
+    /// hit from inside.
+    pub normal: Vec3,
+    pub material: MaterialHandle,
+    pub side: FaceSide,
+    /// Surface coordinates for looking up textures, each from 0 to 1.
+    pub uv: Vec2,
+    /// The index of the hittable, as in [`Scene::hittables`](crate::Scene::hittables).
+    pub hittable: usize,
+}
+
+impl HitPayload {
+    /// The hit, if there is one, on the hittable at index `hittable`.
+    pub(crate) fn into_hit(self, hittable: usize) -> Option<Hit> {
+        match self {
+            HitPayload::Hit {
+                hit_distance,
+                world_normal,
+                world_position,
+                material,
+                side,
+                uv,
+                ..
+            } => Some(Hit {
+                distance: hit_distance,
+                position: from_real_vec3(world_position),
+                normal: world_normal,
+                material,
+                side,
+                uv,
+                hittable,
+            }),
+            HitPayload::Miss => None,
+        }
+    }
 }
 
 impl Hittable {
+    pub fn material(&self) -> MaterialHandle {
+        match self {
+            Hittable::Sphere(sphere) => sphere.material,
+            Hittable::Quad(quad) => quad.material,
+            Hittable::Cylinder(cylinder) => cylinder.material,
+            Hittable::Cone(cone) => cone.material,
+            Hittable::Torus(torus) => torus.material,
+            Hittable::Sdf(sdf) => sdf.material,
+            Hittable::Curve(curve) => curve.material,
+            Hittable::Mesh(mesh) => mesh.material,
+            Hittable::Csg(csg) => csg.material,
+            Hittable::Custom(custom) => custom.material,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut MaterialHandle {
+        match self {
+            Hittable::Sphere(sphere) => &mut sphere.material,
+            Hittable::Quad(quad) => &mut quad.material,
+            Hittable::Cylinder(cylinder) => &mut cylinder.material,
+            Hittable::Cone(cone) => &mut cone.material,
+            Hittable::Torus(torus) => &mut torus.material,
+            Hittable::Sdf(sdf) => &mut sdf.material,
+            Hittable::Curve(curve) => &mut curve.material,
+            Hittable::Mesh(mesh) => &mut mesh.material,
+            Hittable::Csg(csg) => &mut csg.material,
+            Hittable::Custom(custom) => &mut custom.material,
+        }
+    }
+
+    /// A copy of this hittable, moved from local space by `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Hittable {
+        match self {
+            Hittable::Sphere(sphere) => Hittable::Sphere(Sphere {
+                center: transform.transform_point(sphere.center),
+                radius: sphere.radius * transform.scale,
+                ..sphere.clone()
+            }),
+            Hittable::Quad(quad) => Hittable::Quad(Quad {
+                corner: transform.transform_point(quad.corner),
+                u: transform.transform_vector(quad.u),
+                v: transform.transform_vector(quad.v),
+                ..quad.clone()
+            }),
+            Hittable::Cylinder(cylinder) => Hittable::Cylinder(Cylinder {
+                base: transform.transform_point(cylinder.base),
+                axis: transform.transform_vector(cylinder.axis),
+                radius: cylinder.radius * transform.scale,
+                ..cylinder.clone()
+            }),
+            Hittable::Cone(cone) => Hittable::Cone(Cone {
+                base: transform.transform_point(cone.base),
+                axis: transform.transform_vector(cone.axis),
+                radius: cone.radius * transform.scale,
+                ..cone.clone()
+            }),
+            Hittable::Torus(torus) => Hittable::Torus(Torus {
+                center: transform.transform_point(torus.center),
+                axis: transform.rotation * torus.axis,
+                major_radius: torus.major_radius * transform.scale,
+                minor_radius: torus.minor_radius * transform.scale,
+                ..torus.clone()
+            }),
+            Hittable::Sdf(sdf) => Hittable::Sdf(Sdf {
+                transform: transform.mul_transform(&sdf.transform),
+                ..sdf.clone()
+            }),
+            Hittable::Curve(curve) => Hittable::Curve(Curve {
+                points: curve.points.map(|point| transform.transform_point(point)),
+                widths: curve.widths.map(|width| width * transform.scale),
+                ..curve.clone()
+            }),
+            Hittable::Mesh(mesh) => Hittable::Mesh(Mesh {
+                transform: transform.mul_transform(&mesh.transform),
+                ..mesh.clone()
+            }),
+            Hittable::Csg(csg) => Hittable::Csg(Csg {
+                a: Box::new(csg.a.transformed(transform)),
+                b: Box::new(csg.b.transformed(transform)),
+                ..csg.clone()
+            }),
+            Hittable::Custom(custom) => Hittable::Custom(custom.transformed(transform)),
+        }
+    }
+
+    /// A box the hittable fits inside, though not always snugly.
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            Hittable::Sphere(sphere) => Aabb::around(sphere.center, sphere.radius),
+            Hittable::Quad(quad) => Aabb::from_points([
+                quad.corner,
+                quad.corner + quad.u,
+                quad.corner + quad.v,
+                quad.corner + quad.u + quad.v,
+            ]),
+            Hittable::Cylinder(cylinder) => {
+                Aabb::from_points([cylinder.base, cylinder.base + cylinder.axis])
+                    .expand(cylinder.radius)
+            }
+            Hittable::Cone(cone) => {
+                Aabb::from_points([cone.base, cone.base + cone.axis]).expand(cone.radius)
+            }
+            Hittable::Torus(torus) => {
+                Aabb::around(torus.center, torus.major_radius + torus.minor_radius)
+            }
+            Hittable::Sdf(sdf) => Aabb::around(
+                sdf.transform.translation,
+                sdf.shape.bounding_radius() * sdf.transform.scale,
+            ),
+            Hittable::Curve(curve) => {
+                let width = curve.widths.into_iter().fold(0., f32::max);
+                Aabb::from_points(curve.points).expand(width / 2.)
+            }
+            Hittable::Mesh(mesh) => mesh.geometry.bvh().bounds().transformed(&mesh.transform),
+            Hittable::Csg(csg) => match csg.operation {
+                CsgOperation::Union => csg.a.bounding_box().union(csg.b.bounding_box()),
+                CsgOperation::Intersection => {
+                    csg.a.bounding_box().intersection(csg.b.bounding_box())
+                }
+                CsgOperation::Difference => csg.a.bounding_box(),
+            },
+            Hittable::Custom(custom) => custom.shape.bounding_box(),
+        }
+    }
+
     #[inline]
     pub fn check_hit(&self, ray: &Ray, look_clip: &Range<f32>) -> HitPayload {
         match self {
             Hittable::Sphere(sphere) => Self::check_hit_sphere(sphere, ray, look_clip),
+            Hittable::Quad(quad) => Self::check_hit_quad(quad, ray, look_clip),
+            Hittable::Cylinder(cylinder) => axial::check_hit_cylinder(cylinder, ray, look_clip),
+            Hittable::Cone(cone) => axial::check_hit_cone(cone, ray, look_clip),
+            Hittable::Torus(torus) => axial::check_hit_torus(torus, ray, look_clip),
+            Hittable::Sdf(sdf) => sdf::check_hit_sdf(sdf, ray, look_clip),
+            Hittable::Curve(curve) => curve::check_hit_curve(curve, ray, look_clip),
+            Hittable::Mesh(mesh) => mesh::check_hit_mesh(mesh, ray, look_clip),
+            Hittable::Csg(csg) => csg::check_hit_csg(csg, ray, look_clip),
+            Hittable::Custom(custom) => custom.check_hit(ray, look_clip),
         }
     }
 
+    /// Rays that start inside the sphere, or that have its near side
+    /// clipped out, hit the far side from the back.
     #[inline]
     fn check_hit_sphere(sphere: &Sphere, ray: &Ray, look_clip: &Range<f32>) -> HitPayload {
-        let offset_center = ray.origin - sphere.center;
+        let origin = ray.origin;
+        let direction = real_vec3(ray.direction);
+        let center = real_vec3(sphere.center);
+        let radius = real(sphere.radius);
+        let offset_center = origin - center;
+
+        // solve the equation of the ray set equal to the equation of a sphere centered on the origin.
+        // a, b, and c are the quadratic equation co-effiecients
+        let a = direction.length_squared();
+        let half_b = offset_center.dot(direction);
+        let c = offset_center.length_squared() - radius.powi(2);
+
+        let discrim = half_b.powi(2) - a * c;
 
-        if offset_center.length() < sphere.radius {
-            HitPayload::Inside
+        if discrim < 0. {
+            HitPayload::Miss
         } else {
-            // solve the equation of the ray set equal to the equation of a sphere centered on the origin.
-            // a, b, and c are the quadratic equation co-effiecients
-            let a = ray.direction.length_squared();
-            let half_b = offset_center.dot(ray.direction);
-            let c = offset_center.length_squared() - sphere.radius.powi(2);
+            // finish the quadratic equation, preferring the nearer root
+            let sqrtd = discrim.sqrt();
+            let clip = real(look_clip.start)..real(look_clip.end);
 
-            let discrim = half_b.powi(2) - a * c;
+            let mut t = (-half_b - sqrtd) / a;
+            if !clip.contains(&t) {
+                t = (-half_b + sqrtd) / a;
+            }
 
-            if discrim < 0. {
-                HitPayload::Miss
+            if clip.contains(&t) {
+                Self::sphere_hit(ray, center, sphere.material, t)
             } else {
-                // finish the quadratic equation, though we only need the least result
-                let sqrtd = discrim.sqrt();
+                HitPayload::Miss
+            }
+        }
+    }
 
-                let mut t = (-half_b - sqrtd) / a;
-                if !look_clip.contains(&t) {
-                    t = (-half_b + sqrtd) / a;
-                }
+    /// The hit `t` along `ray` on the surface of a sphere around `center`.
+    #[inline]
+    pub(crate) fn sphere_hit(
+        ray: &Ray,
+        center: RealVec3,
+        material: MaterialHandle,
+        t: Real,
+    ) -> HitPayload {
+        let position = ray.origin + real_vec3(ray.direction) * t;
+        let world_normal = from_real_vec3((position - center).normalize());
+        let (uv, tangent) = Self::sphere_uv(world_normal);
 
-                if look_clip.contains(&t) {
-                    let world_position = ray.origin + ray.direction * t;
-                    let world_normal = (world_position - sphere.center).normalize();
-
-                    let (side, outward_normal) = if ray.direction.dot(world_normal) > 0.0 {
-                        (FaceSide::Back, -world_normal)
-                    } else {
-                        (FaceSide::Front, world_normal)
-                    };
-
-                    HitPayload::Hit {
-                        hit_distance: t,
-                        world_normal: outward_normal,
-                        world_position,
-                        material_index: sphere.material_index,
-                        side,
-                    }
-                } else {
-                    HitPayload::Miss
-                }
-            }
+        let (side, outward_normal) = if ray.direction.dot(world_normal) > 0.0 {
+            (FaceSide::Back, -world_normal)
+        } else {
+            (FaceSide::Front, world_normal)
+        };
+
+        HitPayload::Hit {
+            hit_distance: from_real(t),
+            world_normal: outward_normal,
+            world_position: position,
+            material,
+            side,
+            uv,
+            tangent,
         }
     }
+
+    /// Quads have no inside, so rays hit either face.
+    #[inline]
+    fn check_hit_quad(quad: &Quad, ray: &Ray, look_clip: &Range<f32>) -> HitPayload {
+        let (u, v) = (real_vec3(quad.u), real_vec3(quad.v));
+        let corner = real_vec3(quad.corner);
+        let (origin, direction) = (ray.origin, real_vec3(ray.direction));
+        let plane_normal = u.cross(v);
+        let facing = plane_normal.dot(direction);
+        if facing.abs() < 1e-8 {
+            // parallel to the plane, or the quad has no area
+            return HitPayload::Miss;
+        }
+        let t = plane_normal.dot(corner - origin) / facing;
+        if !(real(look_clip.start)..real(look_clip.end)).contains(&t) {
+            return HitPayload::Miss;
+        }
+
+        // how far the hit is along each side, from 0 to 1 inside the quad
+        let position = origin + direction * t;
+        let offset = position - corner;
+        let w = plane_normal / plane_normal.length_squared();
+        let (hit_u, hit_v) = (w.dot(offset.cross(v)), w.dot(u.cross(offset)));
+        if !(0. ..=1.).contains(&hit_u) || !(0. ..=1.).contains(&hit_v) {
+            return HitPayload::Miss;
+        }
+
+        let normal = from_real_vec3(plane_normal.normalize());
+        let (side, outward_normal) = if facing > 0. {
+            (FaceSide::Back, -normal)
+        } else {
+            (FaceSide::Front, normal)
+        };
+        HitPayload::Hit {
+            hit_distance: from_real(t),
+            world_normal: outward_normal,
+            world_position: position,
+            material: quad.material,
+            side,
+            uv: Vec2::new(from_real(hit_u), from_real(hit_v)),
+            tangent: quad.u.normalize(),
+        }
+    }
+
+    /// Latitude and longitude on a sphere, from its outward normal. `u`
+    /// runs around the equator and `v` from the bottom pole to the top.
+    fn sphere_uv(normal: Vec3) -> (Vec2, Vec3) {
+        let theta = (-normal.y).clamp(-1., 1.).acos();
+        let (u, tangent) = axial::around_y(normal);
+        let uv = Vec2::new(u, theta / PI);
+        (uv, tangent)
+    }
 }
 
 impl From<Sphere> for Hittable {
@@ -91,3 +365,57 @@ impl From<Sphere> for Hittable {
         Self::Sphere(value)
     }
 }
+
+impl From<Quad> for Hittable {
+    fn from(value: Quad) -> Self {
+        Self::Quad(value)
+    }
+}
+
+impl From<Cylinder> for Hittable {
+    fn from(value: Cylinder) -> Self {
+        Self::Cylinder(value)
+    }
+}
+
+impl From<Cone> for Hittable {
+    fn from(value: Cone) -> Self {
+        Self::Cone(value)
+    }
+}
+
+impl From<Torus> for Hittable {
+    fn from(value: Torus) -> Self {
+        Self::Torus(value)
+    }
+}
+
+impl From<Sdf> for Hittable {
+    fn from(value: Sdf) -> Self {
+        Self::Sdf(value)
+    }
+}
+
+impl From<Curve> for Hittable {
+    fn from(value: Curve) -> Self {
+        Self::Curve(value)
+    }
+}
+
+impl From<Mesh> for Hittable {
+    fn from(value: Mesh) -> Self {
+        Self::Mesh(value)
+    }
+}
+
+impl From<Csg> for Hittable {
+    fn from(value: Csg) -> Self {
+        Self::Csg(value)
+    }
+}
+
+impl From<CustomShape> for Hittable {
+    fn from(value: CustomShape) -> Self {
+        Self::Custom(value)
+    }
+}
@@ -0,0 +1,257 @@
+//! Intersections for shapes built around an axis. Each is solved in a local
+//! frame where the axis is +Y, which keeps the equations short.
+
+use super::{quartic::solve_quartic, FaceSide, HitPayload};
+use crate::{
+    geom::{from_real_vec3, real_vec3, Ray},
+    Cone, Cylinder, MaterialHandle, Torus,
+};
+use glam::{Quat, Vec2, Vec3};
+use std::{f32::consts::PI, ops::Range};
+
+pub(super) fn check_hit_cylinder(cylinder: &Cylinder, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    let frame = AxisFrame::new(cylinder.base, cylinder.axis);
+    let height = cylinder.axis.length();
+    let local = frame.to_local(ray);
+    let (o, d) = (local.origin, local.direction);
+    let radius = cylinder.radius;
+    let mut closest = Closest::new(clip);
+
+    let a = d.x * d.x + d.z * d.z;
+    let half_b = o.x * d.x + o.z * d.z;
+    let c = o.x * o.x + o.z * o.z - radius * radius;
+    for t in solve_quadratic(a, half_b, c) {
+        let p = o + d * t;
+        if (0. ..=height).contains(&p.y) {
+            let (u, tangent) = around_y(p);
+            closest.offer(LocalHit {
+                t,
+                normal: Vec3::new(p.x, 0., p.z) / radius,
+                uv: Vec2::new(u, p.y / height),
+                tangent,
+            });
+        }
+    }
+    if cylinder.capped {
+        closest.offer_option(disk(&local, 0., radius, -1.));
+        closest.offer_option(disk(&local, height, radius, 1.));
+    }
+    frame.to_world(ray, closest.hit, cylinder.material)
+}
+
+pub(super) fn check_hit_cone(cone: &Cone, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    let frame = AxisFrame::new(cone.base, cone.axis);
+    let height = cone.axis.length();
+    let local = frame.to_local(ray);
+    let (o, d) = (local.origin, local.direction);
+    // the radius shrinks by `slope` for each unit up the axis
+    let slope = cone.radius / height;
+    let k2 = slope * slope;
+    let mut closest = Closest::new(clip);
+
+    // x² + z² = k²(h - y)², measured from the apex
+    let from_apex = height - o.y;
+    let a = d.x * d.x + d.z * d.z - k2 * d.y * d.y;
+    let half_b = o.x * d.x + o.z * d.z + k2 * from_apex * d.y;
+    let c = o.x * o.x + o.z * o.z - k2 * from_apex * from_apex;
+    for t in solve_quadratic(a, half_b, c) {
+        let p = o + d * t;
+        if (0. ..=height).contains(&p.y) {
+            let (u, tangent) = around_y(p);
+            let normal = Vec3::new(p.x, k2 * (height - p.y), p.z)
+                .try_normalize()
+                .unwrap_or(Vec3::Y);
+            closest.offer(LocalHit {
+                t,
+                normal,
+                uv: Vec2::new(u, p.y / height),
+                tangent,
+            });
+        }
+    }
+    if cone.capped {
+        closest.offer_option(disk(&local, 0., cone.radius, -1.));
+    }
+    frame.to_world(ray, closest.hit, cone.material)
+}
+
+pub(super) fn check_hit_torus(torus: &Torus, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    let frame = AxisFrame::new(torus.center, torus.axis);
+    let local = frame.to_local(ray);
+    let (major, minor) = (torus.major_radius as f64, torus.minor_radius as f64);
+
+    // Skip to where the ray meets the bounding sphere, both to skip rays
+    // that miss, and because the quartic is much more precise near the
+    // torus than far from it.
+    let scale = local.direction.length();
+    let direction = local.direction / scale;
+    let bound = torus.major_radius + torus.minor_radius;
+    let half_b = local.origin.dot(direction);
+    let c = local.origin.length_squared() - bound * bound;
+    let discriminant = half_b * half_b - c;
+    if discriminant < 0. {
+        return HitPayload::Miss;
+    }
+    let skip = (-half_b - discriminant.sqrt()).max(0.);
+    let o = (local.origin + direction * skip).as_dvec3();
+    let d = direction.as_dvec3();
+
+    // (|p|² + R² - r²)² = 4R²(x² + z²), expanded in t
+    let od = o.dot(d);
+    let e = o.length_squared() + major * major - minor * minor;
+    let four_r2 = 4. * major * major;
+    let roots = solve_quartic([
+        1.,
+        4. * od,
+        4. * od * od + 2. * e - four_r2 * (d.x * d.x + d.z * d.z),
+        4. * od * e - 2. * four_r2 * (o.x * d.x + o.z * d.z),
+        e * e - four_r2 * (o.x * o.x + o.z * o.z),
+    ]);
+
+    let mut closest = Closest::new(clip);
+    for root in roots {
+        let t = (root as f32 + skip) / scale;
+        let p = local.origin + local.direction * t;
+        let ring = Vec3::new(p.x, 0., p.z).normalize_or_zero() * torus.major_radius;
+        let normal = (p - ring).normalize_or_zero();
+        let (u, tangent) = around_y(p);
+        let around_tube = p.y.atan2(p.dot(ring) / torus.major_radius - torus.major_radius);
+        closest.offer(LocalHit {
+            t,
+            normal,
+            uv: Vec2::new(u, around_tube / (2. * PI) + 0.5),
+            tangent,
+        });
+    }
+    frame.to_world(ray, closest.hit, torus.material)
+}
+
+/// The angle around the Y axis as a coordinate from 0 to 1, and the
+/// direction it increases in.
+pub(super) fn around_y(p: Vec3) -> (f32, Vec3) {
+    let u = ((-p.z).atan2(p.x) + PI) / (2. * PI);
+    // on the axis there's no direction of its own, so pick one
+    let tangent = Vec3::new(p.z, 0., -p.x).try_normalize().unwrap_or(Vec3::X);
+    (u, tangent)
+}
+
+/// The roots of `a t² + 2 half_b t + c`.
+fn solve_quadratic(a: f32, half_b: f32, c: f32) -> Vec<f32> {
+    if a.abs() < 1e-8 {
+        // the quadratic term vanished, like for rays parallel to a cone's side
+        return if half_b == 0. {
+            Vec::new()
+        } else {
+            vec![-c / (2. * half_b)]
+        };
+    }
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0. {
+        return Vec::new();
+    }
+    let sqrt = discriminant.sqrt();
+    vec![(-half_b - sqrt) / a, (-half_b + sqrt) / a]
+}
+
+/// A flat cap at height `y`, facing along `facing` on the Y axis.
+fn disk(ray: &LocalRay, y: f32, radius: f32, facing: f32) -> Option<LocalHit> {
+    if ray.direction.y == 0. {
+        return None;
+    }
+    let t = (y - ray.origin.y) / ray.direction.y;
+    let p = ray.origin + ray.direction * t;
+    (p.x * p.x + p.z * p.z <= radius * radius).then(|| LocalHit {
+        t,
+        normal: Vec3::Y * facing,
+        uv: (Vec2::new(p.x, p.z) / radius + 1.) / 2.,
+        tangent: Vec3::X,
+    })
+}
+
+/// A ray in the local frame. Shapes are small next to the world, so this is
+/// always `f32`, even where rays in world space aren't.
+struct LocalRay {
+    origin: Vec3,
+    direction: Vec3,
+}
+
+/// A hit in the local frame, with its normal pointing out of the shape.
+#[derive(Clone, Copy)]
+struct LocalHit {
+    t: f32,
+    normal: Vec3,
+    uv: Vec2,
+    tangent: Vec3,
+}
+
+/// Keeps the nearest of the hits offered to it within `clip`.
+struct Closest<'a> {
+    clip: &'a Range<f32>,
+    hit: Option<LocalHit>,
+}
+
+impl<'a> Closest<'a> {
+    fn new(clip: &'a Range<f32>) -> Self {
+        Self { clip, hit: None }
+    }
+
+    fn offer(&mut self, hit: LocalHit) {
+        let closer = self.hit.is_none_or(|closest| hit.t < closest.t);
+        if self.clip.contains(&hit.t) && closer {
+            self.hit = Some(hit);
+        }
+    }
+
+    fn offer_option(&mut self, hit: Option<LocalHit>) {
+        if let Some(hit) = hit {
+            self.offer(hit);
+        }
+    }
+}
+
+/// Maps between world space and a frame with `axis` along +Y and `origin`
+/// at zero. Only rotates, so distances along rays are the same in both.
+struct AxisFrame {
+    origin: Vec3,
+    rotation: Quat,
+}
+
+impl AxisFrame {
+    fn new(origin: Vec3, axis: Vec3) -> Self {
+        let axis = axis.try_normalize().unwrap_or(Vec3::Y);
+        Self {
+            origin,
+            rotation: Quat::from_rotation_arc(Vec3::Y, axis),
+        }
+    }
+
+    fn to_local(&self, ray: &Ray) -> LocalRay {
+        let inverse = self.rotation.inverse();
+        LocalRay {
+            origin: inverse * from_real_vec3(ray.origin - real_vec3(self.origin)),
+            direction: inverse * ray.direction,
+        }
+    }
+
+    /// Move `hit` back into world space, facing its normal against `ray`.
+    fn to_world(&self, ray: &Ray, hit: Option<LocalHit>, material: MaterialHandle) -> HitPayload {
+        let Some(hit) = hit else {
+            return HitPayload::Miss;
+        };
+        let normal = self.rotation * hit.normal;
+        let (side, world_normal) = if ray.direction.dot(normal) > 0. {
+            (FaceSide::Back, -normal)
+        } else {
+            (FaceSide::Front, normal)
+        };
+        HitPayload::Hit {
+            hit_distance: hit.t,
+            world_normal,
+            world_position: ray.at(hit.t),
+            material,
+            side,
+            uv: hit.uv,
+            tangent: self.rotation * hit.tangent,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Combining solids by walking along the ray through every place it enters
+//! or leaves each of them, keeping track of which ones it's inside.
+
+use super::{FaceSide, HitPayload, Hittable};
+use crate::{
+    geom::{from_real_vec3, real_vec3, Ray, RealVec3},
+    Csg,
+};
+use glam::{Vec2, Vec3};
+use std::ops::Range;
+
+/// How far past a crossing to look for the next one, in world units.
+const NUDGE: f32 = 1e-4;
+
+/// Stops rays that graze along a surface from finding crossings forever.
+const MAX_CROSSINGS: usize = 64;
+
+/// Where a ray goes into or out of a solid.
+struct Crossing {
+    t: f32,
+    entering: bool,
+    /// Faces against the ray, like the normals of hits.
+    normal: Vec3,
+    position: RealVec3,
+    uv: Vec2,
+    tangent: Vec3,
+}
+
+pub(super) fn check_hit_csg(csg: &Csg, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    let a = crossings(&csg.a, ray, clip);
+    let b = crossings(&csg.b, ray, clip);
+    // a ray that starts inside a solid leaves it before entering it again
+    let mut inside_a = a.first().is_some_and(|crossing| !crossing.entering);
+    let mut inside_b = b.first().is_some_and(|crossing| !crossing.entering);
+    let inside = csg.operation.contains(inside_a, inside_b);
+
+    let mut events: Vec<_> = a
+        .into_iter()
+        .map(|crossing| (true, crossing))
+        .chain(b.into_iter().map(|crossing| (false, crossing)))
+        .collect();
+    events.sort_by(|(_, x), (_, y)| x.t.total_cmp(&y.t));
+
+    for (from_a, crossing) in events {
+        if from_a {
+            inside_a = crossing.entering;
+        } else {
+            inside_b = crossing.entering;
+        }
+        if csg.operation.contains(inside_a, inside_b) != inside {
+            return HitPayload::Hit {
+                hit_distance: crossing.t,
+                world_normal: crossing.normal,
+                world_position: crossing.position,
+                material: csg.material,
+                side: if inside { FaceSide::Back } else { FaceSide::Front },
+                uv: crossing.uv,
+                tangent: crossing.tangent,
+            };
+        }
+    }
+    HitPayload::Miss
+}
+
+/// Every crossing of `hittable`'s surface within `clip`, nearest first.
+fn crossings(hittable: &Hittable, ray: &Ray, clip: &Range<f32>) -> Vec<Crossing> {
+    if let Hittable::Sphere(sphere) = hittable {
+        // Spheres have at most two crossings, so solve for both at once
+        // rather than stepping from one to the next.
+        let offset = from_real_vec3(ray.origin - real_vec3(sphere.center));
+        let a = ray.direction.length_squared();
+        let half_b = offset.dot(ray.direction);
+        let c = offset.length_squared() - sphere.radius * sphere.radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant < 0. {
+            return Vec::new();
+        }
+        let sqrt = discriminant.sqrt();
+        return [(-half_b - sqrt, true), (-half_b + sqrt, false)]
+            .into_iter()
+            .map(|(t, entering)| (t / a, entering))
+            .filter(|(t, _)| clip.contains(t))
+            .map(|(t, entering)| {
+                let position = ray.at(t);
+                let outward = (offset + ray.direction * t) / sphere.radius;
+                let (uv, tangent) = Hittable::sphere_uv(outward);
+                Crossing {
+                    t,
+                    entering,
+                    normal: if entering { outward } else { -outward },
+                    position,
+                    uv,
+                    tangent,
+                }
+            })
+            .collect();
+    }
+
+    let nudge = NUDGE / ray.direction.length();
+    let mut crossings = Vec::new();
+    let mut start = clip.start;
+    while crossings.len() < MAX_CROSSINGS {
+        let HitPayload::Hit {
+            hit_distance,
+            world_normal,
+            world_position,
+            side,
+            uv,
+            tangent,
+            ..
+        } = hittable.check_hit(ray, &(start..clip.end))
+        else {
+            break;
+        };
+        crossings.push(Crossing {
+            t: hit_distance,
+            entering: side == FaceSide::Front,
+            normal: world_normal,
+            position: world_position,
+            uv,
+            tangent,
+        });
+        start = hit_distance + nudge;
+    }
+    crossings
+}
@@ -0,0 +1,197 @@
+//! Intersecting thin curves by splitting them until each piece is nearly
+//! straight. Works in a space where the ray runs along +Z from the origin,
+//! so a piece is only hit if it passes within its width of the Z axis.
+
+use super::{FaceSide, HitPayload};
+use crate::{
+    geom::{from_real_vec3, real_vec3, Ray},
+    Curve, CurveShape,
+};
+use glam::{Quat, Vec2, Vec3, Vec3Swizzles};
+use std::ops::Range;
+
+/// Pieces are never split further than this, however curvy they are.
+const MAX_DEPTH: u32 = 10;
+
+pub(super) fn check_hit_curve(curve: &Curve, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    let speed = ray.direction.length();
+    let forward = ray.direction / speed;
+    let to_ray = Quat::from_rotation_arc(forward, Vec3::Z);
+    let points = curve.points.map(|p| to_ray * from_real_vec3(real_vec3(p) - ray.origin));
+
+    // Split until the pieces bend away from their chords by less than a
+    // small part of the width, from the control polygon's second
+    // differences.
+    let bend = (0..2)
+        .map(|i| {
+            (points[i] - 2. * points[i + 1] + points[i + 2])
+                .xy()
+                .length()
+        })
+        .fold(0., f32::max);
+    let max_width = curve.widths.into_iter().fold(0., f32::max);
+    let tolerance = (max_width * 0.05).max(f32::EPSILON);
+    let depth = (2f32.sqrt() * 6. * bend / (8. * tolerance))
+        .log(4.)
+        .clamp(0., MAX_DEPTH as f32) as u32;
+
+    let mut search = Search {
+        shape: curve.shape,
+        z_clip: clip.start * speed..clip.end * speed,
+        hit: None,
+    };
+    search.piece(
+        Piece {
+            points,
+            widths: curve.widths,
+            u: 0. ..1.,
+        },
+        depth,
+    );
+
+    let Some(hit) = search.hit else {
+        return HitPayload::Miss;
+    };
+    let t = hit.z / speed;
+    HitPayload::Hit {
+        hit_distance: t,
+        world_normal: to_ray.inverse() * hit.normal,
+        world_position: ray.at(t),
+        material: curve.material,
+        side: FaceSide::Front,
+        uv: Vec2::new(hit.u, hit.across),
+        tangent: curve.tangent(hit.u),
+    }
+}
+
+/// Part of the curve as its own Bézier segment, in ray space.
+struct Piece {
+    points: [Vec3; 4],
+    widths: [f32; 4],
+    /// Where the piece is along the whole curve.
+    u: Range<f32>,
+}
+
+impl Piece {
+    /// Split in half, with de Casteljau's algorithm.
+    fn split(&self) -> (Piece, Piece) {
+        let (first, second) = split(self.points);
+        let (first_widths, second_widths) = split(self.widths);
+        let middle = (self.u.start + self.u.end) / 2.;
+        (
+            Piece {
+                points: first,
+                widths: first_widths,
+                u: self.u.start..middle,
+            },
+            Piece {
+                points: second,
+                widths: second_widths,
+                u: middle..self.u.end,
+            },
+        )
+    }
+}
+
+fn split<T>(p: [T; 4]) -> ([T; 4], [T; 4])
+where
+    T: Copy + std::ops::Add<Output = T> + std::ops::Mul<f32, Output = T>,
+{
+    let mid = |a: T, b: T| (a + b) * 0.5;
+    let (p01, p12, p23) = (mid(p[0], p[1]), mid(p[1], p[2]), mid(p[2], p[3]));
+    let (p012, p123) = (mid(p01, p12), mid(p12, p23));
+    let p0123 = mid(p012, p123);
+    ([p[0], p01, p012, p0123], [p0123, p123, p23, p[3]])
+}
+
+/// A hit in ray space.
+struct LocalHit {
+    z: f32,
+    normal: Vec3,
+    u: f32,
+    /// Across the width of the curve, from 0 to 1.
+    across: f32,
+}
+
+struct Search {
+    shape: CurveShape,
+    z_clip: Range<f32>,
+    hit: Option<LocalHit>,
+}
+
+impl Search {
+    fn piece(&mut self, piece: Piece, depth: u32) {
+        // the curve stays within the box around its control points
+        let half_width = piece.widths.into_iter().fold(0., f32::max) / 2.;
+        let (min, max) = piece.points[1..]
+            .iter()
+            .fold((piece.points[0], piece.points[0]), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            });
+        let nearest = self.hit.as_ref().map_or(self.z_clip.end, |hit| hit.z);
+        if min.x - half_width > 0.
+            || max.x + half_width < 0.
+            || min.y - half_width > 0.
+            || max.y + half_width < 0.
+            || min.z - half_width > nearest
+            || max.z + half_width < self.z_clip.start
+        {
+            return;
+        }
+
+        if depth > 0 {
+            let (first, second) = piece.split();
+            self.piece(first, depth - 1);
+            self.piece(second, depth - 1);
+        } else {
+            self.segment(&piece);
+        }
+    }
+
+    /// Treats the piece as straight, and checks where it passes closest
+    /// to the ray.
+    fn segment(&mut self, piece: &Piece) {
+        let (start, end) = (piece.points[0], piece.points[3]);
+        let chord = end - start;
+        let along = if chord.xy().length_squared() > 0. {
+            (-start.xy().dot(chord.xy()) / chord.xy().length_squared()).clamp(0., 1.)
+        } else {
+            0.
+        };
+        let center = start + chord * along;
+        let [w0, w1, w2, w3] = piece.widths;
+        let rest = 1. - along;
+        let width = w0 * rest * rest * rest
+            + w1 * 3. * rest * rest * along
+            + w2 * 3. * rest * along * along
+            + w3 * along * along * along;
+        let half_width = width / 2.;
+        let off_center = center.xy().length_squared();
+        if half_width <= 0. || off_center > half_width * half_width {
+            return;
+        }
+
+        // which side of the curve's center line the ray passes on
+        let side = chord.xy().perp_dot(-center.xy()).signum();
+        let across = (side * off_center.sqrt() / half_width + 1.) / 2.;
+        let (z, normal) = match self.shape {
+            CurveShape::Flat => (center.z, Vec3::NEG_Z),
+            CurveShape::Round => {
+                // the surface of a tube around the center line, facing the ray
+                let depth = (half_width * half_width - off_center).sqrt();
+                let normal = Vec3::new(-center.x, -center.y, -depth) / half_width;
+                (center.z - depth, normal)
+            }
+        };
+        let nearest = self.hit.as_ref().map_or(self.z_clip.end, |hit| hit.z);
+        if z < self.z_clip.start || z >= nearest {
+            return;
+        }
+        self.hit = Some(LocalHit {
+            z,
+            normal,
+            u: piece.u.start + (piece.u.end - piece.u.start) * along,
+            across,
+        });
+    }
+}
@@ -0,0 +1,86 @@
+//! Rays against triangle meshes, searched with each mesh's own BVH in the
+//! mesh's local space.
+
+use super::{FaceSide, HitPayload};
+use crate::{
+    geom::{from_real_vec3, real_vec3, Ray},
+    Mesh,
+};
+use glam::{Vec2, Vec3};
+use std::ops::{ControlFlow, Range};
+
+pub(super) fn check_hit_mesh(mesh: &Mesh, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    // Distances along the ray are the same in local space, since the
+    // transform is affine.
+    let transform = &mesh.transform;
+    let local = Ray {
+        origin: transform.inverse_transform_point(ray.origin),
+        direction: transform.inverse_transform_vector(ray.direction),
+    };
+    let geometry = &mesh.geometry;
+    let mut closest = None;
+    geometry.bvh().traverse(&local, &mut clip.clone(), |idx, clip| {
+        if let Some(hit) = intersect_triangle(geometry.triangle(idx), &local, clip) {
+            clip.end = hit.t;
+            closest = Some(hit);
+        }
+        ControlFlow::<()>::Continue(())
+    });
+
+    let Some(hit) = closest else {
+        return HitPayload::Miss;
+    };
+    let normal = (transform.rotation * hit.normal).normalize();
+    let (side, world_normal) = if ray.direction.dot(normal) > 0. {
+        (FaceSide::Back, -normal)
+    } else {
+        (FaceSide::Front, normal)
+    };
+    HitPayload::Hit {
+        hit_distance: hit.t,
+        world_normal,
+        world_position: ray.at(hit.t),
+        material: mesh.material,
+        side,
+        uv: hit.uv,
+        tangent: (transform.rotation * hit.tangent).normalize(),
+    }
+}
+
+struct TriangleHit {
+    t: f32,
+    /// Not normalized.
+    normal: Vec3,
+    /// Barycentric coordinates, towards the second and third corners.
+    uv: Vec2,
+    tangent: Vec3,
+}
+
+/// The Möller–Trumbore test, which hits both faces.
+fn intersect_triangle([a, b, c]: [Vec3; 3], ray: &Ray, clip: &Range<f32>) -> Option<TriangleHit> {
+    let (ab, ac) = (b - a, c - a);
+    let p = ray.direction.cross(ac);
+    let determinant = ab.dot(p);
+    if determinant.abs() < 1e-12 {
+        // parallel to the triangle, or the triangle has no area
+        return None;
+    }
+    let inverse = 1. / determinant;
+    let offset = from_real_vec3(ray.origin - real_vec3(a));
+    let u = offset.dot(p) * inverse;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = offset.cross(ab);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+    let t = ac.dot(q) * inverse;
+    clip.contains(&t).then(|| TriangleHit {
+        t,
+        normal: ab.cross(ac),
+        uv: Vec2::new(u, v),
+        tangent: ab,
+    })
+}
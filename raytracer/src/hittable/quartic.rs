@@ -0,0 +1,128 @@
+//! Real roots of polynomials up to degree four, for surfaces like the torus
+//! whose intersections with a ray don't reduce to a quadratic. Works in
+//! `f64`, since the quartic formula loses a lot of precision.
+
+/// The real roots of `c[0] x⁴ + c[1] x³ + c[2] x² + c[3] x + c[4]`, in no
+/// particular order. `c[0]` must not be zero.
+pub(crate) fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let [a, b, c_, d] = [c[1] / c[0], c[2] / c[0], c[3] / c[0], c[4] / c[0]];
+
+    // substitute x = y - a/4 to remove the cubic term
+    let shift = a / 4.;
+    let p = b - 3. * a * a / 8.;
+    let q = c_ - a * b / 2. + a * a * a / 8.;
+    let r = d - a * c_ / 4. + a * a * b / 16. - 3. * a.powi(4) / 256.;
+
+    let mut roots = Vec::with_capacity(4);
+    if q.abs() < 1e-12 {
+        // y⁴ + p y² + r is a quadratic in y²
+        for z in solve_quadratic(1., p, r) {
+            if z >= 0. {
+                roots.extend([z.sqrt(), -z.sqrt()]);
+            }
+        }
+    } else {
+        // Ferrari's method: pick m so both sides of
+        // (y² + p/2 + m)² = 2m y² - q y + m² + m p + p²/4 - r
+        // are perfect squares, then split into two quadratics.
+        let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.).max(1e-12);
+        let s = (2. * m).sqrt();
+        let offset = q / (2. * s);
+        roots.extend(solve_quadratic(1., -s, p / 2. + m + offset));
+        roots.extend(solve_quadratic(1., s, p / 2. + m - offset));
+    }
+
+    for root in &mut roots {
+        *root = polish(c, *root - shift);
+    }
+    roots
+}
+
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let discriminant = b * b - 4. * a * c;
+    if discriminant < 0. {
+        return Vec::new();
+    }
+    // avoid subtracting nearly equal numbers
+    let sqrt = discriminant.sqrt();
+    let q = -0.5 * (b + b.signum() * sqrt);
+    if q == 0. {
+        return vec![0.];
+    }
+    vec![q / a, c / q]
+}
+
+/// The largest real root of `x³ + a x² + b x + c`.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    let p = b - a * a / 3.;
+    let q = 2. * a.powi(3) / 27. - a * b / 3. + c;
+    let discriminant = q * q / 4. + p.powi(3) / 27.;
+    let t = if discriminant > 0. {
+        let sqrt = discriminant.sqrt();
+        (-q / 2. + sqrt).cbrt() + (-q / 2. - sqrt).cbrt()
+    } else if p == 0. {
+        // a triple root
+        0.
+    } else {
+        // three real roots, and the first of the trigonometric solutions is
+        // the largest
+        let radius = (-p / 3.).sqrt();
+        let cos = (-q / 2. / radius.powi(3)).clamp(-1., 1.);
+        2. * radius * (cos.acos() / 3.).cos()
+    };
+    t - a / 3.
+}
+
+/// A couple of Newton steps, to win back precision lost in the formula.
+fn polish(c: [f64; 5], mut x: f64) -> f64 {
+    for _ in 0..2 {
+        let value = (((c[0] * x + c[1]) * x + c[2]) * x + c[3]) * x + c[4];
+        let slope = ((4. * c[0] * x + 3. * c[1]) * x + 2. * c[2]) * x + c[3];
+        if slope == 0. {
+            break;
+        }
+        x -= value / slope;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::solve_quartic;
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    fn assert_roots(coefficients: [f64; 5], expected: &[f64]) {
+        let roots = sorted(solve_quartic(coefficients));
+        assert_eq!(roots.len(), expected.len(), "{roots:?}");
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!((root - expected).abs() < 1e-9, "{roots:?} vs {expected:?}");
+        }
+    }
+
+    #[test]
+    fn four_roots() {
+        // (x - 1)(x + 2)(x - 3)(x + 4)
+        assert_roots([1., 2., -13., -14., 24.], &[-4., -2., 1., 3.]);
+    }
+
+    #[test]
+    fn two_roots() {
+        // (x - 1)(x - 2)(x² + 1)
+        assert_roots([2., -6., 6., -6., 4.], &[1., 2.]);
+    }
+
+    #[test]
+    fn biquadratic() {
+        // (x² - 1)(x² - 4)
+        assert_roots([1., 0., -5., 0., 4.], &[-2., -1., 1., 2.]);
+    }
+
+    #[test]
+    fn no_roots() {
+        assert!(solve_quartic([1., 0., 1., 0., 1.]).is_empty());
+    }
+}
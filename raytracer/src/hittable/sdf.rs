@@ -0,0 +1,92 @@
+//! Sphere tracing: step along the ray by the distance to the nearest
+//! surface, which can't overshoot, until the surface is close enough.
+
+use super::{axial::around_y, FaceSide, HitPayload};
+use crate::{
+    geom::{from_real_vec3, Ray},
+    Sdf,
+};
+use glam::{Vec2, Vec3};
+use std::{f32::consts::PI, ops::Range};
+
+/// Gives up on rays that creep along close to a surface without hitting it.
+const MAX_STEPS: usize = 256;
+
+/// How close counts as a hit, in the shape's own units.
+const EPSILON: f32 = 1e-4;
+
+pub(super) fn check_hit_sdf(sdf: &Sdf, ray: &Ray, clip: &Range<f32>) -> HitPayload {
+    // March in the shape's space. The transform is affine, so distances
+    // along the ray are the same in both spaces.
+    let transform = &sdf.transform;
+    let origin = from_real_vec3(transform.inverse_transform_point(ray.origin));
+    let direction = transform.inverse_transform_vector(ray.direction);
+    let speed = direction.length();
+
+    // only march where the ray is inside the bounding sphere
+    let radius = sdf.shape.bounding_radius();
+    let half_b = origin.dot(direction);
+    let c = origin.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - speed * speed * c;
+    if discriminant < 0. {
+        return HitPayload::Miss;
+    }
+    let sqrt = discriminant.sqrt();
+    let enter = (-half_b - sqrt) / (speed * speed);
+    let exit = (-half_b + sqrt) / (speed * speed);
+    let mut t = enter.max(clip.start);
+    let end = exit.min(clip.end);
+
+    for _ in 0..MAX_STEPS {
+        if t > end {
+            return HitPayload::Miss;
+        }
+        let p = origin + direction * t;
+        // Follow the distance either way, so rays starting inside a shape
+        // find their way out.
+        let distance = sdf.shape.distance(p).abs();
+        if distance < EPSILON {
+            if !clip.contains(&t) {
+                return HitPayload::Miss;
+            }
+            let normal = transform.rotation * gradient(sdf, p);
+            let (side, world_normal) = if ray.direction.dot(normal) > 0. {
+                (FaceSide::Back, -normal)
+            } else {
+                (FaceSide::Front, normal)
+            };
+            // spherical coordinates around the shape's origin
+            let around = p.try_normalize().unwrap_or(Vec3::Y);
+            let (u, tangent) = around_y(around);
+            let v = (-around.y).clamp(-1., 1.).acos() / PI;
+            return HitPayload::Hit {
+                hit_distance: t,
+                world_normal,
+                world_position: ray.at(t),
+                material: sdf.material,
+                side,
+                uv: Vec2::new(u, v),
+                tangent: transform.rotation * tangent,
+            };
+        }
+        t += distance / speed;
+    }
+    HitPayload::Miss
+}
+
+/// The direction the distance grows fastest, from four samples around `p`
+/// at the corners of a tetrahedron.
+fn gradient(sdf: &Sdf, p: Vec3) -> Vec3 {
+    const H: f32 = EPSILON;
+    [
+        Vec3::new(1., -1., -1.),
+        Vec3::new(-1., -1., 1.),
+        Vec3::new(-1., 1., -1.),
+        Vec3::new(1., 1., 1.),
+    ]
+    .into_iter()
+    .map(|corner| corner * sdf.shape.distance(p + corner * H))
+    .sum::<Vec3>()
+    .try_normalize()
+    .unwrap_or(Vec3::Y)
+}
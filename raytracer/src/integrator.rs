@@ -0,0 +1,333 @@
+//! Ways of working out the light that comes back along each camera ray.
+
+use crate::{
+    geom::from_real_vec3,
+    hittable::HitPayload,
+    pixel_trace::{Bounce, BounceHit},
+    renderer::RenderFrame,
+    stats::PathStats,
+    util::Vec3Ext,
+    Camera, Hit, Ray, Sampler, Scene,
+};
+use glam::{Vec3, Vec4, Vec4Swizzles};
+use std::ops::Range;
+
+mod photon;
+
+pub use photon::PhotonMapper;
+
+pub(crate) const SKY_COLOR: Vec3 = Vec3::new(0.6, 0.7, 0.9);
+
+/// How many times a path can bounce before it's cut off, by default.
+pub(crate) const MAX_BOUNCES: u32 = 16;
+
+/// Works out a sample for each pixel, from the camera ray through it. The
+/// [`Renderer`](crate::Renderer) takes care of which pixels are traced and
+/// of averaging the samples, so new rendering techniques only need this.
+pub trait Integrator: Send + Sync {
+    /// Called before each frame of `scene` is traced, for work the whole
+    /// frame shares, like shooting photons. `seed` is the frame's, and
+    /// `accumulated` is how many frames the film has averaged before it.
+    /// [`Renderer::trace_pixel`](crate::Renderer::trace_pixel) uses
+    /// whatever the last frame set up. Does nothing by default.
+    fn begin_frame(&mut self, _scene: &Scene, _seed: u64, _accumulated: f32) {}
+
+    /// The light coming back along the camera ray `ray`, as premultiplied
+    /// RGBA, where alpha is how much of the pixel the scene covers. `path`
+    /// traces rays through the scene and hands out random numbers.
+    fn sample(&self, ray: Ray, path: &mut Path<'_>) -> Vec4;
+}
+
+/// What an [`Integrator`] works with while it takes one pixel's sample.
+pub struct Path<'a> {
+    frame: &'a RenderFrame<'a>,
+    sampler: Sampler,
+    /// What the camera ray hit, if it's cached and hasn't been used yet.
+    first_hit: Option<&'a (usize, HitPayload)>,
+    /// How many rays have been traced.
+    traced: u32,
+    depth: f32,
+    stats: PathStats,
+}
+
+impl<'a> Path<'a> {
+    pub(crate) fn new(frame: &'a RenderFrame<'a>, pixel: usize) -> Self {
+        Self {
+            frame,
+            sampler: Sampler::for_pixel(frame.seed, pixel),
+            first_hit: frame.first_hits.and_then(|hits| hits.get(pixel)),
+            traced: 0,
+            depth: f32::INFINITY,
+            stats: PathStats::default(),
+        }
+    }
+
+    pub fn scene(&self) -> &'a Scene {
+        self.frame.scene
+    }
+
+    pub fn camera(&self) -> &'a Camera {
+        self.frame.camera
+    }
+
+    pub fn sampler(&mut self) -> &mut Sampler {
+        &mut self.sampler
+    }
+
+    /// The closest surface along `ray`, passing through surfaces whose
+    /// material culls the side that's hit. The first ray traced should be
+    /// the camera ray, since what it hits may come from a cache.
+    pub fn trace(&mut self, ray: &Ray) -> Option<Hit> {
+        let (hittable, hit) = self.trace_payload(ray);
+        hit.into_hit(hittable)
+    }
+
+    /// Whether anything blocks `ray` within `clip`. Cheaper than
+    /// [`Path::trace`], since any hit will do, but surfaces whose material
+    /// culls the side that's hit block it too.
+    pub fn occluded(&mut self, ray: &Ray, clip: &Range<f32>) -> bool {
+        self.stats.rays += 1;
+        self.traced += 1;
+        self.frame
+            .scene
+            .trace_occlusion(ray, clip, &mut self.stats.intersection_tests)
+    }
+
+    /// Like [`Path::trace`], with what materials need to shade the hit.
+    pub(crate) fn trace_payload(&mut self, ray: &Ray) -> (usize, HitPayload) {
+        let mut ray = ray.clone();
+        loop {
+            self.stats.rays += 1;
+            // still a camera ray if it's cached, even though it isn't traced
+            let (hittable, hit) = match self.first_hit.take() {
+                Some(hit) => hit.clone(),
+                None => self.frame.scene.trace_ray(
+                    &ray,
+                    self.frame.camera.look_clip(),
+                    &mut self.stats.intersection_tests,
+                ),
+            };
+            if let HitPayload::Hit {
+                material,
+                side,
+                world_position,
+                world_normal,
+                hit_distance,
+                ..
+            } = &hit
+            {
+                if self.scene().material(*material).facing(*side).is_none() {
+                    // culled, so carry on through the surface as if it
+                    // wasn't there
+                    ray = Ray::spawn(*world_position, *world_normal, ray.direction);
+                    continue;
+                }
+                if self.traced == 0 {
+                    self.depth = *hit_distance;
+                }
+            }
+            self.traced += 1;
+            return (hittable, hit);
+        }
+    }
+
+    /// What the ray traced last sees along `direction` if it hit nothing:
+    /// the scene's environment map or the sky, or nothing at all for the
+    /// camera ray with a transparent background.
+    pub fn background(&self, direction: Vec3) -> Vec4 {
+        if self.frame.transparent_background && self.traced <= 1 {
+            Vec4::ZERO
+        } else {
+            self.scene().sky(direction).extend(1.)
+        }
+    }
+
+    /// Record a bounce, if this frame is tracing a single pixel.
+    pub(crate) fn log(&self, bounce: impl FnOnce() -> Bounce) {
+        if let Some(log) = self.frame.log {
+            log.lock().push(bounce());
+        }
+    }
+
+    /// How far away the surface the camera ray hit is, and the stats for
+    /// the whole path.
+    pub(crate) fn finish(mut self) -> (f32, PathStats) {
+        // every ray after the camera ray is a bounce
+        self.stats.add_path(self.traced.saturating_sub(1));
+        (self.depth, self.stats)
+    }
+}
+
+/// Light bouncing around the scene, followed back from the camera off each
+/// surface it meets, as their materials scatter it.
+#[derive(Clone, Copy, Debug)]
+pub struct PathTracer {
+    /// How many times a path can bounce before it's cut off.
+    pub max_bounces: u32,
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self {
+            max_bounces: MAX_BOUNCES,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn sample(&self, ray: Ray, path: &mut Path<'_>) -> Vec4 {
+        self.ray_color(ray, self.max_bounces, path)
+    }
+}
+
+impl PathTracer {
+    /// The light coming back along `ray`, with alpha for how much of it
+    /// the scene covers.
+    fn ray_color(&self, ray: Ray, bounce_budget: u32, path: &mut Path<'_>) -> Vec4 {
+        if bounce_budget == 0 {
+            return Vec4::W;
+        }
+        let (_, hit) = path.trace_payload(&ray);
+        let HitPayload::Hit {
+            material: handle,
+            world_position,
+            world_normal,
+            side,
+            ..
+        } = hit
+        else {
+            let color = path.background(ray.direction);
+            // the path ends here
+            path.log(|| Bounce {
+                ray,
+                hit: BounceHit::Sky,
+                emitted: color.xyz(),
+                attenuation: None,
+            });
+            return color;
+        };
+        let material = path.scene().material(handle);
+        let emitted = material.emitted(&hit);
+        let scatter = material.scatter(&hit, &ray, path.sampler());
+        // logged before the next bounce, to keep the log in order
+        path.log(|| Bounce {
+            ray: ray.clone(),
+            hit: BounceHit::Surface {
+                position: from_real_vec3(world_position),
+                normal: world_normal,
+                material: handle,
+                side,
+            },
+            emitted,
+            attenuation: scatter.as_ref().map(|scatter| scatter.attenuation),
+        });
+        let color = if let Some(scatter) = scatter {
+            let bounce = self.ray_color(scatter.ray, bounce_budget - 1, path);
+            emitted + bounce.xyz() * scatter.attenuation
+        } else {
+            emitted
+        };
+        color.extend(1.)
+    }
+}
+
+/// How open to the sky each surface the camera sees is, as shades of grey
+/// from black where it's boxed in to white where nothing is within
+/// `distance`. It ignores materials and lights, so it clears up quickly and
+/// shows the shape of a scene on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusion {
+    /// How far away surfaces can be and still shade each other.
+    pub distance: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self { distance: 1. }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn sample(&self, ray: Ray, path: &mut Path<'_>) -> Vec4 {
+        let HitPayload::Hit {
+            world_position,
+            world_normal,
+            ..
+        } = path.trace_payload(&ray).1
+        else {
+            return path.background(ray.direction);
+        };
+        let direction = (world_normal + Vec3::random_unit(path.sampler()))
+            .try_normalize()
+            .unwrap_or(world_normal);
+        let probe = Ray::spawn(world_position, world_normal, direction);
+        if path.occluded(&probe, &(0.0..self.distance)) {
+            Vec4::W
+        } else {
+            Vec4::ONE
+        }
+    }
+}
+
+/// The normal of each surface the camera sees, facing the camera, as a
+/// color from 0 to 1 in each axis, for checking geometry.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DebugNormals;
+
+impl Integrator for DebugNormals {
+    fn sample(&self, ray: Ray, path: &mut Path<'_>) -> Vec4 {
+        match path.trace(&ray) {
+            Some(hit) => ((hit.normal + Vec3::ONE) / 2.).extend(1.),
+            None => path.background(ray.direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AmbientOcclusion, DebugNormals};
+    use crate::{test_fixtures, EnvironmentMap, Scene, Sphere};
+    use glam::{Vec3, Vec4};
+    use std::sync::Arc;
+
+    #[test]
+    fn normals_face_the_camera() {
+        let mut renderer = test_fixtures::renderer();
+        renderer.set_integrator(DebugNormals);
+        let mut scene = Scene::default();
+        // fills the camera's view, facing it
+        scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0., -1000.),
+            radius: 990.,
+            material: Default::default(),
+        });
+        renderer.render(&scene, &test_fixtures::camera());
+        let middle = renderer.film().average(test_fixtures::WIDTH / 2, test_fixtures::HEIGHT / 2);
+        assert!(middle.distance(Vec4::new(0.5, 0.5, 1., 1.)) < 0.01, "{middle}");
+    }
+
+    #[test]
+    fn open_ground_is_unoccluded() {
+        let mut renderer = test_fixtures::renderer();
+        renderer.set_integrator(AmbientOcclusion { distance: 0.01 });
+        renderer.render(&test_fixtures::sphere_on_ground(), &test_fixtures::camera());
+        // a corner of the ground, well away from the sphere
+        assert_eq!(renderer.film().average(0, 0), Vec4::ONE);
+    }
+
+    #[test]
+    fn misses_see_the_environment() {
+        let mut renderer = test_fixtures::renderer();
+        let mut scene = Scene::default();
+        // red above the horizon and blue below it
+        let texels = (0..64)
+            .map(|y| if y < 32 { Vec3::Z } else { Vec3::X })
+            .collect();
+        let environment = EnvironmentMap::new(1, 64, texels).unwrap();
+        scene.set_environment(Some(Arc::new(environment)));
+        renderer.render(&scene, &test_fixtures::camera());
+        let top = test_fixtures::HEIGHT - 1;
+        assert_eq!(renderer.film().average(0, top), Vec4::new(1., 0., 0., 1.));
+        assert_eq!(renderer.film().average(0, 0), Vec4::new(0., 0., 1., 1.));
+    }
+}
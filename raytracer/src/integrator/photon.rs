@@ -0,0 +1,652 @@
+//! Photon mapping, for caustics that path tracing is slow to find.
+
+use super::{Integrator, Path, MAX_BOUNCES};
+use crate::{
+    bvh::Aabb,
+    geom::{from_real_vec3, real_vec3},
+    hittable::{FaceSide, HitPayload, Hittable},
+    material::Backface,
+    parallel::*,
+    pixel_trace::{Bounce, BounceHit},
+    Material, Ray, Sampler, Scene,
+};
+use glam::{IVec3, Vec2, Vec3, Vec4, Vec4Swizzles};
+use std::{collections::HashMap, f32::consts::PI, ops::Range};
+
+/// How quickly the gather radius shrinks as frames accumulate, from 0 to 1.
+/// Lower values sharpen caustics sooner but leave them noisy for longer.
+/// Progressive photon mapping suggests 2/3.
+const RADIUS_ALPHA: f32 = 2. / 3.;
+
+/// How many photons are shot from the sky at each hittable to see how much
+/// of it the sky reaches, before shooting the ones that are kept.
+const TEST_PHOTONS: usize = 256;
+
+/// Path tracing, with caustics gathered from photons shot from the lights
+/// instead. Caustics are light that reaches a diffuse surface after
+/// bouncing off mirrors and other surfaces that aren't diffuse. Path
+/// tracing only finds them when a bounce happens to head for a light by way
+/// of the mirror, so they take a very long time to clear up, while photons
+/// land on them directly.
+///
+/// Photons are shot again every frame, and the radius they're gathered from
+/// shrinks as frames accumulate, as in progressive photon mapping, so the
+/// blur caustics start with sharpens over time.
+///
+/// Photons come from the sky and from spheres and quads with
+/// [`Material::Emissive`]. Caustics lit by other lights are still path
+/// traced.
+#[derive(Clone, Debug)]
+pub struct PhotonMapper {
+    /// How many photons to shoot each frame. Only those that reach a
+    /// diffuse surface by way of something else are kept.
+    pub photons: usize,
+    /// How far around each point photons are gathered from on the first
+    /// frame. Smaller radii give sharper caustics that are noisier.
+    pub radius: f32,
+    /// How many times a path can bounce before it's cut off, both from the
+    /// camera and from the lights.
+    pub max_bounces: u32,
+    /// This frame's photons.
+    map: PhotonMap,
+}
+
+impl Default for PhotonMapper {
+    fn default() -> Self {
+        Self::new(100_000, 0.1)
+    }
+}
+
+impl PhotonMapper {
+    pub fn new(photons: usize, radius: f32) -> Self {
+        Self {
+            photons,
+            radius,
+            max_bounces: MAX_BOUNCES,
+            map: PhotonMap::default(),
+        }
+    }
+
+    /// How many photons the current frame kept.
+    pub fn stored_photons(&self) -> usize {
+        self.map.photons.len()
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn begin_frame(&mut self, scene: &Scene, seed: u64, accumulated: f32) {
+        let radius = self.radius * (accumulated + 1.).powf((RADIUS_ALPHA - 1.) / 2.);
+        self.map = PhotonMap::shoot(scene, self.photons, self.max_bounces, seed, radius);
+    }
+
+    fn sample(&self, ray: Ray, path: &mut Path<'_>) -> Vec4 {
+        let mut ray = ray;
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut state = PathState::Camera;
+        for bounce in 0..self.max_bounces {
+            let (hittable, hit) = path.trace_payload(&ray);
+            let HitPayload::Hit {
+                material: handle,
+                world_position,
+                world_normal,
+                side,
+                ..
+            } = hit
+            else {
+                let background = path.background(ray.direction);
+                path.log(|| Bounce {
+                    ray,
+                    hit: BounceHit::Sky,
+                    emitted: background.xyz(),
+                    attenuation: None,
+                });
+                if bounce == 0 {
+                    return background;
+                }
+                // the sky's photons already brought in light that came
+                // this way
+                if state != PathState::Caustic {
+                    color += throughput * background.xyz();
+                }
+                break;
+            };
+            let material = path.scene().material(handle);
+            let mut emitted = material.emitted(&hit);
+            if state == PathState::Caustic && self.map.lights_up(hittable, side) {
+                emitted = Vec3::ZERO;
+            }
+            let albedo = material.diffuse_albedo(&hit);
+            if let Some(albedo) = albedo {
+                // logged as light the surface gives off, since that's how
+                // it's added
+                let position = from_real_vec3(world_position);
+                emitted += albedo / PI * self.map.irradiance(position, world_normal);
+            }
+            let scatter = material.scatter(&hit, &ray, path.sampler());
+            path.log(|| Bounce {
+                ray: ray.clone(),
+                hit: BounceHit::Surface {
+                    position: from_real_vec3(world_position),
+                    normal: world_normal,
+                    material: handle,
+                    side,
+                },
+                emitted,
+                attenuation: scatter.as_ref().map(|scatter| scatter.attenuation),
+            });
+            color += throughput * emitted;
+            let Some(scatter) = scatter else {
+                break;
+            };
+            throughput *= scatter.attenuation;
+            state = match (albedo, state) {
+                (Some(_), _) => PathState::Diffuse,
+                (None, PathState::Camera) => PathState::Camera,
+                (None, _) => PathState::Caustic,
+            };
+            ray = scatter.ray;
+        }
+        color.extend(1.)
+    }
+}
+
+/// What a camera path last bounced off, which tells whether the photons
+/// already account for the light it finds next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PathState {
+    /// Nothing diffuse yet.
+    Camera,
+    /// A diffuse surface, on the last bounce.
+    Diffuse,
+    /// Surfaces that aren't diffuse, since the last diffuse one. Light
+    /// that reaches that diffuse surface this way is a caustic.
+    Caustic,
+}
+
+/// Light that reached a diffuse surface after bouncing off something else.
+#[derive(Clone, Copy, Debug)]
+struct Photon {
+    position: Vec3,
+    /// The way it was travelling, not normalized.
+    direction: Vec3,
+    power: Vec3,
+}
+
+/// A frame's photons, sorted into a grid of cells as wide as the gather
+/// radius, so gathering only looks through the cells around a point.
+#[derive(Clone, Debug, Default)]
+struct PhotonMap {
+    photons: Vec<Photon>,
+    /// Where each cell's photons are in `photons`.
+    cells: HashMap<IVec3, Range<usize>>,
+    radius: f32,
+    /// The sides of hittables that photons were shot from.
+    lit: Vec<(usize, FaceSide)>,
+}
+
+impl PhotonMap {
+    /// Shoot `count` photons into `scene`, gathered from within `radius`.
+    fn shoot(scene: &Scene, count: usize, max_bounces: u32, seed: u64, radius: f32) -> Self {
+        let emitters = emitters(scene);
+        let lit = emitters.iter().filter_map(Emitter::lit_side).collect();
+        let bounds = scene.bounds();
+        // emitters are chosen by how much light they give off that gets
+        // anywhere, and photons make up for how often they're chosen
+        let weights: Vec<f32> = emitters
+            .iter()
+            .enumerate()
+            .map(|(idx, emitter)| {
+                // numbered after the photons, so they're all different
+                let mut sampler = Sampler::for_pixel(!seed, count + idx);
+                let power = emitter.power().dot(Vec3::ONE) / 3.;
+                power * emitter.reach(scene, &bounds, &mut sampler)
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        if !(radius > 0. && total > 0.) {
+            return Self { lit, ..Default::default() };
+        }
+        let cumulative: Vec<f32> = weights
+            .iter()
+            .scan(0., |sum, power| {
+                *sum += power;
+                Some(*sum)
+            })
+            .collect();
+
+        let shot: Vec<Option<Photon>> = (0..count)
+            .into_par_iter()
+            .map(|idx| {
+                // flipped, so photons don't share random numbers with the
+                // pixels of the same index
+                let mut sampler = Sampler::for_pixel(!seed, idx);
+                let choice = sampler.next_1d() * total;
+                let chosen = cumulative.partition_point(|sum| *sum <= choice);
+                let chosen = chosen.min(emitters.len() - 1);
+                let emitter = &emitters[chosen];
+                let mut power = emitter.power() * total / (weights[chosen] * count as f32);
+                let ray = emitter.emit(&mut sampler, &bounds);
+                if let Emitter::Sky { radiance, .. } = emitter {
+                    // brighter parts of an environment map send out
+                    // brighter photons
+                    let floor = Vec3::splat(f32::MIN_POSITIVE);
+                    power *= scene.sky(-ray.direction) / radiance.max(floor);
+                }
+                trace_photon(scene, emitter, ray, power, max_bounces, &mut sampler)
+            })
+            .collect();
+
+        let mut photons: Vec<Photon> = shot.into_iter().flatten().collect();
+        photons.sort_by_key(|photon| cell(photon.position, radius).to_array());
+        let mut cells = HashMap::new();
+        let mut start = 0;
+        while start < photons.len() {
+            let key = cell(photons[start].position, radius);
+            let len = photons[start..]
+                .iter()
+                .take_while(|photon| cell(photon.position, radius) == key)
+                .count();
+            cells.insert(key, start..start + len);
+            start += len;
+        }
+        Self {
+            photons,
+            cells,
+            radius,
+            lit,
+        }
+    }
+
+    /// The power arriving per area at `position` on a surface facing
+    /// `normal`, from photons within the gather radius that came from the
+    /// side the normal faces.
+    fn irradiance(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        if self.photons.is_empty() {
+            return Vec3::ZERO;
+        }
+        let center = cell(position, self.radius);
+        let radius_squared = self.radius * self.radius;
+        let mut power = Vec3::ZERO;
+        for z in -1..=1 {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let Some(range) = self.cells.get(&(center + IVec3::new(x, y, z))) else {
+                        continue;
+                    };
+                    for photon in &self.photons[range.clone()] {
+                        if photon.direction.dot(normal) < 0.
+                            && photon.position.distance_squared(position) < radius_squared
+                        {
+                            power += photon.power;
+                        }
+                    }
+                }
+            }
+        }
+        power / (PI * radius_squared)
+    }
+
+    /// Whether photons were shot from `side` of the hittable at `hittable`.
+    fn lights_up(&self, hittable: usize, side: FaceSide) -> bool {
+        self.lit.contains(&(hittable, side))
+    }
+}
+
+/// The grid cell of the photon map `position` is in.
+fn cell(position: Vec3, size: f32) -> IVec3 {
+    (position / size).floor().as_ivec3()
+}
+
+/// Follow a photon carrying `power` along `ray` until it reaches a diffuse
+/// surface, returning it if it bounced off anything else on the way.
+fn trace_photon(
+    scene: &Scene,
+    emitter: &Emitter,
+    mut ray: Ray,
+    mut power: Vec3,
+    max_bounces: u32,
+    sampler: &mut Sampler,
+) -> Option<Photon> {
+    for bounce in 0..max_bounces {
+        let (hittable, hit) = trace_visible(scene, &ray);
+        let HitPayload::Hit {
+            material,
+            world_position,
+            ..
+        } = hit
+        else {
+            return None;
+        };
+        if let Emitter::Sky { target, .. } = emitter {
+            // every photon that hits a hittable first is aimed at it
+            // sometimes, so only those count, to avoid counting twice
+            if bounce == 0 && hittable != *target {
+                return None;
+            }
+        }
+        let material = scene.material(material);
+        if material.diffuse_albedo(&hit).is_some() {
+            return (bounce > 0).then_some(Photon {
+                position: from_real_vec3(world_position),
+                direction: ray.direction,
+                power,
+            });
+        }
+        let scatter = material.scatter(&hit, &ray, sampler)?;
+        power *= scatter.attenuation;
+        ray = scatter.ray;
+    }
+    None
+}
+
+/// The closest surface along `ray`, passing through culled faces as
+/// [`Path::trace`] does.
+fn trace_visible(scene: &Scene, ray: &Ray) -> (usize, HitPayload) {
+    let mut ray = ray.clone();
+    loop {
+        let (hittable, hit) = scene.trace_ray(&ray, &(0.0..f32::INFINITY), &mut 0);
+        if let HitPayload::Hit {
+            material,
+            side,
+            world_position,
+            world_normal,
+            ..
+        } = &hit
+        {
+            if scene.material(*material).facing(*side).is_none() {
+                ray = Ray::spawn(*world_position, *world_normal, ray.direction);
+                continue;
+            }
+        }
+        return (hittable, hit);
+    }
+}
+
+/// Where photons come from.
+#[derive(Clone, Debug)]
+enum Emitter {
+    /// The sky, shining on the hittable at `target` from every direction,
+    /// through a sphere around it. Only photons that bounce off something
+    /// before reaching a diffuse surface are kept, so they're only aimed at
+    /// hittables that might not be diffuse.
+    Sky {
+        target: usize,
+        center: Vec3,
+        radius: f32,
+        /// The sky's light, averaged over every direction.
+        radiance: Vec3,
+    },
+    /// The outside of an emissive sphere.
+    Sphere {
+        hittable: usize,
+        center: Vec3,
+        radius: f32,
+        emission: Vec3,
+    },
+    /// One side of an emissive quad.
+    Quad {
+        hittable: usize,
+        side: FaceSide,
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+        emission: Vec3,
+    },
+}
+
+impl Emitter {
+    /// The total power given off.
+    fn power(&self) -> Vec3 {
+        match self {
+            // radiance over the disk, from every direction
+            Emitter::Sky {
+                radius, radiance, ..
+            } => *radiance * (PI * radius * radius) * 4. * PI,
+            // a diffuse emitter gives off pi times its radiance per area
+            Emitter::Sphere {
+                radius, emission, ..
+            } => *emission * PI * (4. * PI * radius * radius),
+            Emitter::Quad { u, v, emission, .. } => *emission * PI * u.cross(*v).length(),
+        }
+    }
+
+    /// The side of a hittable the emitter shines from, unless it's the sky.
+    fn lit_side(&self) -> Option<(usize, FaceSide)> {
+        match self {
+            Emitter::Sky { .. } => None,
+            Emitter::Sphere { hittable, .. } => Some((*hittable, FaceSide::Front)),
+            Emitter::Quad { hittable, side, .. } => Some((*hittable, *side)),
+        }
+    }
+
+    /// Roughly how much of the emitter's light gets anywhere. For the sky,
+    /// that's how many test photons reach the target before anything else,
+    /// and otherwise it's all of it. Sky light that only gets through gaps
+    /// too small for the test photons to find is left out.
+    fn reach(&self, scene: &Scene, bounds: &Aabb, sampler: &mut Sampler) -> f32 {
+        let Emitter::Sky { target, .. } = self else {
+            return 1.;
+        };
+        let reached = (0..TEST_PHOTONS)
+            .filter(|_| {
+                let (hittable, hit) = trace_visible(scene, &self.emit(sampler, bounds));
+                hittable == *target && hit != HitPayload::Miss
+            })
+            .count();
+        reached as f32 / TEST_PHOTONS as f32
+    }
+
+    /// A ray for a photon to leave along. Sky photons start outside
+    /// `bounds`, so anything in the way can block them.
+    fn emit(&self, sampler: &mut Sampler, bounds: &Aabb) -> Ray {
+        match self {
+            Emitter::Sky { center, radius, .. } => {
+                let direction = uniform_sphere(sampler.next_2d());
+                let (across, up) = direction.any_orthonormal_pair();
+                let disk = sampler.next_2d();
+                let (distance, angle) = (radius * disk.x.sqrt(), 2. * PI * disk.y);
+                let point = *center - direction * *radius
+                    + distance * (angle.cos() * across + angle.sin() * up);
+                let reach = (point - bounds.center()).length() + bounds.max.distance(bounds.min);
+                Ray::new(point - direction * reach, direction)
+            }
+            Emitter::Sphere { center, radius, .. } => {
+                let normal = uniform_sphere(sampler.next_2d());
+                let direction = cosine_hemisphere(normal, sampler.next_2d());
+                Ray::spawn(real_vec3(*center + normal * *radius), normal, direction)
+            }
+            Emitter::Quad {
+                side, corner, u, v, ..
+            } => {
+                let normal = match side {
+                    FaceSide::Front => u.cross(*v).normalize(),
+                    FaceSide::Back => v.cross(*u).normalize(),
+                };
+                let at = sampler.next_2d();
+                let direction = cosine_hemisphere(normal, sampler.next_2d());
+                let position = *corner + at.x * *u + at.y * *v;
+                Ray::spawn(real_vec3(position), normal, direction)
+            }
+        }
+    }
+}
+
+/// Everything in `scene` that photons come from, leaving out any that give
+/// off no light.
+fn emitters(scene: &Scene) -> Vec<Emitter> {
+    let mut emitters = vec![];
+    for (idx, hittable) in scene.world_hittables().iter().enumerate() {
+        let material = scene.material(hittable.material());
+        let emission = |side| match material.facing(side) {
+            Some(Material::Emissive { emission }) => Some(*emission),
+            _ => None,
+        };
+        match hittable {
+            Hittable::Sphere(sphere) => {
+                if let Some(emission) = emission(FaceSide::Front) {
+                    emitters.push(Emitter::Sphere {
+                        hittable: idx,
+                        center: sphere.center,
+                        radius: sphere.radius,
+                        emission,
+                    });
+                }
+            }
+            Hittable::Quad(quad) => {
+                for side in [FaceSide::Front, FaceSide::Back] {
+                    if let Some(emission) = emission(side) {
+                        emitters.push(Emitter::Quad {
+                            hittable: idx,
+                            side,
+                            corner: quad.corner,
+                            u: quad.u,
+                            v: quad.v,
+                            emission,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+        let bounds = hittable.bounding_box();
+        if redirects_light(material) && !bounds.is_empty() {
+            emitters.push(Emitter::Sky {
+                target: idx,
+                center: bounds.center(),
+                radius: bounds.max.distance(bounds.min) / 2.,
+                radiance: scene.sky_average(),
+            });
+        }
+    }
+    emitters.retain(|emitter| emitter.power().max_element() > 0.);
+    emitters
+}
+
+/// Whether light could leave `material` some way other than diffusely, so
+/// the sky's photons could reach a diffuse surface by way of it.
+fn redirects_light(material: &Material) -> bool {
+    !matches!(material, Material::Null | Material::Emissive { .. }) && !always_diffuse(material)
+}
+
+/// Whether [`Material::diffuse_albedo`] finds `material` diffuse wherever
+/// it's hit.
+fn always_diffuse(material: &Material) -> bool {
+    match material {
+        Material::Lambertian { .. } | Material::Textured { .. } => true,
+        Material::Bump { material, .. } => always_diffuse(material),
+        Material::Sided { front, back } => {
+            always_diffuse(front)
+                && match back {
+                    Backface::Shaded | Backface::Culled => true,
+                    Backface::Material(back) => always_diffuse(back),
+                }
+        }
+        Material::Mix { a, b, .. } => always_diffuse(a) && always_diffuse(b),
+        Material::Null
+        | Material::Emissive { .. }
+        | Material::Metal { .. }
+        | Material::Graph { .. }
+        | Material::Procedural { .. }
+        | Material::Custom { .. } => false,
+    }
+}
+
+/// A direction chosen evenly from all around, from two numbers from 0 to 1.
+fn uniform_sphere(at: Vec2) -> Vec3 {
+    let z = 1. - 2. * at.x;
+    let ring = (1. - z * z).max(0.).sqrt();
+    let angle = 2. * PI * at.y;
+    Vec3::new(ring * angle.cos(), ring * angle.sin(), z)
+}
+
+/// A direction on the side of a surface `normal` faces, chosen more often
+/// the closer it is to the normal, as a diffuse surface gives off light.
+fn cosine_hemisphere(normal: Vec3, at: Vec2) -> Vec3 {
+    let (across, up) = normal.any_orthonormal_pair();
+    let (ring, angle) = (at.x.sqrt(), 2. * PI * at.y);
+    ring * angle.cos() * across + ring * angle.sin() * up + (1. - at.x).sqrt() * normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PhotonMapper;
+    use crate::{test_fixtures, Integrator, Material, Quad, Renderer, Scene, Sphere};
+    use glam::{Vec3, Vec4};
+    use std::f32::consts::PI;
+
+    #[test]
+    fn diffuse_scenes_are_path_traced() {
+        let scene = test_fixtures::sphere_on_ground();
+        let mut photons = PhotonMapper::default();
+        photons.begin_frame(&scene, 0, 0.);
+        assert_eq!(photons.stored_photons(), 0);
+
+        let mut renderer = test_fixtures::renderer();
+        let average = |renderer: &mut Renderer| {
+            renderer.set_seed(Some(1));
+            renderer.render_accumulate(&scene, &test_fixtures::camera(), 4);
+            let film = renderer.film();
+            (0..film.height())
+                .flat_map(|y| (0..film.width()).map(move |x| film.average(x, y)))
+                .sum::<Vec4>()
+        };
+        // only the order the colors are added up in differs
+        let path_traced = average(&mut renderer);
+        renderer.set_integrator(photons);
+        let photon_mapped = average(&mut renderer);
+        assert!(path_traced.distance(photon_mapped) < 1e-3, "{path_traced} {photon_mapped}");
+    }
+
+    #[test]
+    fn mirrors_light_up_what_they_reflect_on() {
+        // a ball of light over a mirror, under a ceiling, in a black room
+        let mut scene = Scene::default();
+        let black = scene.add_material(Material::Lambertian { albedo: Vec3::ZERO });
+        scene.add_hittable(Sphere {
+            center: Vec3::ZERO,
+            radius: 20.,
+            material: black,
+        });
+        let mirror = scene.add_material(Material::Metal {
+            albedo: Vec3::ONE,
+            fuzz: 0.,
+            coating: None,
+        });
+        scene.add_hittable(Quad {
+            corner: Vec3::new(-10., 0., -10.),
+            u: Vec3::new(0., 0., 20.),
+            v: Vec3::new(20., 0., 0.),
+            material: mirror,
+        });
+        let ceiling = scene.add_material(Material::Lambertian { albedo: Vec3::ONE });
+        scene.add_hittable(Quad {
+            corner: Vec3::new(-10., 3., -10.),
+            u: Vec3::new(20., 0., 0.),
+            v: Vec3::new(0., 0., 20.),
+            material: ceiling,
+        });
+        let light = scene.add_material(Material::Emissive { emission: Vec3::ONE });
+        let radius = 0.25;
+        scene.add_hittable(Sphere {
+            center: Vec3::Y,
+            radius,
+            material: light,
+        });
+
+        let mut photons = PhotonMapper::new(400_000, 0.3);
+        photons.begin_frame(&scene, 0, 0.);
+
+        // the mirror shows the light as if it were as far below the mirror
+        // as it is above, which is sqrt(20) away from here and seen at an
+        // angle, and the real light is out of the way
+        let point = Vec3::new(2., 3., 0.);
+        let mirrored = Vec3::NEG_Y;
+        let distance = point.distance(mirrored);
+        let cos = (point - mirrored).y / distance;
+        let expected = PI * (radius / distance).powi(2) * cos;
+        let irradiance = photons.map.irradiance(point, Vec3::NEG_Y);
+        let error = (irradiance - Vec3::splat(expected)).abs().max_element() / expected;
+        assert!(error < 0.1, "{irradiance} {expected}");
+    }
+}
@@ -1,14 +1,54 @@
+mod bvh;
 mod camera;
+mod film;
 mod geom;
+mod integrator;
+mod parallel;
+mod pixel_trace;
+mod plugin;
+mod priority;
+mod procedural;
 mod renderer;
+mod sampler;
 mod scene;
+mod sdf;
+mod snapshot;
+mod stats;
+mod texture;
 mod util;
 mod halton;
 mod hittable;
 mod material;
+mod mesh;
+#[cfg(any(test, feature = "test-fixtures"))]
+pub mod test_fixtures;
 
-pub use camera::Camera;
-pub use renderer::Renderer;
-pub use scene::{Scene, Sphere};
-pub use hittable::Hittable;
-pub use material::Material;
+pub use bvh::Aabb;
+pub use camera::{Camera, CameraBookmark, Eye, LensPreset, Projection, Stereo, Turntable};
+pub use film::Film;
+pub use geom::{Ray, Real, RealVec3, Transform};
+pub use integrator::{
+    AmbientOcclusion, DebugNormals, Integrator, Path, PathTracer, PhotonMapper,
+};
+pub use pixel_trace::{Bounce, BounceHit, PixelTrace};
+pub use plugin::{Bsdf, CustomBsdf, CustomShape, Scattered, Shape, SurfaceHit};
+pub use procedural::{ProceduralMaterial, ProceduralTexture, ShadingPoint};
+pub use renderer::{Renderer, RendererError, ThreadPolicy, ViewMode};
+pub use sampler::Sampler;
+pub use scene::{
+    presets, Cone, Csg, CsgOperation, Curve, CurveShape, Cylinder, Diagnostic, MaterialLibrary,
+    Node, NodeId, ObserverId, Quad, Scene, SceneBuilder, SceneChange, SceneHandle, Severity,
+    Sphere, Subject, Torus,
+};
+pub use sdf::{Sdf, SdfShape};
+pub use snapshot::Snapshot;
+pub use stats::RenderStats;
+pub use texture::{
+    ColorRamp, EnvironmentMap, HeightMap, Noise, NoiseKind, NoisePattern, Texture,
+};
+pub use hittable::{FaceSide, Hit, Hittable};
+pub use material::{
+    Backface, GraphNode, Input, Material, MaterialGraph, MaterialHandle, MathOp, MixFactor,
+    NodeKind, ThinFilm,
+};
+pub use mesh::{Mesh, MeshGeometry};
@@ -1,10 +1,209 @@
-use glam::Vec3;
+use glam::{Vec2, Vec3};
+use rand::Rng;
 
-use crate::{geom::Ray, hittable::HitPayload, util::Vec3Ext};
+use crate::{
+    geom::{from_real_vec3, Ray},
+    hittable::{FaceSide, HitPayload},
+    plugin::CustomBsdf,
+    procedural::ProceduralMaterial,
+    texture::{HeightMap, Texture},
+    util::Vec3Ext,
+};
 
+mod graph;
+
+pub use graph::{GraphNode, Input, MaterialGraph, MathOp, NodeKind};
+
+/// Refers to a material in a [`Scene`](crate::Scene). Handles come from
+/// [`Scene::add_material`](crate::Scene::add_material), so they're always
+/// valid for the scene that made them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct MaterialHandle(pub(crate) usize);
+
+impl MaterialHandle {
+    /// The [`Material::Null`] every scene starts with.
+    pub const NULL: Self = Self(0);
+
+    /// The position of the material in [`Scene::materials`](crate::Scene::materials).
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Material {
     Null,
-    Lambertian { albedo: Vec3 }
+    Lambertian { albedo: Vec3 },
+    /// Diffuse like [`Material::Lambertian`], with the albedo looked up
+    /// from a texture.
+    Textured { texture: Texture },
+    /// Gives off light, and doesn't reflect any.
+    Emissive { emission: Vec3 },
+    /// Reflects like a mirror, blurred by `fuzz` from 0 (sharp) to 1.
+    Metal {
+        albedo: Vec3,
+        fuzz: f32,
+        /// An optional film over the metal, for iridescent reflections.
+        #[cfg_attr(feature = "serde", serde(default))]
+        coating: Option<ThinFilm>,
+    },
+    /// Another material, with its normals tilted by a height map so the
+    /// surface looks rough or embossed without changing its shape.
+    Bump {
+        material: Box<Material>,
+        heights: HeightMap,
+        /// How much the surface tilts. At 1, a full step in height between
+        /// neighboring texels tilts it by 45 degrees.
+        strength: f32,
+    },
+    /// Another material on the front of the surface, with a choice of what
+    /// the back does. Every other material shades both sides alike.
+    Sided {
+        front: Box<Material>,
+        back: Backface,
+    },
+    /// Two materials blended together, such as a surface that's partly
+    /// metal and partly paint. Each bounce scatters off one or the other,
+    /// chosen at random by how much of each there is.
+    Mix {
+        a: Box<Material>,
+        b: Box<Material>,
+        factor: MixFactor,
+    },
+    /// Worked out at each hit from a graph of nodes, so textures and math
+    /// can drive any of a BSDF's inputs.
+    Graph { graph: MaterialGraph },
+    /// Picked at each hit by a closure, registered with
+    /// [`ProceduralMaterial::register`].
+    Procedural { shader: ProceduralMaterial },
+    /// A BSDF from outside the crate.
+    Custom { bsdf: CustomBsdf },
+}
+
+/// How much of [`Material::Mix`]'s `b` there is, from 0 for all `a` to 1
+/// for all `b`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MixFactor {
+    /// The same everywhere.
+    Constant(f32),
+    /// The average of the texture's channels, so a noise texture can
+    /// scatter patches of one material over the other.
+    Texture(Texture),
+}
+
+impl MixFactor {
+    /// The factor at a point on a surface, given its surface coordinates
+    /// and world position, clamped from 0 to 1.
+    pub fn at(&self, uv: Vec2, position: Vec3) -> f32 {
+        let factor = match self {
+            MixFactor::Constant(factor) => *factor,
+            MixFactor::Texture(texture) => texture.color(uv, position).dot(Vec3::ONE) / 3.,
+        };
+        factor.clamp(0., 1.)
+    }
+}
+
+/// What the back of a surface does, for [`Material::Sided`].
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backface {
+    /// Shaded the same as the front.
+    Shaded,
+    /// Invisible, so rays carry on through to whatever is behind it. Only
+    /// shading sees through culled faces, and
+    /// [`Scene::raycast`](crate::Scene::raycast) still hits them.
+    Culled,
+    /// Shaded with a material of its own.
+    Material(Box<Material>),
+}
+
+/// A transparent film a few hundred nanometers thick, like soap or oil.
+/// Light reflecting off the top and bottom of the film interferes, tinting
+/// reflections with colors that shift with the viewing angle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ThinFilm {
+    /// In nanometers. Colors are strongest from about 200 to 1000.
+    pub thickness: f32,
+    /// The film's index of refraction. Soap water is about 1.33, and oil
+    /// about 1.5.
+    pub ior: f32,
+}
+
+impl ThinFilm {
+    /// Wavelengths in nanometers that stand in for the red, green, and blue
+    /// channels.
+    const WAVELENGTHS: Vec3 = Vec3::new(650., 510., 475.);
+
+    /// How much light reflects off the film over a surface reflecting
+    /// `base` of the light, for each channel. `cos_incident` is the cosine
+    /// of the angle between the incoming light and the normal.
+    ///
+    /// Uses the Airy formula for a single film, averaging the two
+    /// polarizations rather than tracking them. The surface below is
+    /// treated as a perfect conductor scaled by `base`, flipping the phase
+    /// of the light it reflects.
+    pub fn reflectance(&self, cos_incident: f32, base: Vec3) -> Vec3 {
+        let cos_i = cos_incident.clamp(0., 1.);
+        let sin_t = (1. - cos_i * cos_i).sqrt() / self.ior;
+        let cos_t = (1. - sin_t * sin_t).max(0.).sqrt();
+
+        // Fresnel amplitudes going from air into the film
+        let r_s = (cos_i - self.ior * cos_t) / (cos_i + self.ior * cos_t);
+        let r_p = (self.ior * cos_i - cos_t) / (self.ior * cos_i + cos_t);
+
+        // phase difference between the two reflections
+        let phase =
+            4. * std::f32::consts::PI * self.ior * self.thickness * cos_t / Self::WAVELENGTHS;
+        let airy = |r_film: f32, r_base: f32, phase: f32| {
+            let interference = 2. * r_film * r_base * phase.cos();
+            (r_film * r_film + r_base * r_base + interference)
+                / (1. + r_film * r_film * r_base * r_base + interference)
+        };
+
+        // Signs follow the same convention as `r_s` and `r_p`.
+        let r_base = base.clamp(Vec3::ZERO, Vec3::ONE).to_array().map(f32::sqrt);
+        let mut reflectance = [0.; 3];
+        for (channel, out) in reflectance.iter_mut().enumerate() {
+            let phase = phase[channel];
+            let s = airy(r_s, -r_base[channel], phase);
+            let p = airy(r_p, r_base[channel], phase);
+            *out = (s + p) / 2.;
+        }
+        reflectance.into()
+    }
+}
+
+impl Material {
+    /// Used in place of a material that doesn't exist, in a loud magenta
+    /// so the mistake is easy to spot.
+    pub const FALLBACK: Material = Material::Emissive {
+        emission: Vec3::new(1., 0., 1.),
+    };
+
+    /// The material that shades `side` of the surface, or `None` if that
+    /// side is culled.
+    pub fn facing(&self, side: FaceSide) -> Option<&Material> {
+        match self {
+            Material::Sided { front, back } => match (side, back) {
+                (FaceSide::Front, _) | (FaceSide::Back, Backface::Shaded) => front.facing(side),
+                (FaceSide::Back, Backface::Material(back)) => back.facing(side),
+                (FaceSide::Back, Backface::Culled) => None,
+            },
+            // the bump still applies to whichever side shows through
+            Material::Bump { material, .. } => material.facing(side).map(|_| self),
+            // only culled if neither part shows, and otherwise each part
+            // culls itself when it's chosen
+            Material::Mix { a, b, .. } => {
+                (a.facing(side).is_some() || b.facing(side).is_some()).then_some(self)
+            }
+            _ => Some(self),
+        }
+    }
 }
 
 pub struct ScatterPayload {
@@ -13,28 +212,368 @@ pub struct ScatterPayload {
 }
 
 impl Material {
+    /// Where light hitting the surface goes next, if anywhere, drawing any
+    /// randomness from `rng`. The renderer passes in one generator for each
+    /// pixel, which is much cheaper than looking up the thread's on every
+    /// bounce.
     #[inline]
-    pub fn scatter(&self, hit: &HitPayload, _ray: &Ray) -> Option<ScatterPayload> {
+    pub fn scatter<R: Rng>(
+        &self,
+        hit: &HitPayload,
+        ray: &Ray,
+        rng: &mut R,
+    ) -> Option<ScatterPayload> {
         match self {
             Material::Null => None,
-            Material::Lambertian { albedo } => self.scatter_lambertian(hit, albedo)
+            Material::Lambertian { albedo } => self.scatter_lambertian(hit, albedo, rng),
+            Material::Textured { texture } => match hit {
+                &HitPayload::Hit {
+                    uv, world_position, ..
+                } => {
+                    let albedo = texture.color(uv, from_real_vec3(world_position));
+                    self.scatter_lambertian(hit, &albedo, rng)
+                }
+                HitPayload::Miss => None,
+            },
+            Material::Emissive { .. } => None,
+            Material::Metal {
+                albedo,
+                fuzz,
+                coating,
+            } => self.scatter_metal(hit, ray, *albedo, *fuzz, coating.as_ref(), rng),
+            Material::Bump {
+                material,
+                heights,
+                strength,
+            } => {
+                let mut scatter = material.scatter(&bump(hit, heights, *strength), ray, rng)?;
+                // leave from the real surface, which the bumped normal only
+                // pretends to be tilted from
+                if let HitPayload::Hit {
+                    world_normal,
+                    world_position,
+                    ..
+                } = hit
+                {
+                    let direction = scatter.ray.direction;
+                    scatter.ray = Ray::spawn(*world_position, *world_normal, direction);
+                }
+                Some(scatter)
+            }
+            Material::Sided { .. } => {
+                let &HitPayload::Hit {
+                    world_normal,
+                    world_position,
+                    side,
+                    ..
+                } = hit
+                else {
+                    return None;
+                };
+                match self.facing(side) {
+                    Some(material) => material.scatter(hit, ray, rng),
+                    // culled, so the ray carries on as if nothing was there
+                    None => Some(ScatterPayload {
+                        ray: Ray::spawn(world_position, world_normal, ray.direction),
+                        attenuation: Vec3::ONE,
+                    }),
+                }
+            }
+            Material::Graph { graph } => graph.evaluate(hit).scatter(hit, ray, rng),
+            Material::Procedural { shader } => shader.evaluate(hit).scatter(hit, ray, rng),
+            Material::Custom { bsdf } => bsdf
+                .scatter(hit, ray, rng)
+                .map(|(ray, attenuation)| ScatterPayload { ray, attenuation }),
+            Material::Mix { a, b, factor } => {
+                let &HitPayload::Hit {
+                    uv, world_position, ..
+                } = hit
+                else {
+                    return None;
+                };
+                if rng.gen::<f32>() < factor.at(uv, from_real_vec3(world_position)) {
+                    b.scatter(hit, ray, rng)
+                } else {
+                    a.scatter(hit, ray, rng)
+                }
+            }
         }
     }
 
+    /// Light given off by the surface at `hit`, independent of any incoming
+    /// light.
     #[inline]
-    fn scatter_lambertian(&self, hit: &HitPayload, albedo: &Vec3) -> Option<ScatterPayload> {
+    pub fn emitted(&self, hit: &HitPayload) -> Vec3 {
+        let &HitPayload::Hit {
+            side,
+            uv,
+            world_position,
+            ..
+        } = hit
+        else {
+            return Vec3::ZERO;
+        };
+        match self {
+            Material::Emissive { emission } => *emission,
+            Material::Bump { material, .. } => material.emitted(hit),
+            Material::Sided { .. } => self
+                .facing(side)
+                .map_or(Vec3::ZERO, |material| material.emitted(hit)),
+            Material::Mix { a, b, factor } => {
+                a.emitted(hit).lerp(b.emitted(hit), factor.at(uv, from_real_vec3(world_position)))
+            }
+            Material::Graph { graph } => graph.evaluate(hit).emitted(hit),
+            Material::Procedural { shader } => shader.evaluate(hit).emitted(hit),
+            Material::Custom { bsdf } => bsdf.emitted(hit),
+            Material::Null
+            | Material::Lambertian { .. }
+            | Material::Textured { .. }
+            | Material::Metal { .. } => Vec3::ZERO,
+        }
+    }
+
+    /// The albedo at `hit`, if the surface there is purely diffuse and
+    /// scatters light the same way whichever way it came from. `None` for
+    /// anything else, including lights and BSDFs from outside the crate.
+    pub fn diffuse_albedo(&self, hit: &HitPayload) -> Option<Vec3> {
+        let &HitPayload::Hit {
+            side,
+            uv,
+            world_position,
+            ..
+        } = hit
+        else {
+            return None;
+        };
+        match self {
+            Material::Lambertian { albedo } => Some(*albedo),
+            Material::Textured { texture } => {
+                Some(texture.color(uv, from_real_vec3(world_position)))
+            }
+            Material::Bump { material, .. } => material.diffuse_albedo(hit),
+            Material::Sided { .. } => self.facing(side)?.diffuse_albedo(hit),
+            // only diffuse if whichever part is chosen is
+            Material::Mix { a, b, factor } => {
+                let (a, b) = (a.diffuse_albedo(hit)?, b.diffuse_albedo(hit)?);
+                Some(a.lerp(b, factor.at(uv, from_real_vec3(world_position))))
+            }
+            Material::Graph { graph } => graph.evaluate(hit).diffuse_albedo(hit),
+            Material::Procedural { shader } => shader.evaluate(hit).diffuse_albedo(hit),
+            Material::Null
+            | Material::Emissive { .. }
+            | Material::Metal { .. }
+            | Material::Custom { .. } => None,
+        }
+    }
+
+    #[inline]
+    fn scatter_lambertian<R: Rng>(
+        &self,
+        hit: &HitPayload,
+        albedo: &Vec3,
+        rng: &mut R,
+    ) -> Option<ScatterPayload> {
         match hit {
             HitPayload::Hit { world_normal, world_position, .. } => {
-                let mut rng = rand::thread_rng();
-                let direction = (*world_normal + Vec3::random_unit(&mut rng)).normalize();
-                let scatter_ray = Ray {
-                    origin: *world_position + direction * 0.001,
-                    direction,
-                };
+                let direction = (*world_normal + Vec3::random_unit(rng)).normalize();
+                let scatter_ray = Ray::spawn(*world_position, *world_normal, direction);
                 Some(ScatterPayload { ray: scatter_ray, attenuation: *albedo })
             }
             HitPayload::Miss => None,
-            HitPayload::Inside => None,
         }
     }
-}
\ No newline at end of file
+
+    #[inline]
+    fn scatter_metal<R: Rng>(
+        &self,
+        hit: &HitPayload,
+        ray: &Ray,
+        albedo: Vec3,
+        fuzz: f32,
+        coating: Option<&ThinFilm>,
+        rng: &mut R,
+    ) -> Option<ScatterPayload> {
+        let HitPayload::Hit { world_normal, world_position, .. } = hit else {
+            return None;
+        };
+        let incoming = ray.direction.normalize();
+        let cos_incident = -incoming.dot(*world_normal);
+        let reflected = incoming + 2. * cos_incident * *world_normal;
+        let direction =
+            (reflected + fuzz.clamp(0., 1.) * Vec3::random_in_unit_sphere(rng)).normalize();
+        if direction.dot(*world_normal) <= 0. {
+            // fuzzed below the surface, so absorbed
+            return None;
+        }
+        let attenuation = match coating {
+            Some(film) => film.reflectance(cos_incident, albedo),
+            None => albedo,
+        };
+        Some(ScatterPayload {
+            ray: Ray::spawn(*world_position, *world_normal, direction),
+            attenuation,
+        })
+    }
+}
+
+/// `hit` with its normal tilted along the slope of `heights` at the hit's
+/// surface coordinates.
+fn bump(hit: &HitPayload, heights: &HeightMap, strength: f32) -> HitPayload {
+    let &HitPayload::Hit {
+        hit_distance,
+        world_normal,
+        world_position,
+        material,
+        side,
+        uv,
+        tangent,
+    } = hit
+    else {
+        return HitPayload::Miss;
+    };
+    let bitangent = world_normal.cross(tangent);
+    let slope = heights.gradient(uv) * strength;
+    let bumped = (world_normal - slope.x * tangent - slope.y * bitangent)
+        .try_normalize()
+        .unwrap_or(world_normal);
+    HitPayload::Hit {
+        hit_distance,
+        world_normal: bumped,
+        world_position,
+        material,
+        side,
+        uv,
+        tangent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bump, Backface, Material, MixFactor, ThinFilm};
+    use crate::{
+        geom::RealVec3,
+        hittable::{FaceSide, HitPayload},
+        texture::HeightMap,
+        MaterialHandle, Ray,
+    };
+    use float_eq::assert_float_eq;
+    use glam::{Vec2, Vec3};
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    /// Where a ray from the origin along +Z meets a surface at `z = 1`.
+    fn hit_from(side: FaceSide) -> HitPayload {
+        HitPayload::Hit {
+            hit_distance: 1.,
+            world_normal: Vec3::NEG_Z,
+            world_position: RealVec3::Z,
+            material: MaterialHandle::NULL,
+            side,
+            uv: Vec2::ZERO,
+            tangent: Vec3::X,
+        }
+    }
+
+    #[test]
+    fn culled_backfaces_let_rays_through() {
+        let material = Material::Sided {
+            front: Box::new(Material::Emissive { emission: Vec3::ONE }),
+            back: Backface::Culled,
+        };
+        assert!(material.facing(FaceSide::Front).is_some());
+        assert!(material.facing(FaceSide::Back).is_none());
+        assert_eq!(material.emitted(&hit_from(FaceSide::Front)), Vec3::ONE);
+        assert_eq!(material.emitted(&hit_from(FaceSide::Back)), Vec3::ZERO);
+
+        // a ray leaving the inside of a sphere hits its back
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let hit = hit_from(FaceSide::Back);
+        let scatter = material
+            .scatter(&hit, &ray, &mut SmallRng::seed_from_u64(0))
+            .unwrap();
+        assert_eq!(scatter.attenuation, Vec3::ONE);
+        assert_eq!(scatter.ray.direction, ray.direction);
+        assert!(scatter.ray.origin.z > 1.);
+    }
+
+    #[test]
+    fn mix_scatters_off_each_part_by_factor() {
+        let mix = Material::Mix {
+            a: Box::new(Material::Lambertian { albedo: Vec3::X }),
+            b: Box::new(Material::Emissive { emission: Vec3::ONE }),
+            factor: MixFactor::Constant(0.25),
+        };
+        let hit = hit_from(FaceSide::Front);
+        assert_eq!(mix.emitted(&hit), Vec3::splat(0.25));
+
+        let ray = Ray::new(Vec3::ZERO, Vec3::Z);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let scattered = (0..1000)
+            .filter(|_| mix.scatter(&hit, &ray, &mut rng).is_some())
+            .count();
+        // the emissive part absorbs everything
+        assert!((700..800).contains(&scattered), "{scattered}");
+    }
+
+    #[test]
+    fn mix_factor_is_clamped() {
+        let texture = MixFactor::Texture(crate::Texture::Solid(Vec3::new(0., 0.5, 1.)));
+        assert_eq!(texture.at(Vec2::ZERO, Vec3::ZERO), 0.5);
+        assert_eq!(MixFactor::Constant(2.).at(Vec2::ZERO, Vec3::ZERO), 1.);
+    }
+
+    #[test]
+    fn film_matching_air_is_invisible() {
+        let film = ThinFilm {
+            thickness: 400.,
+            ior: 1.,
+        };
+        let base = Vec3::new(0.9, 0.5, 0.1);
+        assert_float_eq!(
+            film.reflectance(0.7, base).to_array(),
+            base.to_array(),
+            abs <= [0.0001; 3]
+        );
+    }
+
+    #[test]
+    fn film_tints_reflections() {
+        let film = ThinFilm {
+            thickness: 400.,
+            ior: 1.33,
+        };
+        let base = Vec3::splat(0.5);
+        let head_on = film.reflectance(1., base);
+        let grazing = film.reflectance(0.3, base);
+        assert_ne!(head_on.x, head_on.z);
+        assert_ne!(head_on, grazing);
+        for channel in head_on.to_array().into_iter().chain(grazing.to_array()) {
+            assert!((0. ..=1.).contains(&channel), "{channel}");
+        }
+    }
+
+    #[test]
+    fn bumps_tilt_normals_downhill() {
+        let hit = HitPayload::Hit {
+            hit_distance: 1.,
+            world_normal: Vec3::Z,
+            world_position: RealVec3::ZERO,
+            material: MaterialHandle::NULL,
+            side: FaceSide::Front,
+            uv: Vec2::splat(0.5),
+            tangent: Vec3::X,
+        };
+        let rising = HeightMap::from_fn(8, 8, |uv| uv.x).unwrap();
+        let HitPayload::Hit { world_normal, .. } = bump(&hit, &rising, 1.) else {
+            panic!("bumping should keep the hit");
+        };
+        assert!(world_normal.is_normalized());
+        assert!(world_normal.x < 0., "{world_normal}");
+        assert_float_eq!(world_normal.y, 0., abs <= 0.0001);
+
+        let flat = HeightMap::new(1, 1, vec![0.5]).unwrap();
+        let HitPayload::Hit { world_normal, .. } = bump(&hit, &flat, 1.) else {
+            panic!("bumping should keep the hit");
+        };
+        assert_eq!(world_normal, Vec3::Z);
+    }
+}
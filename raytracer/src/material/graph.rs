@@ -0,0 +1,469 @@
+//! Materials built from a graph of nodes, where textures and math feed the
+//! inputs of a BSDF, like the shader editors of other renderers.
+
+use super::{Material, MixFactor};
+use crate::{geom::from_real_vec3, hittable::HitPayload, texture::Texture};
+use glam::{Vec2, Vec3};
+
+/// Nodes that work out a material at each hit, for [`Material::Graph`].
+/// Nodes refer to each other by their index in `nodes`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaterialGraph {
+    pub nodes: Vec<GraphNode>,
+    /// The node whose BSDF shades the surface.
+    pub output: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GraphNode {
+    pub kind: NodeKind,
+    /// Where the node is drawn in an editor. It doesn't change the shading.
+    pub position: Vec2,
+}
+
+/// One of a node's inputs, which is either a value of its own or the
+/// output of another node. Numbers are the average of the three channels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Input {
+    pub value: Vec3,
+    /// The node whose output is used instead of `value`.
+    pub link: Option<usize>,
+}
+
+/// What a node does. The ones up to [`NodeKind::MixColor`] output colors,
+/// and the rest output BSDFs.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeKind {
+    /// A color, or a number in all three channels.
+    Value(Vec3),
+    Texture(Texture),
+    /// Where the hit is, in world space.
+    Position,
+    /// The surface normal at the hit.
+    Normal,
+    /// The hit's surface coordinates, in the first two channels.
+    Uv,
+    /// Each channel of `a` and `b` combined by `op`.
+    Math {
+        op: MathOp,
+        a: Input,
+        b: Input,
+    },
+    /// From `a` at a factor of 0 to `b` at 1.
+    MixColor {
+        a: Input,
+        b: Input,
+        factor: Input,
+    },
+    /// Like [`Material::Lambertian`].
+    Diffuse {
+        color: Input,
+    },
+    /// Like [`Material::Metal`], without a coating.
+    Metal {
+        color: Input,
+        fuzz: Input,
+    },
+    /// Like [`Material::Emissive`].
+    Emission {
+        color: Input,
+    },
+    /// Like [`Material::Mix`], with BSDFs linked to `a` and `b`.
+    MixBsdf {
+        a: Input,
+        b: Input,
+        factor: Input,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// Division by zero gives zero.
+    Divide,
+    Minimum,
+    Maximum,
+}
+
+impl MathOp {
+    pub const ALL: [MathOp; 6] = [
+        MathOp::Add,
+        MathOp::Subtract,
+        MathOp::Multiply,
+        MathOp::Divide,
+        MathOp::Minimum,
+        MathOp::Maximum,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MathOp::Add => "Add",
+            MathOp::Subtract => "Subtract",
+            MathOp::Multiply => "Multiply",
+            MathOp::Divide => "Divide",
+            MathOp::Minimum => "Minimum",
+            MathOp::Maximum => "Maximum",
+        }
+    }
+
+    fn apply(&self, a: Vec3, b: Vec3) -> Vec3 {
+        match self {
+            MathOp::Add => a + b,
+            MathOp::Subtract => a - b,
+            MathOp::Multiply => a * b,
+            MathOp::Divide => Vec3::select(b.cmpeq(Vec3::ZERO), Vec3::ZERO, a / b),
+            MathOp::Minimum => a.min(b),
+            MathOp::Maximum => a.max(b),
+        }
+    }
+}
+
+impl Input {
+    pub fn constant(value: Vec3) -> Self {
+        Self { value, link: None }
+    }
+
+    pub fn linked(node: usize) -> Self {
+        Self {
+            value: Vec3::ZERO,
+            link: Some(node),
+        }
+    }
+}
+
+impl NodeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeKind::Value(_) => "Value",
+            NodeKind::Texture(_) => "Texture",
+            NodeKind::Position => "Position",
+            NodeKind::Normal => "Normal",
+            NodeKind::Uv => "UV",
+            NodeKind::Math { .. } => "Math",
+            NodeKind::MixColor { .. } => "Mix Color",
+            NodeKind::Diffuse { .. } => "Diffuse BSDF",
+            NodeKind::Metal { .. } => "Metal BSDF",
+            NodeKind::Emission { .. } => "Emission",
+            NodeKind::MixBsdf { .. } => "Mix BSDF",
+        }
+    }
+
+    /// Whether the node outputs a BSDF, rather than a color.
+    pub fn is_bsdf(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::Diffuse { .. }
+                | NodeKind::Metal { .. }
+                | NodeKind::Emission { .. }
+                | NodeKind::MixBsdf { .. }
+        )
+    }
+
+    /// The node's inputs, with their names.
+    pub fn inputs(&self) -> Vec<(&'static str, &Input)> {
+        match self {
+            NodeKind::Value(_)
+            | NodeKind::Texture(_)
+            | NodeKind::Position
+            | NodeKind::Normal
+            | NodeKind::Uv => vec![],
+            NodeKind::Math { a, b, .. } => vec![("A", a), ("B", b)],
+            NodeKind::MixColor { a, b, factor } | NodeKind::MixBsdf { a, b, factor } => {
+                vec![("A", a), ("B", b), ("Factor", factor)]
+            }
+            NodeKind::Diffuse { color } | NodeKind::Emission { color } => vec![("Color", color)],
+            NodeKind::Metal { color, fuzz } => vec![("Color", color), ("Fuzz", fuzz)],
+        }
+    }
+
+    /// Like [`NodeKind::inputs`], to edit them.
+    pub fn inputs_mut(&mut self) -> Vec<(&'static str, &mut Input)> {
+        match self {
+            NodeKind::Value(_)
+            | NodeKind::Texture(_)
+            | NodeKind::Position
+            | NodeKind::Normal
+            | NodeKind::Uv => vec![],
+            NodeKind::Math { a, b, .. } => vec![("A", a), ("B", b)],
+            NodeKind::MixColor { a, b, factor } | NodeKind::MixBsdf { a, b, factor } => {
+                vec![("A", a), ("B", b), ("Factor", factor)]
+            }
+            NodeKind::Diffuse { color } | NodeKind::Emission { color } => vec![("Color", color)],
+            NodeKind::Metal { color, fuzz } => vec![("Color", color), ("Fuzz", fuzz)],
+        }
+    }
+}
+
+/// What a graph can look up about a hit.
+struct Surface {
+    uv: Vec2,
+    position: Vec3,
+    normal: Vec3,
+}
+
+impl Default for MaterialGraph {
+    /// A gray diffuse BSDF.
+    fn default() -> Self {
+        Self {
+            nodes: vec![
+                GraphNode {
+                    kind: NodeKind::Value(Vec3::splat(0.8)),
+                    position: Vec2::ZERO,
+                },
+                GraphNode {
+                    kind: NodeKind::Diffuse {
+                        color: Input::linked(0),
+                    },
+                    position: Vec2::new(200., 0.),
+                },
+            ],
+            output: 1,
+        }
+    }
+}
+
+impl MaterialGraph {
+    /// The plain material the graph works out to at `hit`, to shade it
+    /// with. Links that loop back on themselves can't be worked out, so
+    /// they give [`Material::FALLBACK`] for BSDFs and black for colors.
+    pub fn evaluate(&self, hit: &HitPayload) -> Material {
+        let &HitPayload::Hit {
+            uv,
+            world_position,
+            world_normal,
+            ..
+        } = hit
+        else {
+            return Material::Null;
+        };
+        let surface = Surface {
+            uv,
+            position: from_real_vec3(world_position),
+            normal: world_normal,
+        };
+        self.node_bsdf(self.output, &surface, 0)
+    }
+
+    /// Remove the node at `idx`, unlinking inputs that used it and moving
+    /// links to the nodes after it along to their new indices.
+    pub fn remove_node(&mut self, idx: usize) {
+        if idx >= self.nodes.len() {
+            return;
+        }
+        self.nodes.remove(idx);
+        for node in &mut self.nodes {
+            for (_, input) in node.kind.inputs_mut() {
+                input.link = match input.link {
+                    Some(link) if link == idx => None,
+                    Some(link) if link > idx => Some(link - 1),
+                    link => link,
+                };
+            }
+        }
+        if self.output > idx {
+            self.output -= 1;
+        }
+    }
+
+    /// Any path through more links than there are nodes must go around a
+    /// loop.
+    fn is_looping(&self, depth: usize) -> bool {
+        depth > self.nodes.len()
+    }
+
+    fn value(&self, input: &Input, surface: &Surface, depth: usize) -> Vec3 {
+        match input.link {
+            Some(idx) => self.node_value(idx, surface, depth + 1),
+            None => input.value,
+        }
+    }
+
+    fn number(&self, input: &Input, surface: &Surface, depth: usize) -> f32 {
+        self.value(input, surface, depth).dot(Vec3::ONE) / 3.
+    }
+
+    fn node_value(&self, idx: usize, surface: &Surface, depth: usize) -> Vec3 {
+        let Some(node) = self.nodes.get(idx) else {
+            return Vec3::ZERO;
+        };
+        if self.is_looping(depth) {
+            return Vec3::ZERO;
+        }
+        match &node.kind {
+            NodeKind::Value(value) => *value,
+            NodeKind::Texture(texture) => texture.color(surface.uv, surface.position),
+            NodeKind::Position => surface.position,
+            NodeKind::Normal => surface.normal,
+            NodeKind::Uv => surface.uv.extend(0.),
+            NodeKind::Math { op, a, b } => {
+                op.apply(self.value(a, surface, depth), self.value(b, surface, depth))
+            }
+            NodeKind::MixColor { a, b, factor } => {
+                let factor = self.number(factor, surface, depth).clamp(0., 1.);
+                self.value(a, surface, depth)
+                    .lerp(self.value(b, surface, depth), factor)
+            }
+            // BSDFs aren't colors
+            _ => Vec3::ZERO,
+        }
+    }
+
+    fn input_bsdf(&self, input: &Input, surface: &Surface, depth: usize) -> Material {
+        match input.link {
+            Some(idx) => self.node_bsdf(idx, surface, depth + 1),
+            None => Material::Null,
+        }
+    }
+
+    fn node_bsdf(&self, idx: usize, surface: &Surface, depth: usize) -> Material {
+        let Some(node) = self.nodes.get(idx) else {
+            return Material::FALLBACK;
+        };
+        if self.is_looping(depth) {
+            return Material::FALLBACK;
+        }
+        match &node.kind {
+            NodeKind::Diffuse { color } => Material::Lambertian {
+                albedo: self.value(color, surface, depth),
+            },
+            NodeKind::Metal { color, fuzz } => Material::Metal {
+                albedo: self.value(color, surface, depth),
+                fuzz: self.number(fuzz, surface, depth),
+                coating: None,
+            },
+            NodeKind::Emission { color } => Material::Emissive {
+                emission: self.value(color, surface, depth),
+            },
+            NodeKind::MixBsdf { a, b, factor } => Material::Mix {
+                a: Box::new(self.input_bsdf(a, surface, depth)),
+                b: Box::new(self.input_bsdf(b, surface, depth)),
+                factor: MixFactor::Constant(self.number(factor, surface, depth)),
+            },
+            // colors used as BSDFs glow with that color
+            _ => Material::Emissive {
+                emission: self.node_value(idx, surface, depth),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GraphNode, Input, MaterialGraph, MathOp, NodeKind};
+    use crate::{
+        geom::real_vec3,
+        hittable::{FaceSide, HitPayload},
+        Material, MaterialHandle, Texture,
+    };
+    use glam::{Vec2, Vec3};
+
+    fn node(kind: NodeKind) -> GraphNode {
+        GraphNode {
+            kind,
+            position: Vec2::ZERO,
+        }
+    }
+
+    fn hit_at(position: Vec3) -> HitPayload {
+        HitPayload::Hit {
+            hit_distance: 1.,
+            world_normal: Vec3::Y,
+            world_position: real_vec3(position),
+            material: MaterialHandle::NULL,
+            side: FaceSide::Front,
+            uv: Vec2::ZERO,
+            tangent: Vec3::X,
+        }
+    }
+
+    fn albedo(material: Material) -> Vec3 {
+        match material {
+            Material::Lambertian { albedo } => albedo,
+            _ => panic!("expected a diffuse material"),
+        }
+    }
+
+    #[test]
+    fn colors_flow_into_the_bsdf() {
+        let graph = MaterialGraph {
+            nodes: vec![
+                node(NodeKind::Texture(Texture::Solid(Vec3::new(1., 0.5, 0.)))),
+                node(NodeKind::Position),
+                node(NodeKind::Math {
+                    op: MathOp::Multiply,
+                    a: Input::linked(0),
+                    b: Input::linked(1),
+                }),
+                node(NodeKind::Diffuse {
+                    color: Input::linked(2),
+                }),
+            ],
+            output: 3,
+        };
+        let hit = hit_at(Vec3::new(0.5, 1., 2.));
+        assert_eq!(albedo(graph.evaluate(&hit)), Vec3::new(0.5, 0.5, 0.));
+    }
+
+    #[test]
+    fn loops_fall_back() {
+        let graph = MaterialGraph {
+            nodes: vec![
+                node(NodeKind::Math {
+                    op: MathOp::Add,
+                    a: Input::linked(0),
+                    b: Input::constant(Vec3::ONE),
+                }),
+                node(NodeKind::Diffuse {
+                    color: Input::linked(0),
+                }),
+                node(NodeKind::MixBsdf {
+                    a: Input::linked(2),
+                    b: Input::linked(1),
+                    factor: Input::constant(Vec3::splat(0.5)),
+                }),
+            ],
+            output: 1,
+        };
+        let hit = hit_at(Vec3::ZERO);
+        // the loop adds up to a value rather than going on forever
+        assert!(albedo(graph.evaluate(&hit)).is_finite());
+
+        let graph = MaterialGraph { output: 2, ..graph };
+        let Material::Mix { a, .. } = graph.evaluate(&hit) else {
+            panic!("expected a mix");
+        };
+        assert!(matches!(*a, Material::Mix { .. }));
+    }
+
+    #[test]
+    fn removing_nodes_fixes_links() {
+        let mut graph = MaterialGraph {
+            nodes: vec![
+                node(NodeKind::Value(Vec3::ONE)),
+                node(NodeKind::Value(Vec3::X)),
+                node(NodeKind::MixColor {
+                    a: Input::linked(0),
+                    b: Input::linked(1),
+                    factor: Input::constant(Vec3::ONE),
+                }),
+                node(NodeKind::Diffuse {
+                    color: Input::linked(2),
+                }),
+            ],
+            output: 3,
+        };
+        graph.remove_node(0);
+        assert_eq!(graph.output, 2);
+        let inputs = graph.nodes[1].kind.inputs();
+        assert_eq!(inputs[0].1.link, None);
+        assert_eq!(inputs[1].1.link, Some(0));
+        assert_eq!(albedo(graph.evaluate(&hit_at(Vec3::ZERO))), Vec3::X);
+    }
+}
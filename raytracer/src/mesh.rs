@@ -0,0 +1,209 @@
+//! Triangle meshes. The triangles live in a [`MeshGeometry`] with its own
+//! BVH, which any number of [`Mesh`] instances can share, so moving an
+//! instance never touches the triangles.
+
+use crate::{
+    bvh::{Aabb, Bvh},
+    MaterialHandle, Transform,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use glam::Vec3;
+use std::sync::{Arc, OnceLock};
+
+/// Triangles, and the BVH for finding which of them a ray hits.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeshGeometry {
+    positions: Vec<Vec3>,
+    /// Indexes into `positions`. Triangles face the side their corners go
+    /// counterclockwise around.
+    triangles: Vec<[u32; 3]>,
+    /// Built on demand.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    bvh: OnceLock<Bvh>,
+}
+
+impl MeshGeometry {
+    /// Panics if a triangle refers to a position that doesn't exist.
+    pub fn new(positions: Vec<Vec3>, triangles: Vec<[u32; 3]>) -> Self {
+        for triangle in &triangles {
+            assert!(
+                triangle.iter().all(|&idx| (idx as usize) < positions.len()),
+                "triangle {triangle:?} is out of range, the mesh has {} positions",
+                positions.len(),
+            );
+        }
+        Self {
+            positions,
+            triangles,
+            bvh: OnceLock::new(),
+        }
+    }
+
+    /// Read the vertices and faces of a Wavefront OBJ file. Faces with more
+    /// than three corners are split into a fan of triangles. Everything
+    /// else, like normals, texture coordinates and materials, is ignored.
+    pub fn from_obj(source: &str) -> Result<Self> {
+        let mut positions = Vec::new();
+        let mut triangles = Vec::new();
+        for (idx, line) in source.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let parsed = match words.next() {
+                Some("v") => parse_obj_vertex(words).map(|position| positions.push(position)),
+                Some("f") => parse_obj_face(words, positions.len()).map(|corners| {
+                    let fan = corners.windows(2).skip(1);
+                    triangles.extend(fan.map(|pair| [corners[0], pair[0], pair[1]]));
+                }),
+                _ => Ok(()),
+            };
+            parsed.with_context(|| format!("line {}", idx + 1))?;
+        }
+        if triangles.is_empty() {
+            bail!("there are no faces");
+        }
+        Ok(Self::new(positions, triangles))
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    /// The corners of a triangle.
+    pub fn triangle(&self, idx: usize) -> [Vec3; 3] {
+        self.triangles[idx].map(|corner| self.positions[corner as usize])
+    }
+
+    pub(crate) fn bvh(&self) -> &Bvh {
+        self.bvh.get_or_init(|| {
+            let bounds: Vec<_> = (0..self.triangles.len())
+                .map(|idx| Aabb::from_points(self.triangle(idx)))
+                .collect();
+            Bvh::build(&bounds)
+        })
+    }
+}
+
+fn parse_obj_vertex<'a>(words: impl Iterator<Item = &'a str>) -> Result<Vec3> {
+    // an optional fourth weight is left out
+    let coords = words
+        .take(3)
+        .map(|word| {
+            word.parse()
+                .map_err(|_| anyhow!("expected a number, found {word:?}"))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    match coords[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => bail!("expected three coordinates"),
+    }
+}
+
+/// The positions a face's corners refer to, given how many positions have
+/// been read so far. Corners can be `v`, `v/vt`, `v//vn` or `v/vt/vn`, where
+/// `v` counts from 1, or back from the latest position if it's negative.
+fn parse_obj_face<'a>(words: impl Iterator<Item = &'a str>, positions: usize) -> Result<Vec<u32>> {
+    let corners = words
+        .map(|word| {
+            let position = word.split('/').next().unwrap_or_default();
+            let idx: i64 = position
+                .parse()
+                .map_err(|_| anyhow!("expected a vertex index, found {word:?}"))?;
+            let resolved = if idx < 0 { positions as i64 + idx } else { idx - 1 };
+            if !(0..positions as i64).contains(&resolved) {
+                bail!("vertex {idx} doesn't exist, there are {positions} so far");
+            }
+            Ok(resolved as u32)
+        })
+        .collect::<Result<Vec<u32>>>()?;
+    if corners.len() < 3 {
+        bail!("a face needs at least three corners");
+    }
+    Ok(corners)
+}
+
+/// A placed copy of some [`MeshGeometry`].
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mesh {
+    pub geometry: Arc<MeshGeometry>,
+    pub transform: Transform,
+    pub material: MaterialHandle,
+}
+
+impl Mesh {
+    pub fn new(geometry: Arc<MeshGeometry>, material: MaterialHandle) -> Self {
+        Self {
+            geometry,
+            transform: Transform::IDENTITY,
+            material,
+        }
+    }
+
+    /// A box from `-half_size` to `half_size`, with its faces pointing out.
+    pub fn cuboid(half_size: Vec3, material: MaterialHandle) -> Self {
+        let positions = (0..8)
+            .map(|corner| {
+                let sign = |bit| if corner & bit == 0 { -1. } else { 1. };
+                Vec3::new(sign(1), sign(2), sign(4)) * half_size
+            })
+            .collect();
+        // each face as two triangles, counterclockwise from outside
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let triangles = faces
+            .iter()
+            .flat_map(|[a, b, c, d]| [[*a, *b, *c], [*a, *c, *d]])
+            .collect();
+        Self::new(Arc::new(MeshGeometry::new(positions, triangles)), material)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MeshGeometry;
+    use glam::Vec3;
+
+    #[test]
+    fn reads_obj_files() {
+        let geometry = MeshGeometry::from_obj(
+            "
+            # a square, and a triangle using relative indices
+            o square
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0 1.0
+            vn 0 0 1
+            f 1//1 2//1 3//1 4//1
+            v 2 0 0
+            f -1 -5/1 -4
+            ",
+        )
+        .unwrap();
+        assert_eq!(geometry.positions().len(), 5);
+        assert_eq!(geometry.positions()[3], Vec3::Y);
+        assert_eq!(geometry.triangles(), [[0, 1, 2], [0, 2, 3], [4, 0, 1]]);
+    }
+
+    #[test]
+    fn obj_mistakes_have_line_numbers() {
+        let error = |source: &str| format!("{:#}", MeshGeometry::from_obj(source).unwrap_err());
+        assert_eq!(
+            error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4"),
+            "line 4: vertex 4 doesn't exist, there are 3 so far"
+        );
+        assert_eq!(error("v 0 zero 0"), "line 1: expected a number, found \"zero\"");
+        assert_eq!(error("v 0 0 0\nf 1 1"), "line 2: a face needs at least three corners");
+        assert_eq!(error("v 0 0 0"), "there are no faces");
+    }
+}
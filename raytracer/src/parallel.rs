@@ -0,0 +1,136 @@
+//! The subset of rayon the renderer uses. Without the `parallel` feature
+//! this is a single threaded stand-in with the same shape, for targets like
+//! wasm32-unknown-unknown that can't spawn threads.
+
+#[cfg(feature = "parallel")]
+pub(crate) use rayon::{prelude::*, ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+
+#[cfg(not(feature = "parallel"))]
+pub(crate) use serial::*;
+
+#[cfg(not(feature = "parallel"))]
+mod serial {
+    use std::{fmt, iter::Zip, ops::Range, slice};
+
+    /// Runs everything on the calling thread.
+    pub(crate) struct ThreadPool;
+
+    impl ThreadPool {
+        pub fn install<R>(&self, op: impl FnOnce() -> R) -> R {
+            op()
+        }
+
+        pub fn current_num_threads(&self) -> usize {
+            1
+        }
+    }
+
+    pub(crate) struct ThreadPoolBuilder;
+
+    impl ThreadPoolBuilder {
+        pub fn new() -> Self {
+            Self
+        }
+
+        pub fn num_threads(self, _num_threads: usize) -> Self {
+            self
+        }
+
+        /// Never called, since the pool has no threads of its own.
+        pub fn start_handler<H: Fn(usize)>(self, _handler: H) -> Self {
+            self
+        }
+
+        pub fn build(self) -> Result<ThreadPool, ThreadPoolBuildError> {
+            Ok(ThreadPool)
+        }
+    }
+
+    /// Starting no threads can't fail.
+    #[derive(Debug)]
+    pub enum ThreadPoolBuildError {}
+
+    impl fmt::Display for ThreadPoolBuildError {
+        fn fmt(&self, _f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match *self {}
+        }
+    }
+
+    impl std::error::Error for ThreadPoolBuildError {}
+
+    /// An ordinary iterator, wrapped so that it has rayon's method
+    /// signatures instead of [`Iterator`]'s.
+    pub(crate) struct Serial<I>(I);
+
+    impl<I: Iterator> Serial<I> {
+        pub fn map<U, F: FnMut(I::Item) -> U>(self, f: F) -> Serial<std::iter::Map<I, F>> {
+            Serial(self.0.map(f))
+        }
+
+        pub fn for_each<F: FnMut(I::Item)>(self, f: F) {
+            self.0.for_each(f)
+        }
+
+        pub fn collect<C: FromIterator<I::Item>>(self) -> C {
+            self.0.collect()
+        }
+
+        pub fn reduce<ID, OP>(self, identity: ID, op: OP) -> I::Item
+        where
+            ID: Fn() -> I::Item,
+            OP: Fn(I::Item, I::Item) -> I::Item,
+        {
+            self.0.fold(identity(), op)
+        }
+    }
+
+    pub(crate) trait IntoParallelIterator {
+        type Iter;
+        fn into_par_iter(self) -> Self::Iter;
+    }
+
+    impl<A: IntoIterator, B: IntoIterator> IntoParallelIterator for (A, B) {
+        type Iter = Serial<Zip<A::IntoIter, B::IntoIter>>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            Serial(self.0.into_iter().zip(self.1))
+        }
+    }
+
+    type Unzip3<A, B, C> = fn(((A, B), C)) -> (A, B, C);
+
+    impl<A: IntoIterator, B: IntoIterator, C: IntoIterator> IntoParallelIterator for (A, B, C) {
+        type Iter = Serial<
+            std::iter::Map<
+                Zip<Zip<A::IntoIter, B::IntoIter>, C::IntoIter>,
+                Unzip3<A::Item, B::Item, C::Item>,
+            >,
+        >;
+
+        fn into_par_iter(self) -> Self::Iter {
+            let unzip: Unzip3<A::Item, B::Item, C::Item> = |((a, b), c)| (a, b, c);
+            Serial(self.0.into_iter().zip(self.1).zip(self.2).map(unzip))
+        }
+    }
+
+    impl IntoParallelIterator for Range<usize> {
+        type Iter = Serial<Range<usize>>;
+
+        fn into_par_iter(self) -> Self::Iter {
+            Serial(self)
+        }
+    }
+
+    pub(crate) trait IntoParallelRefIterator<'a> {
+        type Iter;
+        fn par_iter(&'a self) -> Self::Iter;
+    }
+
+    impl<'a, T: 'a> IntoParallelRefIterator<'a> for [T] {
+        type Iter = Serial<slice::Iter<'a, T>>;
+
+        fn par_iter(&'a self) -> Self::Iter {
+            Serial(self.iter())
+        }
+    }
+}
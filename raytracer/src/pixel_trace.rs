@@ -0,0 +1,135 @@
+//! A record of everything that happened along one path, for working out
+//! where a pixel's color came from, such as a NaN that shouldn't be there.
+
+use crate::{geom::from_real_vec3, FaceSide, MaterialHandle, Ray};
+use glam::{Vec3, Vec4};
+use std::fmt;
+
+/// One path through a pixel, traced by [`Renderer::trace_pixel`](crate::Renderer::trace_pixel).
+#[derive(Clone, Debug)]
+pub struct PixelTrace {
+    pub x: u32,
+    pub y: u32,
+    /// What the path's random numbers were drawn from.
+    pub seed: u64,
+    /// Each ray along the path, starting with the camera ray.
+    pub bounces: Vec<Bounce>,
+    /// The sample the path added to the pixel, as premultiplied RGBA.
+    pub color: Vec4,
+}
+
+/// A ray along a path, and what it found.
+#[derive(Clone, Debug)]
+pub struct Bounce {
+    pub ray: Ray,
+    pub hit: BounceHit,
+    /// Light given off by the surface that was hit.
+    pub emitted: Vec3,
+    /// How much of the light coming back along the next ray is reflected
+    /// back along this one, or `None` if the path ends here.
+    pub attenuation: Option<Vec3>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BounceHit {
+    Surface {
+        position: Vec3,
+        normal: Vec3,
+        material: MaterialHandle,
+        side: FaceSide,
+    },
+    /// Missed everything, and saw the sky.
+    Sky,
+}
+
+impl PixelTrace {
+    /// The path as line segments from bounce to bounce, for drawing. Rays
+    /// that see the sky go on forever, so they're cut off at `sky_length`.
+    pub fn segments(&self, sky_length: f32) -> Vec<[Vec3; 2]> {
+        self.bounces
+            .iter()
+            .map(|bounce| {
+                let origin = from_real_vec3(bounce.ray.origin);
+                let end = match bounce.hit {
+                    BounceHit::Surface { position, .. } => position,
+                    BounceHit::Sky => origin + bounce.ray.direction.normalize() * sky_length,
+                };
+                [origin, end]
+            })
+            .collect()
+    }
+
+    /// Whether any number along the path is NaN or infinite.
+    pub fn has_invalid_values(&self) -> bool {
+        !self.color.is_finite() || self.bounces.iter().any(|bounce| !bounce.is_finite())
+    }
+}
+
+impl Bounce {
+    fn is_finite(&self) -> bool {
+        let hit_is_finite = match self.hit {
+            BounceHit::Surface {
+                position, normal, ..
+            } => position.is_finite() && normal.is_finite(),
+            BounceHit::Sky => true,
+        };
+        self.ray.origin.is_finite()
+            && self.ray.direction.is_finite()
+            && hit_is_finite
+            && self.emitted.is_finite()
+            && self
+                .attenuation
+                .is_none_or(|attenuation| attenuation.is_finite())
+    }
+}
+
+impl fmt::Display for PixelTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pixel ({}, {}), seed {:#x}", self.x, self.y, self.seed)?;
+        for (idx, bounce) in self.bounces.iter().enumerate() {
+            let flag = if bounce.is_finite() {
+                ""
+            } else {
+                "  <- not finite"
+            };
+            writeln!(f, "bounce {idx}:{flag}")?;
+            writeln!(
+                f,
+                "  ray from {} along {}",
+                bounce.ray.origin, bounce.ray.direction
+            )?;
+            match bounce.hit {
+                BounceHit::Surface {
+                    position,
+                    normal,
+                    material,
+                    side,
+                } => {
+                    let side = match side {
+                        FaceSide::Front => "front",
+                        FaceSide::Back => "back",
+                    };
+                    writeln!(
+                        f,
+                        "  hit the {side} of material {} at {position}, normal {normal}",
+                        material.index()
+                    )?
+                }
+                BounceHit::Sky => writeln!(f, "  missed, and saw the sky")?,
+            }
+            writeln!(f, "  emitted {}", bounce.emitted)?;
+            match bounce.attenuation {
+                Some(attenuation) => writeln!(f, "  scattered, with attenuation {attenuation}")?,
+                None => writeln!(f, "  absorbed")?,
+            }
+        }
+        if let Some(Bounce {
+            attenuation: Some(_),
+            ..
+        }) = self.bounces.last()
+        {
+            writeln!(f, "cut off after {} bounces", self.bounces.len())?;
+        }
+        write!(f, "color {}", self.color)
+    }
+}
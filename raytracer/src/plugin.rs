@@ -0,0 +1,424 @@
+//! Shapes and materials from outside the crate.
+//!
+//! The built-in ones are variants of [`Hittable`](crate::Hittable) and
+//! [`Material`](crate::Material), so rendering them is a plain `match`.
+//! Anything else implements [`Shape`] or [`Bsdf`], and goes in
+//! [`Hittable::Custom`](crate::Hittable::Custom) or
+//! [`Material::Custom`](crate::Material::Custom). Scene files store the
+//! kind of each one and its settings, so each kind registers a loader that
+//! builds it again from them.
+
+use std::{ops::Range, sync::Arc};
+
+use anyhow::Result;
+use glam::{Vec2, Vec3};
+use rand::RngCore;
+
+use crate::{
+    bvh::Aabb,
+    geom::{real_vec3, Ray, Transform},
+    hittable::{FaceSide, HitPayload},
+    procedural::{Registry, ShadingPoint},
+    MaterialHandle,
+};
+
+type ShapeLoader = dyn Fn(&str) -> Result<Arc<dyn Shape>> + Send + Sync;
+type BsdfLoader = dyn Fn(&str) -> Result<Arc<dyn Bsdf>> + Send + Sync;
+
+static SHAPES: Registry<ShapeLoader> = Registry::new();
+static BSDFS: Registry<BsdfLoader> = Registry::new();
+
+/// A kind of object to render, for [`CustomShape`]. Shapes are hit in world
+/// space, after being moved there by [`Shape::transformed`].
+pub trait Shape: Send + Sync {
+    /// What its loader is registered as, with [`CustomShape::register`].
+    fn kind(&self) -> &str;
+
+    /// What the loader needs to build the shape again. Shapes without any
+    /// settings can leave this empty.
+    fn settings(&self) -> String {
+        String::new()
+    }
+
+    /// Where `ray` first meets the surface, if it does within `clip`.
+    fn hit(&self, ray: &Ray, clip: &Range<f32>) -> Option<SurfaceHit>;
+
+    /// A box the shape fits inside.
+    fn bounding_box(&self) -> Aabb;
+
+    /// A copy of the shape, moved by `transform`.
+    fn transformed(&self, transform: &Transform) -> Arc<dyn Shape>;
+}
+
+/// Where a ray met a [`Shape`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceHit {
+    /// How far along the ray the hit is, in lengths of its direction.
+    pub distance: f32,
+    pub position: Vec3,
+    /// Pointing out of the front of the surface. Rays that hit the back
+    /// see it flipped, as with the built-in shapes.
+    pub normal: Vec3,
+    /// Surface coordinates for looking up textures, each from 0 to 1.
+    pub uv: Vec2,
+    /// Along the surface in the direction of increasing `uv.x`.
+    pub tangent: Vec3,
+}
+
+/// How light scatters off a surface, for [`CustomBsdf`].
+pub trait Bsdf: Send + Sync {
+    /// What its loader is registered as, with [`CustomBsdf::register`].
+    fn kind(&self) -> &str;
+
+    /// What the loader needs to build the BSDF again.
+    fn settings(&self) -> String {
+        String::new()
+    }
+
+    /// Where light arriving at `point` along `incoming` goes next, or
+    /// `None` if it's absorbed.
+    fn scatter(
+        &self,
+        point: &ShadingPoint,
+        incoming: Vec3,
+        rng: &mut dyn RngCore,
+    ) -> Option<Scattered>;
+
+    /// Light given off at `point`, independent of any incoming light.
+    fn emitted(&self, _point: &ShadingPoint) -> Vec3 {
+        Vec3::ZERO
+    }
+}
+
+/// Light leaving a surface, from [`Bsdf::scatter`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scattered {
+    pub direction: Vec3,
+    /// How much of each channel carries on.
+    pub attenuation: Vec3,
+}
+
+/// A [`Shape`] in a scene, with the material it's shaded with.
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Saved", try_from = "Saved")
+)]
+pub struct CustomShape {
+    pub shape: Arc<dyn Shape>,
+    pub material: MaterialHandle,
+}
+
+impl CustomShape {
+    pub fn new<S: Shape + 'static>(shape: S, material: MaterialHandle) -> Self {
+        Self {
+            shape: Arc::new(shape),
+            material,
+        }
+    }
+
+    /// Register how to build shapes of `kind` from their settings, for
+    /// loading scenes with them in. Anything registered as `kind` before is
+    /// replaced.
+    pub fn register<F>(kind: &str, loader: F)
+    where
+        F: Fn(&str) -> Result<Arc<dyn Shape>> + Send + Sync + 'static,
+    {
+        SHAPES.insert(kind, Arc::new(loader));
+    }
+
+    pub(crate) fn transformed(&self, transform: &Transform) -> Self {
+        Self {
+            shape: self.shape.transformed(transform),
+            material: self.material,
+        }
+    }
+
+    pub(crate) fn check_hit(&self, ray: &Ray, look_clip: &Range<f32>) -> HitPayload {
+        let Some(hit) = self.shape.hit(ray, look_clip) else {
+            return HitPayload::Miss;
+        };
+        let (side, normal) = if ray.direction.dot(hit.normal) > 0. {
+            (FaceSide::Back, -hit.normal)
+        } else {
+            (FaceSide::Front, hit.normal)
+        };
+        HitPayload::Hit {
+            hit_distance: hit.distance,
+            world_normal: normal,
+            world_position: real_vec3(hit.position),
+            material: self.material,
+            side,
+            uv: hit.uv,
+            tangent: hit.tangent,
+        }
+    }
+}
+
+/// A [`Bsdf`] in a scene, for [`Material::Custom`](crate::Material::Custom).
+#[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Saved", try_from = "Saved")
+)]
+pub struct CustomBsdf(pub Arc<dyn Bsdf>);
+
+impl CustomBsdf {
+    pub fn new<B: Bsdf + 'static>(bsdf: B) -> Self {
+        Self(Arc::new(bsdf))
+    }
+
+    /// Register how to build BSDFs of `kind` from their settings, for
+    /// loading scenes with them in. Anything registered as `kind` before is
+    /// replaced.
+    pub fn register<F>(kind: &str, loader: F)
+    where
+        F: Fn(&str) -> Result<Arc<dyn Bsdf>> + Send + Sync + 'static,
+    {
+        BSDFS.insert(kind, Arc::new(loader));
+    }
+
+    pub(crate) fn scatter(
+        &self,
+        hit: &HitPayload,
+        ray: &Ray,
+        rng: &mut dyn RngCore,
+    ) -> Option<(Ray, Vec3)> {
+        let point = ShadingPoint::from_hit(hit)?;
+        let scattered = self.0.scatter(&point, ray.direction, rng)?;
+        let HitPayload::Hit { world_position, .. } = hit else {
+            return None;
+        };
+        let ray = Ray::spawn(*world_position, point.normal, scattered.direction);
+        Some((ray, scattered.attenuation))
+    }
+
+    pub(crate) fn emitted(&self, hit: &HitPayload) -> Vec3 {
+        ShadingPoint::from_hit(hit).map_or(Vec3::ZERO, |point| self.0.emitted(&point))
+    }
+}
+
+/// How custom shapes and BSDFs are stored in scene files.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    kind: String,
+    #[serde(default)]
+    settings: String,
+    /// Only for shapes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<MaterialHandle>,
+}
+
+#[cfg(feature = "serde")]
+impl From<CustomShape> for Saved {
+    fn from(custom: CustomShape) -> Self {
+        Saved {
+            kind: custom.shape.kind().to_string(),
+            settings: custom.shape.settings(),
+            material: Some(custom.material),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<Saved> for CustomShape {
+    type Error = anyhow::Error;
+
+    fn try_from(saved: Saved) -> Result<Self> {
+        let loader = SHAPES.get(&saved.kind).ok_or_else(|| {
+            anyhow::anyhow!("no loader is registered for {:?} shapes", saved.kind)
+        })?;
+        Ok(Self {
+            shape: loader(&saved.settings)?,
+            material: saved.material.unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<CustomBsdf> for Saved {
+    fn from(custom: CustomBsdf) -> Self {
+        Saved {
+            kind: custom.0.kind().to_string(),
+            settings: custom.0.settings(),
+            material: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<Saved> for CustomBsdf {
+    type Error = anyhow::Error;
+
+    fn try_from(saved: Saved) -> Result<Self> {
+        let loader = BSDFS
+            .get(&saved.kind)
+            .ok_or_else(|| anyhow::anyhow!("no loader is registered for {:?} BSDFs", saved.kind))?;
+        Ok(Self(loader(&saved.settings)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bsdf, CustomBsdf, CustomShape, Scattered, Shape, SurfaceHit};
+    use crate::{
+        bvh::Aabb,
+        geom::{from_real_vec3, RealVec3},
+        procedural::ShadingPoint,
+        Hittable, Material, Ray, Renderer, Scene, Transform,
+    };
+    use glam::{Vec2, Vec3};
+    use rand::RngCore;
+    use std::{ops::Range, sync::Arc};
+
+    /// A disc facing up, for testing.
+    struct Disc {
+        center: Vec3,
+        radius: f32,
+    }
+
+    impl Shape for Disc {
+        fn kind(&self) -> &str {
+            "test disc"
+        }
+
+        fn settings(&self) -> String {
+            self.radius.to_string()
+        }
+
+        fn hit(&self, ray: &Ray, clip: &Range<f32>) -> Option<SurfaceHit> {
+            let origin = from_real_vec3(ray.origin);
+            let distance = (self.center.y - origin.y) / ray.direction.y;
+            let position = origin + ray.direction * distance;
+            let inside = position.distance(self.center) <= self.radius;
+            (clip.contains(&distance) && inside).then_some(SurfaceHit {
+                distance,
+                position,
+                normal: Vec3::Y,
+                uv: Vec2::ZERO,
+                tangent: Vec3::X,
+            })
+        }
+
+        fn bounding_box(&self) -> Aabb {
+            Aabb::around(self.center, self.radius)
+        }
+
+        fn transformed(&self, transform: &Transform) -> Arc<dyn Shape> {
+            Arc::new(Disc {
+                center: transform.transform_point(self.center),
+                radius: self.radius * transform.scale,
+            })
+        }
+    }
+
+    /// Sends every ray straight back where it came from.
+    struct Retroreflector;
+
+    impl Bsdf for Retroreflector {
+        fn kind(&self) -> &str {
+            "test retroreflector"
+        }
+
+        fn scatter(
+            &self,
+            _: &ShadingPoint,
+            incoming: Vec3,
+            _: &mut dyn RngCore,
+        ) -> Option<Scattered> {
+            Some(Scattered {
+                direction: -incoming,
+                attenuation: Vec3::splat(0.5),
+            })
+        }
+    }
+
+    #[test]
+    fn custom_shapes_are_hit() {
+        let mut scene = Scene::default();
+        let material = scene.add_material(Material::Custom {
+            bsdf: CustomBsdf::new(Retroreflector),
+        });
+        let node = scene.add_node(
+            crate::NodeId::ROOT,
+            "moved",
+            Transform::from_translation(Vec3::Y),
+        );
+        let disc = Disc {
+            center: Vec3::ZERO,
+            radius: 1.,
+        };
+        scene.add_hittable_to(node, CustomShape::new(disc, material));
+
+        let ray = Ray::new(Vec3::new(0.5, 5., 0.), Vec3::NEG_Y);
+        let hit = scene.raycast(&ray, &(0.0..100.)).unwrap();
+        assert_eq!(hit.distance, 4.);
+        assert_eq!(hit.normal, Vec3::Y);
+        assert_eq!(hit.material, material);
+        assert!(matches!(
+            &scene.world_hittables()[0],
+            Hittable::Custom(custom) if custom.shape.bounding_box().center() == Vec3::Y
+        ));
+
+        // and the renderer can shade them
+        let mut renderer = Renderer::try_new(4, 4).unwrap();
+        let mut camera = crate::Camera::default();
+        camera.set_size(4, 4);
+        renderer.render(&scene, &camera);
+    }
+
+    #[test]
+    fn custom_bsdfs_scatter() {
+        let bsdf = CustomBsdf::new(Retroreflector);
+        let hit = crate::hittable::HitPayload::Hit {
+            hit_distance: 1.,
+            world_normal: Vec3::Y,
+            world_position: RealVec3::ZERO,
+            material: crate::MaterialHandle::NULL,
+            side: crate::FaceSide::Front,
+            uv: Vec2::ZERO,
+            tangent: Vec3::X,
+        };
+        let ray = Ray::new(Vec3::Y, Vec3::NEG_Y);
+        let (scattered, attenuation) = bsdf.scatter(&hit, &ray, &mut rand::thread_rng()).unwrap();
+        assert_eq!(scattered.direction, Vec3::Y);
+        assert!(scattered.origin.y > 0.);
+        assert_eq!(attenuation, Vec3::splat(0.5));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn saved_with_their_settings() {
+        CustomShape::register("test disc", |settings| {
+            Ok(Arc::new(Disc {
+                center: Vec3::ZERO,
+                radius: settings.parse()?,
+            }))
+        });
+        let mut scene = Scene::default();
+        scene.add_hittable(CustomShape::new(
+            Disc {
+                center: Vec3::ZERO,
+                radius: 2.5,
+            },
+            crate::MaterialHandle::NULL,
+        ));
+        let loaded = Scene::from_ron(&scene.to_ron().unwrap()).unwrap();
+        let Hittable::Custom(custom) = &loaded.hittables()[0] else {
+            panic!("expected a custom shape");
+        };
+        assert_eq!(custom.shape.settings(), "2.5");
+
+        // BSDFs without a loader can be saved, but not loaded again
+        scene.add_material(Material::Custom {
+            bsdf: CustomBsdf::new(Retroreflector),
+        });
+        let error = Scene::from_ron(&scene.to_ron().unwrap())
+            .map(drop)
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("no loader is registered"));
+    }
+}
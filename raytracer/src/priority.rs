@@ -0,0 +1,30 @@
+//! Lowering the scheduling priority of render threads, so that the thread
+//! driving the renderer gets the CPU whenever it needs it.
+
+/// Lower the priority of the calling thread.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn lower_current_thread() {
+    // Linux gives each thread its own nice value, and 0 means the calling
+    // thread rather than the whole process. A failure just leaves the
+    // thread at its normal priority.
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 10);
+    }
+}
+
+/// Lower the priority of the calling thread.
+#[cfg(windows)]
+pub(crate) fn lower_current_thread() {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL,
+    };
+    unsafe {
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL);
+    }
+}
+
+/// Elsewhere, setting a thread's priority either needs permissions a
+/// normal program doesn't have, or changes the whole process, so threads
+/// are left as they are.
+#[cfg(not(any(target_os = "linux", target_os = "android", windows)))]
+pub(crate) fn lower_current_thread() {}
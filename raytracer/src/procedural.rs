@@ -0,0 +1,271 @@
+//! Textures and materials worked out by Rust closures, for trying out
+//! shading that the built-in ones can't do without changing the crate.
+//!
+//! Closures are registered under a name, which is what scene files store,
+//! so a scene using them can be saved and loaded again by any program that
+//! registers the same names before loading it.
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use glam::{Vec2, Vec3};
+use parking_lot::RwLock;
+
+use crate::{
+    geom::from_real_vec3,
+    hittable::{FaceSide, HitPayload},
+    Material,
+};
+
+type ColorFn = dyn Fn(Vec2, Vec3) -> Vec3 + Send + Sync;
+type ShadeFn = dyn Fn(&ShadingPoint) -> Material + Send + Sync;
+
+static TEXTURES: Registry<ColorFn> = Registry::new();
+static MATERIALS: Registry<ShadeFn> = Registry::new();
+
+/// What a [`ProceduralMaterial`] knows about the point it's shading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadingPoint {
+    pub position: Vec3,
+    /// Faces against the incoming ray, like [`Hit::normal`](crate::Hit::normal).
+    pub normal: Vec3,
+    /// Surface coordinates, each from 0 to 1.
+    pub uv: Vec2,
+    pub side: FaceSide,
+}
+
+impl ShadingPoint {
+    pub(crate) fn from_hit(hit: &HitPayload) -> Option<Self> {
+        match *hit {
+            HitPayload::Hit {
+                world_position,
+                world_normal,
+                uv,
+                side,
+                ..
+            } => Some(Self {
+                position: from_real_vec3(world_position),
+                normal: world_normal,
+                uv,
+                side,
+            }),
+            HitPayload::Miss => None,
+        }
+    }
+}
+
+/// A [`Texture`](crate::Texture) whose color comes from a closure, called
+/// with the surface coordinates and world position of each point.
+#[derive(Clone)]
+pub struct ProceduralTexture {
+    name: Arc<str>,
+    color: Arc<ColorFn>,
+}
+
+impl ProceduralTexture {
+    /// Register `color` under `name`, replacing anything registered under
+    /// that name before. Textures already made from the old closure keep
+    /// using it.
+    pub fn register<F>(name: &str, color: F) -> Self
+    where
+        F: Fn(Vec2, Vec3) -> Vec3 + Send + Sync + 'static,
+    {
+        let color: Arc<ColorFn> = Arc::new(color);
+        TEXTURES.insert(name, color.clone());
+        Self {
+            name: name.into(),
+            color,
+        }
+    }
+
+    /// The texture registered under `name`, if there is one.
+    pub fn get(name: &str) -> Option<Self> {
+        TEXTURES.get(name).map(|color| Self {
+            name: name.into(),
+            color,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn color(&self, uv: Vec2, position: Vec3) -> Vec3 {
+        (self.color)(uv, position)
+    }
+}
+
+/// A material picked by a closure at each hit, such as a
+/// [`Material::Lambertian`] with an albedo worked out from the normal.
+#[derive(Clone)]
+pub struct ProceduralMaterial {
+    name: Arc<str>,
+    shade: Arc<ShadeFn>,
+}
+
+impl ProceduralMaterial {
+    /// Register `shade` under `name`, replacing anything registered under
+    /// that name before. Materials already made from the old closure keep
+    /// using it.
+    pub fn register<F>(name: &str, shade: F) -> Self
+    where
+        F: Fn(&ShadingPoint) -> Material + Send + Sync + 'static,
+    {
+        let shade: Arc<ShadeFn> = Arc::new(shade);
+        MATERIALS.insert(name, shade.clone());
+        Self {
+            name: name.into(),
+            shade,
+        }
+    }
+
+    /// The material registered under `name`, if there is one.
+    pub fn get(name: &str) -> Option<Self> {
+        MATERIALS.get(name).map(|shade| Self {
+            name: name.into(),
+            shade,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The material at `point`.
+    pub fn shade(&self, point: &ShadingPoint) -> Material {
+        (self.shade)(point)
+    }
+
+    /// The material at `hit`, or [`Material::Null`] for a miss.
+    pub(crate) fn evaluate(&self, hit: &HitPayload) -> Material {
+        ShadingPoint::from_hit(hit).map_or(Material::Null, |point| self.shade(&point))
+    }
+}
+
+impl fmt::Debug for ProceduralTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProceduralTexture")
+            .field(&self.name)
+            .finish()
+    }
+}
+
+impl fmt::Debug for ProceduralMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ProceduralMaterial")
+            .field(&self.name)
+            .finish()
+    }
+}
+
+/// Textures are the same if they were registered under the same name, even
+/// if the closure has been replaced since.
+impl PartialEq for ProceduralTexture {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+/// Functions registered by name, shared by every thread.
+pub(crate) struct Registry<F: ?Sized> {
+    functions: RwLock<BTreeMap<String, Arc<F>>>,
+}
+
+impl<F: ?Sized> Registry<F> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            functions: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub(crate) fn insert(&self, name: &str, function: Arc<F>) {
+        self.functions.write().insert(name.to_string(), function);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<F>> {
+        self.functions.read().get(name).cloned()
+    }
+}
+
+#[cfg(feature = "serde")]
+mod serde_impls {
+    use super::{ProceduralMaterial, ProceduralTexture};
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    impl Serialize for ProceduralTexture {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&self.name)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ProceduralTexture {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(deserializer)?;
+            Self::get(&name)
+                .ok_or_else(|| D::Error::custom(format!("no procedural texture named {name:?}")))
+        }
+    }
+
+    impl Serialize for ProceduralMaterial {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&self.name)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ProceduralMaterial {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(deserializer)?;
+            Self::get(&name)
+                .ok_or_else(|| D::Error::custom(format!("no procedural material named {name:?}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProceduralMaterial, ProceduralTexture, ShadingPoint};
+    use crate::{hittable::FaceSide, Material, Texture};
+    use glam::{Vec2, Vec3};
+
+    #[test]
+    fn registered_closures_can_be_looked_up() {
+        let texture = ProceduralTexture::register("test stripes", |uv, _| {
+            Vec3::splat((uv.x * 10.).floor() % 2.)
+        });
+        let found = ProceduralTexture::get("test stripes").unwrap();
+        assert_eq!(found, texture);
+        assert_eq!(
+            Texture::Procedural(found).color(Vec2::new(0.15, 0.), Vec3::ZERO),
+            Vec3::ONE
+        );
+        assert!(ProceduralTexture::get("test missing").is_none());
+    }
+
+    #[test]
+    fn materials_are_shaded_per_point() {
+        let material = ProceduralMaterial::register("test normals", |point| Material::Lambertian {
+            albedo: point.normal * 0.5 + 0.5,
+        });
+        let point = ShadingPoint {
+            position: Vec3::ZERO,
+            normal: Vec3::Y,
+            uv: Vec2::ZERO,
+            side: FaceSide::Front,
+        };
+        let Material::Lambertian { albedo } = material.shade(&point) else {
+            panic!("expected a lambertian material");
+        };
+        assert_eq!(albedo, Vec3::new(0.5, 1., 0.5));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn saved_by_name() {
+        ProceduralTexture::register("test saved", |_, position| position);
+        let texture = Texture::Procedural(ProceduralTexture::get("test saved").unwrap());
+        let source = ron::to_string(&texture).unwrap();
+        assert!(source.contains("test saved"));
+        let loaded: Texture = ron::from_str(&source).unwrap();
+        assert_eq!(loaded, texture);
+        assert!(ron::from_str::<Texture>("Procedural(\"test unknown\")").is_err());
+    }
+}
@@ -1,44 +1,238 @@
-use crate::{geom::Ray, hittable::HitPayload, util::color_rgb, Camera, Scene};
-use glam::Vec3;
-use rayon::{prelude::*, ThreadPool};
-use std::borrow::Cow;
+use crate::{
+    camera::CameraRays,
+    film::{Averages, PixelSum},
+    scene::GeometryVersion,
+    geom::Ray,
+    hittable::{Hit, HitPayload},
+    integrator::{Path, PathTracer},
+    parallel::*,
+    pixel_trace::{Bounce, PixelTrace},
+    priority,
+    stats::{PathStats, RenderStats},
+    util::{color_rgba, heatmap_color, pack_rgba},
+    Camera, Film, Integrator, Scene, Snapshot,
+};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use parking_lot::Mutex;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    borrow::Cow,
+    fmt,
+    ops::{ControlFlow, Range},
+    time::Duration,
+};
+use tracing::{debug_span, info_span};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// What pixels with NaN or infinite samples show, when they're marked.
+const INVALID_COLOR: Vec4 = Vec4::new(1., 0., 1., 1.);
+
+/// What the renderer writes into the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ViewMode {
+    /// The path traced scene.
+    #[default]
+    Shaded,
+    /// The number of intersection tests each pixel needed, normalized to the
+    /// most expensive pixel in the image.
+    IntersectionHeatmap,
+}
+
+/// How many render threads to use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ThreadPolicy {
+    /// One per logical core.
+    #[default]
+    All,
+    /// One per physical core, leaving hyperthreads alone.
+    Physical,
+    /// Exactly this many, or one if it is 0.
+    Fixed(usize),
+    /// One per logical core except one, so the thread driving the renderer
+    /// stays responsive.
+    AllButOne,
+}
+
+impl ThreadPolicy {
+    pub fn num_threads(self) -> usize {
+        match self {
+            ThreadPolicy::All => num_cpus::get(),
+            ThreadPolicy::Physical => num_cpus::get_physical(),
+            ThreadPolicy::Fixed(num_threads) => num_threads.max(1),
+            ThreadPolicy::AllButOne => num_cpus::get().saturating_sub(1).max(1),
+        }
+    }
+}
+
+/// Ways setting up a [`Renderer`] can fail.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RendererError {
+    /// The render threads couldn't be started.
+    ThreadPool(ThreadPoolBuildError),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RendererError::ThreadPool(err) => write!(f, "couldn't start render threads: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RendererError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RendererError::ThreadPool(err) => Some(err),
+        }
+    }
+}
+
+impl From<ThreadPoolBuildError> for RendererError {
+    fn from(err: ThreadPoolBuildError) -> Self {
+        RendererError::ThreadPool(err)
+    }
+}
 
 pub struct Renderer {
     image_data: Vec<u32>,
-    accumulation: Vec<Vec3>,
-    frame_count: f32,
-    width: u32,
-    height: u32,
+    film: Film,
+    integrator: Box<dyn Integrator>,
+    /// Trace one in this many pixels each frame. See
+    /// [`Renderer::set_interleave`].
+    interleave: u32,
+    /// Which of the interleaved sets of pixels the next frame traces.
+    next_phase: u32,
+    /// See [`Renderer::set_frame_blend`].
+    frame_blend: Option<f32>,
     pub use_accumulation: bool,
+    pub view_mode: ViewMode,
+    /// Leave the background out, so camera rays that miss everything are
+    /// transparent instead of sky colored. The sky still lights the scene.
+    /// Reset the accumulation after changing this.
+    pub transparent_background: bool,
+    /// Keep what each camera ray hits and reuse it while the camera and the
+    /// scene's geometry stay the same, so that after changing materials
+    /// only the shading and bounces are redone. While the cache is in use
+    /// every frame shoots the same camera rays, so edges stop being
+    /// antialiased, and it takes memory for a hit per pixel.
+    pub cache_first_bounce: bool,
+    /// Let NaN and infinite samples into the accumulation, where they show
+    /// as magenta until it's reset, instead of leaving them out. Either way
+    /// they're counted in [`RenderStats::invalid_samples`]. Reset the
+    /// accumulation after changing this.
+    pub mark_invalid_samples: bool,
+    first_bounce: Option<FirstBounceCache>,
     pool: ThreadPool,
+    threads: Threads,
+    /// Threads waiting to replace `pool` at the start of the next frame.
+    pending_pool: Option<(Threads, ThreadPool)>,
+    /// The frame [`Renderer::render_step`] is part way through.
+    partial_frame: Option<PartialFrame>,
+    /// Stats for every frame finished so far.
+    totals: RenderStats,
+    /// Where each frame's seed comes from, once [`Renderer::set_seed`] has
+    /// given it one. Otherwise each frame gets a random seed.
+    seeds: Option<SmallRng>,
+}
+
+/// What the render threads were started with.
+#[derive(Clone, Copy, Default, PartialEq)]
+struct Threads {
+    policy: ThreadPolicy,
+    low_priority: bool,
+}
+
+impl Threads {
+    fn build(self) -> Result<ThreadPool, ThreadPoolBuildError> {
+        let builder = ThreadPoolBuilder::new().num_threads(self.policy.num_threads());
+        if self.low_priority {
+            builder.start_handler(|_| priority::lower_current_thread()).build()
+        } else {
+            builder.build()
+        }
+    }
+}
+
+/// What the camera rays hit, for [`Renderer::cache_first_bounce`].
+struct FirstBounceCache {
+    geometry: GeometryVersion,
+    view_projection: Mat4,
+    projection: crate::Projection,
+    size: [u32; 2],
+    jitter: Vec2,
+    /// What each ray hit, and the index of the hittable it hit.
+    hits: Vec<(usize, HitPayload)>,
+}
+
+impl FirstBounceCache {
+    fn matches(&self, scene: &Scene, camera: &Camera) -> bool {
+        self.geometry == scene.geometry_version()
+            && self.view_projection == camera.view_projection()
+            && self.projection == camera.projection()
+            && self.size == camera.size()
+    }
+
+    /// The cached hits, if they are still right for `camera`.
+    fn hits_for(&self, scene: &Scene, camera: &Camera) -> Option<&[(usize, HitPayload)]> {
+        self.matches(scene, camera).then_some(self.hits.as_slice())
+    }
+}
+
+struct PartialFrame {
+    /// Samples for the rows traced so far.
+    samples: Vec<PixelSum>,
+    depth: Vec<f32>,
+    interleave: Interleave,
+    jitter: Vec2,
+    seed: u64,
+    next_row: u32,
+    stats: RenderStats,
+    trace_time: Duration,
 }
 
 impl Renderer {
+    /// Panics if the render threads can't be started. Use
+    /// [`Renderer::try_new`] to handle that instead.
     pub fn new(width: u32, height: u32) -> Self {
-        let length = width as usize * height as usize;
-        let mut accumulation = Vec::with_capacity(length);
-        accumulation.resize(length, Vec3::ZERO);
+        Self::try_new(width, height).unwrap_or_else(|err| panic!("{err}"))
+    }
 
-        Self {
-            image_data: Vec::with_capacity(width as usize * height as usize),
-            accumulation,
-            frame_count: 0.,
-            width,
-            height,
+    pub fn try_new(width: u32, height: u32) -> Result<Self, RendererError> {
+        let film = Film::new(width, height);
+        Ok(Self {
+            image_data: Vec::with_capacity(film.len()),
+            film,
+            integrator: Box::new(PathTracer::default()),
+            interleave: 1,
+            next_phase: 0,
+            frame_blend: None,
             use_accumulation: true,
-            pool: rayon::ThreadPoolBuilder::default().build().unwrap(),
-        }
+            view_mode: ViewMode::default(),
+            transparent_background: false,
+            cache_first_bounce: false,
+            mark_invalid_samples: false,
+            first_bounce: None,
+            pool: Threads::default().build()?,
+            threads: Threads::default(),
+            pending_pool: None,
+            partial_frame: None,
+            totals: RenderStats::default(),
+            seeds: None,
+        })
     }
 
     #[inline(always)]
     fn image_len(&self) -> usize {
-        self.width as usize * self.height as usize
+        self.film.len()
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
-        if (self.width, self.height) != (width, height) {
-            self.width = width;
-            self.height = height;
+        if (self.film.width(), self.film.height()) != (width, height) {
+            self.film.resize(width, height);
             self.reset_accumulation();
             self.image_data.truncate(0);
             self.image_data.resize(self.image_len(), 0);
@@ -46,23 +240,391 @@ impl Renderer {
     }
 
     pub fn reset_accumulation(&mut self) {
-        self.accumulation.truncate(0);
-        self.accumulation.resize(self.image_len(), Vec3::ZERO);
-        self.frame_count = 0.0;
+        self.partial_frame = None;
+        self.film.reset();
+    }
+
+    /// The samples rendered so far.
+    pub fn film(&self) -> &Film {
+        &self.film
+    }
+
+    pub fn integrator(&self) -> &dyn Integrator {
+        &*self.integrator
+    }
+
+    /// Work out each pixel's samples with `integrator` from now on, in
+    /// place of the [`PathTracer`] renderers start with. This resets the
+    /// accumulation.
+    pub fn set_integrator(&mut self, integrator: impl Integrator + 'static) {
+        self.integrator = Box::new(integrator);
+        self.reset_accumulation();
+    }
+
+    /// Stats for every frame this renderer has finished, for watching its
+    /// throughput over time. Unlike the stats each render returns, these
+    /// keep counting across frames split over several calls, and aren't
+    /// reset with the accumulation.
+    pub fn total_stats(&self) -> &RenderStats {
+        &self.totals
+    }
+
+    pub fn interleave(&self) -> u32 {
+        self.interleave
+    }
+
+    /// Trace only one in every `interleave` pixels each frame, taking turns
+    /// in a pattern that spreads them out, so large images update faster
+    /// while interacting. Pixels that haven't been traced yet are filled
+    /// in from their neighbours. 2 traces a checkerboard, and the most is
+    /// 4. Each frame counts as that fraction of a frame in
+    /// [`Renderer::frame_count`]. Changing this resets the accumulation.
+    pub fn set_interleave(&mut self, interleave: u32) {
+        let interleave = interleave.clamp(1, MAX_INTERLEAVE);
+        if self.interleave != interleave {
+            self.interleave = interleave;
+            self.next_phase = 0;
+            self.reset_accumulation();
+        }
+    }
+
+    pub fn frame_blend(&self) -> Option<f32> {
+        self.frame_blend
+    }
+
+    /// Blend each new sample into its pixel with this weight, between 0 and
+    /// 1, keeping an exponential moving average instead of averaging every
+    /// sample since the last reset equally. Old samples fade out, so the
+    /// image catches up with changes to the scene without a reset, but it
+    /// never gets less noisy than about `1 / weight` samples would be. Until
+    /// a pixel has that many it's a plain average, so resets still clear
+    /// up quickly. This can be changed part way through without a reset.
+    pub fn set_frame_blend(&mut self, weight: Option<f32>) {
+        self.frame_blend = weight
+            .filter(|weight| !weight.is_nan())
+            .map(|weight| weight.clamp(MIN_FRAME_BLEND, 1.));
+    }
+
+    /// When blending frames, scale down the pixels `interleave` is about to
+    /// trace to `1 / weight - 1` samples' worth, so that the next sample
+    /// makes up `weight` of their average.
+    fn make_room_for_frame(&mut self, interleave: Interleave) {
+        if let Some(weight) = self.frame_blend {
+            let traces = |pixel| interleave.traces(pixel);
+            self.film.make_room_for_samples(&self.pool, weight, traces);
+        }
+    }
+
+    /// Let the integrator get ready for a frame of `scene`.
+    fn begin_frame(&mut self, scene: &Scene, seed: u64) {
+        let accumulated = self.film.frame_count();
+        let integrator = &mut self.integrator;
+        self.pool.install(|| integrator.begin_frame(scene, seed, accumulated));
+    }
+
+    /// Move on to the next set of interleaved pixels.
+    fn next_interleave(&mut self) -> Interleave {
+        let interleave = Interleave {
+            every: self.interleave,
+            phase: self.next_phase,
+            width: self.film.width() as usize,
+        };
+        self.next_phase = (self.next_phase + 1) % self.interleave;
+        interleave
+    }
+
+    /// The camera rays for a new frame. With `cache_first_bounce`, this
+    /// also makes sure the cache holds what they hit, tracing them if it
+    /// is out of date, and returns how many intersection tests that took.
+    fn frame_rays<'c>(&mut self, scene: &Scene, camera: &'c Camera) -> (CameraRays<'c>, u64) {
+        if !self.cache_first_bounce {
+            self.first_bounce = None;
+            return (camera.rays(), 0);
+        }
+        if let Some(cache) = self.first_bounce.as_ref().filter(|c| c.matches(scene, camera)) {
+            return (camera.rays_with_jitter(cache.jitter), 0);
+        }
+
+        let _span = info_span!("cache first bounce").entered();
+        let rays = camera.rays();
+        let traced: Vec<((usize, HitPayload), u64)> = self.pool.install(|| {
+            (0..rays.len())
+                .into_par_iter()
+                .map(|pixel| {
+                    let mut tests = 0;
+                    let hit = scene.trace_ray(&rays.ray(pixel), camera.look_clip(), &mut tests);
+                    (hit, tests)
+                })
+                .collect()
+        });
+        let tests = traced.iter().map(|(_, tests)| tests).sum();
+        self.first_bounce = Some(FirstBounceCache {
+            geometry: scene.geometry_version(),
+            view_projection: camera.view_projection(),
+            projection: camera.projection(),
+            size: camera.size(),
+            jitter: rays.jitter(),
+            hits: traced.into_iter().map(|(hit, _)| hit).collect(),
+        });
+        (rays, tests)
+    }
+
+    /// The averages of the samples for each pixel.
+    fn averages(&self) -> Averages<'_> {
+        self.film.averages()
+    }
+
+    /// Draw the random numbers for each frame from a sequence that starts
+    /// at `seed`, so the same scene, camera and settings render exactly the
+    /// same image however many threads there are. `None` goes back to
+    /// random numbers that are different every time.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seeds = seed.map(SmallRng::seed_from_u64);
     }
 
+    fn next_seed(&mut self) -> u64 {
+        match &mut self.seeds {
+            Some(seeds) => seeds.gen(),
+            None => rand::random(),
+        }
+    }
+
+    /// Seed accumulation with an existing HDR image, as if it were the
+    /// average of `weight` frames, so rendering continues refining it instead
+    /// of starting over. `hdr` must match the renderer's size, with
+    /// premultiplied alpha and rows ordered bottom to top, like
+    /// [`Snapshot::hdr`].
+    ///
+    /// This has no lasting effect if `use_accumulation` is off.
+    pub fn warm_start(&mut self, hdr: &[Vec4], weight: f32) -> anyhow::Result<()> {
+        self.film.warm_start(hdr, weight)
+    }
+
+    /// Carry the accumulated image over to a new view when only the camera
+    /// has moved, instead of starting over, as [`Film::reproject`]
+    /// describes.
+    pub fn reproject(&mut self, from: &Camera, to: &Camera) {
+        self.partial_frame = None;
+        if self.film.reproject(from, to) {
+            self.resolve();
+        }
+    }
+
+    /// How many frames have been accumulated since the last reset.
+    pub fn frame_count(&self) -> f32 {
+        self.film.frame_count()
+    }
+
+    /// Copy out the current image and the HDR average behind it, without
+    /// affecting accumulation.
+    pub fn snapshot(&self) -> Snapshot {
+        let averages = self.averages();
+        let hdr: Vec<Vec4> = self.pool.install(|| {
+            (0..self.image_len())
+                .into_par_iter()
+                .map(|pixel| averages.get(pixel))
+                .collect()
+        });
+        let image = match self.view_mode {
+            ViewMode::Shaded => self
+                .pool
+                .install(|| hdr.par_iter().map(color_rgba).collect()),
+            // The heatmap is normalized over the whole image, so reuse the last resolve.
+            ViewMode::IntersectionHeatmap => self.image_data.clone(),
+        };
+        Snapshot {
+            width: self.film.width(),
+            height: self.film.height(),
+            frame_count: self.film.frame_count(),
+            image,
+            hdr,
+        }
+    }
+
+    /// The camera space depth of the closest surface seen through each
+    /// pixel, for compositing. Pixels that only see sky are infinitely far
+    /// away. Rows are ordered bottom to top, like [`Snapshot::hdr`].
+    pub fn render_depth(&self, scene: &Scene, camera: &Camera) -> Vec<f32> {
+        let _span = info_span!("render depth").entered();
+        let forward = camera.look_direction();
+        let clip = camera.look_clip();
+        let rays = camera.rays();
+        self.pool.install(|| {
+            (0..rays.len())
+                .into_par_iter()
+                .map(|pixel| {
+                    let ray = rays.ray(pixel);
+                    scene
+                        .raycast(&ray, clip)
+                        .map_or(f32::INFINITY, |hit| hit.distance * ray.direction.dot(forward))
+                })
+                .collect()
+        })
+    }
+
+    /// Find the closest surface along each of `rays`, spread across the
+    /// render threads, for tools like light map and ambient occlusion
+    /// bakers that need many rays traced without an image. Each result is
+    /// what [`Scene::raycast`] would give for the ray at the same index.
+    pub fn trace_batch(
+        &self,
+        scene: &Scene,
+        rays: &[Ray],
+        clip: &Range<f32>,
+    ) -> Vec<Option<Hit>> {
+        self.pool
+            .install(|| rays.par_iter().map(|ray| scene.raycast(ray, clip)).collect())
+    }
+
+    /// Trace one path through the center of the pixel at `x` and `y`,
+    /// counting from the bottom left, recording every bounce along the
+    /// way, for finding out where a pixel's color comes from. The path's
+    /// random numbers are drawn from `seed`, so the same seed traces the
+    /// same path again. Nothing is added to the image.
+    pub fn trace_pixel(
+        &self,
+        scene: &Scene,
+        camera: &Camera,
+        x: u32,
+        y: u32,
+        seed: u64,
+    ) -> PixelTrace {
+        let log = Mutex::new(Vec::new());
+        let ctx = RenderFrame {
+            scene,
+            camera,
+            integrator: &*self.integrator,
+            transparent_background: self.transparent_background,
+            mark_invalid_samples: true,
+            interleave: Interleave::default(),
+            seed,
+            first_hits: None,
+            log: Some(&log),
+        };
+        let pixel = y as usize * camera.size()[0] as usize + x as usize;
+        let ray = camera.ray_for_pixel(x, y, Vec2::ZERO);
+        let color = self.integrator.sample(ray, &mut Path::new(&ctx, pixel));
+        PixelTrace {
+            x,
+            y,
+            seed,
+            bounces: log.into_inner(),
+            color,
+        }
+    }
+
+    /// The current image, with straight alpha and the top row first.
+    #[cfg(feature = "image")]
+    pub fn as_image(&self) -> image::RgbaImage {
+        self.snapshot().to_image()
+    }
+
+    /// The linear average of the samples for each pixel, with premultiplied
+    /// alpha and the top row first.
+    #[cfg(feature = "image")]
+    pub fn as_hdr_image(&self) -> image::Rgba32FImage {
+        self.snapshot().to_hdr_image()
+    }
+
+    /// How many threads are rendering. A change of [`ThreadPolicy`] shows
+    /// up here once the next frame starts.
     pub fn num_threads(&self) -> usize {
         self.pool.current_num_threads()
     }
 
+    /// The most recently set policy, even if it hasn't taken effect yet.
+    pub fn thread_policy(&self) -> ThreadPolicy {
+        self.next_threads().policy
+    }
+
+    /// Whether the render threads run below normal priority, even if the
+    /// change hasn't taken effect yet.
+    pub fn low_priority(&self) -> bool {
+        self.next_threads().low_priority
+    }
+
+    fn next_threads(&self) -> Threads {
+        self.pending_pool
+            .as_ref()
+            .map_or(self.threads, |(threads, _)| *threads)
+    }
+
+    /// Panics if the render threads can't be started. Use
+    /// [`Renderer::try_set_num_threads`] to handle that instead.
     pub fn set_num_threads(&mut self, num_threads: usize) {
-        self.pool = rayon::ThreadPoolBuilder::default()
-            .num_threads(num_threads)
-            .build()
-            .unwrap();
+        self.try_set_num_threads(num_threads)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Use `num_threads` render threads, or one per core if it is 0. See
+    /// [`Renderer::try_set_thread_policy`].
+    pub fn try_set_num_threads(&mut self, num_threads: usize) -> Result<(), RendererError> {
+        self.try_set_thread_policy(match num_threads {
+            0 => ThreadPolicy::All,
+            num_threads => ThreadPolicy::Fixed(num_threads),
+        })
+    }
+
+    /// Panics if the render threads can't be started. Use
+    /// [`Renderer::try_set_thread_policy`] to handle that instead.
+    pub fn set_thread_policy(&mut self, policy: ThreadPolicy) {
+        self.try_set_thread_policy(policy)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Start new render threads to match `policy`. They replace the old
+    /// ones at the start of the next frame, so a frame that
+    /// [`Renderer::render_step`] is part way through finishes on the
+    /// threads it started on. On error the old threads are kept.
+    pub fn try_set_thread_policy(&mut self, policy: ThreadPolicy) -> Result<(), RendererError> {
+        self.try_set_threads(Threads {
+            policy,
+            ..self.next_threads()
+        })
+    }
+
+    /// Panics if the render threads can't be started. Use
+    /// [`Renderer::try_set_low_priority`] to handle that instead.
+    pub fn set_low_priority(&mut self, low_priority: bool) {
+        self.try_set_low_priority(low_priority)
+            .unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Run the render threads below normal priority, so that with
+    /// [`ThreadPolicy::AllButOne`] an interactive front end never has to
+    /// wait for them. Only Linux and Windows support this, and elsewhere
+    /// the threads keep their normal priority. Like
+    /// [`Renderer::try_set_thread_policy`], this starts new threads that
+    /// are used from the next frame.
+    pub fn try_set_low_priority(&mut self, low_priority: bool) -> Result<(), RendererError> {
+        self.try_set_threads(Threads {
+            low_priority,
+            ..self.next_threads()
+        })
+    }
+
+    fn try_set_threads(&mut self, threads: Threads) -> Result<(), RendererError> {
+        if threads == self.threads {
+            self.pending_pool = None;
+            return Ok(());
+        }
+        self.pending_pool = Some((threads, threads.build()?));
+        Ok(())
     }
 
-    pub fn render<'a>(&mut self, scene: &'a Scene, camera: &'a Camera) -> Cow<[u32]> {
+    /// Switch to the threads from the last change of policy, if any.
+    fn apply_thread_policy(&mut self) {
+        if let Some((threads, pool)) = self.pending_pool.take() {
+            self.threads = threads;
+            self.pool = pool;
+        }
+    }
+
+    pub fn render<'a>(
+        &mut self,
+        scene: &'a Scene,
+        camera: &'a Camera,
+    ) -> (Cow<'_, [u32]>, RenderStats) {
         self.render_accumulate(scene, camera, 1)
     }
 
@@ -71,116 +633,978 @@ impl Renderer {
         scene: &'a Scene,
         camera: &'a Camera,
         frames: usize,
-    ) -> Cow<[u32]> {
-        let ctx = RenderFrame { scene, camera };
+    ) -> (Cow<'_, [u32]>, RenderStats) {
+        self.render_with_progress(scene, camera, frames, |_| ControlFlow::Continue(()))
+    }
+
+    /// Like [`Renderer::render_accumulate`], but calls `progress` with stats
+    /// for the frames finished so far after each frame. Returning
+    /// [`ControlFlow::Break`] stops early, and the image includes only the
+    /// frames that were finished.
+    pub fn render_with_progress<'a, P>(
+        &mut self,
+        scene: &'a Scene,
+        camera: &'a Camera,
+        frames: usize,
+        mut progress: P,
+    ) -> (Cow<'_, [u32]>, RenderStats)
+    where
+        P: FnMut(&RenderStats) -> ControlFlow<()>,
+    {
+        let _span = info_span!("render", frames).entered();
+        self.apply_thread_policy();
+        let mut stats = RenderStats::default();
+        let mut trace_time = Default::default();
 
         if !self.use_accumulation {
             self.reset_accumulation();
         }
 
         self.image_data.resize(self.image_len(), 0);
-        self.accumulation.resize(self.image_len(), Vec3::ZERO);
 
-        for _ in 0..frames {
-            self.frame_count += 1.;
+        for frame in 0..frames {
+            let _span = info_span!("frame", frame).entered();
+            let interleave = self.next_interleave();
+            self.make_room_for_frame(interleave);
 
-            let dirs = camera.get_ray_directions();
-            let rays = dirs
-                .iter()
-                .map(|direction| Ray {
-                    direction: *direction,
-                    origin: camera.position(),
-                })
-                .collect::<Vec<_>>();
+            let t0 = Instant::now();
+            let seed = self.next_seed();
+            self.begin_frame(scene, seed);
+            let (rays, cache_tests) = self.frame_rays(scene, camera);
+            stats.intersection_tests += cache_tests;
+            let ctx = RenderFrame {
+                scene,
+                camera,
+                integrator: &*self.integrator,
+                transparent_background: self.transparent_background,
+                mark_invalid_samples: self.mark_invalid_samples,
+                interleave,
+                seed,
+                first_hits: self.first_bounce.as_ref().and_then(|c| c.hits_for(scene, camera)),
+                log: None,
+            };
+            let path_stats = trace(
+                &self.pool,
+                self.view_mode,
+                &ctx,
+                &rays,
+                0,
+                &mut self.film.accumulation,
+                &mut self.film.depth,
+            );
+            trace_time += t0.elapsed();
+            self.film.frame_count += 1. / self.interleave as f32;
 
-            self.image_data.resize(self.image_len(), 0);
-            self.pool.install(|| {
-                (&mut self.accumulation, rays)
-                    .into_par_iter()
-                    .for_each(|(acc, ray)| {
-                        *acc += ctx.per_pixel(ray);
-                    });
-            });
+            stats.add_path_stats(ctx.interleave.count(0..self.image_len()), path_stats);
+            stats.frames += 1;
+
+            if progress(&stats).is_break() {
+                break;
+            }
         }
 
-        let frame_count = self.frame_count;
-        self.pool.install(|| {
-            (&mut self.accumulation, &mut self.image_data)
+        let t0 = Instant::now();
+        self.resolve();
+        stats.stage_times = vec![("trace", trace_time), ("resolve", t0.elapsed())];
+        self.totals.add_frames(stats.clone());
+
+        (Cow::Borrowed(self.image_data.as_slice()), stats)
+    }
+
+    /// Render part of a frame, stopping once `budget` has passed, for
+    /// callers that can't block for a whole frame, like a browser's event
+    /// loop. Rows are traced in bands, so a step can run over budget by up
+    /// to one band. Returns the image once the frame is finished, and
+    /// `None` while it is still in progress.
+    ///
+    /// Progress is kept between calls until the accumulation is reset, so
+    /// reset it if the scene or camera change part way through a frame.
+    pub fn render_step<'a>(
+        &mut self,
+        scene: &'a Scene,
+        camera: &'a Camera,
+        budget: Duration,
+    ) -> Option<(Cow<'_, [u32]>, RenderStats)> {
+        let stats = self.step(scene, camera, budget)?;
+        Some((Cow::Borrowed(self.image_data.as_slice()), stats))
+    }
+
+    /// Render for `budget`, finishing as many frames as fit and then
+    /// starting on the next, so an interactive front end can spend the
+    /// same time rendering each of its own frames whatever the resolution.
+    /// Like [`Renderer::render_step`], this can run over budget by up to
+    /// one band of rows.
+    ///
+    /// Returns the image as of the last finished frame, and stats for the
+    /// frames finished during this call. A frame left part way through is
+    /// counted when a later call finishes it.
+    pub fn render_for<'a>(
+        &mut self,
+        scene: &'a Scene,
+        camera: &'a Camera,
+        budget: Duration,
+    ) -> (Cow<'_, [u32]>, RenderStats) {
+        let start = Instant::now();
+        let mut stats = RenderStats::default();
+        while let Some(frame_stats) =
+            self.step(scene, camera, budget.saturating_sub(start.elapsed()))
+        {
+            stats.add_frames(frame_stats);
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        self.image_data.resize(self.image_len(), 0);
+        (Cow::Borrowed(self.image_data.as_slice()), stats)
+    }
+
+    /// The work behind [`Renderer::render_step`], returning stats once a
+    /// frame is finished.
+    fn step(&mut self, scene: &Scene, camera: &Camera, budget: Duration) -> Option<RenderStats> {
+        const BAND_ROWS: u32 = 8;
+        let _span = info_span!("render step").entered();
+        let start = Instant::now();
+        if self.partial_frame.is_none() {
+            self.apply_thread_policy();
+        }
+        let len = self.image_len();
+        let mut frame = match self.partial_frame.take() {
+            Some(frame) => frame,
+            None => {
+                let mut depth = self.film.depth.clone();
+                // pixels this frame skips keep their depth
+                depth.resize(len, f32::INFINITY);
+                let seed = self.next_seed();
+                self.begin_frame(scene, seed);
+                let (rays, cache_tests) = self.frame_rays(scene, camera);
+                let mut stats = RenderStats::default();
+                stats.intersection_tests += cache_tests;
+                PartialFrame {
+                    samples: vec![PixelSum::default(); len],
+                    depth,
+                    interleave: self.next_interleave(),
+                    jitter: rays.jitter(),
+                    seed,
+                    next_row: 0,
+                    stats,
+                    trace_time: Duration::ZERO,
+                }
+            }
+        };
+        let ctx = RenderFrame {
+            scene,
+            camera,
+            integrator: &*self.integrator,
+            transparent_background: self.transparent_background,
+            mark_invalid_samples: self.mark_invalid_samples,
+            interleave: frame.interleave,
+            seed: frame.seed,
+            first_hits: self.first_bounce.as_ref().and_then(|c| c.hits_for(scene, camera)),
+            log: None,
+        };
+
+        // every step of a frame shoots the same rays
+        let rays = camera.rays_with_jitter(frame.jitter);
+        let (width, height) = (self.film.width(), self.film.height());
+        while frame.next_row < height {
+            let rows = frame.next_row..(frame.next_row + BAND_ROWS).min(height);
+            let pixels = rows.start as usize * width as usize..rows.end as usize * width as usize;
+            let path_stats = trace(
+                &self.pool,
+                self.view_mode,
+                &ctx,
+                &rays,
+                pixels.start,
+                &mut frame.samples[pixels.clone()],
+                &mut frame.depth[pixels.clone()],
+            );
+            let primary_rays = frame.interleave.count(pixels.clone());
+            frame.stats.add_path_stats(primary_rays, path_stats);
+            frame.next_row = rows.end;
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        frame.trace_time += start.elapsed();
+
+        if frame.next_row < height {
+            self.partial_frame = Some(frame);
+            return None;
+        }
+
+        if !self.use_accumulation {
+            self.reset_accumulation();
+        }
+        self.image_data.resize(len, 0);
+        self.make_room_for_frame(frame.interleave);
+        for (acc, sample) in self.film.accumulation.iter_mut().zip(frame.samples) {
+            *acc += sample;
+        }
+        self.film.depth = frame.depth;
+        self.film.frame_count += 1. / self.interleave as f32;
+        let t0 = Instant::now();
+        self.resolve();
+
+        let mut stats = frame.stats;
+        stats.frames = 1;
+        stats.stage_times = vec![("trace", frame.trace_time), ("resolve", t0.elapsed())];
+        self.totals.add_frames(stats.clone());
+        Some(stats)
+    }
+
+    /// Turn the accumulated samples into the displayed image.
+    fn resolve(&mut self) {
+        let _span = info_span!("resolve").entered();
+        let mut image_data = std::mem::take(&mut self.image_data);
+        let averages = self.averages();
+        self.pool.install(|| match self.view_mode {
+            ViewMode::Shaded => (&mut image_data, 0..self.image_len())
                 .into_par_iter()
-                .for_each(|(acc, output)| {
-                    *output = color_rgb(*acc / frame_count);
-                });
+                .for_each(|(output, pixel)| {
+                    let average = averages.get(pixel);
+                    *output = if average.is_finite() {
+                        color_rgba(&average)
+                    } else {
+                        color_rgba(&INVALID_COLOR)
+                    };
+                }),
+            ViewMode::IntersectionHeatmap => {
+                let max_tests = self
+                    .film
+                    .accumulation
+                    .par_iter()
+                    .map(|acc| acc.color.value().x)
+                    .reduce(|| 0.0, f32::max)
+                    .max(1.0);
+                (&self.film.accumulation, &mut image_data)
+                    .into_par_iter()
+                    .for_each(|(acc, output)| {
+                        let tests = acc.color.value().x;
+                        *output = pack_rgba(&heatmap_color(tests / max_tests).extend(1.));
+                    })
+            }
         });
+        self.image_data = image_data;
+    }
+}
+
+/// Trace one sample for each pixel in `targets`, which starts at
+/// `first_pixel` in the image, adding it to the pixel and replacing the
+/// pixel's depth.
+fn trace(
+    pool: &ThreadPool,
+    view_mode: ViewMode,
+    ctx: &RenderFrame,
+    rays: &CameraRays<'_>,
+    first_pixel: usize,
+    targets: &mut [PixelSum],
+    depths: &mut [f32],
+) -> PathStats {
+    let _span = debug_span!("trace", pixels = targets.len()).entered();
+    let pixels = first_pixel..first_pixel + targets.len();
+    pool.install(|| {
+        (targets, depths, pixels)
+            .into_par_iter()
+            .map(|(acc, depth, pixel)| {
+                let mut path_stats = PathStats::default();
+                if !ctx.interleave.traces(pixel) {
+                    return path_stats;
+                }
+
+                let mut path = Path::new(ctx, pixel);
+                let color = ctx.integrator.sample(rays.ray(pixel), &mut path);
+                let hit_distance;
+                (hit_distance, path_stats) = path.finish();
+                *depth = hit_distance;
+                if !color.is_finite() {
+                    path_stats.invalid_samples += 1;
+                    if !ctx.mark_invalid_samples && view_mode == ViewMode::Shaded {
+                        return path_stats;
+                    }
+                }
+                *acc += match view_mode {
+                    ViewMode::Shaded => color,
+                    ViewMode::IntersectionHeatmap => {
+                        Vec3::splat(path_stats.intersection_tests as f32).extend(1.)
+                    }
+                };
+                path_stats
+            })
+            .reduce(PathStats::default, |a, b| a + b)
+    })
+}
 
-        Cow::Borrowed(self.image_data.as_slice())
+/// What's the same for every pixel of a frame.
+pub(crate) struct RenderFrame<'a> {
+    pub scene: &'a Scene,
+    pub camera: &'a Camera,
+    pub integrator: &'a dyn Integrator,
+    pub transparent_background: bool,
+    pub mark_invalid_samples: bool,
+    /// Which pixels to trace.
+    pub interleave: Interleave,
+    /// Where this frame's random numbers start from.
+    pub seed: u64,
+    /// What each camera ray hits, if it's cached.
+    pub first_hits: Option<&'a [(usize, HitPayload)]>,
+    /// Where to record every bounce, when tracing a single pixel for
+    /// [`Renderer::trace_pixel`].
+    pub log: Option<&'a Mutex<Vec<Bounce>>>,
+}
+
+/// The least weight [`Renderer::set_frame_blend`] can give new samples.
+const MIN_FRAME_BLEND: f32 = 0.001;
+
+/// The most pixels [`Renderer::set_interleave`] can spread samples over.
+const MAX_INTERLEAVE: u32 = 4;
+
+/// One of the sets of pixels that interleaved frames take turns tracing.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Interleave {
+    /// How many sets there are.
+    every: u32,
+    phase: u32,
+    width: usize,
+}
+
+impl Default for Interleave {
+    /// Every pixel.
+    fn default() -> Self {
+        Self {
+            every: 1,
+            phase: 0,
+            width: 1,
+        }
     }
 }
 
-struct RenderFrame<'a> {
-    scene: &'a Scene,
-    camera: &'a Camera,
+impl Interleave {
+    /// Which set a pixel belongs to. Each row is shifted along from the
+    /// last, so that the pixels in a set are spread out in both directions.
+    fn phase_of(&self, pixel: usize) -> u32 {
+        let (x, y) = (pixel % self.width, pixel / self.width);
+        let shift = (self.every as usize / 2).max(1);
+        ((x + y * shift) % self.every as usize) as u32
+    }
+
+    fn traces(&self, pixel: usize) -> bool {
+        self.phase_of(pixel) == self.phase
+    }
+
+    /// How many of `pixels` are traced.
+    fn count(&self, pixels: Range<usize>) -> u64 {
+        if self.every == 1 {
+            return pixels.len() as u64;
+        }
+        pixels.filter(|pixel| self.traces(*pixel)).count() as u64
+    }
 }
 
-impl<'a> RenderFrame<'a> {
-    /// Called once per pixel to figure out its color.
-    fn per_pixel(&self, ray: Ray) -> Vec3 {
-        self.ray_color(ray, 16)
+#[cfg(test)]
+mod tests {
+    use super::INVALID_COLOR;
+    use crate::{
+        geom::from_real_vec3,
+        integrator::MAX_BOUNCES,
+        pixel_trace::BounceHit,
+        test_fixtures,
+        util::{color_rgb, color_rgba},
+        Backface, Material, MaterialHandle, Ray, Scene, Sphere,
+    };
+    use glam::{Vec3, Vec4};
+    use std::{ops::ControlFlow, time::Duration};
+
+    #[test]
+    fn missing_material_uses_fallback() {
+        let mut renderer = test_fixtures::renderer();
+        let mut other = Scene::default();
+        other.add_material(Material::Null);
+        let stale = other.add_material(Material::Null);
+
+        let mut scene = Scene::default();
+        let sphere = scene.add_hittable(Sphere {
+            // fills the camera's view
+            center: Vec3::new(0., 0., -1000.),
+            radius: 990.,
+            material: MaterialHandle::NULL,
+        });
+        *scene.hittable_mut(sphere).material_mut() = stale;
+        let camera = test_fixtures::camera();
+
+        let (image, _) = renderer.render(&scene, &camera);
+        // the magenta of Material::FALLBACK
+        let expected = color_rgb(Vec3::new(1., 0., 1.));
+        assert!(image.iter().all(|pixel| *pixel == expected));
     }
 
-    fn ray_color(&self, ray: Ray, bounce_budget: u32) -> Vec3 {
-        const SKY_COLOR: Vec3 = Vec3::new(0.6, 0.7, 0.9);
+    #[test]
+    fn empty_scene_is_sky() {
+        let mut renderer = test_fixtures::renderer();
+        let (image, _) = renderer.render(&test_fixtures::empty_scene(), &test_fixtures::camera());
+        let sky = color_rgb(test_fixtures::SKY_COLOR);
+        assert_eq!(
+            image.len(),
+            (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize
+        );
+        assert!(image.iter().all(|pixel| *pixel == sky));
+    }
 
-        if bounce_budget == 0 {
-            Vec3::new(0.0, 0.0, 0.0)
-        } else {
-            match self.trace_ray(&ray) {
-                ref hit @ HitPayload::Hit { ref material_index, .. } => {
-                    let material = self.scene.material(*material_index);
-                    if let Some(scatter) = material.scatter(hit, &ray) {
-                        self.ray_color(scatter.ray, bounce_budget - 1) * scatter.attenuation
-                    } else {
-                        Vec3::ZERO
-                    }
-                }
-                HitPayload::Miss => SKY_COLOR,
-                HitPayload::Inside => Vec3::ZERO,
-            }
+    #[test]
+    fn camera_can_be_inside_a_sphere() {
+        let mut renderer = test_fixtures::renderer();
+        let camera = test_fixtures::camera();
+        let mut scene = Scene::default();
+        let emission = Vec3::new(0.25, 0.5, 0.75);
+        let glow = scene.add_material(Material::Emissive { emission });
+        scene.add_hittable(Sphere {
+            center: camera.position(),
+            radius: 5.,
+            material: glow,
+        });
+
+        // every ray sees the inside of the sphere, not black
+        let (image, _) = renderer.render(&scene, &camera);
+        assert!(image.iter().all(|pixel| *pixel == color_rgb(emission)));
+    }
+
+    #[test]
+    fn backfaces_can_be_culled_or_shaded_apart() {
+        let mut renderer = test_fixtures::renderer();
+        let camera = test_fixtures::camera();
+        let front = Vec3::new(1., 0., 0.);
+        let back = Vec3::new(0., 0., 1.);
+        let mut scene = Scene::default();
+        let material = scene.add_material(Material::Null);
+        scene.add_hittable(Sphere {
+            center: camera.position(),
+            radius: 5.,
+            material,
+        });
+
+        // from inside, the camera only sees the back of the sphere
+        for (backface, expected) in [
+            (Backface::Shaded, front),
+            (Backface::Culled, test_fixtures::SKY_COLOR),
+            (Backface::Material(Box::new(Material::Emissive { emission: back })), back),
+        ] {
+            scene.materials_mut()[material.index()] = Material::Sided {
+                front: Box::new(Material::Emissive { emission: front }),
+                back: backface,
+            };
+            renderer.reset_accumulation();
+            let (image, _) = renderer.render(&scene, &camera);
+            assert!(image.iter().all(|pixel| *pixel == color_rgb(expected)));
         }
     }
 
-    /// Shoot a ray from a given location and return information the closest hit, if any.
-    fn trace_ray(&self, ray: &Ray) -> HitPayload {
-        let look_clip = self.camera.look_clip();
-        self.scene
-            .hittables()
-            .iter()
-            .map(|hittable| hittable.check_hit(ray, look_clip))
-            .fold(HitPayload::Miss, |acc, next| {
-                match (acc, next) {
-                    (acc @ HitPayload::Hit { .. }, next @ HitPayload::Hit { .. }) => {
-                        match (&acc, &next) {
-                            (
-                                HitPayload::Hit {
-                                    hit_distance: d_acc,
-                                    ..
-                                },
-                                HitPayload::Hit {
-                                    hit_distance: d_next,
-                                    ..
-                                },
-                            ) if d_next < d_acc => next,
-                            _ => acc,
-                        }
-                    }
-                    (hit @ HitPayload::Hit { .. }, HitPayload::Miss)
-                    | (HitPayload::Hit { .. }, hit @ HitPayload::Inside)
-                    | (HitPayload::Miss, hit @ HitPayload::Hit { .. })
-                    | (hit @ HitPayload::Miss, HitPayload::Miss)
-                    | (HitPayload::Miss, hit @ HitPayload::Inside)
-                    | (hit @ HitPayload::Inside, HitPayload::Hit { .. })
-                    | (hit @ HitPayload::Inside, HitPayload::Miss)
-                    | (hit @ HitPayload::Inside, HitPayload::Inside) => hit,
-                }
+    #[test]
+    fn transparent_background() {
+        let mut renderer = test_fixtures::renderer();
+        renderer.transparent_background = true;
+        let scene = test_fixtures::single_sphere();
+        renderer.render(&scene, &test_fixtures::camera());
+        let snapshot = renderer.snapshot();
+
+        // the corners miss, and the middle sees the sphere lit by the sky
+        assert_eq!(snapshot.hdr[0], Vec4::ZERO);
+        assert_eq!(snapshot.image[0], 0);
+        let middle = snapshot.hdr[(test_fixtures::HEIGHT / 2 * test_fixtures::WIDTH
+            + test_fixtures::WIDTH / 2) as usize];
+        assert_eq!(middle.w, 1.);
+        assert!(middle.x > 0.);
+    }
+
+    #[test]
+    fn render_step_finishes_frames_in_pieces() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let camera = test_fixtures::camera();
+
+        // A zero budget still makes progress, one band of rows at a time.
+        let mut steps = 1;
+        while renderer
+            .render_step(&scene, &camera, Duration::ZERO)
+            .is_none()
+        {
+            steps += 1;
+        }
+        assert_eq!(steps, (test_fixtures::HEIGHT as usize).div_ceil(8));
+        assert_eq!(renderer.frame_count(), 1.);
+
+        let (_, stats) = renderer
+            .render_step(&scene, &camera, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(stats.frames, 1);
+        assert_eq!(
+            stats.primary_rays,
+            (test_fixtures::WIDTH * test_fixtures::HEIGHT) as u64
+        );
+        assert_eq!(renderer.frame_count(), 2.);
+
+        assert!(renderer
+            .render_step(&scene, &camera, Duration::ZERO)
+            .is_none());
+        renderer.reset_accumulation();
+        let (image, _) = renderer
+            .render_step(&test_fixtures::empty_scene(), &camera, Duration::from_secs(60))
+            .unwrap();
+        let sky = color_rgb(test_fixtures::SKY_COLOR);
+        assert!(image.iter().all(|pixel| *pixel == sky));
+    }
+
+    #[test]
+    fn reprojection_keeps_a_still_view() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let camera = test_fixtures::camera();
+        renderer.render_accumulate(&scene, &camera, 8);
+        let before = renderer.snapshot();
+
+        renderer.reproject(&camera, &camera);
+        assert_eq!(renderer.frame_count(), 4.);
+        let after = renderer.snapshot();
+        for (a, b) in before.hdr.iter().zip(&after.hdr) {
+            assert!(a.distance(*b) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn reprojection_follows_the_camera() {
+        let mut renderer = test_fixtures::renderer();
+        let mut scene = Scene::default();
+        let glow = scene.add_material(Material::Emissive {
+            emission: Vec3::new(1., 0., 0.),
+        });
+        scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0.5, 0.),
+            radius: 0.5,
+            material: glow,
+        });
+        let camera = test_fixtures::camera();
+        let mut moved = camera.clone();
+        moved.set_position(Vec3::new(0.4, 0.5, 3.5));
+        let expected = test_fixtures::renderer().render(&scene, &moved).0.to_vec();
+
+        renderer.render(&scene, &camera);
+        // differing pixels that have samples, and ones that don't
+        let differences = |image: &[u32]| {
+            let differing = image.iter().zip(&expected).filter(|(a, b)| a != b);
+            differing.fold((0, 0), |(traced, empty), (pixel, _)| match pixel {
+                0 => (traced, empty + 1),
+                _ => (traced + 1, empty),
             })
+        };
+        let (stale, _) = differences(&renderer.snapshot().image);
+        renderer.reproject(&camera, &moved);
+        let (reprojected, disoccluded) = differences(&renderer.snapshot().image);
+        // only pixels along the edge of the sphere, where jitter decides
+        // what they see, should differ, apart from the side of it that has
+        // just come into view, which is left empty until it's traced
+        assert!(stale > 40, "{stale}");
+        assert!(reprojected < stale / 4, "{reprojected} of {stale}");
+        assert!(disoccluded > 0);
+    }
+
+    #[test]
+    fn interleaving_fills_in_untraced_pixels() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::empty_scene();
+        let camera = test_fixtures::camera();
+        renderer.set_interleave(2);
+        let pixels = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as u64;
+
+        let (image, stats) = renderer.render(&scene, &camera);
+        let sky = color_rgb(test_fixtures::SKY_COLOR);
+        assert!(image.iter().all(|pixel| *pixel == sky));
+        assert_eq!(stats.primary_rays, pixels / 2);
+        assert_eq!(renderer.frame_count(), 0.5);
+
+        // the next frame traces the other half
+        let (_, stats) = renderer.render_accumulate(&scene, &camera, 3);
+        assert_eq!(stats.primary_rays, pixels * 3 / 2);
+        assert_eq!(renderer.frame_count(), 2.);
+        let sky = test_fixtures::SKY_COLOR.extend(1.);
+        assert!(renderer.snapshot().hdr.iter().all(|c| c.distance(sky) < 1e-5));
+
+        // without accumulation, the untraced half is filled in every frame
+        renderer.use_accumulation = false;
+        renderer.render(&scene, &camera);
+        let (image, _) = renderer.render(&scene, &camera);
+        assert!(image.iter().all(|pixel| *pixel == color_rgb(test_fixtures::SKY_COLOR)));
+    }
+
+    #[test]
+    fn first_bounce_cache_lasts_until_geometry_changes() {
+        let mut renderer = test_fixtures::renderer();
+        renderer.cache_first_bounce = true;
+        let mut scene = Scene::default();
+        // nothing bounces, so every intersection test is for a camera ray
+        let glow = scene.add_material(Material::Emissive {
+            emission: Vec3::new(1., 0., 0.),
+        });
+        let sphere = scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0.5, 0.),
+            radius: 0.5,
+            material: glow,
+        });
+        let camera = test_fixtures::camera();
+        let pixels = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as u64;
+        let center = (test_fixtures::HEIGHT / 2 * test_fixtures::WIDTH + test_fixtures::WIDTH / 2)
+            as usize;
+
+        let (_, stats) = renderer.render(&scene, &camera);
+        assert!(stats.intersection_tests > 0);
+        let (_, stats) = renderer.render_accumulate(&scene, &camera, 2);
+        assert_eq!(stats.intersection_tests, 0);
+        assert_eq!(stats.primary_rays, pixels * 2);
+
+        // a material change only needs shading redone
+        scene.materials_mut()[glow.index()] = Material::Emissive {
+            emission: Vec3::new(0., 1., 0.),
+        };
+        renderer.reset_accumulation();
+        let (image, stats) = renderer.render(&scene, &camera);
+        assert_eq!(stats.intersection_tests, 0);
+        assert_eq!(image[center], color_rgb(Vec3::new(0., 1., 0.)));
+
+        *scene.hittable_mut(sphere) = Sphere {
+            center: Vec3::new(0.5, 0.5, 0.),
+            radius: 0.5,
+            material: glow,
+        }
+        .into();
+        let (_, stats) = renderer.render(&scene, &camera);
+        assert!(stats.intersection_tests > 0);
+    }
+
+    #[test]
+    fn render_for_fits_frames_in_the_budget() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let camera = test_fixtures::camera();
+
+        // one band of rows, which doesn't finish a frame
+        let (image, stats) = renderer.render_for(&scene, &camera, Duration::ZERO);
+        assert_eq!(image.len(), (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize);
+        assert_eq!(stats.frames, 0);
+        assert_eq!(renderer.frame_count(), 0.);
+
+        let (_, stats) = renderer.render_for(&scene, &camera, Duration::from_millis(200));
+        assert!(stats.frames >= 1);
+        assert_eq!(renderer.total_stats().frames, stats.frames);
+        assert_eq!(renderer.frame_count(), stats.frames as f32);
+        let pixels = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as u64;
+        assert_eq!(stats.primary_rays, stats.frames as u64 * pixels);
+        let stages: Vec<_> = stats.stage_times.iter().map(|(name, _)| *name).collect();
+        assert_eq!(stages, ["trace", "resolve"]);
+
+        // the totals carry on across resets
+        renderer.reset_accumulation();
+        renderer.render(&scene, &camera);
+        let totals = renderer.total_stats();
+        assert_eq!(totals.frames, stats.frames + 1);
+        assert_eq!(totals.primary_rays, stats.primary_rays + pixels);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn thread_changes_wait_for_the_next_frame() {
+        use super::ThreadPolicy;
+
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let camera = test_fixtures::camera();
+        assert_eq!(renderer.num_threads(), 1);
+
+        renderer.set_thread_policy(ThreadPolicy::Fixed(2));
+        assert_eq!(renderer.thread_policy(), ThreadPolicy::Fixed(2));
+        assert_eq!(renderer.num_threads(), 1);
+        assert!(renderer
+            .render_step(&scene, &camera, Duration::ZERO)
+            .is_none());
+        assert_eq!(renderer.num_threads(), 2);
+
+        // a frame in progress finishes on the threads it started on
+        renderer.set_thread_policy(ThreadPolicy::Fixed(3));
+        while renderer
+            .render_step(&scene, &camera, Duration::ZERO)
+            .is_none()
+        {
+            assert_eq!(renderer.num_threads(), 2);
+        }
+        renderer.render(&scene, &camera);
+        assert_eq!(renderer.num_threads(), 3);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn seeded_renders_dont_depend_on_threads() {
+        use super::ThreadPolicy;
+
+        let scene = test_fixtures::sphere_on_ground();
+        let render = |threads| {
+            let mut renderer = test_fixtures::renderer();
+            renderer.set_thread_policy(ThreadPolicy::Fixed(threads));
+            renderer.set_seed(Some(7));
+            renderer.render_accumulate(&scene, &test_fixtures::camera(), 4);
+            renderer.snapshot().hdr
+        };
+        let bits = |hdr: Vec<Vec4>| -> Vec<[u32; 4]> {
+            hdr.iter().map(|c| c.to_array().map(f32::to_bits)).collect()
+        };
+        assert_eq!(bits(render(1)), bits(render(3)));
+    }
+
+    #[cfg(all(feature = "parallel", target_os = "linux"))]
+    #[test]
+    fn low_priority_threads_are_niced() {
+        use super::Renderer;
+
+        let mut renderer = test_fixtures::renderer();
+        let nice = |renderer: &Renderer| {
+            renderer
+                .pool
+                .install(|| unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) })
+        };
+        let normal = nice(&renderer);
+
+        renderer.set_low_priority(true);
+        assert!(renderer.low_priority());
+        renderer.render(&test_fixtures::empty_scene(), &test_fixtures::camera());
+        assert!(nice(&renderer) > normal);
+        // the thread driving the renderer is left alone
+        assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, normal);
+    }
+
+    #[test]
+    fn depth_is_camera_space() {
+        let renderer = test_fixtures::renderer();
+        let scene = test_fixtures::single_sphere();
+        let depth = renderer.render_depth(&scene, &test_fixtures::camera());
+        assert_eq!(
+            depth.len(),
+            (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize
+        );
+        // the middle of the image sees the front of the sphere
+        let middle = depth[(test_fixtures::HEIGHT / 2 * test_fixtures::WIDTH
+            + test_fixtures::WIDTH / 2) as usize];
+        let expected = 4. - 0.75f32.sqrt();
+        assert!((middle - expected).abs() < 0.1, "{middle} != {expected}");
+        assert_eq!(depth[0], f32::INFINITY);
+    }
+
+    #[test]
+    fn stats_count_rays() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let (_, stats) = renderer.render_accumulate(&scene, &test_fixtures::camera(), 3);
+        let pixels = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as u64;
+        assert_eq!(stats.frames, 3);
+        assert_eq!(stats.primary_rays, pixels * 3);
+        assert!(stats.secondary_rays > 0);
+        // the BVH lets rays skip hittables they can't hit
+        assert!(stats.intersection_tests > 0);
+        assert!(stats.intersection_tests < stats.rays_traced() * scene.hittables().len() as u64);
+        assert_eq!(renderer.frame_count(), 3.);
+    }
+
+    #[test]
+    fn bounce_histogram_counts_every_path() {
+        let mut renderer = test_fixtures::renderer();
+        let (_, stats) = renderer.render(&test_fixtures::empty_scene(), &test_fixtures::camera());
+        assert_eq!(stats.bounce_histogram[0], stats.primary_rays);
+        assert!(stats.bounce_histogram[1..].iter().all(|count| *count == 0));
+
+        let scene = test_fixtures::sphere_on_ground();
+        let (_, stats) = renderer.render_accumulate(&scene, &test_fixtures::camera(), 2);
+        let histogram = &stats.bounce_histogram;
+        assert_eq!(histogram.iter().sum::<u64>(), stats.primary_rays);
+        let bounces = histogram.iter().enumerate().map(|(n, count)| n as u64 * count);
+        assert_eq!(bounces.sum::<u64>(), stats.secondary_rays);
+    }
+
+    #[test]
+    fn invalid_samples_are_left_out_or_marked() {
+        let mut renderer = test_fixtures::renderer();
+        let mut scene = Scene::default();
+        let broken = scene.add_material(Material::Emissive {
+            emission: Vec3::new(f32::NAN, 1., 1.),
+        });
+        scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0., -1000.),
+            radius: 990.,
+            material: broken,
+        });
+        let camera = test_fixtures::camera();
+
+        let (image, stats) = renderer.render(&scene, &camera);
+        assert_eq!(stats.invalid_samples, stats.primary_rays);
+        assert!(image.iter().all(|pixel| *pixel == 0));
+        assert!(renderer.snapshot().hdr.iter().all(|color| color.is_finite()));
+
+        renderer.mark_invalid_samples = true;
+        renderer.reset_accumulation();
+        let (image, _) = renderer.render(&scene, &camera);
+        assert!(image.iter().all(|pixel| *pixel == color_rgba(&INVALID_COLOR)));
+        assert_eq!(renderer.total_stats().invalid_samples, 2 * stats.primary_rays);
+    }
+
+    #[test]
+    fn trace_pixel_records_each_bounce() {
+        let renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let camera = test_fixtures::camera();
+        let (x, y) = (test_fixtures::WIDTH / 2, test_fixtures::HEIGHT / 2);
+
+        let trace = renderer.trace_pixel(&scene, &camera, x, y, 7);
+        assert!(!trace.has_invalid_values());
+        let first = &trace.bounces[0];
+        assert_eq!(from_real_vec3(first.ray.origin), camera.position());
+        let BounceHit::Surface { material, .. } = first.hit else {
+            panic!("the camera ray should hit the ball, not {:?}", first.hit);
+        };
+        assert_eq!(material, scene.hittable(1).material());
+        // every bounce but the last scatters
+        let (last, rest) = trace.bounces.split_last().unwrap();
+        assert!(rest.iter().all(|bounce| bounce.attenuation.is_some()));
+        assert!(last.attenuation.is_none() || trace.bounces.len() == MAX_BOUNCES as usize);
+        assert_eq!(trace.to_string(), renderer.trace_pixel(&scene, &camera, x, y, 7).to_string());
+        let segments = trace.segments(1.);
+        assert_eq!(segments.len(), trace.bounces.len());
+        assert_eq!(segments[0][0], camera.position());
+    }
+
+    #[test]
+    fn trace_batch_matches_raycast() {
+        let renderer = test_fixtures::renderer();
+        let scene = test_fixtures::sphere_on_ground();
+        let rays: Vec<_> = (0..64)
+            .map(|i| Ray::new(Vec3::new(0., 0.5, 4.), Vec3::new(i as f32 / 32. - 1., -0.3, -1.)))
+            .collect();
+        let clip = 0.001..100.;
+        let hits = renderer.trace_batch(&scene, &rays, &clip);
+        assert_eq!(hits.len(), rays.len());
+        for (ray, hit) in rays.iter().zip(&hits) {
+            assert_eq!(*hit, scene.raycast(ray, &clip));
+        }
+        assert!(hits.iter().any(Option::is_some));
+    }
+
+    #[test]
+    fn snapshot_matches_render() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::single_sphere();
+        let image = renderer
+            .render_accumulate(&scene, &test_fixtures::camera(), 2)
+            .0
+            .into_owned();
+        let snapshot = renderer.snapshot();
+        assert_eq!(snapshot.image, image);
+        assert_eq!(snapshot.hdr.len(), image.len());
+        assert_eq!(snapshot.frame_count, 2.);
+        // taking a snapshot doesn't disturb accumulation
+        assert_eq!(renderer.frame_count(), 2.);
+    }
+
+    #[test]
+    fn warm_start() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::empty_scene();
+        let camera = test_fixtures::camera();
+        let len = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize;
+
+        assert!(renderer.warm_start(&vec![Vec4::ONE; len - 1], 1.).is_err());
+        assert!(renderer.warm_start(&vec![Vec4::ONE; len], -1.).is_err());
+
+        // A warm start image of pure white, weighted equally to one frame of sky.
+        renderer.warm_start(&vec![Vec4::ONE; len], 1.).unwrap();
+        let (image, _) = renderer.render_accumulate(&scene, &camera, 1);
+        let expected = color_rgb((Vec3::ONE + test_fixtures::SKY_COLOR) / 2.);
+        assert!(image.iter().all(|pixel| *pixel == expected));
+        assert_eq!(renderer.frame_count(), 2.);
+    }
+
+    #[test]
+    fn blended_frames_fade_out_old_samples() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::empty_scene();
+        let camera = test_fixtures::camera();
+        let len = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize;
+        renderer.set_frame_blend(Some(0.25));
+
+        // however long white was accumulated, sky replaces a quarter of it
+        // each frame
+        renderer.warm_start(&vec![Vec4::ONE; len], 100.).unwrap();
+        let sky = test_fixtures::SKY_COLOR.extend(1.);
+        for frames in 1..=3 {
+            renderer.render(&scene, &camera);
+            let white = 0.75f32.powi(frames);
+            let expected = Vec4::ONE * white + sky * (1. - white);
+            assert!(renderer.snapshot().hdr.iter().all(|c| c.distance(expected) < 1e-5));
+        }
+
+        // until there are enough samples to blend, they're averaged
+        renderer.set_frame_blend(Some(0.1));
+        renderer.warm_start(&vec![Vec4::ONE; len], 1.).unwrap();
+        renderer.render(&scene, &camera);
+        let expected = (Vec4::ONE + sky) / 2.;
+        assert!(renderer.snapshot().hdr.iter().all(|c| c.distance(expected) < 1e-5));
+    }
+
+    #[test]
+    fn pixels_keep_their_own_sample_counts() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::empty_scene();
+        let camera = test_fixtures::camera();
+        let len = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize;
+        renderer.set_interleave(2);
+
+        // one frame of white everywhere, and then sky in only half the pixels
+        renderer.warm_start(&vec![Vec4::ONE; len], 1.).unwrap();
+        renderer.render(&scene, &camera);
+        assert_eq!(renderer.film().sample_count(0, 0), 2.);
+        assert_eq!(renderer.film().sample_count(1, 0), 1.);
+        let snapshot = renderer.snapshot();
+        let sky = test_fixtures::SKY_COLOR.extend(1.);
+        assert!(snapshot.hdr[0].distance((Vec4::ONE + sky) / 2.) < 1e-5);
+        assert_eq!(snapshot.hdr[1], Vec4::ONE);
+    }
+
+    #[test]
+    fn alpha_accumulates_premultiplied() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::empty_scene();
+        let camera = test_fixtures::camera();
+        let len = (test_fixtures::WIDTH * test_fixtures::HEIGHT) as usize;
+
+        // One frame of nothing at all, then one frame of opaque sky.
+        renderer.warm_start(&vec![Vec4::ZERO; len], 1.).unwrap();
+        let (image, _) = renderer.render_accumulate(&scene, &camera, 1);
+        let expected = color_rgba(&(test_fixtures::SKY_COLOR.extend(1.) / 2.));
+        assert!(image.iter().all(|pixel| *pixel == expected));
+        assert_eq!(image[0] >> 24, 127);
+
+        let snapshot = renderer.snapshot();
+        assert_eq!(snapshot.hdr[0].w, 0.5);
+        assert_eq!(snapshot.hdr[0].truncate(), test_fixtures::SKY_COLOR / 2.);
+    }
+
+    #[test]
+    fn progress_can_cancel() {
+        let mut renderer = test_fixtures::renderer();
+        let scene = test_fixtures::single_sphere();
+        let mut reported = Vec::new();
+        let (_, stats) =
+            renderer.render_with_progress(&scene, &test_fixtures::camera(), 10, |stats| {
+                reported.push(stats.frames);
+                if stats.frames == 3 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            });
+        assert_eq!(reported, vec![1, 2, 3]);
+        assert_eq!(stats.frames, 3);
+        assert_eq!(renderer.frame_count(), 3.);
     }
 }
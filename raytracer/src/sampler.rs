@@ -0,0 +1,55 @@
+//! Where the random numbers for each sample come from.
+
+use glam::Vec2;
+use rand::{rngs::SmallRng, Rng, RngCore, SeedableRng};
+
+/// The random numbers for one pixel's sample, for an
+/// [`Integrator`](crate::Integrator) to choose directions and the like
+/// with. It's an [`RngCore`], so anything in `rand` can draw from it too.
+pub struct Sampler {
+    rng: SmallRng,
+}
+
+impl Sampler {
+    /// Numbers drawn from `seed`, so the same seed gives the same numbers.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The numbers for `pixel` in a frame seeded with `frame_seed`. Every
+    /// frame has its own seed, so pixels get new samples each frame, and
+    /// mixing in the pixel keeps neighbouring pixels' noise unrelated.
+    pub(crate) fn for_pixel(frame_seed: u64, pixel: usize) -> Self {
+        Self::new(frame_seed ^ (pixel as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    /// A number from 0 up to 1.
+    pub fn next_1d(&mut self) -> f32 {
+        self.rng.gen()
+    }
+
+    /// A point in the unit square, each coordinate from 0 up to 1.
+    pub fn next_2d(&mut self) -> Vec2 {
+        Vec2::new(self.next_1d(), self.next_1d())
+    }
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
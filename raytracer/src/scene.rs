@@ -1,26 +1,200 @@
-use crate::{hittable::Hittable, material::Material};
+use crate::{
+    bvh::{Aabb, Bvh},
+    camera::CameraBookmark,
+    geom::{Ray, Transform},
+    hittable::{Hit, HitPayload, Hittable},
+    integrator::SKY_COLOR,
+    material::{Material, MaterialHandle},
+    texture::EnvironmentMap,
+};
 use glam::Vec3;
+use std::{
+    ops::{ControlFlow, Range},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock,
+    },
+};
+use tracing::info_span;
 
+mod builder;
+mod dsl;
+mod handle;
+mod library;
+mod observers;
+pub mod presets;
+mod spheres;
+mod validate;
+
+pub use builder::SceneBuilder;
+pub use handle::SceneHandle;
+pub use library::MaterialLibrary;
+use observers::Observers;
+pub use observers::{ObserverId, SceneChange};
+use spheres::Spheres;
+pub use validate::{Diagnostic, Severity, Subject};
+
+/// Identifies a node in a [`Scene`]'s hierarchy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// The node every other node descends from.
+    pub const ROOT: NodeId = NodeId(0);
+}
+
+/// A group of hittables and other nodes that move together.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Node {
+    pub name: String,
+    /// Placement relative to the parent node.
+    pub transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    hittables: Vec<usize>,
+}
+
+impl Node {
+    fn new(name: String, transform: Transform, parent: Option<NodeId>) -> Self {
+        Self {
+            name,
+            transform,
+            parent,
+            children: Vec::new(),
+            hittables: Vec::new(),
+        }
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// Indexes of the hittables attached directly to this node.
+    pub fn hittables(&self) -> &[usize] {
+        &self.hittables
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Scene {
+    nodes: Vec<Node>,
+    /// Hittables in the local space of the node they're attached to.
     hittables: Vec<Hittable>,
+    hittable_nodes: Vec<NodeId>,
     materials: Vec<Material>,
+    /// Saved camera views, so a composed shot can be found again.
+    #[cfg_attr(feature = "serde", serde(default))]
+    bookmarks: Vec<CameraBookmark>,
+    /// The light from far away, seen wherever rays miss everything.
+    #[cfg_attr(feature = "serde", serde(default))]
+    environment: Option<Arc<EnvironmentMap>>,
+    /// `hittables` moved into world space, built on demand for rendering.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    world_hittables: OnceLock<Vec<Hittable>>,
+    /// BVHs over `world_hittables`, built on demand with them.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    world_bvh: OnceLock<WorldBvh>,
+    /// The last BVHs, kept when only node transforms have changed since, so
+    /// they can be refit rather than rebuilt.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    stale_bvh: Option<WorldBvh>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    geometry_version: GeometryVersion,
+    #[cfg_attr(feature = "serde", serde(skip, default = "default_use_bvh"))]
+    use_bvh: bool,
+    /// Callbacks from [`Scene::on_change`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    observers: Observers,
+}
+
+#[cfg(feature = "serde")]
+fn default_use_bvh() -> bool {
+    true
+}
+
+/// What rays are traced against. Spheres are common and cheap to test, so
+/// they're kept apart from the other hittables, where they can be tested a
+/// leaf at a time.
+#[derive(Clone)]
+struct WorldBvh {
+    spheres: Spheres,
+    /// Over the hittables that aren't spheres, by their place in `others`.
+    bvh: Bvh,
+    /// Where each of those is in the world hittables.
+    others: Vec<usize>,
+}
+
+impl WorldBvh {
+    fn build(hittables: &[Hittable]) -> WorldBvh {
+        let others: Vec<usize> = (0..hittables.len())
+            .filter(|idx| !matches!(hittables[*idx], Hittable::Sphere(_)))
+            .collect();
+        WorldBvh {
+            spheres: Spheres::build(hittables),
+            bvh: Bvh::build(&Self::bounds(hittables, &others)),
+            others,
+        }
+    }
+
+    /// Fits the BVHs to hittables that have moved. There must be the same
+    /// hittables as it was built from.
+    fn refit(&mut self, hittables: &[Hittable]) {
+        self.spheres.refit(hittables);
+        self.bvh.refit(&Self::bounds(hittables, &self.others));
+    }
+
+    fn bounds(hittables: &[Hittable], indexes: &[usize]) -> Vec<Aabb> {
+        indexes.iter().map(|idx| hittables[*idx].bounding_box()).collect()
+    }
+}
+
+/// Identifies the state of a scene's geometry, uniquely among all scenes,
+/// so that things built from it can tell when it has changed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GeometryVersion(u64);
+
+impl Default for GeometryVersion {
+    /// A version no scene has had before.
+    fn default() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
 }
 
 impl Default for Scene {
     fn default() -> Self {
         Self {
+            nodes: vec![Node::new("Root".to_string(), Transform::IDENTITY, None)],
             hittables: Default::default(),
+            hittable_nodes: Default::default(),
             materials: vec![Material::Null],
+            bookmarks: Vec::new(),
+            environment: None,
+            world_hittables: OnceLock::new(),
+            world_bvh: OnceLock::new(),
+            stale_bvh: None,
+            geometry_version: GeometryVersion::default(),
+            use_bvh: true,
+            observers: Observers::default(),
         }
     }
 }
 
 impl Scene {
+    /// Hittables as they were added, relative to the node they're attached to.
     pub fn hittables(&self) -> &[Hittable] {
         self.hittables.as_slice()
     }
 
     pub fn hittables_mut(&mut self) -> &mut [Hittable] {
+        self.hittables_changed();
         &mut self.hittables
     }
 
@@ -28,9 +202,376 @@ impl Scene {
         &self.hittables[idx]
     }
 
+    pub fn hittable_mut(&mut self, idx: usize) -> &mut Hittable {
+        self.hittables_changed();
+        &mut self.hittables[idx]
+    }
+
+    /// All hittables with the transforms of their nodes applied, in the
+    /// same order as [`Scene::hittables`].
+    pub fn world_hittables(&self) -> &[Hittable] {
+        self.world_hittables.get_or_init(|| {
+            let _span = info_span!("world hittables", count = self.hittables.len()).entered();
+            let world_transforms = self.world_transforms();
+            self.hittables
+                .iter()
+                .zip(&self.hittable_nodes)
+                .map(|(hittable, node)| hittable.transformed(&world_transforms[node.0]))
+                .collect()
+        })
+    }
+
+    /// A box around all of the world hittables, such as for fitting the
+    /// scene in view with [`Camera::frame`](crate::Camera::frame). Empty if
+    /// there are no hittables.
+    pub fn bounds(&self) -> Aabb {
+        self.world_hittables()
+            .iter()
+            .map(Hittable::bounding_box)
+            .fold(Aabb::EMPTY, Aabb::union)
+    }
+
+    /// Find the closest surface along `ray`, for uses like picking and
+    /// collision probes. Only hits within `clip` count, measured in lengths
+    /// of `ray.direction`. Rays that start inside a hittable hit the back of
+    /// the surface where they leave it.
+    pub fn raycast(&self, ray: &Ray, clip: &Range<f32>) -> Option<Hit> {
+        let (hittable, hit) = self.trace_ray(ray, clip, &mut 0);
+        hit.into_hit(hittable)
+    }
+
+    /// Whether anything blocks `ray` within `clip`, like between a surface
+    /// and a light. Cheaper than [`Scene::raycast`], since it stops at the
+    /// first hit it finds rather than looking for the closest.
+    pub fn occluded(&self, ray: &Ray, clip: &Range<f32>) -> bool {
+        self.trace_occlusion(ray, clip, &mut 0)
+    }
+
+    /// [`Scene::occluded`], counting the hittables it tested in `tests`.
+    pub(crate) fn trace_occlusion(&self, ray: &Ray, clip: &Range<f32>, tests: &mut u64) -> bool {
+        let hittables = self.world_hittables();
+        if !self.use_bvh {
+            return hittables.iter().any(|hittable| {
+                *tests += 1;
+                hittable.check_hit(ray, clip) != HitPayload::Miss
+            });
+        }
+        let world = self.world_bvh();
+        world.spheres.occluded(ray, clip, tests)
+            || world
+                .bvh
+                .traverse(ray, &mut clip.clone(), |idx, clip| {
+                    *tests += 1;
+                    match hittables[world.others[idx]].check_hit(ray, clip) {
+                        HitPayload::Hit { .. } => ControlFlow::Break(()),
+                        HitPayload::Miss => ControlFlow::Continue(()),
+                    }
+                })
+                .is_some()
+    }
+
+    /// The closest hit along `ray`, and the index of the hittable it
+    /// belongs to. Counts the hittables it tested in `tests`.
+    pub(crate) fn trace_ray(
+        &self,
+        ray: &Ray,
+        clip: &Range<f32>,
+        tests: &mut u64,
+    ) -> (usize, HitPayload) {
+        if !self.use_bvh {
+            return self.trace_ray_without_bvh(ray, clip, tests);
+        }
+        let hittables = self.world_hittables();
+        let world = self.world_bvh();
+        let mut clip = clip.clone();
+        let mut closest = world.spheres.trace_ray(ray, &mut clip, tests);
+        world.bvh.traverse(ray, &mut clip, |idx, clip| {
+            let idx = world.others[idx];
+            *tests += 1;
+            let hit = hittables[idx].check_hit(ray, clip);
+            if let HitPayload::Hit { hit_distance, .. } = hit {
+                // only closer hits count from here on
+                clip.end = hit_distance;
+                closest = (idx, hit);
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        closest
+    }
+
+    /// [`Scene::trace_ray`] by testing every hittable in turn.
+    fn trace_ray_without_bvh(
+        &self,
+        ray: &Ray,
+        clip: &Range<f32>,
+        tests: &mut u64,
+    ) -> (usize, HitPayload) {
+        let mut clip = clip.clone();
+        let mut closest = (0, HitPayload::Miss);
+        for (idx, hittable) in self.world_hittables().iter().enumerate() {
+            *tests += 1;
+            match hittable.check_hit(ray, &clip) {
+                hit @ HitPayload::Hit { hit_distance, .. } => {
+                    clip.end = hit_distance;
+                    closest = (idx, hit);
+                }
+                HitPayload::Miss => {}
+            }
+        }
+        closest
+    }
+
+    pub fn use_bvh(&self) -> bool {
+        self.use_bvh
+    }
+
+    /// Whether rays are only tested against the hittables a BVH finds near
+    /// them. Turning it off tests every hittable, which is only ever
+    /// slower, but gives benchmarks something to compare against. On by
+    /// default, and not saved.
+    pub fn set_use_bvh(&mut self, use_bvh: bool) {
+        self.use_bvh = use_bvh;
+    }
+
+    /// The BVHs over [`Scene::world_hittables`].
+    fn world_bvh(&self) -> &WorldBvh {
+        self.world_bvh.get_or_init(|| {
+            let hittables = self.world_hittables();
+            let _span = info_span!("world bvh", refit = self.stale_bvh.is_some()).entered();
+            match &self.stale_bvh {
+                Some(stale) => {
+                    let mut bvh = stale.clone();
+                    bvh.refit(hittables);
+                    bvh
+                }
+                None => WorldBvh::build(hittables),
+            }
+        })
+    }
+
+    /// Forget everything built from the hittables after they change.
+    fn hittables_changed(&mut self) {
+        self.world_hittables.take();
+        self.world_bvh.take();
+        self.stale_bvh = None;
+        self.geometry_version = GeometryVersion::default();
+        self.observers.notify(SceneChange::Hittables);
+    }
+
+    /// Forget the hittables' world positions after nodes move. None are
+    /// added or removed, so the BVH over them only needs refitting.
+    fn transforms_changed(&mut self) {
+        self.world_hittables.take();
+        if let Some(bvh) = self.world_bvh.take() {
+            self.stale_bvh = Some(bvh);
+        }
+        self.geometry_version = GeometryVersion::default();
+        self.observers.notify(SceneChange::Nodes);
+    }
+
+    /// Changes whenever anything that decides where rays hit does, but
+    /// not for changes to materials.
+    pub(crate) fn geometry_version(&self) -> GeometryVersion {
+        self.geometry_version
+    }
+
+    /// Add a hittable to the root node.
     pub fn add_hittable<H: Into<Hittable>>(&mut self, hittable: H) -> usize {
-        self.hittables.push(hittable.into());
-        self.hittables.len() - 1
+        self.add_hittable_to(NodeId::ROOT, hittable)
+    }
+
+    /// Add a hittable positioned relative to `node`.
+    ///
+    /// If the hittable's material handle came from a different scene and is
+    /// out of range for this one, it's shaded with [`Material::FALLBACK`].
+    pub fn add_hittable_to<H: Into<Hittable>>(&mut self, node: NodeId, hittable: H) -> usize {
+        let hittable = hittable.into();
+        self.hittables_changed();
+        self.hittables.push(hittable);
+        self.hittable_nodes.push(node);
+        let idx = self.hittables.len() - 1;
+        self.nodes[node.0].hittables.push(idx);
+        idx
+    }
+
+    /// Add a copy of a hittable next to the original, attached to the same
+    /// node and using the same material. Returns the index of the copy.
+    pub fn duplicate_hittable(&mut self, idx: usize) -> usize {
+        let mut copy = self.hittables[idx].clone();
+        match &mut copy {
+            Hittable::Sphere(sphere) => sphere.center.x += sphere.radius * 2.,
+            Hittable::Quad(quad) => quad.corner += quad.u,
+            Hittable::Cylinder(cylinder) => cylinder.base.x += cylinder.radius * 2.,
+            Hittable::Cone(cone) => cone.base.x += cone.radius * 2.,
+            Hittable::Torus(torus) => {
+                torus.center.x += (torus.major_radius + torus.minor_radius) * 2.
+            }
+            Hittable::Sdf(sdf) => {
+                sdf.transform.translation.x +=
+                    sdf.shape.bounding_radius() * sdf.transform.scale * 2.
+            }
+            Hittable::Curve(curve) => {
+                let beside = Vec3::Z * curve.widths.into_iter().fold(0., f32::max) * 2.;
+                for point in &mut curve.points {
+                    *point += beside;
+                }
+            }
+            Hittable::Mesh(mesh) => {
+                let bounds = mesh.geometry.bvh().bounds();
+                let width = (bounds.max.x - bounds.min.x) * mesh.transform.scale;
+                mesh.transform.translation.x += width;
+            }
+            Hittable::Csg(csg) => {
+                let beside = Transform::from_translation(Vec3::X * 2.);
+                *csg.a = csg.a.transformed(&beside);
+                *csg.b = csg.b.transformed(&beside);
+            }
+            Hittable::Custom(custom) => {
+                let bounds = custom.shape.bounding_box();
+                let beside = Transform::from_translation(Vec3::X * (bounds.max.x - bounds.min.x));
+                *custom = custom.transformed(&beside);
+            }
+        }
+        self.add_hittable_to(self.hittable_nodes[idx], copy)
+    }
+
+    /// The node a hittable is attached to.
+    pub fn hittable_node(&self, idx: usize) -> NodeId {
+        self.hittable_nodes[idx]
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.transforms_changed();
+        &mut self.nodes[id.0]
+    }
+
+    pub fn add_node<S: Into<String>>(
+        &mut self,
+        parent: NodeId,
+        name: S,
+        transform: Transform,
+    ) -> NodeId {
+        self.transforms_changed();
+        let id = NodeId(self.nodes.len());
+        self.nodes
+            .push(Node::new(name.into(), transform, Some(parent)));
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    /// Move `node` to be a child of `new_parent`, keeping its local
+    /// transform. Fails if that would make a node its own ancestor.
+    pub fn set_parent(&mut self, node: NodeId, new_parent: NodeId) -> anyhow::Result<()> {
+        if node == NodeId::ROOT {
+            anyhow::bail!("the root node can't be reparented");
+        }
+        let mut ancestor = Some(new_parent);
+        while let Some(id) = ancestor {
+            if id == node {
+                anyhow::bail!("a node can't be parented to its own descendant");
+            }
+            ancestor = self.nodes[id.0].parent;
+        }
+
+        self.transforms_changed();
+        if let Some(old_parent) = self.nodes[node.0].parent {
+            self.nodes[old_parent.0]
+                .children
+                .retain(|child| *child != node);
+        }
+        self.nodes[new_parent.0].children.push(node);
+        self.nodes[node.0].parent = Some(new_parent);
+        Ok(())
+    }
+
+    /// The transform from a node's local space to world space.
+    pub fn world_transform(&self, id: NodeId) -> Transform {
+        let node = &self.nodes[id.0];
+        match node.parent {
+            Some(parent) => self.world_transform(parent).mul_transform(&node.transform),
+            None => node.transform,
+        }
+    }
+
+    /// Visit every node depth first, parents before children, along with
+    /// its depth in the tree.
+    pub fn walk<F: FnMut(NodeId, &Node, usize)>(&self, mut visit: F) {
+        let mut stack = vec![(NodeId::ROOT, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[id.0];
+            visit(id, node, depth);
+            stack.extend(node.children.iter().rev().map(|child| (*child, depth + 1)));
+        }
+    }
+
+    /// World transforms for every node, indexed the same as `nodes`.
+    fn world_transforms(&self) -> Vec<Transform> {
+        let mut transforms = vec![Transform::IDENTITY; self.nodes.len()];
+        self.walk(|id, node, _| {
+            transforms[id.0] = match node.parent {
+                Some(parent) => transforms[parent.0].mul_transform(&node.transform),
+                None => node.transform,
+            };
+        });
+        transforms
+    }
+
+    /// Parse a scene from its RON representation.
+    #[cfg(feature = "serde")]
+    pub fn from_ron(source: &str) -> anyhow::Result<Self> {
+        let _span = info_span!("parse scene", bytes = source.len()).entered();
+        let scene: Self = ron::from_str(source)?;
+        scene.validate_materials()?;
+        Ok(scene)
+    }
+
+    /// Check that every hittable refers to a material that exists, so that
+    /// mistakes in scene files are reported instead of shown in magenta.
+    #[cfg(feature = "serde")]
+    fn validate_materials(&self) -> anyhow::Result<()> {
+        for (idx, hittable) in self.hittables.iter().enumerate() {
+            let material = hittable.material();
+            if self.material_handle(material.index()).is_none() {
+                anyhow::bail!(
+                    "object {idx} uses material {}, but there are only {} materials",
+                    material.index(),
+                    self.materials.len()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        let _span = info_span!("serialize scene").entered();
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Read a scene from a `.ron` file.
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let path = path.as_ref();
+        let _span = info_span!("load scene", path = %path.display()).entered();
+        let source =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        Self::from_ron(&source).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let _span = info_span!("save scene", path = %path.display()).entered();
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
     }
 
     pub fn materials(&self) -> &[Material] {
@@ -38,23 +579,83 @@ impl Scene {
     }
 
     pub fn materials_mut(&mut self) -> &mut [Material] {
+        self.observers.notify(SceneChange::Materials);
         &mut self.materials
     }
 
-    pub fn material(&self, idx: usize) -> &Material {
-        &self.materials[idx]
+    /// The material `handle` refers to, or [`Material::FALLBACK`] if it
+    /// isn't one of this scene's materials.
+    pub fn material(&self, handle: MaterialHandle) -> &Material {
+        self.materials.get(handle.0).unwrap_or(&Material::FALLBACK)
+    }
+
+    /// The handle for the material at `idx` in [`Scene::materials`], if
+    /// there is one.
+    pub fn material_handle(&self, idx: usize) -> Option<MaterialHandle> {
+        (idx < self.materials.len()).then_some(MaterialHandle(idx))
     }
 
-    pub fn add_material(&mut self, material: Material) -> usize {
+    pub fn add_material(&mut self, material: Material) -> MaterialHandle {
+        self.observers.notify(SceneChange::Materials);
         self.materials.push(material);
-        self.materials.len() - 1
+        MaterialHandle(self.materials.len() - 1)
+    }
+
+    pub fn bookmarks(&self) -> &[CameraBookmark] {
+        &self.bookmarks
+    }
+
+    pub fn bookmarks_mut(&mut self) -> &mut [CameraBookmark] {
+        self.observers.notify(SceneChange::Bookmarks);
+        &mut self.bookmarks
+    }
+
+    pub fn add_bookmark(&mut self, bookmark: CameraBookmark) -> usize {
+        self.observers.notify(SceneChange::Bookmarks);
+        self.bookmarks.push(bookmark);
+        self.bookmarks.len() - 1
+    }
+
+    pub fn remove_bookmark(&mut self, idx: usize) -> CameraBookmark {
+        self.observers.notify(SceneChange::Bookmarks);
+        self.bookmarks.remove(idx)
+    }
+
+    pub fn environment(&self) -> Option<&Arc<EnvironmentMap>> {
+        self.environment.as_ref()
+    }
+
+    /// Light the scene with `environment` from far away in every direction,
+    /// or with a plain sky if it's `None`.
+    pub fn set_environment(&mut self, environment: Option<Arc<EnvironmentMap>>) {
+        self.observers.notify(SceneChange::Environment);
+        self.environment = environment;
+    }
+
+    /// The light arriving from far away, looking along `direction`.
+    pub(crate) fn sky(&self, direction: Vec3) -> Vec3 {
+        match &self.environment {
+            Some(environment) => environment.color(direction),
+            None => SKY_COLOR,
+        }
+    }
+
+    /// The light arriving from far away, averaged over every direction.
+    pub(crate) fn sky_average(&self) -> Vec3 {
+        match &self.environment {
+            Some(environment) => environment.average(),
+            None => SKY_COLOR,
+        }
     }
 }
 
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sphere {
     pub center: Vec3,
     pub radius: f32,
-    pub material_index: usize,
+    #[cfg_attr(feature = "serde", serde(alias = "material_index"))]
+    pub material: MaterialHandle,
 }
 
 impl Default for Sphere {
@@ -62,7 +663,940 @@ impl Default for Sphere {
         Self {
             center: Vec3::ZERO,
             radius: 1.0,
-            material_index: 0,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// A flat parallelogram with one corner at `corner` and sides along `u` and
+/// `v`, like a rectangle when the sides are perpendicular. Both faces can be
+/// hit, with the front facing along `u × v`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: MaterialHandle,
+}
+
+impl Quad {
+    pub fn center(&self) -> Vec3 {
+        self.corner + (self.u + self.v) / 2.
+    }
+
+    /// Points out of the front face.
+    pub fn normal(&self) -> Vec3 {
+        self.u.cross(self.v).normalize_or_zero()
+    }
+}
+
+/// A cylinder from `base` to `base + axis`. Without caps it's an open tube,
+/// which can be hit from inside.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cylinder {
+    /// The center of the bottom end.
+    pub base: Vec3,
+    /// From the center of the bottom end to the center of the top.
+    pub axis: Vec3,
+    pub radius: f32,
+    /// Whether the ends are closed with flat disks.
+    pub capped: bool,
+    pub material: MaterialHandle,
+}
+
+impl Default for Cylinder {
+    fn default() -> Self {
+        Self {
+            base: Vec3::ZERO,
+            axis: Vec3::Y,
+            radius: 0.5,
+            capped: true,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// A cone with its base at `base` and its tip at `base + axis`.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cone {
+    /// The center of the base.
+    pub base: Vec3,
+    /// From the center of the base to the tip.
+    pub axis: Vec3,
+    /// The radius of the base.
+    pub radius: f32,
+    /// Whether the base is closed with a flat disk.
+    pub capped: bool,
+    pub material: MaterialHandle,
+}
+
+impl Default for Cone {
+    fn default() -> Self {
+        Self {
+            base: Vec3::ZERO,
+            axis: Vec3::Y,
+            radius: 0.5,
+            capped: true,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// A ring-shaped tube, like a donut.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Torus {
+    pub center: Vec3,
+    /// The direction through the hole.
+    pub axis: Vec3,
+    /// From the center to the middle of the tube.
+    pub major_radius: f32,
+    /// The radius of the tube.
+    pub minor_radius: f32,
+    pub material: MaterialHandle,
+}
+
+impl Default for Torus {
+    fn default() -> Self {
+        Self {
+            center: Vec3::ZERO,
+            axis: Vec3::Y,
+            major_radius: 1.,
+            minor_radius: 0.25,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// A strand swept along a cubic Bézier curve, for thin things like wires,
+/// grass, and hair. Curves have no inside, so rays hit them from any side.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Curve {
+    /// The curve starts at the first point and ends at the last, and bends
+    /// towards the two in between.
+    pub points: [Vec3; 4],
+    /// The width at each control point, blended along the curve the same
+    /// way as the points.
+    pub widths: [f32; 4],
+    pub shape: CurveShape,
+    pub material: MaterialHandle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CurveShape {
+    /// A ribbon that always faces the ray, which is cheap and looks right
+    /// for strands too thin to see the shading across.
+    Flat,
+    /// A tube, shaded like a cylinder.
+    Round,
+}
+
+impl Curve {
+    /// The point `u` of the way along the curve, from 0 to 1.
+    pub fn point(&self, u: f32) -> Vec3 {
+        let [p0, p1, p2, p3] = self.points;
+        let v = 1. - u;
+        p0 * (v * v * v) + p1 * (3. * v * v * u) + p2 * (3. * v * u * u) + p3 * (u * u * u)
+    }
+
+    /// The direction of the curve `u` of the way along it.
+    pub fn tangent(&self, u: f32) -> Vec3 {
+        let [p0, p1, p2, p3] = self.points;
+        let v = 1. - u;
+        let tangent = (p1 - p0) * (v * v) + (p2 - p1) * (2. * v * u) + (p3 - p2) * (u * u);
+        tangent.try_normalize().unwrap_or(Vec3::X)
+    }
+}
+
+impl Default for Curve {
+    /// A straight strand along X, one unit long.
+    fn default() -> Self {
+        Self {
+            points: [-0.5, -1. / 6., 1. / 6., 0.5].map(|x| Vec3::X * x),
+            widths: [0.05; 4],
+            shape: CurveShape::Round,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// Two hittables combined as solids, like a sphere with a hole drilled
+/// through it. Both should be closed, so every ray that goes into one comes
+/// back out, and the whole surface uses the combination's own material.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub a: Box<Hittable>,
+    pub b: Box<Hittable>,
+    pub material: MaterialHandle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CsgOperation {
+    /// Inside either shape.
+    Union,
+    /// Inside both shapes.
+    Intersection,
+    /// Inside the first shape but not the second.
+    Difference,
+}
+
+impl CsgOperation {
+    pub fn contains(self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_a || inside_b,
+            CsgOperation::Intersection => inside_a && inside_b,
+            CsgOperation::Difference => inside_a && !inside_b,
+        }
+    }
+}
+
+impl Csg {
+    pub fn new(
+        operation: CsgOperation,
+        a: impl Into<Hittable>,
+        b: impl Into<Hittable>,
+        material: MaterialHandle,
+    ) -> Self {
+        Self {
+            operation,
+            a: Box::new(a.into()),
+            b: Box::new(b.into()),
+            material,
+        }
+    }
+
+    pub fn union(a: impl Into<Hittable>, b: impl Into<Hittable>, material: MaterialHandle) -> Self {
+        Self::new(CsgOperation::Union, a, b, material)
+    }
+
+    pub fn intersection(
+        a: impl Into<Hittable>,
+        b: impl Into<Hittable>,
+        material: MaterialHandle,
+    ) -> Self {
+        Self::new(CsgOperation::Intersection, a, b, material)
+    }
+
+    /// `a` with `b` cut out of it.
+    pub fn difference(
+        a: impl Into<Hittable>,
+        b: impl Into<Hittable>,
+        material: MaterialHandle,
+    ) -> Self {
+        Self::new(CsgOperation::Difference, a, b, material)
+    }
+}
+
+impl Default for Quad {
+    /// A unit square in the XZ plane, facing up.
+    fn default() -> Self {
+        Self {
+            corner: Vec3::new(-0.5, 0., -0.5),
+            u: Vec3::Z,
+            v: Vec3::X,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        presets::{self, Preset},
+        Cone, Csg, Curve, CurveShape, Cylinder, NodeId, Quad, Scene, Torus,
+    };
+    use crate::{
+        hittable::HitPayload, FaceSide, Hittable, Material, MaterialHandle, Mesh, Ray, Sdf,
+        SdfShape, Sphere, Transform,
+    };
+    use float_eq::assert_float_eq;
+    use glam::{Quat, Vec2, Vec3};
+    use std::f32::consts::FRAC_PI_4;
+
+    fn world_center(scene: &Scene, idx: usize) -> Vec3 {
+        center(&scene.world_hittables()[idx])
+    }
+
+    fn center(hittable: &Hittable) -> Vec3 {
+        match hittable {
+            Hittable::Sphere(sphere) => sphere.center,
+            Hittable::Quad(quad) => quad.center(),
+            Hittable::Cylinder(cylinder) => cylinder.base + cylinder.axis / 2.,
+            Hittable::Cone(cone) => cone.base,
+            Hittable::Torus(torus) => torus.center,
+            Hittable::Sdf(sdf) => sdf.transform.translation,
+            Hittable::Curve(curve) => curve.point(0.5),
+            Hittable::Mesh(mesh) => mesh.transform.translation,
+            Hittable::Csg(csg) => center(&csg.a),
+            Hittable::Custom(custom) => custom.shape.bounding_box().center(),
+        }
+    }
+
+    #[test]
+    fn moving_parent_moves_children() {
+        let mut scene = Scene::default();
+        let group = scene.add_node(NodeId::ROOT, "group", Transform::IDENTITY);
+        let sub_group = scene.add_node(group, "sub group", Transform::from_translation(Vec3::Y));
+        let a = scene.add_hittable_to(group, Sphere::default());
+        let b = scene.add_hittable_to(sub_group, Sphere::default());
+        let c = scene.add_hittable(Sphere::default());
+        assert_eq!(world_center(&scene, b), Vec3::Y);
+
+        scene.node_mut(group).transform.translation = Vec3::X;
+        assert_eq!(world_center(&scene, a), Vec3::X);
+        assert_eq!(world_center(&scene, b), Vec3::new(1., 1., 0.));
+        assert_eq!(world_center(&scene, c), Vec3::ZERO);
+    }
+
+    #[test]
+    fn reparenting() {
+        let mut scene = Scene::default();
+        let a = scene.add_node(NodeId::ROOT, "a", Transform::from_translation(Vec3::X));
+        let b = scene.add_node(NodeId::ROOT, "b", Transform::from_translation(Vec3::Y));
+        let sphere = scene.add_hittable_to(b, Sphere::default());
+
+        scene.set_parent(b, a).unwrap();
+        assert_eq!(scene.node(a).children(), &[b]);
+        assert_eq!(scene.node(NodeId::ROOT).children(), &[a]);
+        assert_eq!(world_center(&scene, sphere), Vec3::new(1., 1., 0.));
+
+        assert!(scene.set_parent(a, b).is_err());
+        assert!(scene.set_parent(a, a).is_err());
+        assert!(scene.set_parent(NodeId::ROOT, a).is_err());
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut scene = Scene::default();
+        let group = scene.add_node(NodeId::ROOT, "group", Transform::from_translation(Vec3::Y));
+        let material = scene.add_material(Material::Null);
+        let original = scene.add_hittable_to(
+            group,
+            Sphere {
+                center: Vec3::ZERO,
+                radius: 0.5,
+                material,
+            },
+        );
+        let copy = scene.duplicate_hittable(original);
+        assert_eq!(copy, original + 1);
+        assert_eq!(scene.hittable_node(copy), group);
+        assert_eq!(scene.node(group).hittables(), &[original, copy]);
+        assert_eq!(world_center(&scene, copy), Vec3::new(1., 1., 0.));
+        let Hittable::Sphere(sphere) = scene.hittable(copy) else {
+            panic!("copy should be a sphere");
+        };
+        assert_eq!(sphere.radius, 0.5);
+        assert_eq!(sphere.material, material);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ron_round_trip() {
+        let mut scene = crate::presets::demo();
+        let group = scene.add_node(NodeId::ROOT, "group", Transform::from_translation(Vec3::Y));
+        scene.add_hittable_to(group, Sphere::default());
+        scene.add_bookmark(crate::Camera::default().bookmark("start"));
+
+        let loaded = Scene::from_ron(&scene.to_ron().unwrap()).unwrap();
+        assert_eq!(loaded.bookmarks(), scene.bookmarks());
+        assert_eq!(loaded.hittables().len(), scene.hittables().len());
+        assert_eq!(loaded.materials().len(), scene.materials().len());
+        assert_eq!(loaded.node(group).name, "group");
+        let last = scene.hittables().len() - 1;
+        assert_eq!(world_center(&loaded, last), world_center(&scene, last));
+    }
+
+    #[test]
+    fn material_handles() {
+        let mut scene = Scene::default();
+        let handle = scene.add_material(Material::Null);
+        assert_eq!(scene.material_handle(handle.index()), Some(handle));
+        assert_eq!(scene.material_handle(handle.index() + 1), None);
+        assert_eq!(scene.material_handle(0), Some(MaterialHandle::NULL));
+    }
+
+    #[test]
+    fn foreign_material_handle() {
+        let mut other = Scene::default();
+        other.add_material(Material::Null);
+        let material = other.add_material(Material::Null);
+
+        let mut scene = Scene::default();
+        let idx = scene.add_hittable(Sphere {
+            material,
+            ..Sphere::default()
+        });
+        let material = scene.material(scene.hittables()[idx].material());
+        // the magenta of Material::FALLBACK
+        let magenta = Vec3::new(1., 0., 1.);
+        assert!(matches!(material, Material::Emissive { emission } if *emission == magenta));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ron_rejects_missing_material() {
+        let mut scene = Scene::default();
+        let material = scene.add_material(Material::Null);
+        scene.add_hittable(Sphere {
+            material,
+            ..Sphere::default()
+        });
+        let source = scene.to_ron().unwrap();
+        let source = source.replacen(&format!("material: {}", material.index()), "material: 7", 1);
+        let err = Scene::from_ron(&source).map(drop).unwrap_err();
+        assert!(err.to_string().contains("material 7"), "{err}");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ron_rejects_height_maps_of_the_wrong_size() {
+        let mut scene = Scene::default();
+        scene.add_material(Material::Bump {
+            material: Box::new(Material::Null),
+            heights: crate::HeightMap::new(2, 2, vec![0.; 4]).unwrap(),
+            strength: 1.,
+        });
+        let source = scene.to_ron().unwrap();
+        assert!(Scene::from_ron(&source).is_ok());
+        for (from, to) in [("width: 2", "width: 3"), ("width: 2", "width: 0")] {
+            let source = source.replacen(from, to, 1);
+            let err = Scene::from_ron(&source).map(drop).unwrap_err();
+            assert!(format!("{err:#}").contains("height map"), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn walk_order() {
+        let mut scene = Scene::default();
+        let a = scene.add_node(NodeId::ROOT, "a", Transform::IDENTITY);
+        scene.add_node(a, "a1", Transform::IDENTITY);
+        scene.add_node(NodeId::ROOT, "b", Transform::IDENTITY);
+        let mut visited = Vec::new();
+        scene.walk(|_, node, depth| visited.push((node.name.clone(), depth)));
+        assert_eq!(
+            visited,
+            vec![
+                ("Root".to_string(), 0),
+                ("a".to_string(), 1),
+                ("a1".to_string(), 2),
+                ("b".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn raycast_finds_closest_hit() {
+        let mut scene = Scene::default();
+        let material = scene.add_material(Material::Null);
+        let far = scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0., -10.),
+            radius: 1.,
+            material,
+        });
+        let near = scene.add_hittable(Sphere {
+            center: Vec3::new(0., 0., -5.),
+            radius: 1.,
+            material,
+        });
+        let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+
+        let hit = scene.raycast(&ray, &(0.01..100.)).unwrap();
+        assert_eq!(hit.hittable, near);
+        assert_eq!(hit.distance, 4.);
+        assert_eq!(hit.position, Vec3::new(0., 0., -4.));
+        assert_eq!(hit.normal, Vec3::Z);
+        assert_eq!(hit.side, FaceSide::Front);
+
+        // clipping out the near sphere finds the far one
+        let hit = scene.raycast(&ray, &(7.0..100.)).unwrap();
+        assert_eq!(hit.hittable, far);
+        assert!(scene.raycast(&ray, &(0.01..3.)).is_none());
+
+        let inside = Ray::new(Vec3::new(0., 0., -5.), ray.direction);
+        let hit = scene.raycast(&inside, &(0.01..100.)).unwrap();
+        assert_eq!(hit.hittable, near);
+        assert_eq!(hit.distance, 1.);
+        assert_eq!(hit.position, Vec3::new(0., 0., -6.));
+        assert_eq!(hit.normal, Vec3::Z);
+        assert_eq!(hit.side, FaceSide::Back);
+    }
+
+    #[test]
+    fn quads_are_two_sided() {
+        let mut scene = Scene::default();
+        let quad = scene.add_hittable(Quad {
+            corner: Vec3::new(-1., -1., -5.),
+            u: Vec3::X * 2.,
+            v: Vec3::Y * 2.,
+            material: MaterialHandle::NULL,
+        });
+        let ray = Ray::new(Vec3::new(0.5, 0.5, 0.), Vec3::NEG_Z);
+
+        let hit = scene.raycast(&ray, &(0.01..100.)).unwrap();
+        assert_eq!(hit.hittable, quad);
+        assert_eq!(hit.position, Vec3::new(0.5, 0.5, -5.));
+        assert_eq!(hit.normal, Vec3::Z);
+        assert_eq!(hit.side, FaceSide::Front);
+        assert_eq!(hit.uv, glam::Vec2::new(0.75, 0.75));
+
+        let behind = Ray::new(Vec3::new(0.5, 0.5, -10.), Vec3::Z);
+        let hit = scene.raycast(&behind, &(0.01..100.)).unwrap();
+        assert_eq!(hit.normal, Vec3::NEG_Z);
+        assert_eq!(hit.side, FaceSide::Back);
+
+        let beside = Ray::new(Vec3::new(1.5, 0., 0.), ray.direction);
+        assert!(scene.raycast(&beside, &(0.01..100.)).is_none());
+    }
+
+    fn down(origin: Vec3) -> Ray {
+        Ray::new(origin, Vec3::NEG_Y)
+    }
+
+    #[test]
+    fn cylinders_have_optional_caps() {
+        let mut scene = Scene::default();
+        scene.add_hittable(Cylinder {
+            base: Vec3::new(0., 0., -5.),
+            axis: Vec3::Y * 2.,
+            radius: 1.,
+            ..Cylinder::default()
+        });
+        let side = Ray::new(Vec3::new(0., 1., 0.), Vec3::NEG_Z);
+        let hit = scene.raycast(&side, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 4., abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [0., 0., 1.], abs <= [0.0001; 3]);
+
+        let top = down(Vec3::new(0., 5., -5.));
+        let hit = scene.raycast(&top, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 3., abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [0., 1., 0.], abs <= [0.0001; 3]);
+
+        let Hittable::Cylinder(cylinder) = scene.hittable_mut(0) else {
+            unreachable!();
+        };
+        cylinder.capped = false;
+        assert!(scene.raycast(&top, &(0.01..100.)).is_none());
+    }
+
+    #[test]
+    fn cones_narrow_to_a_point() {
+        let mut scene = Scene::default();
+        scene.add_hittable(Cone {
+            base: Vec3::new(0., 0., -5.),
+            axis: Vec3::Y * 2.,
+            radius: 1.,
+            ..Cone::default()
+        });
+        let halfway = Ray::new(Vec3::new(0., 1., 0.), Vec3::NEG_Z);
+        let hit = scene.raycast(&halfway, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 4.5, abs <= 0.0001);
+        let expected = Vec3::new(0., 0.25, 0.5).normalize();
+        assert_float_eq!(hit.normal.to_array(), expected.to_array(), abs <= [0.0001; 3]);
+
+        let below = Ray::new(Vec3::new(0., -5., -5.), Vec3::Y);
+        let hit = scene.raycast(&below, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 5., abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [0., -1., 0.], abs <= [0.0001; 3]);
+
+        let beside_tip = down(Vec3::new(0.6, 5., -5.));
+        let hit = scene.raycast(&beside_tip, &(0.01..100.)).unwrap();
+        // the side is 0.4 from the axis 0.8 above the base
+        assert_float_eq!(hit.distance, 5. - 0.8, abs <= 0.0001);
+    }
+
+    #[test]
+    fn torus_has_a_hole() {
+        let mut scene = Scene::default();
+        scene.add_hittable(Torus {
+            center: Vec3::ZERO,
+            axis: Vec3::Y,
+            major_radius: 1.,
+            minor_radius: 0.25,
+            material: MaterialHandle::NULL,
+        });
+
+        let hit = scene
+            .raycast(&down(Vec3::new(1., 5., 0.)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 4.75, abs <= 0.001);
+        assert_float_eq!(hit.normal.to_array(), [0., 1., 0.], abs <= [0.001; 3]);
+
+        let side = Ray::new(Vec3::new(-5., 0., 0.), Vec3::X);
+        let hit = scene.raycast(&side, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 3.75, abs <= 0.001);
+        assert_float_eq!(hit.normal.to_array(), [-1., 0., 0.], abs <= [0.001; 3]);
+
+        let through_hole = down(Vec3::new(0., 5., 0.));
+        assert!(scene.raycast(&through_hole, &(0.01..100.)).is_none());
+
+        // tilting the axis stands the torus on its edge
+        let Hittable::Torus(torus) = scene.hittable_mut(0) else {
+            unreachable!();
+        };
+        torus.axis = Vec3::Z;
+        let hit = scene.raycast(&through_hole, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 3.75, abs <= 0.001);
+    }
+
+    #[cfg(feature = "f64")]
+    #[test]
+    fn planets_are_hit_precisely() {
+        let mut scene = Scene::default();
+        let radius = 6.4e6;
+        scene.add_hittable(Sphere {
+            center: Vec3::NEG_Y * radius,
+            radius,
+            material: MaterialHandle::NULL,
+        });
+
+        let hit = scene
+            .raycast(&down(Vec3::new(3., 1.5, -2.)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 1.5, abs <= 0.01);
+        assert_float_eq!(hit.position.y, 0., abs <= 0.01);
+    }
+
+    #[cfg(feature = "f64")]
+    #[test]
+    fn grazing_bounces_leave_planets() {
+        let mut scene = Scene::default();
+        let radius = 6.4e6;
+        scene.add_hittable(Sphere {
+            center: Vec3::ZERO,
+            radius,
+            material: MaterialHandle::NULL,
+        });
+
+        let ray = down(Vec3::Y * (radius + 1.5));
+        let hit = scene.raycast(&ray, &(0.01..100.)).unwrap();
+        let grazing = Vec3::new(1., 1e-3, 0.).normalize();
+        let bounce = Ray::spawn(ray.at(hit.distance), hit.normal, grazing);
+        assert!(bounce.origin.y - f64::from(radius) < 0.1);
+        assert!(!scene.occluded(&bounce, &(0.0..1000.)));
+    }
+
+    #[test]
+    fn sdfs_are_sphere_traced() {
+        let mut scene = Scene::default();
+        let blob = SdfShape::sphere(1.)
+            .smooth_union(SdfShape::sphere(1.).translated(Vec3::X * 2.), 0.5)
+            .subtract(SdfShape::capsule(Vec3::NEG_Y * 2., Vec3::Y * 2., 0.25));
+        scene.add_hittable(Sdf {
+            shape: blob,
+            transform: Transform {
+                translation: Vec3::Z,
+                scale: 2.,
+                ..Transform::IDENTITY
+            },
+            material: MaterialHandle::NULL,
+        });
+
+        let hit = scene
+            .raycast(&down(Vec3::new(-1., 5., 1.)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 5. - 3f32.sqrt(), abs <= 0.001);
+        let expected = [-0.5, 3f32.sqrt() / 2., 0.];
+        assert_float_eq!(hit.normal.to_array(), expected, abs <= [0.001; 3]);
+        assert_eq!(hit.side, FaceSide::Front);
+
+        // the blend fills in the gap where the spheres only touch
+        let hit = scene
+            .raycast(&down(Vec3::new(2., 5., 1.)), &(0.01..100.))
+            .unwrap();
+        assert!(hit.position.y > 0.5);
+
+        // the capsule is drilled through the middle of the first sphere
+        assert!(scene
+            .raycast(&down(Vec3::new(0., 5., 1.)), &(0.01..100.))
+            .is_none());
+
+        // and rays from inside find their way out
+        let inside = Ray::new(Vec3::new(-1., 0., 1.), Vec3::NEG_X);
+        let hit = scene.raycast(&inside, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 1., abs <= 0.001);
+        assert_eq!(hit.side, FaceSide::Back);
+    }
+
+    #[test]
+    fn csg_drills_a_hole() {
+        let mut scene = Scene::default();
+        let drill = Cylinder {
+            base: Vec3::NEG_Y * 2.,
+            axis: Vec3::Y * 4.,
+            radius: 0.25,
+            ..Cylinder::default()
+        };
+        scene.add_hittable(Csg::difference(
+            Sphere::default(),
+            drill,
+            MaterialHandle::NULL,
+        ));
+
+        assert!(scene
+            .raycast(&down(Vec3::new(0., 5., 0.)), &(0.01..100.))
+            .is_none());
+        let hit = scene
+            .raycast(&down(Vec3::new(0.6, 5., 0.)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 4.2, abs <= 0.0001);
+        assert_eq!(hit.side, FaceSide::Front);
+
+        // from inside the solid, out into the hole
+        let into_hole = Ray::new(Vec3::new(0.6, 0., 0.), Vec3::NEG_X);
+        let hit = scene.raycast(&into_hole, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 0.35, abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [1., 0., 0.], abs <= [0.0001; 3]);
+        assert_eq!(hit.side, FaceSide::Back);
+
+        // from inside the hole, into the far wall of it
+        let across_hole = Ray::new(Vec3::ZERO, Vec3::X);
+        let hit = scene.raycast(&across_hole, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 0.25, abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [-1., 0., 0.], abs <= [0.0001; 3]);
+        assert_eq!(hit.side, FaceSide::Front);
+    }
+
+    #[test]
+    fn csg_unions_and_intersections() {
+        let a = Sphere {
+            center: Vec3::NEG_X * 0.5,
+            ..Sphere::default()
+        };
+        let b = Sphere {
+            center: Vec3::X * 0.5,
+            ..Sphere::default()
+        };
+        let along_x = Ray::new(Vec3::NEG_X * 5., Vec3::X);
+
+        let mut union = Scene::default();
+        union.add_hittable(Csg::union(a.clone(), b.clone(), MaterialHandle::NULL));
+        let hit = union.raycast(&along_x, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 3.5, abs <= 0.0001);
+        // skips the surface of `b` that's inside `a`
+        let hit = union.raycast(&along_x, &(hit.distance + 0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 6.5, abs <= 0.0001);
+        assert_eq!(hit.side, FaceSide::Back);
+
+        let mut intersection = Scene::default();
+        intersection.add_hittable(Csg::intersection(a, b, MaterialHandle::NULL));
+        let hit = intersection.raycast(&along_x, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 4.5, abs <= 0.0001);
+        let hit = intersection
+            .raycast(&along_x, &(hit.distance + 0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 5.5, abs <= 0.0001);
+
+        let beside_lens = down(Vec3::new(0.9, 5., 0.));
+        assert!(intersection.raycast(&beside_lens, &(0.01..100.)).is_none());
+    }
+
+    #[test]
+    fn curves_are_thin_tubes() {
+        let mut scene = Scene::default();
+        let straight = scene.add_hittable(Curve {
+            points: [-1., -1. / 3., 1. / 3., 1.].map(|x| Vec3::X * x),
+            widths: [0.2, 0.2, 0.2, 0.],
+            ..Curve::default()
+        });
+
+        let hit = scene
+            .raycast(&down(Vec3::new(-0.5, 5., 0.)), &(0.01..100.))
+            .unwrap();
+        // widths blend like the points, so the taper has barely started
+        let width = 0.2 * (1. - 0.25f32.powi(3));
+        assert_float_eq!(hit.distance, 5. - width / 2., abs <= 0.001);
+        assert_float_eq!(hit.normal.to_array(), [0., 1., 0.], abs <= [0.001; 3]);
+        assert_float_eq!(hit.uv.x, 0.25, abs <= 0.01);
+
+        // off to the side of the tube, the normal tilts towards the ray
+        let hit = scene
+            .raycast(&down(Vec3::new(-0.5, 5., 0.05)), &(0.01..100.))
+            .unwrap();
+        assert!(hit.normal.z > 0.4);
+        assert!(scene
+            .raycast(&down(Vec3::new(-0.5, 5., 0.15)), &(0.01..100.))
+            .is_none());
+        // and it tapers to a point at the end
+        assert!(scene
+            .raycast(&down(Vec3::new(0.95, 5., 0.05)), &(0.01..100.))
+            .is_none());
+
+        let Hittable::Curve(curve) = scene.hittable_mut(straight) else {
+            unreachable!();
+        };
+        curve.shape = CurveShape::Flat;
+        let hit = scene
+            .raycast(&down(Vec3::new(-0.5, 5., 0.05)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 5., abs <= 0.001);
+        assert_float_eq!(hit.normal.to_array(), [0., 1., 0.], abs <= [0.001; 3]);
+    }
+
+    #[test]
+    fn curves_bend() {
+        let mut scene = Scene::default();
+        // an arch whose middle is three quarters as high as its middle points
+        scene.add_hittable(Curve {
+            points: [
+                Vec3::new(-1., 0., 0.),
+                Vec3::new(-1., 1., 0.),
+                Vec3::new(1., 1., 0.),
+                Vec3::new(1., 0., 0.),
+            ],
+            widths: [0.02; 4],
+            ..Curve::default()
+        });
+        let toward = Ray::new(Vec3::new(0., 0.75, 5.), Vec3::NEG_Z);
+        let hit = scene.raycast(&toward, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 4.99, abs <= 0.001);
+        assert_float_eq!(hit.uv.x, 0.5, abs <= 0.01);
+
+        let under_arch = Ray::new(Vec3::new(0., 0.5, 5.), Vec3::NEG_Z);
+        assert!(scene.raycast(&under_arch, &(0.01..100.)).is_none());
+    }
+
+    #[test]
+    fn meshes_are_instanced() {
+        let mut scene = Scene::default();
+        let node = scene.add_node(NodeId::ROOT, "box", Transform::from_translation(Vec3::X * 3.));
+        let cube = Mesh::cuboid(Vec3::ONE, MaterialHandle::NULL);
+        let geometry = cube.geometry.clone();
+        scene.add_hittable_to(node, cube);
+        scene.add_hittable(Mesh::new(geometry, MaterialHandle::NULL));
+
+        for x in [0., 3.] {
+            let hit = scene
+                .raycast(&down(Vec3::new(x + 0.5, 5., 0.5)), &(0.01..100.))
+                .unwrap();
+            assert_float_eq!(hit.distance, 4., abs <= 0.0001);
+            assert_float_eq!(hit.normal.to_array(), [0., 1., 0.], abs <= [0.0001; 3]);
+            assert_eq!(hit.side, FaceSide::Front);
+        }
+
+        let inside = Ray::new(Vec3::new(3., 0., 0.), Vec3::Z);
+        let hit = scene.raycast(&inside, &(0.01..100.)).unwrap();
+        assert_float_eq!(hit.distance, 1., abs <= 0.0001);
+        assert_float_eq!(hit.normal.to_array(), [0., 0., -1.], abs <= [0.0001; 3]);
+        assert_eq!(hit.side, FaceSide::Back);
+
+        // turned on its corner, the box reaches further along X
+        scene.node_mut(node).transform.rotation = Quat::from_rotation_y(FRAC_PI_4);
+        let hit = scene
+            .raycast(&down(Vec3::new(3. + 1.3, 5., 0.)), &(0.01..100.))
+            .unwrap();
+        assert_float_eq!(hit.distance, 4., abs <= 0.0001);
+    }
+
+    #[test]
+    fn moving_nodes_refits_the_bvh() {
+        let mut scene = Scene::default();
+        let node = scene.add_node(NodeId::ROOT, "group", Transform::IDENTITY);
+        for i in 0..50 {
+            let center = Vec3::new((i % 10) as f32, (i / 10) as f32, 0.) * 3.;
+            scene.add_hittable_to(
+                if i % 2 == 0 { node } else { NodeId::ROOT },
+                Sphere {
+                    center,
+                    ..Sphere::default()
+                },
+            );
         }
+        let brute_force = |scene: &Scene, ray: &Ray| {
+            scene
+                .world_hittables()
+                .iter()
+                .filter_map(|hittable| match hittable.check_hit(ray, &(0.01..100.)) {
+                    HitPayload::Hit { hit_distance, .. } => Some(hit_distance),
+                    _ => None,
+                })
+                .reduce(f32::min)
+        };
+        let check = |scene: &Scene| {
+            for x in 0..30 {
+                for y in 0..15 {
+                    let origin = Vec3::new(x as f32, y as f32, 10.);
+                    let ray = Ray::new(origin, Vec3::new(0.1, -0.05, -1.));
+                    let hit = scene.raycast(&ray, &(0.01..100.)).map(|hit| hit.distance);
+                    assert_eq!(hit, brute_force(scene, &ray));
+                }
+            }
+        };
+        check(&scene);
+
+        scene.node_mut(node).transform.translation = Vec3::new(1.5, 4., -2.);
+        check(&scene);
+        assert!(scene.stale_bvh.is_some(), "the BVH should be refit, not rebuilt");
+
+        scene.add_hittable(Sphere::default());
+        check(&scene);
+        assert!(scene.stale_bvh.is_none());
+    }
+
+    #[test]
+    fn tracing_without_the_bvh_finds_the_same_hits() {
+        let mut scene = presets::random_spheres(100, 1);
+        let camera = Preset::RandomSpheres.camera();
+        let rays: Vec<_> = (0..40)
+            .flat_map(|x| (0..30).map(move |y| (x * 4, y * 4)))
+            .map(|(x, y)| camera.ray_for_pixel(x, y, Vec2::ZERO))
+            .collect();
+        let clip = 0.001..100.;
+        let with_bvh: Vec<_> = rays.iter().map(|ray| scene.raycast(ray, &clip)).collect();
+        scene.set_use_bvh(false);
+        for (ray, expected) in rays.iter().zip(with_bvh) {
+            let hit = scene.raycast(ray, &clip);
+            assert_eq!(hit.map(|hit| hit.hittable), expected.map(|hit| hit.hittable));
+        }
+    }
+
+    #[test]
+    fn occlusion_stops_at_any_hit() {
+        let mut scene = Scene::default();
+        for z in [-2., -4.] {
+            scene.add_hittable(Sphere {
+                center: Vec3::Z * z,
+                radius: 0.5,
+                ..Sphere::default()
+            });
+        }
+        let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+        assert!(scene.occluded(&ray, &(0.01..100.)));
+        // the light is in front of the first sphere
+        assert!(!scene.occluded(&ray, &(0.01..1.)));
+        let beside = Ray::new(Vec3::X, Vec3::NEG_Z);
+        assert!(!scene.occluded(&beside, &(0.01..100.)));
+    }
+
+    #[test]
+    fn bounds_include_node_transforms() {
+        let mut scene = Scene::default();
+        assert!(scene.bounds().is_empty());
+        let group = scene.add_node(NodeId::ROOT, "group", Transform::from_translation(Vec3::Y));
+        scene.add_hittable_to(
+            group,
+            Sphere {
+                center: Vec3::X,
+                radius: 0.5,
+                ..Sphere::default()
+            },
+        );
+        scene.add_hittable(Quad {
+            corner: Vec3::new(-2., 0., -1.),
+            u: Vec3::X,
+            v: Vec3::Z * 2.,
+            ..Quad::default()
+        });
+        let bounds = scene.bounds();
+        assert_eq!(bounds.min, Vec3::new(-2., 0., -1.));
+        assert_eq!(bounds.max, Vec3::new(1.5, 1.5, 1.));
     }
 }
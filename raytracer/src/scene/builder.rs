@@ -0,0 +1,242 @@
+use super::{NodeId, Scene};
+use crate::{CameraBookmark, Hittable, Material, MaterialHandle, Quad, Sphere, Transform};
+use glam::Vec3;
+use std::collections::HashMap;
+
+/// Describes a scene fluently, referring to materials by name instead of by
+/// index.
+///
+/// ```
+/// # use halide_raytracer::{Material, SceneBuilder, Transform};
+/// # use glam::Vec3;
+/// let scene = SceneBuilder::new()
+///     .material("ground", Material::Lambertian { albedo: Vec3::splat(0.5) })
+///     .material("red", Material::Lambertian { albedo: Vec3::X })
+///     .sphere(Vec3::new(0., -1000., 0.), 1000., "ground")
+///     .group("balls", Transform::from_translation(Vec3::Y), |balls| {
+///         balls.sphere(Vec3::ZERO, 0.5, "red").sphere(Vec3::X, 0.5, "red")
+///     })
+///     .build()
+///     .unwrap();
+/// assert_eq!(scene.hittables().len(), 3);
+/// ```
+///
+/// Materials can be defined before or after the objects that use them.
+/// Mistakes, like using a material that was never defined, are reported by
+/// [`SceneBuilder::build`].
+pub struct SceneBuilder {
+    scene: Scene,
+    /// The node new objects and groups are added to.
+    current: NodeId,
+    materials: HashMap<String, MaterialHandle>,
+    hittables: Vec<PendingHittable>,
+    errors: Vec<String>,
+}
+
+/// An object waiting for its material to be looked up.
+struct PendingHittable {
+    node: NodeId,
+    hittable: Hittable,
+    material: String,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self {
+            scene: Scene::default(),
+            current: NodeId::ROOT,
+            materials: HashMap::new(),
+            hittables: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add to an existing scene. Its materials can be referred to as `#0`,
+    /// `#1` and so on, by their index in [`Scene::materials`], and new
+    /// objects and groups go in its root node.
+    pub fn extend(scene: Scene) -> Self {
+        let materials = (0..scene.materials().len())
+            .map(|idx| (format!("#{idx}"), MaterialHandle(idx)))
+            .collect();
+        Self {
+            scene,
+            materials,
+            ..Self::default()
+        }
+    }
+
+    /// Define a material that objects can refer to as `name`.
+    pub fn material<S: Into<String>>(mut self, name: S, material: Material) -> Self {
+        let name = name.into();
+        let idx = self.scene.add_material(material);
+        if self.materials.insert(name.clone(), idx).is_some() {
+            self.errors
+                .push(format!("material {name:?} is defined twice"));
+        }
+        self
+    }
+
+    pub fn sphere<S: Into<String>>(self, center: Vec3, radius: f32, material: S) -> Self {
+        self.hittable(
+            Sphere {
+                center,
+                radius,
+                ..Sphere::default()
+            },
+            material,
+        )
+    }
+
+    /// Add a [`Quad`] with a corner at `corner` and sides along `u` and `v`.
+    pub fn quad<S: Into<String>>(self, corner: Vec3, u: Vec3, v: Vec3, material: S) -> Self {
+        self.hittable(
+            Quad {
+                corner,
+                u,
+                v,
+                material: MaterialHandle::NULL,
+            },
+            material,
+        )
+    }
+
+    fn hittable<H: Into<Hittable>, S: Into<String>>(mut self, hittable: H, material: S) -> Self {
+        self.hittables.push(PendingHittable {
+            node: self.current,
+            hittable: hittable.into(),
+            material: material.into(),
+        });
+        self
+    }
+
+    /// Add a node to the hierarchy. Objects and groups added by `contents`
+    /// go in that node.
+    pub fn group<S, F>(mut self, name: S, transform: Transform, contents: F) -> Self
+    where
+        S: Into<String>,
+        F: FnOnce(Self) -> Self,
+    {
+        let parent = self.current;
+        self.current = self.scene.add_node(parent, name, transform);
+        let mut builder = contents(self);
+        builder.current = parent;
+        builder
+    }
+
+    pub fn bookmark(mut self, bookmark: CameraBookmark) -> Self {
+        self.scene.add_bookmark(bookmark);
+        self
+    }
+
+    pub fn build(mut self) -> anyhow::Result<Scene> {
+        for mut pending in std::mem::take(&mut self.hittables) {
+            match self.materials.get(&pending.material) {
+                Some(&material) => {
+                    *pending.hittable.material_mut() = material;
+                    self.scene.add_hittable_to(pending.node, pending.hittable);
+                }
+                None => self
+                    .errors
+                    .push(format!("material {:?} is not defined", pending.material)),
+            }
+        }
+
+        if !self.errors.is_empty() {
+            anyhow::bail!("invalid scene:\n{}", self.errors.join("\n"));
+        }
+        Ok(self.scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneBuilder;
+    use crate::{Hittable, Material, NodeId, Transform};
+    use glam::Vec3;
+
+    #[test]
+    fn resolves_materials_and_groups() {
+        let scene = SceneBuilder::new()
+            .sphere(Vec3::ZERO, 1., "light")
+            .group("group", Transform::from_translation(Vec3::Y), |group| {
+                group.sphere(Vec3::X, 0.5, "grey")
+            })
+            .material(
+                "grey",
+                Material::Lambertian {
+                    albedo: Vec3::splat(0.5),
+                },
+            )
+            .material(
+                "light",
+                Material::Emissive {
+                    emission: Vec3::ONE,
+                },
+            )
+            .build()
+            .unwrap();
+
+        let root = scene.node(NodeId::ROOT);
+        assert_eq!(root.hittables(), &[0]);
+        assert_eq!(root.children().len(), 1);
+        let group = scene.node(root.children()[0]);
+        assert_eq!(group.name, "group");
+        assert_eq!(group.hittables(), &[1]);
+
+        let Hittable::Sphere(light) = &scene.hittables()[0] else {
+            panic!("expected a sphere");
+        };
+        assert!(matches!(
+            scene.material(light.material),
+            Material::Emissive { .. }
+        ));
+        let Hittable::Sphere(ball) = &scene.world_hittables()[1] else {
+            panic!("expected a sphere");
+        };
+        assert_eq!(ball.center, Vec3::new(1., 1., 0.));
+        assert!(matches!(
+            scene.material(ball.material),
+            Material::Lambertian { .. }
+        ));
+    }
+
+    #[test]
+    fn reports_mistakes() {
+        let err = SceneBuilder::new()
+            .material("a", Material::Null)
+            .material("a", Material::Null)
+            .sphere(Vec3::ZERO, 1., "b")
+            .build()
+            .map(drop)
+            .unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("\"a\" is defined twice"), "{message}");
+        assert!(message.contains("\"b\" is not defined"), "{message}");
+    }
+
+    #[test]
+    fn extends_scenes() {
+        let mut scene = crate::presets::demo();
+        let materials = scene.materials().len();
+        let hittables = scene.hittables().len();
+        scene = SceneBuilder::extend(scene)
+            .material("new", Material::Null)
+            .sphere(Vec3::ZERO, 1., "#1")
+            .sphere(Vec3::X, 1., "new")
+            .build()
+            .unwrap();
+        assert_eq!(scene.materials().len(), materials + 1);
+        assert_eq!(scene.hittables().len(), hittables + 2);
+        assert_eq!(scene.hittables()[hittables].material().index(), 1);
+        assert_eq!(
+            scene.hittables()[hittables + 1].material().index(),
+            materials
+        );
+    }
+}
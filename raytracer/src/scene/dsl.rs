@@ -0,0 +1,433 @@
+//! The parser for [`Scene::from_dsl`].
+
+use super::{Scene, SceneBuilder, SceneChange};
+use crate::{Material, ThinFilm, Transform};
+use anyhow::{anyhow, bail, Context, Result};
+use glam::Vec3;
+use rand::Rng;
+use std::str::SplitWhitespace;
+
+/// The most times `repeat` blocks can run their statements, multiplied
+/// together when they're nested, so a typo can't hang whoever's waiting.
+const MAX_REPEATS: usize = 10_000;
+
+impl Scene {
+    /// Parse a scene from a small line based text format, for describing
+    /// scenes by hand.
+    ///
+    /// ```text
+    /// # Comments start with a hash.
+    /// material ground lambertian 0.5 0.5 0.5
+    /// material light emissive 4 4 4
+    /// sphere 0 -1000 0 1000 ground
+    /// group lamps at 0 3 0 scale 0.5 {
+    ///     sphere -1 0 0 0.5 light
+    ///     sphere 1 0 0 0.5 light
+    /// }
+    /// ```
+    ///
+    /// Each line is one statement:
+    ///
+    /// - `material <name> lambertian <r> <g> <b>`, `material <name> emissive <r> <g> <b>`,
+    ///   `material <name> metal <r> <g> <b> <fuzz> [film <thickness> <ior>]`,
+    ///   or `material <name> null`
+    /// - `sphere <x> <y> <z> <radius> <material>`
+    /// - `quad <x> <y> <z> <ux> <uy> <uz> <vx> <vy> <vz> <material>`, with a
+    ///   corner at `x y z` and sides along `u` and `v`
+    /// - `group <name> [at <x> <y> <z>] [scale <s>] {`, closed by a line with just `}`
+    /// - `repeat <count> {`, also closed by `}`, to run the statements inside
+    ///   over and over, up to 10,000 times in all, counting the repeats it's
+    ///   inside
+    ///
+    /// Any number can be `rand <min> <max>` instead, for a number picked at
+    /// random each time the statement runs:
+    ///
+    /// ```text
+    /// repeat 100 {
+    ///     sphere rand -10 10 0.3 rand -10 10 0.3 ball
+    /// }
+    /// ```
+    pub fn from_dsl(source: &str) -> Result<Scene> {
+        parse(source, SceneBuilder::new())
+    }
+
+    /// Run statements like the ones [`Scene::from_dsl`] reads, adding to
+    /// this scene. Its materials can be used as `#0`, `#1` and so on, as
+    /// with [`SceneBuilder::extend`]. If there's a mistake, nothing is
+    /// added.
+    pub fn run_dsl(&mut self, source: &str) -> Result<()> {
+        let mut scene = parse(source, SceneBuilder::extend(self.clone()))?;
+        // the copy was built on without telling anyone
+        scene.observers = std::mem::take(&mut self.observers);
+        *self = scene;
+        for change in [SceneChange::Materials, SceneChange::Nodes, SceneChange::Hittables] {
+            self.observers.notify(change);
+        }
+        Ok(())
+    }
+}
+
+fn parse(source: &str, builder: SceneBuilder) -> Result<Scene> {
+    let mut lines = source
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    parse_block(&mut lines, builder, None, 1)?.build()
+}
+
+/// Parse statements until the end of the input, or the `}` closing the group
+/// opened on line `group_line`. The block runs `runs` times in all, for the
+/// `repeat` blocks it's inside.
+fn parse_block<'a, I>(
+    lines: &mut I,
+    mut builder: SceneBuilder,
+    group_line: Option<usize>,
+    runs: usize,
+) -> Result<SceneBuilder>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    while let Some((line_number, line)) = lines.next() {
+        if line == "}" {
+            if group_line.is_none() {
+                bail!("line {line_number}: unexpected `}}`");
+            }
+            return Ok(builder);
+        }
+
+        let mut tokens = Tokens {
+            words: line.split_whitespace(),
+        };
+        let statement = tokens.word("a statement")?;
+        let result = match statement {
+            "material" => parse_material(&mut tokens).map(|(name, material)| {
+                builder = std::mem::take(&mut builder).material(name, material);
+            }),
+            "sphere" => parse_sphere(&mut tokens).map(|(center, radius, material)| {
+                builder = std::mem::take(&mut builder).sphere(center, radius, material);
+            }),
+            "quad" => parse_quad(&mut tokens).map(|(corner, u, v, material)| {
+                builder = std::mem::take(&mut builder).quad(corner, u, v, material);
+            }),
+            "group" => {
+                let (name, transform) =
+                    parse_group(&mut tokens).with_context(|| format!("line {line_number}"))?;
+                let mut error = None;
+                builder = builder.group(name, transform, |group| {
+                    parse_block(lines, group, Some(line_number), runs).unwrap_or_else(|err| {
+                        error = Some(err);
+                        SceneBuilder::new()
+                    })
+                });
+                if let Some(err) = error {
+                    return Err(err);
+                }
+                Ok(())
+            }
+            "repeat" => {
+                let count =
+                    parse_repeat(&mut tokens).with_context(|| format!("line {line_number}"))?;
+                let runs = count.saturating_mul(runs);
+                if runs > MAX_REPEATS {
+                    bail!("line {line_number}: repeat can't run more than {MAX_REPEATS} times");
+                }
+                let body = block_lines(lines, line_number)?;
+                for _ in 0..count {
+                    let mut body = body.iter().copied();
+                    builder = parse_block(&mut body, builder, Some(line_number), runs)?;
+                }
+                Ok(())
+            }
+            other => Err(anyhow!("unknown statement {other:?}")),
+        };
+        result
+            .and_then(|()| tokens.end())
+            .with_context(|| format!("line {line_number}"))?;
+    }
+
+    if let Some(line_number) = group_line {
+        bail!("line {line_number}: group is never closed with `}}`");
+    }
+    Ok(builder)
+}
+
+fn parse_material(tokens: &mut Tokens) -> Result<(String, Material)> {
+    let name = tokens.word("a material name")?.to_string();
+    let material = match tokens.word("a material type")? {
+        "lambertian" => Material::Lambertian {
+            albedo: tokens.vec3()?,
+        },
+        "emissive" => Material::Emissive {
+            emission: tokens.vec3()?,
+        },
+        "metal" => Material::Metal {
+            albedo: tokens.vec3()?,
+            fuzz: tokens.number()?,
+            coating: if tokens.keyword("film") {
+                Some(ThinFilm {
+                    thickness: tokens.number()?,
+                    ior: tokens.number()?,
+                })
+            } else {
+                None
+            },
+        },
+        "null" => Material::Null,
+        other => bail!("unknown material type {other:?}"),
+    };
+    Ok((name, material))
+}
+
+fn parse_sphere(tokens: &mut Tokens) -> Result<(Vec3, f32, String)> {
+    let center = tokens.vec3()?;
+    let radius = tokens.number()?;
+    let material = tokens.word("a material name")?.to_string();
+    Ok((center, radius, material))
+}
+
+fn parse_quad(tokens: &mut Tokens) -> Result<(Vec3, Vec3, Vec3, String)> {
+    let corner = tokens.vec3()?;
+    let u = tokens.vec3()?;
+    let v = tokens.vec3()?;
+    let material = tokens.word("a material name")?.to_string();
+    Ok((corner, u, v, material))
+}
+
+fn parse_group(tokens: &mut Tokens) -> Result<(String, Transform)> {
+    let name = tokens.word("a group name")?.to_string();
+    let mut transform = Transform::IDENTITY;
+    loop {
+        match tokens.word("`{`")? {
+            "at" => transform.translation = tokens.vec3()?,
+            "scale" => transform.scale = tokens.number()?,
+            "{" => return Ok((name, transform)),
+            other => bail!("expected `at`, `scale` or `{{`, found {other:?}"),
+        }
+    }
+}
+
+fn parse_repeat(tokens: &mut Tokens) -> Result<usize> {
+    let word = tokens.word("a count")?;
+    let count = word
+        .parse()
+        .map_err(|_| anyhow!("expected a count, found {word:?}"))?;
+    match tokens.word("`{`")? {
+        "{" => Ok(count),
+        other => bail!("expected `{{`, found {other:?}"),
+    }
+}
+
+/// The lines of the block opened on line `open_line`, up to and including
+/// the `}` that closes it, so they can be parsed more than once.
+fn block_lines<'a, I>(lines: &mut I, open_line: usize) -> Result<Vec<(usize, &'a str)>>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    let mut block = Vec::new();
+    let mut depth = 0;
+    for (line_number, line) in lines {
+        block.push((line_number, line));
+        if line.ends_with('{') {
+            depth += 1;
+        } else if line == "}" {
+            if depth == 0 {
+                return Ok(block);
+            }
+            depth -= 1;
+        }
+    }
+    bail!("line {open_line}: repeat is never closed with `}}`")
+}
+
+struct Tokens<'a> {
+    words: SplitWhitespace<'a>,
+}
+
+impl<'a> Tokens<'a> {
+    fn word(&mut self, expected: &str) -> Result<&'a str> {
+        self.words
+            .next()
+            .ok_or_else(|| anyhow!("expected {expected}"))
+    }
+
+    /// Consume `keyword` if it's the next word.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = self.words.clone().next() == Some(keyword);
+        if found {
+            self.words.next();
+        }
+        found
+    }
+
+    fn number(&mut self) -> Result<f32> {
+        let word = self.word("a number")?;
+        if word == "rand" {
+            let (min, max) = (self.number()?, self.number()?);
+            if min >= max {
+                bail!("expected rand's minimum to be less than its maximum");
+            }
+            return Ok(rand::thread_rng().gen_range(min..max));
+        }
+        word.parse()
+            .map_err(|_| anyhow!("expected a number, found {word:?}"))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.number()?, self.number()?, self.number()?))
+    }
+
+    fn end(&mut self) -> Result<()> {
+        match self.words.next() {
+            Some(word) => bail!("unexpected {word:?}"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Hittable, Material, NodeId, Scene, ThinFilm};
+    use glam::Vec3;
+
+    #[test]
+    fn parses_scene() {
+        let scene = Scene::from_dsl(
+            "
+            # a comment
+            material ground lambertian 0.5 0.5 0.5
+            sphere 0 -1000 0 1000 ground
+            group lamps at 0 3 0 scale 0.5 {
+                sphere 2 0 0 1 light
+                group inner {
+                }
+            }
+            material light emissive 4 4 4
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(scene.hittables().len(), 2);
+        let lamps = scene.node(NodeId::ROOT).children()[0];
+        assert_eq!(scene.node(lamps).name, "lamps");
+        assert_eq!(scene.node(lamps).children().len(), 1);
+
+        let Hittable::Sphere(lamp) = &scene.world_hittables()[1] else {
+            panic!("expected a sphere");
+        };
+        assert_eq!(lamp.center, Vec3::new(1., 3., 0.));
+        assert_eq!(lamp.radius, 0.5);
+        assert!(matches!(
+            scene.material(lamp.material),
+            Material::Emissive { emission } if *emission == Vec3::splat(4.)
+        ));
+    }
+
+    #[test]
+    fn parses_metal() {
+        let scene = Scene::from_dsl(
+            "
+            material plain metal 0.9 0.9 0.9 0.1
+            material bubble metal 0.5 0.5 0.5 0 film 380 1.33
+            ",
+        )
+        .unwrap();
+        assert!(matches!(
+            scene.materials()[1],
+            Material::Metal { fuzz, coating: None, .. } if fuzz == 0.1
+        ));
+        assert!(matches!(
+            scene.materials()[2],
+            Material::Metal {
+                coating: Some(ThinFilm { thickness, ior }),
+                ..
+            } if thickness == 380. && ior == 1.33
+        ));
+    }
+
+    #[test]
+    fn parses_quad() {
+        let scene = Scene::from_dsl(
+            "
+            material wall lambertian 0.7 0.7 0.7
+            quad -1 0 -1  2 0 0  0 2 0 wall
+            ",
+        )
+        .unwrap();
+        let Hittable::Quad(wall) = &scene.hittables()[0] else {
+            panic!("expected a quad");
+        };
+        assert_eq!(wall.corner, Vec3::new(-1., 0., -1.));
+        assert_eq!(wall.normal(), Vec3::Z);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = |source: &str| format!("{:#}", Scene::from_dsl(source).map(drop).unwrap_err());
+        assert_eq!(
+            error("material a null\nsphere 0 0 zero 1 a"),
+            "line 2: expected a number, found \"zero\""
+        );
+        assert_eq!(
+            error("sphere 0 0 0 1 a extra"),
+            "line 1: unexpected \"extra\""
+        );
+        assert_eq!(error("cube"), "line 1: unknown statement \"cube\"");
+        assert_eq!(
+            error("group g {\nsphere 0 0 0 1 a"),
+            "line 1: group is never closed with `}`"
+        );
+        assert_eq!(error("}"), "line 1: unexpected `}`");
+        assert!(error("sphere 0 0 0 1 missing").contains("\"missing\" is not defined"));
+        assert_eq!(
+            error("repeat 2 {\nsphere 0 0 0 1 a"),
+            "line 1: repeat is never closed with `}`"
+        );
+        assert_eq!(
+            error("repeat 100 {\nrepeat 101 {\n}\n}"),
+            "line 2: repeat can't run more than 10000 times"
+        );
+        assert_eq!(
+            error("sphere 0 0 0 rand 1 0 a"),
+            "line 1: expected rand's minimum to be less than its maximum"
+        );
+    }
+
+    #[test]
+    fn repeats_with_random_numbers() {
+        let scene = Scene::from_dsl(
+            "
+            material ball lambertian 0.5 0.5 0.5
+            repeat 10 {
+                sphere rand -5 5 0 rand -5 5 0.5 ball
+                group pair {
+                    sphere 0 0 0 0.5 ball
+                }
+            }
+            ",
+        )
+        .unwrap();
+        assert_eq!(scene.hittables().len(), 20);
+        assert_eq!(scene.node(NodeId::ROOT).children().len(), 10);
+        for hittable in scene.hittables() {
+            let Hittable::Sphere(sphere) = hittable else {
+                panic!("expected a sphere");
+            };
+            assert!((-5.0..5.).contains(&sphere.center.x));
+        }
+    }
+
+    #[test]
+    fn runs_on_existing_scenes() {
+        let mut scene = Scene::from_dsl("material red lambertian 1 0 0").unwrap();
+        scene.run_dsl("sphere 0 0 0 1 #1").unwrap();
+        assert_eq!(scene.hittables().len(), 1);
+        assert_eq!(scene.hittables()[0].material().index(), 1);
+
+        // mistakes leave the scene as it was
+        assert!(scene
+            .run_dsl("sphere 0 0 0 1 #1\nsphere 0 0 0 1 missing")
+            .is_err());
+        assert_eq!(scene.hittables().len(), 1);
+    }
+}
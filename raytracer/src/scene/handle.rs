@@ -0,0 +1,138 @@
+//! Sharing a scene between a thread that edits it and threads that render
+//! it.
+
+use super::Scene;
+use parking_lot::RwLock;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// A [`Scene`] shared between threads, so one can edit it while others
+/// render it. Renders work from [snapshots](SceneHandle::snapshot), which
+/// don't change under them: edits only show up in snapshots taken after
+/// they're published. Clones of the handle share the same scene.
+#[derive(Clone, Default)]
+pub struct SceneHandle {
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    published: RwLock<Arc<Scene>>,
+    version: AtomicU64,
+}
+
+impl SceneHandle {
+    pub fn new(scene: Scene) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                published: RwLock::new(Arc::new(scene)),
+                version: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The scene as it was last published. Holding on to it doesn't hold
+    /// up edits.
+    pub fn snapshot(&self) -> Arc<Scene> {
+        self.shared.published.read().clone()
+    }
+
+    /// Goes up each time a scene is published, so a render can tell that
+    /// its snapshot is out of date.
+    pub fn version(&self) -> u64 {
+        self.shared.version.load(Ordering::Acquire)
+    }
+
+    /// Show a copy of `scene` in snapshots from now on, such as a scene
+    /// that's been edited on its own.
+    pub fn publish(&self, scene: &Scene) {
+        self.replace(Arc::new(scene.clone()));
+    }
+
+    /// Show `scene` in snapshots from now on.
+    pub fn replace(&self, scene: Arc<Scene>) {
+        *self.shared.published.write() = scene;
+        self.shared.version.fetch_add(1, Ordering::Release);
+    }
+
+    /// Edit the published scene, copying it first if there are snapshots of
+    /// it. Snapshots wait for the edit to finish, so it should be quick. The
+    /// copy keeps the scene's [`on_change`](Scene::on_change) callbacks, so
+    /// they hear about the edit either way.
+    pub fn edit<R>(&self, edit: impl FnOnce(&mut Scene) -> R) -> R {
+        let mut published = self.shared.published.write();
+        if Arc::get_mut(&mut published).is_none() {
+            let mut copy = Scene::clone(&published);
+            copy.observers = published.observers.handed_over();
+            *published = Arc::new(copy);
+        }
+        let result = edit(Arc::make_mut(&mut published));
+        self.shared.version.fetch_add(1, Ordering::Release);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneHandle;
+    use crate::{Scene, SceneChange, Sphere};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn snapshots_dont_see_later_edits() {
+        let handle = SceneHandle::new(Scene::default());
+        let before = handle.snapshot();
+        let version = handle.version();
+
+        handle.edit(|scene| scene.add_hittable(Sphere::default()));
+        assert!(before.hittables().is_empty());
+        assert_eq!(handle.snapshot().hittables().len(), 1);
+        assert!(handle.version() > version);
+
+        let mut scene = Scene::default();
+        scene.add_hittable(Sphere::default());
+        scene.add_hittable(Sphere::default());
+        handle.publish(&scene);
+        assert_eq!(handle.snapshot().hittables().len(), 2);
+    }
+
+    #[test]
+    fn edits_under_snapshots_are_reported() {
+        let mut scene = Scene::default();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        scene.on_change({
+            let changes = changes.clone();
+            move |change| changes.lock().unwrap().push(change)
+        });
+        let handle = SceneHandle::new(scene);
+
+        let _snapshot = handle.snapshot();
+        handle.edit(|scene| scene.add_hittable(Sphere::default()));
+        handle.edit(|scene| scene.add_hittable(Sphere::default()));
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [SceneChange::Hittables, SceneChange::Hittables]
+        );
+    }
+
+    #[test]
+    fn edits_while_rendering() {
+        let handle = SceneHandle::new(Scene::default());
+        std::thread::scope(|scope| {
+            let reader = handle.clone();
+            scope.spawn(move || {
+                for _ in 0..100 {
+                    let scene = reader.snapshot();
+                    let count = scene.hittables().len();
+                    assert_eq!(scene.world_hittables().len(), count);
+                }
+            });
+            for _ in 0..100 {
+                handle.edit(|scene| scene.add_hittable(Sphere::default()));
+            }
+        });
+        assert_eq!(handle.snapshot().hittables().len(), 100);
+    }
+}
@@ -0,0 +1,152 @@
+//! Materials saved apart from any scene, so they can be shared and reused.
+
+use super::Scene;
+use crate::{Material, MaterialHandle};
+#[cfg(feature = "serde")]
+use tracing::info_span;
+
+/// A collection of materials, such as the ones from one scene to be used
+/// in another with [`Scene::import_materials`]. Materials refer to each
+/// other by value rather than by handle, so they stay the same wherever
+/// they're imported.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaterialLibrary {
+    pub materials: Vec<Material>,
+}
+
+impl MaterialLibrary {
+    /// Parse a library from its RON representation.
+    #[cfg(feature = "serde")]
+    pub fn from_ron(source: &str) -> anyhow::Result<Self> {
+        let _span = info_span!("parse material library", bytes = source.len()).entered();
+        Ok(ron::from_str(source)?)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Read a library from a `.ron` file.
+    #[cfg(feature = "serde")]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        let path = path.as_ref();
+        let _span = info_span!("load material library", path = %path.display()).entered();
+        let source =
+            std::fs::read_to_string(path).with_context(|| format!("Reading {}", path.display()))?;
+        Self::from_ron(&source).with_context(|| format!("Parsing {}", path.display()))
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let _span = info_span!("save material library", path = %path.display()).entered();
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+}
+
+impl Scene {
+    /// The scene's materials as a library, in the same order, leaving out
+    /// [`Material::Null`] since every scene already has one.
+    pub fn material_library(&self) -> MaterialLibrary {
+        MaterialLibrary {
+            materials: self
+                .materials
+                .iter()
+                .filter(|material| !matches!(material, Material::Null))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Add every material in `library` to the scene after the ones it
+    /// already has. The handles they were given are returned in the
+    /// library's order, so the material at `idx` in the library is now
+    /// `handles[idx]`.
+    pub fn add_materials(&mut self, library: &MaterialLibrary) -> Vec<MaterialHandle> {
+        library
+            .materials
+            .iter()
+            .map(|material| self.add_material(material.clone()))
+            .collect()
+    }
+
+    /// Like [`Scene::add_materials`], with a library read from a file.
+    #[cfg(feature = "serde")]
+    pub fn import_materials<P: AsRef<std::path::Path>>(
+        &mut self,
+        path: P,
+    ) -> anyhow::Result<Vec<MaterialHandle>> {
+        let library = MaterialLibrary::load(path)?;
+        Ok(self.add_materials(&library))
+    }
+
+    /// Save the scene's materials to a file, as from
+    /// [`Scene::material_library`].
+    #[cfg(feature = "serde")]
+    pub fn export_materials<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        self.material_library().save(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Material, Scene, Sphere};
+    use glam::Vec3;
+
+    #[test]
+    fn imported_materials_are_remapped() {
+        let mut source = Scene::default();
+        source.add_material(Material::Lambertian { albedo: Vec3::X });
+        source.add_material(Material::Emissive {
+            emission: Vec3::ONE,
+        });
+        let library = source.material_library();
+        assert_eq!(library.materials.len(), 2);
+
+        let mut scene = crate::presets::demo();
+        let before = scene.materials().len();
+        let handles = scene.add_materials(&library);
+        assert_eq!(
+            handles
+                .iter()
+                .map(|handle| handle.index())
+                .collect::<Vec<_>>(),
+            vec![before, before + 1]
+        );
+        assert!(matches!(
+            scene.material(handles[1]),
+            Material::Emissive { .. }
+        ));
+        // the handles can be used right away
+        scene.add_hittable(Sphere {
+            material: handles[0],
+            ..Sphere::default()
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ron_round_trip() {
+        use super::MaterialLibrary;
+
+        let library = MaterialLibrary {
+            materials: vec![
+                Material::Lambertian { albedo: Vec3::Y },
+                Material::Sided {
+                    front: Box::new(Material::Lambertian { albedo: Vec3::Z }),
+                    back: crate::Backface::Culled,
+                },
+            ],
+        };
+        let loaded = MaterialLibrary::from_ron(&library.to_ron().unwrap()).unwrap();
+        assert_eq!(loaded.materials.len(), 2);
+        assert!(matches!(loaded.materials[1], Material::Sided { .. }));
+    }
+}
@@ -0,0 +1,146 @@
+//! Callbacks for when a scene changes, from [`Scene::on_change`].
+
+use super::Scene;
+use std::sync::Arc;
+
+/// What changed in a [`Scene`], for callbacks added with
+/// [`Scene::on_change`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneChange {
+    /// Hittables were added, or might have been edited.
+    Hittables,
+    /// Nodes were added, moved, or reparented, moving the hittables in
+    /// them.
+    Nodes,
+    /// Materials were added, or might have been edited.
+    Materials,
+    /// The environment map was set or cleared.
+    Environment,
+    /// Camera bookmarks were added, removed, or might have been edited.
+    /// They don't change what the scene looks like.
+    Bookmarks,
+}
+
+impl SceneChange {
+    /// Whether the change can make a render of the scene look different.
+    pub fn is_visible(self) -> bool {
+        self != SceneChange::Bookmarks
+    }
+}
+
+/// Identifies a callback added with [`Scene::on_change`], to remove it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObserverId(u64);
+
+type Callback = dyn Fn(SceneChange) + Send + Sync;
+
+/// A scene's callbacks. They belong to the scene they were added to, so
+/// copies of it start without any.
+#[derive(Default)]
+pub(super) struct Observers {
+    callbacks: Vec<(ObserverId, Arc<Callback>)>,
+    next_id: u64,
+}
+
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Observers {
+    /// The same callbacks, for a copy that takes the place of the scene
+    /// they were added to.
+    pub(super) fn handed_over(&self) -> Self {
+        Self {
+            callbacks: self.callbacks.clone(),
+            next_id: self.next_id,
+        }
+    }
+
+    pub(super) fn notify(&self, change: SceneChange) {
+        for (_, callback) in &self.callbacks {
+            callback(change);
+        }
+    }
+}
+
+impl Scene {
+    /// Call `callback` whenever the scene changes, so things made from it,
+    /// like a render or a list of problems in it, can be brought up to
+    /// date. Anything that hands out mutable access counts as a change, as
+    /// there's no telling what's done with it.
+    ///
+    /// Callbacks aren't copied along with the scene, and a scene loaded in
+    /// place of this one has none of them. The copy
+    /// [`SceneHandle::edit`](super::SceneHandle::edit) makes when there
+    /// are snapshots is the exception, since it takes this scene's place.
+    pub fn on_change<F>(&mut self, callback: F) -> ObserverId
+    where
+        F: Fn(SceneChange) + Send + Sync + 'static,
+    {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        self.observers.callbacks.push((id, Arc::new(callback)));
+        id
+    }
+
+    /// Stop calling a callback added with [`Scene::on_change`].
+    pub fn remove_observer(&mut self, id: ObserverId) {
+        self.observers
+            .callbacks
+            .retain(|(callback_id, _)| *callback_id != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SceneChange;
+    use crate::{Material, NodeId, Scene, Sphere, Transform};
+    use std::sync::{Arc, Mutex};
+
+    fn record(scene: &mut Scene) -> (super::ObserverId, Arc<Mutex<Vec<SceneChange>>>) {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let id = scene.on_change({
+            let changes = changes.clone();
+            move |change| changes.lock().unwrap().push(change)
+        });
+        (id, changes)
+    }
+
+    #[test]
+    fn edits_are_reported() {
+        let mut scene = Scene::default();
+        let (id, changes) = record(&mut scene);
+        scene.add_material(Material::Null);
+        scene.add_hittable(Sphere::default());
+        scene.add_node(NodeId::ROOT, "group", Transform::IDENTITY);
+        scene.materials_mut();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            [
+                SceneChange::Materials,
+                SceneChange::Hittables,
+                SceneChange::Nodes,
+                SceneChange::Materials,
+            ]
+        );
+
+        scene.remove_observer(id);
+        scene.add_hittable(Sphere::default());
+        assert_eq!(changes.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn copies_have_their_own_observers() {
+        let mut scene = Scene::default();
+        let (_, changes) = record(&mut scene);
+        let mut copy = scene.clone();
+        copy.add_hittable(Sphere::default());
+        assert!(changes.lock().unwrap().is_empty());
+
+        // but running statements keeps them, as it's still the same scene
+        scene.run_dsl("sphere 0 0 0 1 #0").unwrap();
+        assert!(changes.lock().unwrap().contains(&SceneChange::Hittables));
+    }
+}
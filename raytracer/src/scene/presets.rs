@@ -0,0 +1,488 @@
+//! Programmatically generated scenes, for benchmarks, tests, and demos.
+
+use crate::{Camera, Material, MaterialHandle, Noise, Quad, Scene, Sphere, Texture};
+use glam::Vec3;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{fmt, str::FromStr};
+
+/// The built-in scene generators, with default parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// Three spheres sitting on a large ground sphere.
+    Demo,
+    /// A field of small randomly colored spheres, like the final scene of
+    /// _Ray Tracing in One Weekend_.
+    RandomSpheres,
+    /// A box with colored side walls and a light in the ceiling, open
+    /// towards the camera.
+    CornellBox,
+    /// A cube of spheres.
+    SphereGrid,
+    /// A sphere with smaller spheres recursively budding off of it.
+    SphereFlake,
+    /// A Menger sponge built out of spheres.
+    MengerSponge,
+    /// Marble and wood spheres textured with procedural noise, like
+    /// _Ray Tracing: The Next Week_.
+    NoiseSpheres,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 7] = [
+        Preset::Demo,
+        Preset::RandomSpheres,
+        Preset::CornellBox,
+        Preset::SphereGrid,
+        Preset::SphereFlake,
+        Preset::MengerSponge,
+        Preset::NoiseSpheres,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Demo => "demo",
+            Preset::RandomSpheres => "random-spheres",
+            Preset::CornellBox => "cornell-box",
+            Preset::SphereGrid => "sphere-grid",
+            Preset::SphereFlake => "sphere-flake",
+            Preset::MengerSponge => "menger-sponge",
+            Preset::NoiseSpheres => "noise-spheres",
+        }
+    }
+
+    pub fn scene(&self) -> Scene {
+        match self {
+            Preset::Demo => demo(),
+            Preset::RandomSpheres => random_spheres(400, 0),
+            Preset::CornellBox => cornell_box(),
+            Preset::SphereGrid => sphere_grid(8),
+            Preset::SphereFlake => sphere_flake(3),
+            Preset::MengerSponge => menger_sponge(2),
+            Preset::NoiseSpheres => noise_spheres(),
+        }
+    }
+
+    /// A camera positioned to show the whole scene.
+    pub fn camera(&self) -> Camera {
+        let mut camera = Camera::default();
+        match self {
+            Preset::Demo => camera.set_position((0., 0.75, 4.).into()),
+            Preset::RandomSpheres => {
+                camera.set_position((13., 2., 3.).into());
+                camera.set_look_direction(Vec3::new(-13., -2., -3.));
+            }
+            Preset::CornellBox => {
+                camera.set_position((0., 1., 4.5).into());
+                camera.set_vertical_fov(40.);
+            }
+            Preset::SphereGrid => {
+                camera.set_position((0., 0., 6.).into());
+                camera.set_vertical_fov(45.);
+            }
+            Preset::SphereFlake => {
+                camera.set_position((0., 2.5, 6.).into());
+                camera.set_look_direction(Vec3::new(0., -1.5, -6.));
+                camera.set_vertical_fov(40.);
+            }
+            Preset::MengerSponge => {
+                camera.set_position((3., 3., 5.).into());
+                camera.set_look_direction(Vec3::new(-3., -2., -5.));
+                camera.set_vertical_fov(40.);
+            }
+            Preset::NoiseSpheres => {
+                camera.set_position((0., 1.5, 5.).into());
+                camera.set_look_direction(Vec3::new(0., -0.5, -5.));
+            }
+        }
+        camera
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|preset| preset.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(Preset::name).collect();
+                anyhow::anyhow!("unknown preset {s:?}, expected one of {}", names.join(", "))
+            })
+    }
+}
+
+/// Adds a sphere large enough to look like a flat floor at `y = 0`.
+fn add_ground(scene: &mut Scene, albedo: Vec3) {
+    let material = scene.add_material(Material::Lambertian { albedo });
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0., -10_000., 0.),
+        radius: 10_000.,
+        material,
+    });
+}
+
+/// Three spheres sitting on a large ground sphere.
+pub fn demo() -> Scene {
+    let mut scene = Scene::default();
+    add_ground(&mut scene, Vec3::new(0.9, 0.2, 0.1));
+    let ball_material = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.7, 0.7, 0.7),
+    });
+    for x in [-1.1, 0., 1.1] {
+        scene.add_hittable(Sphere {
+            center: Vec3::new(x, 0.5, 0.),
+            radius: 0.5,
+            material: ball_material,
+        });
+    }
+    scene
+}
+
+/// `count` small spheres scattered on a ground plane, each with its own
+/// random color, plus three large spheres in the middle. The same `seed`
+/// always produces the same scene.
+pub fn random_spheres(count: usize, seed: u64) -> Scene {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut scene = Scene::default();
+    add_ground(&mut scene, Vec3::new(0.5, 0.5, 0.5));
+
+    // Place each sphere in its own cell of a square grid, so they never overlap.
+    let side = (count as f32).sqrt().ceil() as usize;
+    let half = side as f32 / 2.;
+    for idx in 0..count {
+        let cell = Vec3::new((idx % side) as f32 - half, 0., (idx / side) as f32 - half);
+        let offset = Vec3::new(rng.gen_range(0.1..0.9), 0.2, rng.gen_range(0.1..0.9));
+        let albedo = rng.gen::<Vec3>() * rng.gen::<Vec3>();
+        let material = scene.add_material(Material::Lambertian { albedo });
+        scene.add_hittable(Sphere {
+            center: cell + offset,
+            radius: 0.2,
+            material,
+        });
+    }
+
+    for (x, albedo) in [
+        (-4., Vec3::new(0.4, 0.2, 0.1)),
+        (0., Vec3::new(0.7, 0.6, 0.5)),
+        (4., Vec3::new(0.2, 0.4, 0.8)),
+    ] {
+        let material = scene.add_material(Material::Lambertian { albedo });
+        scene.add_hittable(Sphere {
+            center: Vec3::new(x, 1., 0.),
+            radius: 1.,
+            material,
+        });
+    }
+
+    scene
+}
+
+/// A 2x2x2 box centered above the origin with a red left wall and a green
+/// right wall, containing two spheres and lit by a panel in the ceiling. The
+/// box is open towards +Z.
+pub fn cornell_box() -> Scene {
+    let mut scene = Scene::default();
+
+    let white = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.73, 0.73, 0.73),
+    });
+    let red = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.65, 0.05, 0.05),
+    });
+    let green = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.12, 0.45, 0.15),
+    });
+
+    // sides are ordered so each wall faces into the box
+    let corner = Vec3::new(-1., 0., -1.);
+    for (corner, u, v, material) in [
+        (corner, Vec3::Z * 2., Vec3::X * 2., white),
+        (corner + Vec3::Y * 2., Vec3::X * 2., Vec3::Z * 2., white),
+        (corner, Vec3::X * 2., Vec3::Y * 2., white),
+        (corner, Vec3::Y * 2., Vec3::Z * 2., red),
+        (corner + Vec3::X * 2., Vec3::Z * 2., Vec3::Y * 2., green),
+    ] {
+        scene.add_hittable(Quad {
+            corner,
+            u,
+            v,
+            material,
+        });
+    }
+
+    let light = scene.add_material(Material::Emissive {
+        emission: Vec3::splat(15.),
+    });
+    // just below the ceiling, so the ceiling doesn't hide it
+    scene.add_hittable(Quad {
+        corner: Vec3::new(-0.3, 1.999, -0.3),
+        u: Vec3::X * 0.6,
+        v: Vec3::Z * 0.6,
+        material: light,
+    });
+
+    scene.add_hittable(Sphere {
+        center: Vec3::new(-0.4, 0.35, -0.3),
+        radius: 0.35,
+        material: white,
+    });
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0.45, 0.25, 0.3),
+        radius: 0.25,
+        material: white,
+    });
+
+    scene
+}
+
+/// `n * n * n` spheres arranged in a cube centered on the origin, with
+/// colors varying along each axis.
+pub fn sphere_grid(n: usize) -> Scene {
+    let mut scene = Scene::default();
+    let spacing = 3. / n as f32;
+    let half = (n as f32 - 1.) / 2.;
+    for z in 0..n {
+        for y in 0..n {
+            for x in 0..n {
+                let grid = Vec3::new(x as f32, y as f32, z as f32);
+                let albedo = (grid + 0.5) / n as f32;
+                let material = scene.add_material(Material::Lambertian { albedo });
+                scene.add_hittable(Sphere {
+                    center: (grid - half) * spacing,
+                    radius: spacing * 0.4,
+                    material,
+                });
+            }
+        }
+    }
+    scene
+}
+
+/// A unit sphere with nine spheres a third of its size touching it, each of
+/// which has nine more spheres touching it, recursing `depth` times. This
+/// makes `(9^(depth + 1) - 1) / 8` spheres, about 66k for a depth of 5 and
+/// 600k for a depth of 6.
+pub fn sphere_flake(depth: u32) -> Scene {
+    let mut scene = Scene::default();
+    add_ground(&mut scene, Vec3::splat(0.5));
+    let materials: Vec<MaterialHandle> = (0..=depth)
+        .map(|level| {
+            let t = level as f32 / depth.max(1) as f32;
+            scene.add_material(Material::Lambertian {
+                albedo: Vec3::new(0.8, 0.7, 0.3).lerp(Vec3::new(0.2, 0.4, 0.8), t),
+            })
+        })
+        .collect();
+    add_flake(&mut scene, &materials, Vec3::Y, 1., Vec3::Y, depth);
+    scene
+}
+
+fn add_flake(
+    scene: &mut Scene,
+    materials: &[MaterialHandle],
+    center: Vec3,
+    radius: f32,
+    axis: Vec3,
+    depth: u32,
+) {
+    use std::f32::consts::{FRAC_PI_3, FRAC_PI_6};
+
+    scene.add_hittable(Sphere {
+        center,
+        radius,
+        material: materials[materials.len() - 1 - depth as usize],
+    });
+    if depth == 0 {
+        return;
+    }
+
+    // Six children around the equator, and three more above them, in a
+    // frame where the axis pointing away from the parent is +Y.
+    let frame = glam::Quat::from_rotation_arc(Vec3::Y, axis);
+    let ring = (0..6).map(|idx| (0., idx as f32 * FRAC_PI_3));
+    let top = (0..3).map(|idx| (FRAC_PI_3, FRAC_PI_6 + idx as f32 * 2. * FRAC_PI_3));
+    let child_radius = radius / 3.;
+    for (elevation, azimuth) in ring.chain(top) {
+        let direction = frame
+            * Vec3::new(
+                elevation.cos() * azimuth.cos(),
+                elevation.sin(),
+                elevation.cos() * azimuth.sin(),
+            );
+        let child_center = center + direction * (radius + child_radius);
+        add_flake(
+            scene,
+            materials,
+            child_center,
+            child_radius,
+            direction,
+            depth - 1,
+        );
+    }
+}
+
+/// A Menger sponge with a side length of 2 sitting on the ground, made of
+/// `20^depth` spheres, one for each of the smallest cubes. A depth of 4 is
+/// 160k spheres.
+pub fn menger_sponge(depth: u32) -> Scene {
+    let mut scene = Scene::default();
+    add_ground(&mut scene, Vec3::splat(0.5));
+    let material = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.7, 0.7, 0.75),
+    });
+    add_sponge(&mut scene, material, Vec3::Y, 2., depth);
+    scene
+}
+
+fn add_sponge(scene: &mut Scene, material: MaterialHandle, center: Vec3, size: f32, depth: u32) {
+    if depth == 0 {
+        scene.add_hittable(Sphere {
+            center,
+            radius: size / 2.,
+            material,
+        });
+        return;
+    }
+
+    let sub_size = size / 3.;
+    for z in -1..=1_i32 {
+        for y in -1..=1_i32 {
+            for x in -1..=1_i32 {
+                // Remove the center of each face, and the center of the cube.
+                let centered_axes = [x, y, z].iter().filter(|c| **c == 0).count();
+                if centered_axes < 2 {
+                    let offset = Vec3::new(x as f32, y as f32, z as f32) * sub_size;
+                    add_sponge(scene, material, center + offset, sub_size, depth - 1);
+                }
+            }
+        }
+    }
+}
+
+/// A marble sphere and a wooden sphere on a marble floor.
+pub fn noise_spheres() -> Scene {
+    let mut scene = Scene::default();
+    let floor = scene.add_material(Material::Textured {
+        texture: Texture::Noise(Noise::marble(0)),
+    });
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0., -10_000., 0.),
+        radius: 10_000.,
+        material: floor,
+    });
+    let marble = scene.add_material(Material::Textured {
+        texture: Texture::Noise(Noise::marble(1)),
+    });
+    let wood = scene.add_material(Material::Textured {
+        texture: Texture::Noise(Noise::wood(2)),
+    });
+    for (x, material) in [(-0.6, marble), (0.6, wood)] {
+        scene.add_hittable(Sphere {
+            center: Vec3::new(x, 0.5, 0.),
+            radius: 0.5,
+            material,
+        });
+    }
+    scene
+}
+
+/// A sphere of `material` on a gray floor, to see what the material looks
+/// like by itself, from [`material_preview_camera`].
+pub fn material_preview(material: Material) -> Scene {
+    let mut scene = Scene::default();
+    add_ground(&mut scene, Vec3::splat(0.5));
+    let material = scene.add_material(material);
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0., 0.5, 0.),
+        radius: 0.5,
+        material,
+    });
+    scene
+}
+
+/// Looks down at the sphere in [`material_preview`] from in front and a
+/// little to the side, framing it tightly.
+pub fn material_preview_camera() -> Camera {
+    let mut camera = Camera::default();
+    let position = Vec3::new(0.8, 1.1, 2.);
+    camera.set_position(position);
+    camera.set_look_direction(Vec3::new(0., 0.45, 0.) - position);
+    camera.set_vertical_fov(35.);
+    camera
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hittable;
+    use glam::Vec2;
+
+    fn centers(scene: &Scene) -> Vec<Vec3> {
+        scene
+            .hittables()
+            .iter()
+            .filter_map(|hittable| match hittable {
+                Hittable::Sphere(sphere) => Some(sphere.center),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_spheres_is_seeded() {
+        let a = random_spheres(50, 7);
+        let b = random_spheres(50, 7);
+        let c = random_spheres(50, 8);
+        // ground + requested spheres + the three big ones
+        assert_eq!(a.hittables().len(), 54);
+        assert_eq!(centers(&a), centers(&b));
+        assert_ne!(centers(&a), centers(&c));
+    }
+
+    #[test]
+    fn sphere_grid_count() {
+        assert_eq!(sphere_grid(3).hittables().len(), 27);
+    }
+
+    #[test]
+    fn sphere_flake_count() {
+        // ground + 1 + 9 + 81
+        assert_eq!(sphere_flake(2).hittables().len(), 92);
+    }
+
+    #[test]
+    fn menger_sponge_count() {
+        // ground + 20 * 20
+        assert_eq!(menger_sponge(2).hittables().len(), 401);
+    }
+
+    #[test]
+    fn material_preview_frames_the_sphere() {
+        let scene = material_preview(Material::Emissive {
+            emission: Vec3::ONE,
+        });
+        let mut camera = material_preview_camera();
+        camera.set_size(16, 16);
+        let ray = camera.ray_for_pixel(8, 8, Vec2::ZERO);
+        let hit = scene.raycast(&ray, &(0.001..f32::INFINITY)).unwrap();
+        assert!(matches!(
+            scene.material(hit.material),
+            Material::Emissive { .. }
+        ));
+    }
+
+    #[test]
+    fn preset_names_round_trip() {
+        for preset in Preset::ALL {
+            assert_eq!(preset.name().parse::<Preset>().unwrap(), preset);
+        }
+        assert!("nope".parse::<Preset>().is_err());
+    }
+}
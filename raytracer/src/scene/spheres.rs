@@ -0,0 +1,233 @@
+use crate::{
+    bvh::{Aabb, Bvh},
+    geom::{from_real, real, real_vec3, Ray, Real, RealVec3},
+    hittable::{HitPayload, Hittable},
+    MaterialHandle,
+};
+use glam::Vec3;
+use std::ops::{ControlFlow, Range};
+
+/// The spheres among the world hittables, kept as a structure of arrays in
+/// the order of a BVH over them, so each leaf's spheres are next to each
+/// other in every array. Rays test a whole leaf in one loop, which reads
+/// memory in order and lets the compiler use SIMD across spheres, instead
+/// of going through [`Hittable::check_hit`] for each one.
+#[derive(Clone, Debug)]
+pub(super) struct Spheres {
+    bvh: Bvh,
+    /// Where each sphere is in the world hittables.
+    hittables: Vec<usize>,
+    /// The x, y, and z of each center.
+    centers: [Vec<f32>; 3],
+    radii: Vec<f32>,
+    materials: Vec<MaterialHandle>,
+}
+
+impl Spheres {
+    pub fn build(hittables: &[Hittable]) -> Spheres {
+        let indexes: Vec<usize> = (0..hittables.len())
+            .filter(|idx| matches!(hittables[*idx], Hittable::Sphere(_)))
+            .collect();
+        let bounds: Vec<Aabb> = indexes.iter().map(|idx| hittables[*idx].bounding_box()).collect();
+        let bvh = Bvh::build(&bounds);
+        let mut spheres = Spheres {
+            hittables: bvh.order().iter().map(|primitive| indexes[*primitive]).collect(),
+            bvh,
+            centers: Default::default(),
+            radii: Vec::new(),
+            materials: Vec::new(),
+        };
+        spheres.copy_from(hittables);
+        spheres
+    }
+
+    /// Follows spheres that have moved, keeping the tree's shape, like
+    /// [`Bvh::refit`]. The hittables must be the same ones the spheres
+    /// were built from.
+    pub fn refit(&mut self, hittables: &[Hittable]) {
+        self.copy_from(hittables);
+        // the tree wants bounds in the order it was built from
+        let mut bounds = vec![Aabb::EMPTY; self.hittables.len()];
+        for (slot, primitive) in self.bvh.order().iter().enumerate() {
+            bounds[*primitive] = Aabb::around(self.center(slot), self.radii[slot]);
+        }
+        self.bvh.refit(&bounds);
+    }
+
+    fn copy_from(&mut self, hittables: &[Hittable]) {
+        for values in &mut self.centers {
+            values.clear();
+        }
+        self.radii.clear();
+        self.materials.clear();
+        for idx in &self.hittables {
+            let Hittable::Sphere(sphere) = &hittables[*idx] else {
+                panic!("hittable {idx} is no longer a sphere");
+            };
+            for (values, value) in self.centers.iter_mut().zip(sphere.center.to_array()) {
+                values.push(value);
+            }
+            self.radii.push(sphere.radius);
+            self.materials.push(sphere.material);
+        }
+    }
+
+    fn center(&self, slot: usize) -> Vec3 {
+        let [x, y, z] = &self.centers;
+        Vec3::new(x[slot], y[slot], z[slot])
+    }
+
+    /// The closest sphere `ray` hits within `clip`, by its index in the
+    /// world hittables, shrinking `clip` to end there.
+    pub fn trace_ray(
+        &self,
+        ray: &Ray,
+        clip: &mut Range<f32>,
+        tests: &mut u64,
+    ) -> (usize, HitPayload) {
+        let mut closest = None;
+        self.bvh.traverse_leaves(ray, clip, |slots, clip| {
+            *tests += slots.len() as u64;
+            if let Some((slot, t)) = self.check_leaf(ray, slots, clip) {
+                // only closer hits count from here on
+                clip.end = from_real(t);
+                closest = Some((slot, t));
+            }
+            ControlFlow::<()>::Continue(())
+        });
+        match closest {
+            Some((slot, t)) => {
+                let center = real_vec3(self.center(slot));
+                let hit = Hittable::sphere_hit(ray, center, self.materials[slot], t);
+                (self.hittables[slot], hit)
+            }
+            None => (0, HitPayload::Miss),
+        }
+    }
+
+    /// Whether `ray` hits any sphere within `clip`.
+    pub fn occluded(&self, ray: &Ray, clip: &Range<f32>, tests: &mut u64) -> bool {
+        self.bvh
+            .traverse_leaves(ray, &mut clip.clone(), |slots, clip| {
+                *tests += slots.len() as u64;
+                match self.check_leaf(ray, slots, clip) {
+                    Some(_) => ControlFlow::Break(()),
+                    None => ControlFlow::Continue(()),
+                }
+            })
+            .is_some()
+    }
+
+    /// Tests `ray` against every sphere in `slots` at once, returning the
+    /// slot and distance of the closest hit. The math is the same as
+    /// [`Hittable::check_hit`]'s, so the results match it exactly, but it's
+    /// written without early exits so the loop can be vectorized.
+    #[inline]
+    fn check_leaf(
+        &self,
+        ray: &Ray,
+        slots: Range<usize>,
+        clip: &Range<f32>,
+    ) -> Option<(usize, Real)> {
+        let origin = ray.origin;
+        let direction = real_vec3(ray.direction);
+        let a = direction.length_squared();
+        let clip = real(clip.start)..real(clip.end);
+        let [xs, ys, zs] = &self.centers;
+        let (xs, ys, zs) = (&xs[slots.clone()], &ys[slots.clone()], &zs[slots.clone()]);
+        let radii = &self.radii[slots.clone()];
+
+        let mut nearest = None;
+        let mut end = clip.end;
+        for i in 0..radii.len() {
+            let center = RealVec3::new(real(xs[i]), real(ys[i]), real(zs[i]));
+            let radius = real(radii[i]);
+            let offset_center = origin - center;
+            let half_b = offset_center.dot(direction);
+            let c = offset_center.length_squared() - radius.powi(2);
+            let discrim = half_b.powi(2) - a * c;
+            let sqrtd = discrim.max(0.).sqrt();
+            let near = (-half_b - sqrtd) / a;
+            let t = if clip.contains(&near) {
+                near
+            } else {
+                (-half_b + sqrtd) / a
+            };
+
+            if discrim >= 0. && (clip.start..end).contains(&t) {
+                end = t;
+                nearest = Some(slots.start + i);
+            }
+        }
+        nearest.map(|slot| (slot, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spheres;
+    use crate::{hittable::HitPayload, Hittable, Quad, Ray, Sphere};
+    use glam::Vec3;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn matches_testing_each_sphere() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hittables: Vec<Hittable> = (0..200)
+            .map(|_| {
+                Sphere {
+                    center: Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 20. - 10.,
+                    radius: rng.gen_range(0.1..1.5),
+                    ..Sphere::default()
+                }
+                .into()
+            })
+            .collect();
+        // other hittables are left out
+        hittables.insert(7, Quad::default().into());
+        let mut spheres = Spheres::build(&hittables);
+
+        let mut check = |hittables: &[Hittable], spheres: &Spheres| {
+            for _ in 0..500 {
+                let origin = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 24. - 12.;
+                let direction = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * 2. - 1.;
+                let ray = Ray::new(origin, direction);
+                let clip = 0.001..30.;
+                let results: Vec<_> = hittables
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, hittable)| matches!(hittable, Hittable::Sphere(_)))
+                    .map(|(idx, hittable)| (idx, hittable.check_hit(&ray, &clip)))
+                    .collect();
+                let (idx, hit) = spheres.trace_ray(&ray, &mut clip.clone(), &mut 0);
+                let closest = results
+                    .into_iter()
+                    .filter_map(|(idx, hit)| match hit {
+                        HitPayload::Hit { hit_distance, .. } => Some((hit_distance, idx)),
+                        _ => None,
+                    })
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+                match closest {
+                    Some((_, expected)) => {
+                        assert_eq!(idx, expected);
+                        assert!(hit == hittables[expected].check_hit(&ray, &clip));
+                        assert!(spheres.occluded(&ray, &clip, &mut 0));
+                    }
+                    None => {
+                        assert!(hit == HitPayload::Miss);
+                        assert!(!spheres.occluded(&ray, &clip, &mut 0));
+                    }
+                }
+            }
+        };
+        check(&hittables, &spheres);
+
+        for hittable in &mut hittables {
+            if let Hittable::Sphere(sphere) = hittable {
+                sphere.center = sphere.center * 0.5 + Vec3::X;
+            }
+        }
+        spheres.refit(&hittables);
+        check(&hittables, &spheres);
+    }
+}
@@ -0,0 +1,342 @@
+//! Checks for things in a scene that are probably mistakes, like spheres
+//! with no size, or positions that aren't numbers at all.
+
+use super::{NodeId, Scene};
+use crate::{Hittable, Material, MeshGeometry, Transform};
+use glam::Vec3;
+use std::{collections::HashSet, fmt};
+
+/// How much a [`Diagnostic`] matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The scene renders the way it says, but that's likely not what was
+    /// meant, like an object nothing can see.
+    Warning,
+    /// Part of the scene can't render properly.
+    Error,
+}
+
+/// What a [`Diagnostic`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subject {
+    Node(NodeId),
+    /// A hittable, by its index in [`Scene::hittables`].
+    Hittable(usize),
+    /// A material, by its index in [`Scene::materials`].
+    Material(usize),
+}
+
+/// A problem found by [`Scene::validate`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub subject: Subject,
+    pub message: String,
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::Node(id) => write!(f, "node {}", id.0),
+            Subject::Hittable(idx) => write!(f, "object {idx}"),
+            Subject::Material(idx) => write!(f, "material {idx}"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}: {}", self.subject, self.message)
+    }
+}
+
+impl Scene {
+    /// Look for problems in the scene, such as objects with no size,
+    /// positions that aren't finite, materials that don't exist or aren't
+    /// used, and triangles with no area. None of them stop the scene from
+    /// rendering, so it's up to the caller what to do about them.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        for (idx, node) in self.nodes.iter().enumerate() {
+            let mut report = Report {
+                subject: Subject::Node(NodeId(idx)),
+                diagnostics: &mut diagnostics,
+            };
+            report.transform(&node.transform);
+        }
+
+        let mut used = HashSet::new();
+        for (idx, hittable) in self.hittables.iter().enumerate() {
+            let mut report = Report {
+                subject: Subject::Hittable(idx),
+                diagnostics: &mut diagnostics,
+            };
+            report.hittable(hittable);
+            let material = hittable.material();
+            if material.index() >= self.materials.len() {
+                report.push(
+                    Severity::Error,
+                    format!(
+                        "uses material {}, but there are only {} materials",
+                        material.index(),
+                        self.materials.len()
+                    ),
+                );
+            }
+            used.insert(material.index());
+        }
+
+        for (idx, material) in self.materials.iter().enumerate() {
+            if !matches!(material, Material::Null) && !used.contains(&idx) {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Warning,
+                    subject: Subject::Material(idx),
+                    message: "isn't used by any object".to_string(),
+                });
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// Collects diagnostics about one subject.
+struct Report<'a> {
+    subject: Subject,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl Report<'_> {
+    fn push(&mut self, severity: Severity, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            subject: self.subject,
+            message,
+        });
+    }
+
+    fn hittable(&mut self, hittable: &Hittable) {
+        match hittable {
+            Hittable::Sphere(sphere) => {
+                self.position("center", sphere.center);
+                self.size("radius", sphere.radius);
+            }
+            Hittable::Quad(quad) => {
+                self.position("corner", quad.corner);
+                self.position("u", quad.u);
+                self.position("v", quad.v);
+                if quad.u.cross(quad.v) == Vec3::ZERO {
+                    self.push(Severity::Warning, "has no area".to_string());
+                }
+            }
+            Hittable::Cylinder(cylinder) => {
+                self.axis(cylinder.base, cylinder.axis);
+                self.size("radius", cylinder.radius);
+            }
+            Hittable::Cone(cone) => {
+                self.axis(cone.base, cone.axis);
+                self.size("radius", cone.radius);
+            }
+            Hittable::Torus(torus) => {
+                self.axis(torus.center, torus.axis);
+                self.size("major radius", torus.major_radius);
+                self.size("minor radius", torus.minor_radius);
+            }
+            Hittable::Sdf(sdf) => self.transform(&sdf.transform),
+            Hittable::Curve(curve) => {
+                for point in curve.points {
+                    self.position("control point", point);
+                }
+                // tapering to nothing at the ends is fine
+                if curve.widths.iter().any(|width| !width.is_finite()) {
+                    self.push(
+                        Severity::Error,
+                        format!("widths are {:?}, which aren't all finite", curve.widths),
+                    );
+                } else if curve.widths.iter().any(|width| *width < 0.) {
+                    self.push(
+                        Severity::Warning,
+                        format!("widths are {:?}, but none should be negative", curve.widths),
+                    );
+                }
+            }
+            Hittable::Mesh(mesh) => {
+                self.transform(&mesh.transform);
+                self.geometry(&mesh.geometry);
+            }
+            Hittable::Csg(csg) => {
+                self.hittable(&csg.a);
+                self.hittable(&csg.b);
+            }
+            // only the shape knows what it needs
+            Hittable::Custom(_) => (),
+        }
+    }
+
+    fn transform(&mut self, transform: &Transform) {
+        self.position("translation", transform.translation);
+        if !transform.rotation.is_finite() {
+            self.push(
+                Severity::Error,
+                format!("rotation is {}, which isn't finite", transform.rotation),
+            );
+        }
+        self.size("scale", transform.scale);
+    }
+
+    fn axis(&mut self, base: Vec3, axis: Vec3) {
+        self.position("base", base);
+        self.position("axis", axis);
+        if axis == Vec3::ZERO {
+            self.push(Severity::Warning, "axis has no length".to_string());
+        }
+    }
+
+    fn position(&mut self, name: &str, value: Vec3) {
+        if !value.is_finite() {
+            self.push(
+                Severity::Error,
+                format!("{name} is {value}, which isn't finite"),
+            );
+        }
+    }
+
+    /// Sizes should be positive, or the hittable can't be seen, or is
+    /// turned inside out.
+    fn size(&mut self, name: &str, value: f32) {
+        if !value.is_finite() {
+            self.push(
+                Severity::Error,
+                format!("{name} is {value}, which isn't finite"),
+            );
+        } else if value <= 0. {
+            self.push(
+                Severity::Warning,
+                format!("{name} is {value}, but it should be positive"),
+            );
+        }
+    }
+
+    fn geometry(&mut self, geometry: &MeshGeometry) {
+        let positions = geometry.positions();
+        let not_finite = positions
+            .iter()
+            .filter(|position| !position.is_finite())
+            .count();
+        if not_finite > 0 {
+            self.push(
+                Severity::Error,
+                format!("{not_finite} of the mesh's positions aren't finite"),
+            );
+        }
+
+        let mut out_of_range = 0;
+        let mut degenerate = 0;
+        for triangle in geometry.triangles() {
+            let corners = triangle.map(|corner| positions.get(corner as usize).copied());
+            let [Some(a), Some(b), Some(c)] = corners else {
+                out_of_range += 1;
+                continue;
+            };
+            if (b - a).cross(c - a) == Vec3::ZERO {
+                degenerate += 1;
+            }
+        }
+        if out_of_range > 0 {
+            self.push(
+                Severity::Error,
+                format!(
+                    "{out_of_range} triangles use positions past the {} the mesh has",
+                    positions.len()
+                ),
+            );
+        }
+        if degenerate > 0 {
+            self.push(
+                Severity::Warning,
+                format!("{degenerate} triangles have no area"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Severity, Subject};
+    use crate::{
+        Csg, Material, MaterialHandle, Mesh, MeshGeometry, NodeId, Quad, Scene, Sphere, Transform,
+    };
+    use glam::Vec3;
+    use std::sync::Arc;
+
+    #[test]
+    fn finds_problems() {
+        let mut scene = Scene::default();
+        let used = scene.add_material(Material::Lambertian { albedo: Vec3::ONE });
+        scene.add_hittable(Sphere {
+            center: Vec3::ZERO,
+            radius: 1.,
+            material: used,
+        });
+        assert_eq!(scene.validate(), vec![]);
+
+        let unused = scene.add_material(Material::Lambertian { albedo: Vec3::ONE });
+        let node = scene.add_node(
+            NodeId::ROOT,
+            "squashed",
+            Transform {
+                scale: 0.,
+                ..Transform::IDENTITY
+            },
+        );
+        scene.add_hittable(Sphere {
+            center: Vec3::new(f32::NAN, 0., 0.),
+            radius: -1.,
+            material: used,
+        });
+        let quad = scene.add_hittable(Quad {
+            u: Vec3::X,
+            v: Vec3::X * 2.,
+            material: used,
+            ..Quad::default()
+        });
+        // adding checks the material, but editing can't
+        *scene.hittable_mut(quad).material_mut() = MaterialHandle(7);
+        let geometry = MeshGeometry::new(
+            vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::X * 2.],
+            vec![[0, 1, 2], [0, 1, 3]],
+        );
+        scene.add_hittable(Csg::union(
+            Sphere::default(),
+            Mesh::new(Arc::new(geometry), used),
+            used,
+        ));
+
+        let found: Vec<_> = scene
+            .validate()
+            .into_iter()
+            .map(|diagnostic| (diagnostic.subject, diagnostic.severity))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (Subject::Node(node), Severity::Warning),
+                (Subject::Hittable(1), Severity::Error),
+                (Subject::Hittable(1), Severity::Warning),
+                (Subject::Hittable(2), Severity::Warning),
+                (Subject::Hittable(2), Severity::Error),
+                (Subject::Hittable(3), Severity::Warning),
+                (Subject::Material(unused.index()), Severity::Warning),
+            ]
+        );
+        assert_eq!(
+            scene.validate()[4].to_string(),
+            "error: object 2: uses material 7, but there are only 3 materials"
+        );
+    }
+}
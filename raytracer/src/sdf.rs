@@ -0,0 +1,249 @@
+//! Shapes described by signed distance functions, for blends and carved
+//! shapes that are awkward to intersect analytically. They're rendered by
+//! sphere tracing, which is slower than the other hittables.
+
+use crate::{MaterialHandle, Transform};
+use glam::{Vec2, Vec3, Vec3Swizzles};
+
+/// An [`SdfShape`] placed in the scene.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sdf {
+    pub shape: SdfShape,
+    /// Places the shape, which is modeled around the origin.
+    pub transform: Transform,
+    pub material: MaterialHandle,
+}
+
+impl Default for Sdf {
+    fn default() -> Self {
+        Self {
+            shape: SdfShape::sphere(1.),
+            transform: Transform::IDENTITY,
+            material: MaterialHandle::NULL,
+        }
+    }
+}
+
+/// A tree of distance functions and ways to combine them.
+///
+/// ```
+/// # use halide_raytracer::SdfShape;
+/// # use glam::Vec3;
+/// let blob = SdfShape::sphere(0.5)
+///     .smooth_union(SdfShape::sphere(0.3).translated(Vec3::X * 0.6), 0.2)
+///     .subtract(SdfShape::rounded_box(Vec3::splat(0.2), 0.05));
+/// assert!(blob.distance(Vec3::ZERO) > 0.);
+/// assert!(blob.distance(Vec3::Y * 0.4) < 0.);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SdfShape {
+    Sphere {
+        radius: f32,
+    },
+    /// A box from `-half_size` to `half_size`, with its edges rounded off
+    /// by `rounding`.
+    Box {
+        half_size: Vec3,
+        rounding: f32,
+    },
+    /// A ring around the Y axis.
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+    },
+    /// A line from `a` to `b`, thickened by `radius`.
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    Translate {
+        offset: Vec3,
+        shape: Box<SdfShape>,
+    },
+    Union(Box<SdfShape>, Box<SdfShape>),
+    Intersection(Box<SdfShape>, Box<SdfShape>),
+    /// The first shape, with the second cut out of it.
+    Subtraction(Box<SdfShape>, Box<SdfShape>),
+    /// A union that melts the shapes together where they're closer than
+    /// `smoothness`.
+    SmoothUnion {
+        a: Box<SdfShape>,
+        b: Box<SdfShape>,
+        smoothness: f32,
+    },
+}
+
+impl SdfShape {
+    pub fn sphere(radius: f32) -> Self {
+        Self::Sphere { radius }
+    }
+
+    pub fn rounded_box(half_size: Vec3, rounding: f32) -> Self {
+        Self::Box {
+            half_size,
+            rounding,
+        }
+    }
+
+    pub fn torus(major_radius: f32, minor_radius: f32) -> Self {
+        Self::Torus {
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    pub fn capsule(a: Vec3, b: Vec3, radius: f32) -> Self {
+        Self::Capsule { a, b, radius }
+    }
+
+    pub fn translated(self, offset: Vec3) -> Self {
+        Self::Translate {
+            offset,
+            shape: Box::new(self),
+        }
+    }
+
+    pub fn union(self, other: SdfShape) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: SdfShape) -> Self {
+        Self::Intersection(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: SdfShape) -> Self {
+        Self::Subtraction(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfShape, smoothness: f32) -> Self {
+        Self::SmoothUnion {
+            a: Box::new(self),
+            b: Box::new(other),
+            smoothness,
+        }
+    }
+
+    /// How far `p` is from the surface, negative inside the shape. Combined
+    /// shapes may underestimate the distance, but never overestimate it.
+    pub fn distance(&self, p: Vec3) -> f32 {
+        match self {
+            SdfShape::Sphere { radius } => p.length() - radius,
+            SdfShape::Box {
+                half_size,
+                rounding,
+            } => {
+                let q = p.abs() - *half_size + *rounding;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.) - rounding
+            }
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => Vec2::new(p.xz().length() - major_radius, p.y).length() - minor_radius,
+            SdfShape::Capsule { a, b, radius } => {
+                let pa = p - *a;
+                let ba = *b - *a;
+                let along = (pa.dot(ba) / ba.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+                (pa - ba * along).length() - radius
+            }
+            SdfShape::Translate { offset, shape } => shape.distance(p - *offset),
+            SdfShape::Union(a, b) => a.distance(p).min(b.distance(p)),
+            SdfShape::Intersection(a, b) => a.distance(p).max(b.distance(p)),
+            SdfShape::Subtraction(a, b) => a.distance(p).max(-b.distance(p)),
+            SdfShape::SmoothUnion { a, b, smoothness } => {
+                let (a, b) = (a.distance(p), b.distance(p));
+                let k = smoothness.max(f32::EPSILON);
+                let h = (0.5 + 0.5 * (b - a) / k).clamp(0., 1.);
+                b + (a - b) * h - k * h * (1. - h)
+            }
+        }
+    }
+
+    /// The radius of a sphere around the origin that contains the whole
+    /// shape, so rays that miss it can be skipped.
+    pub fn bounding_radius(&self) -> f32 {
+        match self {
+            SdfShape::Sphere { radius } => *radius,
+            SdfShape::Box { half_size, .. } => half_size.length(),
+            SdfShape::Torus {
+                major_radius,
+                minor_radius,
+            } => major_radius + minor_radius,
+            SdfShape::Capsule { a, b, radius } => a.length().max(b.length()) + radius,
+            SdfShape::Translate { offset, shape } => offset.length() + shape.bounding_radius(),
+            SdfShape::Union(a, b) => a.bounding_radius().max(b.bounding_radius()),
+            SdfShape::Intersection(a, b) => a.bounding_radius().min(b.bounding_radius()),
+            SdfShape::Subtraction(a, _) => a.bounding_radius(),
+            // blending can bulge out past either shape, by up to a quarter
+            // of the smoothness
+            SdfShape::SmoothUnion { a, b, smoothness } => {
+                a.bounding_radius().max(b.bounding_radius()) + smoothness / 4.
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SdfShape;
+    use float_eq::assert_float_eq;
+    use glam::Vec3;
+
+    #[test]
+    fn primitive_distances() {
+        let sphere = SdfShape::sphere(1.);
+        assert_float_eq!(sphere.distance(Vec3::new(0., 3., 0.)), 2., abs <= 0.0001);
+        assert_float_eq!(sphere.distance(Vec3::ZERO), -1., abs <= 0.0001);
+
+        let cube = SdfShape::rounded_box(Vec3::ONE, 0.);
+        assert_float_eq!(cube.distance(Vec3::new(3., 0., 0.)), 2., abs <= 0.0001);
+        assert_float_eq!(cube.distance(Vec3::new(2., 2., 1.)), 2f32.sqrt(), abs <= 0.0001);
+
+        let torus = SdfShape::torus(1., 0.25);
+        assert_float_eq!(torus.distance(Vec3::new(1., 1., 0.)), 0.75, abs <= 0.0001);
+        assert_float_eq!(torus.distance(Vec3::ZERO), 0.75, abs <= 0.0001);
+
+        let capsule = SdfShape::capsule(Vec3::ZERO, Vec3::Y, 0.5);
+        assert_float_eq!(capsule.distance(Vec3::new(1., 0.5, 0.)), 0.5, abs <= 0.0001);
+        assert_float_eq!(capsule.distance(Vec3::new(0., 3., 0.)), 1.5, abs <= 0.0001);
+    }
+
+    #[test]
+    fn combinators() {
+        let a = SdfShape::sphere(1.);
+        let b = SdfShape::sphere(1.).translated(Vec3::X * 1.5);
+        let between = Vec3::new(0.75, 0.8, 0.);
+
+        let union = a.clone().union(b.clone());
+        let smooth = a.clone().smooth_union(b.clone(), 0.5);
+        // blending fills in the crease between the spheres
+        assert!(smooth.distance(between) < union.distance(between));
+        // and leaves points far from the crease alone
+        let far = Vec3::new(-2., 0., 0.);
+        assert_float_eq!(smooth.distance(far), union.distance(far), abs <= 0.0001);
+
+        let carved = a.clone().subtract(b.clone());
+        assert!(carved.distance(Vec3::X) > 0.);
+        assert!(carved.distance(Vec3::NEG_X * 0.5) < 0.);
+
+        let lens = a.intersect(b);
+        assert!(lens.distance(Vec3::X * 0.75) < 0.);
+        assert!(lens.distance(Vec3::ZERO) > 0.);
+    }
+
+    #[test]
+    fn bounds_contain_the_shape() {
+        let shape = SdfShape::sphere(0.5)
+            .smooth_union(SdfShape::capsule(Vec3::ZERO, Vec3::Y * 2., 0.2), 0.3)
+            .translated(Vec3::X);
+        let radius = shape.bounding_radius();
+        for n in 0..200 {
+            let angle = n as f32 * 0.7;
+            let direction = Vec3::new(angle.cos(), (angle * 0.3).sin(), angle.sin());
+            let outside = direction.normalize() * (radius + 0.01);
+            assert!(shape.distance(outside) > 0.);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use glam::Vec4;
+
+/// An owned copy of a renderer's output at one point in time.
+///
+/// Rows are stored bottom to top, matching [`Renderer::render`](crate::Renderer::render).
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub width: u32,
+    pub height: u32,
+    /// Number of frames that were averaged to produce this image.
+    pub frame_count: f32,
+    /// Tone-mapped pixels encoded as sRGB, packed as little endian RGBA with
+    /// premultiplied alpha.
+    pub image: Vec<u32>,
+    /// The linear average of all samples taken for each pixel, with
+    /// premultiplied alpha.
+    pub hdr: Vec<Vec4>,
+}
+
+impl Snapshot {
+    /// How different two renders of the same view are, as the root mean
+    /// square of the differences between their linear colors, or `None` if
+    /// they aren't the same size. Useful for judging whether a change to a
+    /// material or sampler made a visible difference.
+    pub fn rms_difference(&self, other: &Snapshot) -> Option<f32> {
+        if (self.width, self.height) != (other.width, other.height)
+            || self.hdr.len() != other.hdr.len()
+        {
+            return None;
+        }
+        if self.hdr.is_empty() {
+            return Some(0.);
+        }
+        let sum: f32 = self
+            .hdr
+            .iter()
+            .zip(&other.hdr)
+            .map(|(a, b)| (*a - *b).length_squared())
+            .sum();
+        Some((sum / (self.hdr.len() * 4) as f32).sqrt())
+    }
+
+    /// The image as unpacked, premultiplied RGBA bytes, with rows ordered top
+    /// to bottom.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let width = self.width as usize;
+        if width == 0 {
+            return Vec::new();
+        }
+        let mut buffer = vec![0; self.image.len() * 4];
+        for (idx, row) in self.image.chunks_exact(width).rev().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let offset = (idx * width + x) * 4;
+                buffer[offset..offset + 4].copy_from_slice(&pixel.to_le_bytes());
+            }
+        }
+        buffer
+    }
+
+    /// The image as unpacked RGBA bytes with straight (not premultiplied)
+    /// alpha, as most image formats expect, with rows ordered top to bottom.
+    pub fn to_straight_rgba8(&self) -> Vec<u8> {
+        let mut buffer = self.to_rgba8();
+        for pixel in buffer.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for channel in &mut pixel[..3] {
+                if let Some(straight) = (*channel as u32 * 255 + alpha / 2).checked_div(alpha) {
+                    *channel = straight.min(255) as u8;
+                }
+            }
+        }
+        buffer
+    }
+
+    /// Encode the image as an RGBA PNG.
+    #[cfg(feature = "png")]
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        use png_pong::PngRaster;
+        let path = path.as_ref();
+        let _span = tracing::info_span!("save png", path = %path.display()).entered();
+
+        let raster = pix::Raster::<pix::rgb::SRgba8>::with_u8_buffer(
+            self.width,
+            self.height,
+            self.to_straight_rgba8(),
+        );
+
+        let mut out_data = Vec::new();
+        let mut encoder = png_pong::Encoder::new(&mut out_data).into_step_enc();
+        let step = png_pong::Step {
+            raster: PngRaster::Rgba8(raster),
+            delay: 0,
+        };
+        encoder.encode(&step)?;
+        std::fs::write(path, out_data)?;
+        Ok(())
+    }
+
+    /// Write the linear HDR image as an RGBA OpenEXR file. EXR expects
+    /// premultiplied alpha, so it's stored as is.
+    #[cfg(feature = "exr")]
+    pub fn save_exr<P: AsRef<std::path::Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let _span = tracing::info_span!("save exr", path = %path.display()).entered();
+        let width = self.width as usize;
+        let height = self.height as usize;
+        anyhow::ensure!(self.hdr.len() == width * height, "snapshot has no HDR image");
+        exr::prelude::write_rgba_file(path, width, height, |x, y| {
+            let color = self.hdr[(height - 1 - y) * width + x];
+            (color.x, color.y, color.z, color.w)
+        })?;
+        Ok(())
+    }
+
+    /// Like [`Snapshot::save_exr`], with an extra `Z` channel holding
+    /// `depth`, as from [`Renderer::render_depth`](crate::Renderer::render_depth),
+    /// for compositing in other tools.
+    #[cfg(feature = "exr")]
+    pub fn save_exr_with_depth<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        depth: &[f32],
+    ) -> anyhow::Result<()> {
+        use exr::prelude::{Image, SpecificChannels, WritableImage};
+        let path = path.as_ref();
+        let _span = tracing::info_span!("save exr", path = %path.display()).entered();
+
+        let width = self.width as usize;
+        let height = self.height as usize;
+        anyhow::ensure!(self.hdr.len() == width * height, "snapshot has no HDR image");
+        anyhow::ensure!(
+            depth.len() == width * height,
+            "depth buffer doesn't match the snapshot's size"
+        );
+        let channels = SpecificChannels::build()
+            .with_channel("R")
+            .with_channel("G")
+            .with_channel("B")
+            .with_channel("A")
+            .with_channel("Z")
+            .with_pixel_fn(|position: exr::math::Vec2<usize>| {
+                let idx = (height - 1 - position.y()) * width + position.x();
+                let color = self.hdr[idx];
+                (color.x, color.y, color.z, color.w, depth[idx])
+            });
+        Image::from_channels((width, height), channels)
+            .write()
+            .to_file(path)?;
+        Ok(())
+    }
+
+    /// The image with straight alpha, for use with the `image` crate.
+    #[cfg(feature = "image")]
+    pub fn to_image(&self) -> image::RgbaImage {
+        image::RgbaImage::from_raw(self.width, self.height, self.to_straight_rgba8())
+            .expect("snapshot image should match its size")
+    }
+
+    /// The linear HDR image with premultiplied alpha, for use with the
+    /// `image` crate. Empty if the snapshot has no HDR image.
+    #[cfg(feature = "image")]
+    pub fn to_hdr_image(&self) -> image::Rgba32FImage {
+        let width = self.width as usize;
+        if width == 0 || self.hdr.len() != width * self.height as usize {
+            return image::Rgba32FImage::new(0, 0);
+        }
+        let pixels = self
+            .hdr
+            .chunks_exact(width)
+            .rev()
+            .flatten()
+            .flat_map(|color| color.to_array())
+            .collect();
+        image::Rgba32FImage::from_raw(self.width, self.height, pixels)
+            .expect("snapshot HDR image should match its size")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+
+    #[test]
+    fn rgba8_flips_rows() {
+        let snapshot = Snapshot {
+            width: 2,
+            height: 2,
+            frame_count: 1.,
+            image: vec![0x04030201, 0x08070605, 0x0c0b0a09, 0x100f0e0d],
+            hdr: vec![],
+        };
+        assert_eq!(
+            snapshot.to_rgba8(),
+            vec![9, 10, 11, 12, 13, 14, 15, 16, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+
+        let empty = Snapshot {
+            width: 0,
+            height: 2,
+            frame_count: 0.,
+            image: vec![],
+            hdr: vec![],
+        };
+        assert!(empty.to_rgba8().is_empty());
+    }
+
+    #[test]
+    fn rms_difference() {
+        use glam::Vec4;
+
+        let snapshot = |hdr: Vec<Vec4>| Snapshot {
+            width: hdr.len() as u32,
+            height: 1,
+            frame_count: 1.,
+            image: vec![0; hdr.len()],
+            hdr,
+        };
+        let a = snapshot(vec![Vec4::ZERO, Vec4::ONE]);
+        let b = snapshot(vec![Vec4::ZERO, Vec4::new(0., 1., 1., 1.)]);
+        assert_eq!(a.rms_difference(&a), Some(0.));
+        // one channel of one pixel is off by 1, out of 8 channels
+        assert_eq!(a.rms_difference(&b), Some((1_f32 / 8.).sqrt()));
+        assert_eq!(a.rms_difference(&snapshot(vec![Vec4::ZERO])), None);
+    }
+
+    #[test]
+    fn straight_alpha() {
+        let snapshot = Snapshot {
+            width: 3,
+            height: 1,
+            frame_count: 1.,
+            image: vec![0xff336699, 0x80204060, 0x00000000],
+            hdr: vec![],
+        };
+        assert_eq!(
+            snapshot.to_straight_rgba8(),
+            vec![0x99, 0x66, 0x33, 0xff, 0xbf, 0x80, 0x40, 0x80, 0, 0, 0, 0]
+        );
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn image_conversions() {
+        use glam::Vec4;
+
+        let snapshot = Snapshot {
+            width: 1,
+            height: 2,
+            frame_count: 1.,
+            image: vec![0xff0000ff, 0x80004000],
+            hdr: vec![Vec4::new(1., 0., 0., 1.), Vec4::new(0., 0.25, 0., 0.5)],
+        };
+
+        let image = snapshot.to_image();
+        assert_eq!(image.dimensions(), (1, 2));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0x80, 0, 0x80]);
+        assert_eq!(image.get_pixel(0, 1).0, [0xff, 0, 0, 0xff]);
+
+        let hdr = snapshot.to_hdr_image();
+        assert_eq!(hdr.get_pixel(0, 0).0, [0., 0.25, 0., 0.5]);
+        assert_eq!(hdr.get_pixel(0, 1).0, [1., 0., 0., 1.]);
+
+        let empty = Snapshot {
+            width: 0,
+            height: 2,
+            frame_count: 0.,
+            image: vec![],
+            hdr: vec![],
+        };
+        assert!(empty.to_image().is_empty());
+        assert!(empty.to_hdr_image().is_empty());
+    }
+
+    #[cfg(feature = "exr")]
+    #[test]
+    fn exr_round_trip() {
+        use exr::prelude::{read_first_rgba_layer_from_file, RgbaChannels};
+        use glam::Vec4;
+
+        let snapshot = Snapshot {
+            width: 2,
+            height: 2,
+            frame_count: 1.,
+            image: vec![0; 4],
+            hdr: vec![
+                Vec4::new(0., 0., 0., 0.),
+                Vec4::new(1., 0., 0., 1.),
+                Vec4::new(0., 2., 0., 1.),
+                Vec4::new(0., 0., 0.25, 0.5),
+            ],
+        };
+        let path = std::env::temp_dir().join("halide-snapshot-test.exr");
+        snapshot.save_exr(&path).unwrap();
+
+        let image = read_first_rgba_layer_from_file(
+            &path,
+            |size, _: &RgbaChannels| vec![Vec4::ZERO; size.area()],
+            |pixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                pixels[position.y() * 2 + position.x()] = Vec4::new(r, g, b, a);
+            },
+        )
+        .unwrap();
+        std::fs::remove_file(&path).ok();
+
+        // rows come back top to bottom
+        assert_eq!(
+            image.layer_data.channel_data.pixels,
+            vec![snapshot.hdr[2], snapshot.hdr[3], snapshot.hdr[0], snapshot.hdr[1]]
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::integrator::MAX_BOUNCES;
+use std::{ops::Add, time::Duration};
+
+/// How many bounces [`RenderStats::bounce_histogram`] tells apart. The last
+/// bucket also counts longer paths.
+const BOUNCE_BUCKETS: usize = MAX_BOUNCES as usize + 1;
+
+/// Throughput numbers for a call to [`Renderer::render_accumulate`](crate::Renderer::render_accumulate).
+#[derive(Clone, Debug, Default)]
+pub struct RenderStats {
+    /// Number of accumulation frames rendered.
+    pub frames: usize,
+    /// Rays shot from the camera, one per pixel per frame.
+    pub primary_rays: u64,
+    /// Rays spawned by scattering off of surfaces.
+    pub secondary_rays: u64,
+    /// Ray-object intersection tests performed, across all rays.
+    pub intersection_tests: u64,
+    /// Samples that came out NaN or infinite. These are left out of the
+    /// image, unless the renderer is set to
+    /// [mark them](crate::Renderer::mark_invalid_samples).
+    pub invalid_samples: u64,
+    /// How many paths bounced each number of times after leaving the
+    /// camera, starting from none, for seeing how many are cut off by the
+    /// bounce limit. The last entry also counts any longer paths.
+    pub bounce_histogram: Vec<u64>,
+    /// Wall time spent in each stage of the render, in order.
+    pub stage_times: Vec<(&'static str, Duration)>,
+}
+
+impl RenderStats {
+    pub fn rays_traced(&self) -> u64 {
+        self.primary_rays + self.secondary_rays
+    }
+
+    /// The average number of bounces each camera ray took before terminating.
+    pub fn average_bounces(&self) -> f32 {
+        if self.primary_rays == 0 {
+            0.0
+        } else {
+            self.secondary_rays as f32 / self.primary_rays as f32
+        }
+    }
+
+    /// The average number of intersection tests needed to trace one ray.
+    pub fn tests_per_ray(&self) -> f32 {
+        let rays = self.rays_traced();
+        if rays == 0 {
+            0.0
+        } else {
+            self.intersection_tests as f32 / rays as f32
+        }
+    }
+
+    /// Count the work done tracing `primary_rays` camera rays.
+    pub(crate) fn add_path_stats(&mut self, primary_rays: u64, path_stats: PathStats) {
+        self.primary_rays += primary_rays;
+        self.secondary_rays += path_stats.rays - primary_rays;
+        self.intersection_tests += path_stats.intersection_tests;
+        self.invalid_samples += path_stats.invalid_samples;
+        add_histogram(&mut self.bounce_histogram, &path_stats.bounces);
+    }
+
+    /// Add in the stats for more frames, summing the time of stages with
+    /// the same name.
+    pub fn add_frames(&mut self, other: RenderStats) {
+        self.frames += other.frames;
+        self.primary_rays += other.primary_rays;
+        self.secondary_rays += other.secondary_rays;
+        self.intersection_tests += other.intersection_tests;
+        self.invalid_samples += other.invalid_samples;
+        add_histogram(&mut self.bounce_histogram, &other.bounce_histogram);
+        for (name, duration) in other.stage_times {
+            match self.stage_times.iter_mut().find(|(stage, _)| *stage == name) {
+                Some((_, total)) => *total += duration,
+                None => self.stage_times.push((name, duration)),
+            }
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.stage_times.iter().map(|(_, d)| *d).sum()
+    }
+
+    /// Camera samples (one primary ray each) completed per second.
+    pub fn samples_per_second(&self) -> f64 {
+        per_second(self.primary_rays, self.total_time())
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        per_second(self.rays_traced(), self.total_time())
+    }
+}
+
+/// Add `other`'s counts to `histogram`'s, making room for any more buckets.
+fn add_histogram(histogram: &mut Vec<u64>, other: &[u64]) {
+    if histogram.len() < other.len() {
+        histogram.resize(other.len(), 0);
+    }
+    for (total, count) in histogram.iter_mut().zip(other) {
+        *total += count;
+    }
+}
+
+fn per_second(count: u64, duration: Duration) -> f64 {
+    let secs = duration.as_secs_f64();
+    if secs > 0.0 {
+        count as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+/// Counters collected while tracing a single path.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct PathStats {
+    pub rays: u64,
+    pub intersection_tests: u64,
+    pub invalid_samples: u64,
+    /// How many paths bounced each number of times.
+    pub bounces: [u64; BOUNCE_BUCKETS],
+}
+
+impl PathStats {
+    /// Count a path that bounced `bounces` times.
+    pub fn add_path(&mut self, bounces: u32) {
+        self.bounces[(bounces as usize).min(BOUNCE_BUCKETS - 1)] += 1;
+    }
+}
+
+impl Add for PathStats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            rays: self.rays + rhs.rays,
+            intersection_tests: self.intersection_tests + rhs.intersection_tests,
+            invalid_samples: self.invalid_samples + rhs.invalid_samples,
+            bounces: std::array::from_fn(|idx| self.bounces[idx] + rhs.bounces[idx]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RenderStats;
+    use std::time::Duration;
+
+    #[test]
+    fn throughput() {
+        let stats = RenderStats {
+            frames: 2,
+            primary_rays: 100,
+            secondary_rays: 250,
+            intersection_tests: 700,
+            invalid_samples: 0,
+            bounce_histogram: vec![20, 30, 50],
+            stage_times: vec![
+                ("trace", Duration::from_millis(400)),
+                ("resolve", Duration::from_millis(100)),
+            ],
+        };
+        assert_eq!(stats.rays_traced(), 350);
+        assert_eq!(stats.average_bounces(), 2.5);
+        assert_eq!(stats.tests_per_ray(), 2.0);
+        assert_eq!(stats.samples_per_second(), 200.0);
+        assert_eq!(stats.rays_per_second(), 700.0);
+    }
+
+    #[test]
+    fn histograms_add_up() {
+        let mut stats = RenderStats {
+            bounce_histogram: vec![1, 2],
+            ..Default::default()
+        };
+        stats.add_frames(RenderStats {
+            bounce_histogram: vec![3, 4, 5],
+            ..Default::default()
+        });
+        assert_eq!(stats.bounce_histogram, [4, 6, 5]);
+    }
+
+    #[test]
+    fn empty() {
+        let stats = RenderStats::default();
+        assert_eq!(stats.average_bounces(), 0.0);
+        assert_eq!(stats.tests_per_ray(), 0.0);
+        assert_eq!(stats.samples_per_second(), 0.0);
+    }
+}
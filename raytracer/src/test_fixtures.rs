@@ -0,0 +1,69 @@
+//! Canonical tiny scenes and cameras, for tests and benchmarks that need
+//! reproducible inputs. Enabled by the `test-fixtures` feature.
+
+use crate::{Camera, Material, Renderer, Scene, Sphere};
+use glam::Vec3;
+
+/// Image width used by [`camera`] and [`renderer`].
+pub const WIDTH: u32 = 32;
+/// Image height used by [`camera`] and [`renderer`].
+pub const HEIGHT: u32 = 24;
+
+/// The color of the sky, as seen by rays that miss everything.
+pub const SKY_COLOR: Vec3 = crate::integrator::SKY_COLOR;
+
+/// A scene with nothing in it.
+pub fn empty_scene() -> Scene {
+    Scene::default()
+}
+
+/// A single gray unit sphere at the origin.
+pub fn single_sphere() -> Scene {
+    let mut scene = Scene::default();
+    let material = scene.add_material(Material::Lambertian {
+        albedo: Vec3::splat(0.5),
+    });
+    scene.add_hittable(Sphere {
+        center: Vec3::ZERO,
+        radius: 1.0,
+        material,
+    });
+    scene
+}
+
+/// A small sphere resting on a ground sphere.
+pub fn sphere_on_ground() -> Scene {
+    let mut scene = Scene::default();
+    let ground_material = scene.add_material(Material::Lambertian {
+        albedo: Vec3::splat(0.7),
+    });
+    let ball_material = scene.add_material(Material::Lambertian {
+        albedo: Vec3::new(0.9, 0.2, 0.1),
+    });
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0., -1000., 0.),
+        radius: 1000.,
+        material: ground_material,
+    });
+    scene.add_hittable(Sphere {
+        center: Vec3::new(0., 0.5, 0.),
+        radius: 0.5,
+        material: ball_material,
+    });
+    scene
+}
+
+/// A `WIDTH`x`HEIGHT` camera on the +Z axis, looking at the origin.
+pub fn camera() -> Camera {
+    let mut camera = Camera::default();
+    camera.set_size(WIDTH, HEIGHT);
+    camera.set_position(Vec3::new(0., 0.5, 4.));
+    camera
+}
+
+/// A single threaded `WIDTH`x`HEIGHT` renderer.
+pub fn renderer() -> Renderer {
+    let mut renderer = Renderer::new(WIDTH, HEIGHT);
+    renderer.set_num_threads(1);
+    renderer
+}
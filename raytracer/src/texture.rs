@@ -0,0 +1,333 @@
+use anyhow::{ensure, Result};
+use glam::{Vec2, Vec3};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use crate::procedural::ProceduralTexture;
+
+mod noise;
+
+pub use noise::{ColorRamp, Noise, NoiseKind, NoisePattern};
+
+/// Colors that vary over a surface, for
+/// [`Material::Textured`](crate::Material::Textured).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Texture {
+    /// The same color everywhere.
+    Solid(Vec3),
+    Noise(Noise),
+    /// Worked out by a closure, registered with
+    /// [`ProceduralTexture::register`].
+    Procedural(ProceduralTexture),
+}
+
+impl Texture {
+    /// The color at a point on a surface, given its surface coordinates
+    /// and world position.
+    pub fn color(&self, uv: Vec2, position: Vec3) -> Vec3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Noise(noise) => noise.color(position),
+            Texture::Procedural(procedural) => procedural.color(uv, position),
+        }
+    }
+}
+
+/// A grayscale image of heights, for bumping the surface of a
+/// [`Material::Bump`](crate::Material::Bump). Looked up by surface
+/// coordinates, wrapping around horizontally and clamping vertically, which
+/// suits the way coordinates wrap around spheres.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SavedHeightMap", try_from = "SavedHeightMap")
+)]
+pub struct HeightMap {
+    width: u32,
+    height: u32,
+    /// Rows from bottom to top, like [`Snapshot`](crate::Snapshot).
+    heights: Vec<f32>,
+}
+
+impl HeightMap {
+    /// `heights` are in rows from bottom to top, and usually from 0 to 1.
+    pub fn new(width: u32, height: u32, heights: Vec<f32>) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "height map must not be empty");
+        ensure!(
+            heights.len() == width as usize * height as usize,
+            "expected {} heights for a {width}x{height} height map, got {}",
+            width as usize * height as usize,
+            heights.len()
+        );
+        Ok(Self {
+            width,
+            height,
+            heights,
+        })
+    }
+
+    /// Fill a height map by calling `f` with the surface coordinates of the
+    /// center of each texel.
+    pub fn from_fn(width: u32, height: u32, mut f: impl FnMut(Vec2) -> f32) -> Result<Self> {
+        let size = Vec2::new(width as f32, height as f32);
+        let heights = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f((Vec2::new(x as f32, y as f32) + 0.5) / size))
+            .collect();
+        Self::new(width, height, heights)
+    }
+
+    /// Convert an image to grayscale heights, with black as 0 and white as 1.
+    #[cfg(feature = "image")]
+    pub fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        let luma = image::imageops::flip_vertical(&image.to_luma32f());
+        Self::new(luma.width(), luma.height(), luma.into_raw())
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    /// The height at `uv`, blended between the four nearest texels.
+    pub fn sample(&self, uv: Vec2) -> f32 {
+        let texel = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let base = texel.floor();
+        let t = texel - base;
+        let (x, y) = (base.x as i64, base.y as i64);
+        let bottom = lerp(self.texel(x, y), self.texel(x + 1, y), t.x);
+        let top = lerp(self.texel(x, y + 1), self.texel(x + 1, y + 1), t.x);
+        lerp(bottom, top, t.y)
+    }
+
+    /// How steeply the height changes at `uv`, as the difference in height
+    /// across one texel along each axis.
+    pub fn gradient(&self, uv: Vec2) -> Vec2 {
+        let step = Vec2::ONE / Vec2::new(self.width as f32, self.height as f32);
+        let du = Vec2::new(step.x, 0.);
+        let dv = Vec2::new(0., step.y);
+        Vec2::new(
+            self.sample(uv + du) - self.sample(uv - du),
+            self.sample(uv + dv) - self.sample(uv - dv),
+        ) / 2.
+    }
+
+    fn texel(&self, x: i64, y: i64) -> f32 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.heights[y * self.width as usize + x]
+    }
+}
+
+/// How height maps are stored in scene files, checked by [`HeightMap::new`]
+/// when they're loaded.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedHeightMap {
+    width: u32,
+    height: u32,
+    heights: Vec<f32>,
+}
+
+#[cfg(feature = "serde")]
+impl From<HeightMap> for SavedHeightMap {
+    fn from(map: HeightMap) -> Self {
+        SavedHeightMap {
+            width: map.width,
+            height: map.height,
+            heights: map.heights,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SavedHeightMap> for HeightMap {
+    type Error = anyhow::Error;
+
+    fn try_from(saved: SavedHeightMap) -> Result<Self> {
+        HeightMap::new(saved.width, saved.height, saved.heights)
+    }
+}
+
+/// An HDR panorama of the light arriving from every direction, for a
+/// [`Scene`](crate::Scene)'s environment. It's laid out like an
+/// [equirectangular](crate::Projection::Equirectangular) render from the
+/// default camera, with -Z in the middle, +X to the right of it and +Y at
+/// the top.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SavedEnvironmentMap", try_from = "SavedEnvironmentMap")
+)]
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    /// Rows from bottom to top, like [`Snapshot`](crate::Snapshot).
+    texels: Vec<Vec3>,
+    /// The light arriving from all directions, on average.
+    average: Vec3,
+}
+
+impl EnvironmentMap {
+    /// `texels` are linear colors, in rows from bottom to top.
+    pub fn new(width: u32, height: u32, texels: Vec<Vec3>) -> Result<Self> {
+        ensure!(width > 0 && height > 0, "environment map must not be empty");
+        ensure!(
+            texels.len() == width as usize * height as usize,
+            "expected {} texels for a {width}x{height} environment map, got {}",
+            width as usize * height as usize,
+            texels.len()
+        );
+        ensure!(
+            texels
+                .iter()
+                .all(|texel| texel.is_finite() && texel.min_element() >= 0.),
+            "environment map colors must be finite and not negative"
+        );
+        // rows nearer the poles cover less of the sphere around the scene
+        let mut total = Vec3::ZERO;
+        let mut weights = 0.;
+        for (y, row) in texels.chunks_exact(width as usize).enumerate() {
+            let latitude = ((y as f32 + 0.5) / height as f32 - 0.5) * PI;
+            total += row.iter().sum::<Vec3>() * latitude.cos();
+            weights += latitude.cos() * width as f32;
+        }
+        Ok(Self {
+            width,
+            height,
+            texels,
+            average: total / weights,
+        })
+    }
+
+    /// Read the colors of an image, such as a decoded `.hdr` file.
+    #[cfg(feature = "image")]
+    pub fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        let rgb = image::imageops::flip_vertical(&image.to_rgb32f());
+        let texels = rgb.pixels().map(|pixel| Vec3::from_array(pixel.0)).collect();
+        Self::new(rgb.width(), rgb.height(), texels)
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    /// The light arriving from `direction`, blended between the four
+    /// nearest texels.
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        let Some(direction) = direction.try_normalize() else {
+            return self.average;
+        };
+        let uv = Vec2::new(
+            (direction.x.atan2(-direction.z) / PI + 1.) / 2.,
+            (direction.y.clamp(-1., 1.).asin() / FRAC_PI_2 + 1.) / 2.,
+        );
+        let texel = uv * Vec2::new(self.width as f32, self.height as f32) - 0.5;
+        let base = texel.floor();
+        let t = texel - base;
+        let (x, y) = (base.x as i64, base.y as i64);
+        let bottom = self.texel(x, y).lerp(self.texel(x + 1, y), t.x);
+        let top = self.texel(x, y + 1).lerp(self.texel(x + 1, y + 1), t.x);
+        bottom.lerp(top, t.y)
+    }
+
+    /// The light arriving from all directions, on average.
+    pub fn average(&self) -> Vec3 {
+        self.average
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+}
+
+/// How environment maps are stored in scene files, checked by
+/// [`EnvironmentMap::new`] when they're loaded.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedEnvironmentMap {
+    width: u32,
+    height: u32,
+    texels: Vec<Vec3>,
+}
+
+#[cfg(feature = "serde")]
+impl From<EnvironmentMap> for SavedEnvironmentMap {
+    fn from(map: EnvironmentMap) -> Self {
+        SavedEnvironmentMap {
+            width: map.width,
+            height: map.height,
+            texels: map.texels,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SavedEnvironmentMap> for EnvironmentMap {
+    type Error = anyhow::Error;
+
+    fn try_from(saved: SavedEnvironmentMap) -> Result<Self> {
+        EnvironmentMap::new(saved.width, saved.height, saved.texels)
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EnvironmentMap, HeightMap};
+    use float_eq::assert_float_eq;
+    use glam::{Vec2, Vec3};
+
+    #[test]
+    fn wrong_size_is_an_error() {
+        assert!(HeightMap::new(2, 2, vec![0.; 3]).is_err());
+        assert!(HeightMap::new(0, 0, vec![]).is_err());
+    }
+
+    #[test]
+    fn samples_blend_texels() {
+        let map = HeightMap::new(2, 1, vec![0., 1.]).unwrap();
+        assert_float_eq!(map.sample(Vec2::new(0.25, 0.5)), 0., abs <= 0.0001);
+        assert_float_eq!(map.sample(Vec2::new(0.5, 0.5)), 0.5, abs <= 0.0001);
+        // wraps around from the right edge back to the left
+        assert_float_eq!(map.sample(Vec2::new(1., 0.5)), 0.5, abs <= 0.0001);
+    }
+
+    #[test]
+    fn gradient_follows_slope() {
+        let map = HeightMap::from_fn(8, 8, |uv| uv.y).unwrap();
+        let gradient = map.gradient(Vec2::new(0.3, 0.5));
+        assert_float_eq!(gradient.x, 0., abs <= 0.0001);
+        assert_float_eq!(gradient.y, 1. / 8., abs <= 0.0001);
+    }
+
+    #[test]
+    fn environment_maps_wrap_around_the_scene() {
+        assert!(EnvironmentMap::new(2, 1, vec![Vec3::ONE]).is_err());
+        assert!(EnvironmentMap::new(1, 1, vec![Vec3::NEG_ONE]).is_err());
+
+        // dark below the horizon, with a red stripe straight ahead
+        let map = EnvironmentMap::new(
+            8,
+            2,
+            [
+                vec![Vec3::ZERO; 8],
+                (0..8)
+                    .map(|x| if x == 3 || x == 4 { Vec3::X } else { Vec3::ONE })
+                    .collect(),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        assert_eq!(map.color(Vec3::new(0., 1., -1.)), Vec3::X);
+        assert_eq!(map.color(Vec3::new(0., 1., 1.)), Vec3::ONE);
+        assert_eq!(map.color(Vec3::NEG_Y), Vec3::ZERO);
+        assert_float_eq!(map.average().y, 0.375, abs <= 0.0001);
+    }
+}
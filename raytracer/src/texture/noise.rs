@@ -0,0 +1,305 @@
+//! Gradient noise, for textures that need no image. Lattice gradients come
+//! from hashing the lattice coordinates with a seed, so noise needs no
+//! tables and is the same on every run.
+
+use glam::{IVec3, Vec3, Vec3Swizzles};
+
+/// Colors from layered noise, evaluated at world positions.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Noise {
+    pub kind: NoiseKind,
+    pub pattern: NoisePattern,
+    pub seed: u32,
+    /// How many features fit in one world unit.
+    pub frequency: f32,
+    /// Layers of noise, each twice the frequency and half the strength of
+    /// the last. More octaves add finer detail.
+    pub octaves: u32,
+    pub ramp: ColorRamp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NoiseKind {
+    /// Ken Perlin's improved noise, on a cube lattice.
+    Perlin,
+    /// Simplex noise, on a lattice of tetrahedra. Has fewer axis-aligned
+    /// artifacts than Perlin noise.
+    Simplex,
+}
+
+/// How noise is shaped into a value for the color ramp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NoisePattern {
+    /// Soft blotches.
+    Smooth,
+    /// The absolute value of each octave, giving billowy, creased clouds.
+    Turbulence,
+    /// Stripes along the z axis, warped by turbulence.
+    Marble,
+    /// Rings around the y axis, warped by turbulence.
+    Wood,
+}
+
+/// Colors blended between stops, by a value from 0 to 1.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorRamp {
+    /// Positions and colors, in order of position.
+    pub stops: Vec<(f32, Vec3)>,
+}
+
+impl Noise {
+    /// White marble with gray veins.
+    pub fn marble(seed: u32) -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            pattern: NoisePattern::Marble,
+            seed,
+            frequency: 4.,
+            octaves: 7,
+            ramp: ColorRamp::new(Vec3::splat(0.25), Vec3::splat(0.9)),
+        }
+    }
+
+    /// Light and dark brown growth rings.
+    pub fn wood(seed: u32) -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            pattern: NoisePattern::Wood,
+            seed,
+            frequency: 2.,
+            octaves: 4,
+            ramp: ColorRamp::new(Vec3::new(0.35, 0.2, 0.08), Vec3::new(0.7, 0.45, 0.22)),
+        }
+    }
+
+    pub fn color(&self, position: Vec3) -> Vec3 {
+        self.ramp.sample(self.value(position))
+    }
+
+    /// The pattern at `position`, from 0 to 1.
+    pub fn value(&self, position: Vec3) -> f32 {
+        let p = position * self.frequency;
+        let value = match self.pattern {
+            NoisePattern::Smooth => 0.5 * (1. + self.fractal(p, false)),
+            NoisePattern::Turbulence => self.fractal(p, true),
+            NoisePattern::Marble => 0.5 * (1. + (p.z + 10. * self.fractal(p, true)).sin()),
+            NoisePattern::Wood => (p.xz().length() + 2. * self.fractal(p, true)).fract(),
+        };
+        value.clamp(0., 1.)
+    }
+
+    /// Octaves of noise summed, scaled back to between -1 and 1.
+    fn fractal(&self, p: Vec3, turbulent: bool) -> f32 {
+        let mut sum = 0.;
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut p = p;
+        for octave in 0..self.octaves.max(1) {
+            // reseed each octave so features don't line up at the origin
+            let seed = self.seed.wrapping_add(octave);
+            let noise = match self.kind {
+                NoiseKind::Perlin => perlin(seed, p),
+                NoiseKind::Simplex => simplex(seed, p),
+            };
+            sum += amplitude * if turbulent { noise.abs() } else { noise };
+            total += amplitude;
+            amplitude *= 0.5;
+            p *= 2.;
+        }
+        sum / total
+    }
+}
+
+impl ColorRamp {
+    /// A ramp from `low` at 0 to `high` at 1.
+    pub fn new(low: Vec3, high: Vec3) -> Self {
+        Self {
+            stops: vec![(0., low), (1., high)],
+        }
+    }
+
+    pub fn sample(&self, t: f32) -> Vec3 {
+        let Some(&(first_position, first)) = self.stops.first() else {
+            return Vec3::ZERO;
+        };
+        if t <= first_position {
+            return first;
+        }
+        for pair in self.stops.windows(2) {
+            let [(start, low), (end, high)] = [pair[0], pair[1]];
+            if t <= end {
+                let span = end - start;
+                let blend = if span > 0. { (t - start) / span } else { 1. };
+                return low.lerp(high, blend);
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+}
+
+/// Perlin noise, from about -1 to 1.
+fn perlin(seed: u32, p: Vec3) -> f32 {
+    let cell = p.floor();
+    let f = p - cell;
+    let corner = cell.as_ivec3();
+    // quintic fade, so the noise has a smooth second derivative
+    let w = f * f * f * (f * (f * 6. - 15.) + 10.);
+
+    let dot = |offset: IVec3| {
+        gradient(hash(seed, corner + offset)).dot(f - offset.as_vec3())
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(dot(IVec3::new(0, 0, 0)), dot(IVec3::new(1, 0, 0)), w.x);
+    let x10 = lerp(dot(IVec3::new(0, 1, 0)), dot(IVec3::new(1, 1, 0)), w.x);
+    let x01 = lerp(dot(IVec3::new(0, 0, 1)), dot(IVec3::new(1, 0, 1)), w.x);
+    let x11 = lerp(dot(IVec3::new(0, 1, 1)), dot(IVec3::new(1, 1, 1)), w.x);
+    lerp(lerp(x00, x10, w.y), lerp(x01, x11, w.y), w.z)
+}
+
+/// Simplex noise, from about -1 to 1.
+fn simplex(seed: u32, p: Vec3) -> f32 {
+    const SKEW: f32 = 1. / 3.;
+    const UNSKEW: f32 = 1. / 6.;
+
+    let corner = (p + (p.x + p.y + p.z) * SKEW).floor();
+    let origin = corner - (corner.x + corner.y + corner.z) * UNSKEW;
+    let x0 = p - origin;
+    let corner = corner.as_ivec3();
+
+    // which of the six tetrahedra in the skewed cube the point is in
+    let (i1, i2) = if x0.x >= x0.y {
+        if x0.y >= x0.z {
+            (IVec3::X, IVec3::X + IVec3::Y)
+        } else if x0.x >= x0.z {
+            (IVec3::X, IVec3::X + IVec3::Z)
+        } else {
+            (IVec3::Z, IVec3::X + IVec3::Z)
+        }
+    } else if x0.y < x0.z {
+        (IVec3::Z, IVec3::Y + IVec3::Z)
+    } else if x0.x < x0.z {
+        (IVec3::Y, IVec3::Y + IVec3::Z)
+    } else {
+        (IVec3::Y, IVec3::X + IVec3::Y)
+    };
+
+    [IVec3::ZERO, i1, i2, IVec3::ONE]
+        .into_iter()
+        .enumerate()
+        .map(|(n, offset)| {
+            let x = x0 - offset.as_vec3() + n as f32 * UNSKEW;
+            let falloff = 0.6 - x.length_squared();
+            if falloff <= 0. {
+                0.
+            } else {
+                falloff.powi(4) * gradient(hash(seed, corner + offset)).dot(x)
+            }
+        })
+        .sum::<f32>()
+        * 32.
+}
+
+/// The twelve directions to the edges of a cube, as in improved Perlin
+/// noise.
+const GRADIENTS: [Vec3; 12] = [
+    Vec3::new(1., 1., 0.),
+    Vec3::new(-1., 1., 0.),
+    Vec3::new(1., -1., 0.),
+    Vec3::new(-1., -1., 0.),
+    Vec3::new(1., 0., 1.),
+    Vec3::new(-1., 0., 1.),
+    Vec3::new(1., 0., -1.),
+    Vec3::new(-1., 0., -1.),
+    Vec3::new(0., 1., 1.),
+    Vec3::new(0., -1., 1.),
+    Vec3::new(0., 1., -1.),
+    Vec3::new(0., -1., -1.),
+];
+
+fn gradient(hash: u32) -> Vec3 {
+    GRADIENTS[hash as usize % GRADIENTS.len()]
+}
+
+fn hash(seed: u32, p: IVec3) -> u32 {
+    let mut h = seed
+        ^ (p.x as u32).wrapping_mul(0x8da6_b343)
+        ^ (p.y as u32).wrapping_mul(0xd816_3841)
+        ^ (p.z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^= h >> 16;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{perlin, simplex, ColorRamp, Noise, NoiseKind, NoisePattern};
+    use float_eq::assert_float_eq;
+    use glam::Vec3;
+
+    fn sample_points() -> impl Iterator<Item = Vec3> {
+        (0..500).map(|n| {
+            let n = n as f32;
+            Vec3::new(n * 0.37, n * -0.11 + 3., (n * 0.7).sin() * 5.)
+        })
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        for p in sample_points() {
+            for noise in [perlin(7, p), simplex(7, p)] {
+                assert!((-1.1..=1.1).contains(&noise), "{noise} at {p}");
+            }
+        }
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice() {
+        assert_float_eq!(perlin(3, Vec3::new(2., -5., 1.)), 0., abs <= 0.0001);
+    }
+
+    #[test]
+    fn seeds_change_the_noise() {
+        let p = Vec3::new(0.3, 1.7, -2.2);
+        assert_eq!(perlin(1, p), perlin(1, p));
+        assert_ne!(perlin(1, p), perlin(2, p));
+        assert_ne!(simplex(1, p), simplex(2, p));
+    }
+
+    #[test]
+    fn patterns_map_to_ramp() {
+        for pattern in [
+            NoisePattern::Smooth,
+            NoisePattern::Turbulence,
+            NoisePattern::Marble,
+            NoisePattern::Wood,
+        ] {
+            let noise = Noise {
+                kind: NoiseKind::Simplex,
+                pattern,
+                ..Noise::marble(0)
+            };
+            for p in sample_points() {
+                let value = noise.value(p);
+                assert!((0. ..=1.).contains(&value), "{pattern:?}: {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn ramp_blends_between_stops() {
+        let ramp = ColorRamp {
+            stops: vec![(0.2, Vec3::ZERO), (0.6, Vec3::ONE), (1., Vec3::X)],
+        };
+        assert_eq!(ramp.sample(0.), Vec3::ZERO);
+        assert_float_eq!(ramp.sample(0.4).y, 0.5, abs <= 0.0001);
+        assert_float_eq!(ramp.sample(0.8).y, 0.5, abs <= 0.0001);
+        assert_eq!(ramp.sample(2.), Vec3::X);
+    }
+}
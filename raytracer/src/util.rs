@@ -1,7 +1,43 @@
 use glam::{Vec3, Vec4};
 use rand::Rng;
+use std::ops::AddAssign;
 
+/// Packs a premultiplied linear color as little endian RGBA, with the color
+/// encoded as sRGB for display and saving, and then premultiplied again.
 pub(crate) fn color_rgba(c: &Vec4) -> u32 {
+    let c = c.clamp(Vec4::ZERO, Vec4::ONE);
+    if c.w <= 0. {
+        return 0;
+    }
+    let straight = c.truncate() / c.w;
+    let encoded = Vec3::new(
+        linear_to_srgb(straight.x),
+        linear_to_srgb(straight.y),
+        linear_to_srgb(straight.z),
+    );
+    pack_rgba(&(encoded * c.w).extend(c.w))
+}
+
+#[cfg(test)]
+pub(crate) fn color_rgb(c: Vec3) -> u32 {
+    color_rgba(&c.extend(1.))
+}
+
+/// The sRGB transfer function, for a linear value in [0, 1].
+pub(crate) fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else if v >= 1. {
+        // exactly, so white doesn't come out a step darker
+        1.
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Packs a color that's already encoded for display, like a
+/// [heatmap](heatmap_color) color, as little endian RGBA.
+pub(crate) fn pack_rgba(c: &Vec4) -> u32 {
     let c = c.clamp(Vec4::ZERO, Vec4::ONE);
     let r = (c.x * 255.) as u32;
     let g = (c.y * 255.) as u32;
@@ -10,11 +46,59 @@ pub(crate) fn color_rgba(c: &Vec4) -> u32 {
     a << 24 | b << 16 | g << 8 | r
 }
 
-pub(crate) fn color_rgb(c: Vec3) -> u32 {
-    color_rgba(&c.extend(1.))
+/// Maps `t` in [0, 1] onto a blue-green-yellow-red ramp, for visualizing
+/// scalar quantities.
+pub(crate) fn heatmap_color(t: f32) -> Vec3 {
+    const STOPS: [Vec3; 4] = [
+        Vec3::new(0.0, 0.0, 0.5),
+        Vec3::new(0.0, 0.8, 0.2),
+        Vec3::new(1.0, 0.9, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    ];
+    let scaled = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let idx = (scaled as usize).min(STOPS.len() - 2);
+    STOPS[idx].lerp(STOPS[idx + 1], scaled - idx as f32)
+}
+
+/// A running total of samples that keeps track of what each addition lost
+/// to rounding and adds it back with the next (Kahan summation). Once
+/// a plain `f32` total is thousands of times bigger than each sample, most
+/// of every new sample is rounded away, so long renders drift and band.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct CompensatedSum {
+    sum: Vec4,
+    /// What the last addition lost to rounding, negated.
+    compensation: Vec4,
+}
+
+impl CompensatedSum {
+    pub fn value(&self) -> Vec4 {
+        self.sum
+    }
+}
+
+impl From<Vec4> for CompensatedSum {
+    fn from(sum: Vec4) -> Self {
+        Self {
+            sum,
+            compensation: Vec4::ZERO,
+        }
+    }
+}
+
+impl AddAssign<Vec4> for CompensatedSum {
+    /// Non-finite samples leave the value non-finite, like a plain sum.
+    fn add_assign(&mut self, sample: Vec4) {
+        let corrected = sample - self.compensation;
+        let sum = self.sum + corrected;
+        // how much more than `corrected` the total went up by
+        self.compensation = (sum - self.sum) - corrected;
+        self.sum = sum;
+    }
 }
 
 pub trait Vec3Ext {
+    #[allow(dead_code)]
     fn reflect(self, normal: Self) -> Self;
     fn random_in_unit_sphere<R: Rng>(rng: &mut R) -> Self;
     fn random_unit<R: Rng>(rng: &mut R) -> Self;
@@ -49,9 +133,9 @@ impl Vec3Ext for Vec3 {
 
 #[cfg(test)]
 mod tests {
-    use crate::util::Vec3Ext;
+    use crate::util::{color_rgb, color_rgba, heatmap_color, CompensatedSum, Vec3Ext};
     use float_eq::assert_float_eq;
-    use glam::Vec3;
+    use glam::{Vec3, Vec4};
 
     #[test]
     fn reflect() {
@@ -60,4 +144,38 @@ mod tests {
         let y = x.reflect(normal);
         assert_float_eq!(y.to_array(), Vec3::Y.to_array(), abs <= [0.001, 0.001, 0.001]);
     }
+
+    #[test]
+    fn heatmap_endpoints() {
+        assert_eq!(heatmap_color(0.0), Vec3::new(0.0, 0.0, 0.5));
+        assert_eq!(heatmap_color(1.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(heatmap_color(2.0), heatmap_color(1.0));
+    }
+
+    #[test]
+    fn colors_are_encoded_as_srgb() {
+        assert_eq!(color_rgb(Vec3::ZERO), 0xff00_0000);
+        assert_eq!(color_rgb(Vec3::ONE), 0xffff_ffff);
+        // linear 0.5 is much lighter than half way once encoded
+        assert_eq!(color_rgb(Vec3::splat(0.5)), 0xffbb_bbbb);
+        // and premultiplied again, so half covered mid grey is half of that
+        assert_eq!(color_rgba(&Vec4::new(0.25, 0.25, 0.25, 0.5)), 0x7f5d_5d5d);
+        assert_eq!(color_rgba(&Vec4::ZERO), 0);
+    }
+
+    #[test]
+    fn compensated_sums_dont_drift() {
+        let sample = Vec4::new(0.1, 0.7, 3.3, 1.);
+        let mut plain = Vec4::ZERO;
+        let mut compensated = CompensatedSum::default();
+        for _ in 0..1_000_000 {
+            plain += sample;
+            compensated += sample;
+        }
+        let expected = sample * 1_000_000.;
+        // a plain f32 sum is noticeably off by now
+        assert!(((plain - expected) / expected).abs().max_element() > 1e-3);
+        let error = (compensated.value() - expected) / expected;
+        assert!(error.abs().max_element() < 1e-6, "{error}");
+    }
 }
@@ -0,0 +1,137 @@
+use crate::{script::Script, viewport::Viewport};
+use halide_raytracer::Scene;
+use imgui::{Condition, InputTextFlags};
+
+const HELP: &str = "\
+Scripts are Rhai (rhai.rs), working on the scene and the active viewport's
+camera and renderer. Variables are kept from one run to the next.
+  vec3(x, y, z)            a vector, with .x .y .z, + - * / and length()
+  rand(min, max)           a random number
+  print(value)             show a value here
+  scene.lambertian(color)  add a diffuse material, returning its number
+  scene.emissive(color)    add a light's material
+  scene.sphere(center, radius, material)
+  scene.quad(corner, u, v, material)
+                           add an object, returning its number
+  scene.objects, scene.materials
+                           how many there are
+  scene.run(statements)    run statements as in scene files
+  camera.position, camera.fov
+                           read or set
+  camera.look_direction, camera.look_at(point)
+  renderer.transparent_background, renderer.cache_first_bounce,
+  renderer.interleave      read or set
+  renderer.frames          how many frames have been accumulated
+  renderer.use_integrator(\"path\"|\"photons\"|\"ao\"|\"normals\")
+  renderer.reset()         start accumulating again
+For example, a hundred random spheres:
+  let m = scene.lambertian(vec3(0.8, 0.3, 0.2));
+  for i in 0..100 { scene.sphere(vec3(rand(-5, 5), 0.2, rand(-5, 5)), 0.2, m); }
+Ctrl+Enter starts a new line. `clear` empties this log, and `help` shows
+this.";
+
+/// The "Console" window, for changing the scene, camera and renderer with
+/// scripts, such as a loop that adds a hundred random spheres.
+#[derive(Default)]
+pub(crate) struct Console {
+    pub open: bool,
+    input: String,
+    /// What's been run and what came of it, oldest first.
+    log: Vec<(Entry, String)>,
+    /// Set when something is logged, so the log scrolls down to show it.
+    scroll_to_bottom: bool,
+    /// Set after running, to keep typing without clicking back into the
+    /// input.
+    focus_input: bool,
+    script: Script,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Entry {
+    Input,
+    Output,
+    Error,
+}
+
+impl Console {
+    /// Draw the window, if it's open. Camera commands move `viewport`'s
+    /// camera.
+    pub fn build(&mut self, ui: &imgui::Ui, scene: &mut Scene, viewport: &mut Viewport) {
+        if !self.open {
+            return;
+        }
+
+        let mut open = self.open;
+        ui.window("Console")
+            .size([520., 320.], Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                let line_height = ui.text_line_height_with_spacing();
+                let input_height = line_height * 4.;
+                ui.child_window("log")
+                    .size([0., -input_height])
+                    .border(true)
+                    .build(|| {
+                        for (entry, text) in &self.log {
+                            match entry {
+                                Entry::Input => ui.text_disabled(format!("> {text}")),
+                                Entry::Output => ui.text(text),
+                                Entry::Error => ui.text_colored([1., 0.4, 0.4, 1.], text),
+                            }
+                        }
+                        if std::mem::take(&mut self.scroll_to_bottom) {
+                            ui.set_scroll_here_y_with_ratio(1.);
+                        }
+                    });
+
+                if std::mem::take(&mut self.focus_input) {
+                    ui.set_keyboard_focus_here();
+                }
+                let submitted = ui
+                    .input_text_multiline("##input", &mut self.input, [-60., line_height * 3.])
+                    .flags(
+                        InputTextFlags::ENTER_RETURNS_TRUE
+                            | InputTextFlags::CTRL_ENTER_FOR_NEW_LINE,
+                    )
+                    .build();
+                ui.same_line();
+                let clicked = ui.button("Run");
+                if (submitted || clicked) && !self.input.trim().is_empty() {
+                    let input = std::mem::take(&mut self.input);
+                    self.run(input.trim(), scene, viewport);
+                    self.focus_input = true;
+                }
+            });
+        self.open = open;
+    }
+
+    /// Run a command or script, logging what happened.
+    fn run(&mut self, input: &str, scene: &mut Scene, viewport: &mut Viewport) {
+        self.log(Entry::Input, input);
+        match input {
+            "help" => {
+                self.log(Entry::Output, HELP);
+                return;
+            }
+            "clear" => {
+                self.log.clear();
+                return;
+            }
+            _ => {}
+        }
+        let (printed, result) = self.script.run(input, scene, viewport);
+        for line in printed {
+            self.log(Entry::Output, &line);
+        }
+        match result {
+            Ok(Some(value)) => self.log(Entry::Output, &value),
+            Ok(None) => {}
+            Err(err) => self.log(Entry::Error, &format!("{err:#}")),
+        }
+    }
+
+    fn log(&mut self, entry: Entry, text: &str) {
+        self.log.push((entry, text.to_string()));
+        self.scroll_to_bottom = true;
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Notices when a file changes on disk by polling its modification time.
+pub(crate) struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl FileWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Start watching `path`, treating its current contents as seen.
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: modified(path),
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true once each time the file changes. Checks the disk at most
+    /// every [`Self::POLL_INTERVAL`].
+    pub fn poll(&mut self) -> bool {
+        if self.last_check.elapsed() < Self::POLL_INTERVAL {
+            return false;
+        }
+        self.check()
+    }
+
+    fn check(&mut self) -> bool {
+        self.last_check = Instant::now();
+        let modified = modified(&self.path);
+        // A missing file is often an editor replacing it, so wait for it to come back.
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileWatcher;
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    #[test]
+    fn notices_changes() {
+        let path = std::env::temp_dir().join("halide-file-watcher-test.ron");
+        std::fs::write(&path, "()").unwrap();
+        let mut watcher = FileWatcher::new(&path);
+        assert!(!watcher.check());
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(watcher.check());
+        assert!(!watcher.check());
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!watcher.check());
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::Result;
+use halide_raytracer::{Camera, Renderer, Scene};
+use imgui::Condition;
+use std::{
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+/// The "Render to file" dialog, which renders a copy of the scene on a
+/// background thread, independent of any viewport.
+pub(crate) struct FinalRender {
+    pub open: bool,
+    width: u32,
+    height: u32,
+    samples: usize,
+    path: String,
+    job: Option<Job>,
+    status: Option<String>,
+    /// Whether the last render failed, rather than saving or being
+    /// cancelled.
+    failed: bool,
+}
+
+struct Job {
+    samples: usize,
+    started: Instant,
+    finished: Arc<AtomicUsize>,
+    cancel: Arc<AtomicBool>,
+    /// Stop after the sample in progress, and save what's been rendered.
+    finish_early: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Option<PathBuf>>>,
+}
+
+impl Default for FinalRender {
+    fn default() -> Self {
+        Self {
+            open: false,
+            width: 1920,
+            height: 1080,
+            samples: 256,
+            path: "render.png".to_string(),
+            job: None,
+            status: None,
+            failed: false,
+        }
+    }
+}
+
+impl FinalRender {
+    /// Draw the dialog. `scene` and `camera` are copied when a render starts.
+    pub fn build(&mut self, ui: &imgui::Ui, scene: &Scene, camera: &Camera) {
+        self.poll();
+        if !self.open {
+            return;
+        }
+
+        let mut open = self.open;
+        ui.window("Render to file")
+            .size([320., 180.], Condition::FirstUseEver)
+            .opened(&mut open)
+            .build(|| {
+                let running = self.job.is_some();
+                {
+                    let _disabled = ui.begin_disabled(running);
+                    imgui::Drag::new("Width")
+                        .range(1, 16384)
+                        .build(ui, &mut self.width);
+                    imgui::Drag::new("Height")
+                        .range(1, 16384)
+                        .build(ui, &mut self.height);
+                    imgui::Drag::new("Samples")
+                        .range(1, 1 << 16)
+                        .build(ui, &mut self.samples);
+                    ui.input_text("File (.png or .exr)", &mut self.path).build();
+                }
+
+                if let Some(job) = &self.job {
+                    let done = job.finished.load(Ordering::Relaxed);
+                    imgui::ProgressBar::new(done as f32 / job.samples as f32)
+                        .overlay_text(format!("{done} / {} samples", job.samples))
+                        .build(ui);
+                    if ui.button("Cancel") {
+                        job.cancel.store(true, Ordering::Relaxed);
+                    }
+                    ui.same_line();
+                    if ui.button("Stop and save") {
+                        job.finish_early.store(true, Ordering::Relaxed);
+                    }
+                } else if ui.button("Render") {
+                    self.start(scene, camera);
+                }
+
+                if let Some(status) = &self.status {
+                    ui.text_wrapped(status);
+                }
+            });
+        self.open = open;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.job.is_some()
+    }
+
+    /// Whether the last render failed, with the reason in the dialog.
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    /// The path the render in progress will be saved to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Stop the render in progress, saving it as it stands if `save` is
+    /// set. It might take a moment for the render to see this, and until
+    /// it does it [is still running](FinalRender::is_running).
+    pub fn stop(&self, save: bool) {
+        if let Some(job) = &self.job {
+            let flag = if save { &job.finish_early } else { &job.cancel };
+            flag.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// How far the render in progress has got, with a guess at how long
+    /// it has left, to show outside the dialog.
+    pub fn progress(&self) -> Option<String> {
+        let job = self.job.as_ref()?;
+        let done = job.finished.load(Ordering::Relaxed);
+        let mut progress = format!("{done} / {} samples", job.samples);
+        if done > 0 {
+            let per_sample = job.started.elapsed() / done as u32;
+            let left = per_sample * job.samples.saturating_sub(done) as u32;
+            progress += &format!(", {} left", format_duration(left));
+        }
+        Some(progress)
+    }
+
+    fn start(&mut self, scene: &Scene, camera: &Camera) {
+        let scene = scene.clone();
+        let mut camera = camera.clone();
+        let (width, height, samples) = (self.width, self.height, self.samples);
+        let path = PathBuf::from(&self.path);
+        // catch typos now, rather than after a long render
+        if let Err(err) = image_format(&path) {
+            self.status = Some(format!("{err:#}"));
+            return;
+        }
+        let finished = Arc::new(AtomicUsize::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let finish_early = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::spawn({
+            let finished = finished.clone();
+            let cancel = cancel.clone();
+            let finish_early = finish_early.clone();
+            move || {
+                camera.set_size(width, height);
+                let mut renderer = Renderer::try_new(width, height)?;
+                renderer.render_with_progress(&scene, &camera, samples, |stats| {
+                    finished.store(stats.frames, Ordering::Relaxed);
+                    if cancel.load(Ordering::Relaxed) || finish_early.load(Ordering::Relaxed) {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                });
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+                save(&renderer, &path)?;
+                Ok(Some(path))
+            }
+        });
+
+        self.status = None;
+        self.failed = false;
+        self.job = Some(Job {
+            samples,
+            started: Instant::now(),
+            finished,
+            cancel,
+            finish_early,
+            thread,
+        });
+    }
+
+    /// Collect the result of a finished render.
+    fn poll(&mut self) {
+        match &self.job {
+            Some(job) if job.thread.is_finished() => {}
+            _ => return,
+        }
+        let Some(job) = self.job.take() else {
+            return;
+        };
+        let result = job.thread.join();
+        self.failed = !matches!(result, Ok(Ok(_)));
+        self.status = Some(match result {
+            Ok(Ok(Some(path))) => format!("Saved {}", path.display()),
+            Ok(Ok(None)) => "Cancelled".to_string(),
+            Ok(Err(err)) => format!("Render failed: {err:#}"),
+            Err(_) => "Render thread panicked".to_string(),
+        });
+    }
+}
+
+/// A duration to the nearest second, in the two largest units, like
+/// "1h 05m" or "42s".
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs_f32().round() as u64;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, secs) => format!("{secs}s"),
+        (0, mins, secs) => format!("{mins}m {secs:02}s"),
+        (hours, mins, _) => format!("{hours}h {mins:02}m"),
+    }
+}
+
+enum ImageFormat {
+    Png,
+    Exr,
+}
+
+fn image_format(path: &Path) -> Result<ImageFormat> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
+    match extension.as_deref() {
+        Some("png") => Ok(ImageFormat::Png),
+        Some("exr") => Ok(ImageFormat::Exr),
+        _ => anyhow::bail!("Unrecognized image type, use .png or .exr"),
+    }
+}
+
+/// Write the renderer's image, choosing the format by file extension.
+fn save(renderer: &Renderer, path: &Path) -> Result<()> {
+    match image_format(path)? {
+        ImageFormat::Png => renderer.snapshot().save_png(path),
+        ImageFormat::Exr => renderer.snapshot().save_exr(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_duration;
+    use std::time::Duration;
+
+    #[test]
+    fn durations_are_short() {
+        assert_eq!(format_duration(Duration::from_millis(41_600)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(185)), "3m 05s");
+        assert_eq!(format_duration(Duration::from_secs(3900)), "1h 05m");
+    }
+}
@@ -0,0 +1,208 @@
+use glam::Vec3;
+use halide_raytracer::Camera;
+use imgui::{Key, MouseButton};
+use serde::{Deserialize, Serialize};
+
+/// Remappable camera controls.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Bindings {
+    /// Held to fly the camera. Dragging with it turns the camera.
+    #[serde(with = "mouse_button_name")]
+    pub fly_button: MouseButton,
+    #[serde(with = "key_name")]
+    pub forward: Key,
+    #[serde(with = "key_name")]
+    pub back: Key,
+    #[serde(with = "key_name")]
+    pub left: Key,
+    #[serde(with = "key_name")]
+    pub right: Key,
+    #[serde(with = "key_name")]
+    pub up: Key,
+    #[serde(with = "key_name")]
+    pub down: Key,
+    #[serde(with = "key_name")]
+    pub roll_left: Key,
+    #[serde(with = "key_name")]
+    pub roll_right: Key,
+    /// Movement speed multiplier while shift is held.
+    pub fast_multiplier: f32,
+    /// Movement speed multiplier while ctrl is held.
+    pub slow_multiplier: f32,
+    /// Scales how far the camera turns for a given mouse movement.
+    pub mouse_sensitivity: f32,
+    pub invert_mouse_y: bool,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self {
+            fly_button: MouseButton::Right,
+            forward: Key::W,
+            back: Key::S,
+            left: Key::A,
+            right: Key::D,
+            up: Key::E,
+            down: Key::Q,
+            roll_left: Key::Z,
+            roll_right: Key::C,
+            fast_multiplier: 4.,
+            slow_multiplier: 0.25,
+            mouse_sensitivity: 1.,
+            invert_mouse_y: false,
+        }
+    }
+}
+
+impl Bindings {
+    /// The file bindings are saved to, in the config directory.
+    pub const FILE_NAME: &'static str = "input.ron";
+
+    /// Keys that move the camera, with labels for display.
+    pub fn movement_keys_mut(&mut self) -> [(&'static str, &mut Key); 8] {
+        [
+            ("Forward", &mut self.forward),
+            ("Back", &mut self.back),
+            ("Left", &mut self.left),
+            ("Right", &mut self.right),
+            ("Up", &mut self.up),
+            ("Down", &mut self.down),
+            ("Roll left", &mut self.roll_left),
+            ("Roll right", &mut self.roll_right),
+        ]
+    }
+
+    /// Fly the camera around while the fly button is held. Returns true if
+    /// the camera moved.
+    pub fn fly_camera(&self, ui: &imgui::Ui, camera: &mut Camera) -> bool {
+        if !ui.is_mouse_down(self.fly_button) {
+            return false;
+        }
+
+        let dt = ui.io().delta_time;
+        let mut moved = false;
+
+        let mut camera_offset = Vec3::ZERO;
+        for (key, direction) in [
+            (self.right, Vec3::X),
+            (self.left, Vec3::NEG_X),
+            (self.up, Vec3::Y),
+            (self.down, Vec3::NEG_Y),
+            (self.forward, Vec3::Z),
+            (self.back, Vec3::NEG_Z),
+        ] {
+            if ui.is_key_down(key) {
+                camera_offset += direction;
+            }
+        }
+        if camera_offset != Vec3::ZERO {
+            let mut speed = 1.;
+            if ui.io().key_shift {
+                speed *= self.fast_multiplier;
+            }
+            if ui.io().key_ctrl {
+                speed *= self.slow_multiplier;
+            }
+            camera.cancel_animation();
+            camera.relative_move(camera_offset.normalize(), dt * speed);
+            moved = true;
+        }
+
+        let mut roll = 0.;
+        if ui.is_key_down(self.roll_right) {
+            roll += 1.;
+        }
+        if ui.is_key_down(self.roll_left) {
+            roll -= 1.;
+        }
+        if roll != 0. {
+            camera.cancel_animation();
+            camera.relative_roll(roll, dt);
+            moved = true;
+        }
+
+        let drag = ui.mouse_drag_delta_with_button(self.fly_button);
+        ui.reset_mouse_drag_delta(self.fly_button);
+        if drag[0].abs() > 0. || drag[1].abs() > 0. {
+            let pitch = if self.invert_mouse_y {
+                drag[1]
+            } else {
+                -drag[1]
+            };
+            let camera_rotate = [pitch, -drag[0]].map(|d| d * self.mouse_sensitivity);
+            camera.cancel_animation();
+            camera.relative_turn(camera_rotate, dt);
+            moved = true;
+        }
+
+        moved
+    }
+}
+
+/// Stores keys by their name, so config files stay readable.
+mod key_name {
+    use imgui::Key;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(key: &Key, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{key:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Key, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Key::VARIANTS
+            .into_iter()
+            .find(|key| format!("{key:?}") == name)
+            .ok_or_else(|| D::Error::custom(format!("unknown key {name:?}")))
+    }
+}
+
+mod mouse_button_name {
+    use imgui::MouseButton;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        button: &MouseButton,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{button:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<MouseButton, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        MouseButton::VARIANTS
+            .into_iter()
+            .find(|button| format!("{button:?}") == name)
+            .ok_or_else(|| D::Error::custom(format!("unknown mouse button {name:?}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bindings;
+    use imgui::{Key, MouseButton};
+
+    #[test]
+    fn ron_round_trip() {
+        let bindings = Bindings {
+            fly_button: MouseButton::Middle,
+            forward: Key::UpArrow,
+            mouse_sensitivity: 2.,
+            ..Default::default()
+        };
+        let source = ron::to_string(&bindings).unwrap();
+        assert!(source.contains("\"UpArrow\""));
+        assert_eq!(ron::from_str::<Bindings>(&source).unwrap(), bindings);
+    }
+
+    #[test]
+    fn missing_fields_use_defaults() {
+        let bindings: Bindings = ron::from_str("(forward: \"I\")").unwrap();
+        assert_eq!(bindings.forward, Key::I);
+        assert_eq!(bindings.back, Key::S);
+        assert!(ron::from_str::<Bindings>("(forward: \"Nope\")").is_err());
+    }
+}
@@ -1,25 +1,74 @@
 use anyhow::Result;
+use console::Console;
+use file_watcher::FileWatcher;
+use final_render::FinalRender;
 use glam::Vec3;
-use glium::{backend::Facade, texture::RawImage2d, uniforms::SamplerBehavior};
-use halide_raytracer::{Camera, Material, Renderer, Scene, Sphere};
-use imgui::{Condition, Key, MouseButton, TextureId, Textures};
+use glium::{backend::Facade, glutin::event_loop::ControlFlow};
+use halide_raytracer::{
+    presets::Preset, Backface, Camera, Diagnostic, EnvironmentMap, LensPreset, Material,
+    MaterialGraph, Mesh, MeshGeometry, MixFactor, NodeId, PixelTrace, Scene, SceneHandle,
+    Severity, Sphere, Subject, ThinFilm, ThreadPolicy, Turntable, ViewMode,
+};
+use imgui::{Condition, Key, MouseButton, Textures};
 use imgui_glium_renderer::Texture;
+use input::Bindings;
+use material_previews::{dropped_material, MaterialPreviews};
+use node_editor::NodeEditor;
+use settings::Settings;
 use std::{
     collections::{HashMap, VecDeque},
-    rc::Rc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
 };
-use system::System;
-use timer::Timer;
+use system::{FrameLimit, System};
+use viewport::Viewport;
 
+mod console;
+mod file_watcher;
+mod final_render;
+mod input;
+mod material_previews;
+mod node_editor;
+mod pixel_buffers;
+mod render_thread;
+mod script;
+mod settings;
 mod system;
+mod throughput;
 mod timer;
+mod viewport;
 
 fn main() -> Result<()> {
     let system = System::new("Halide")?;
     let mut interface = App::default();
 
-    system.main_loop(move |ui, textures, gl_ctx| {
+    system.main_loop(move |ui, textures, gl_ctx, window_state| {
+        for path in window_state.dropped_files.drain(..) {
+            interface.on_file_dropped(path);
+        }
+        if let Some(result) = window_state.screenshot_result.take() {
+            interface.export_status = Some(match result {
+                Ok(path) => format!("Saved screenshot {}", path.display()),
+                Err(err) => format!("Screenshot failed: {err}"),
+            });
+        }
+        window_state.frame_limit = interface.frame_limit;
+        interface.scale_factor = window_state.scale_factor as f32;
+        if window_state.close_requested {
+            window_state.close_requested = false;
+            interface.request_exit();
+        }
         interface.on_ui_render(ui, textures, gl_ctx);
+        window_state.screenshot = interface.screenshot_request.take();
+        window_state.title_status = interface.final_render.progress();
+        if interface.exit == Exit::Now {
+            interface.on_exit();
+            return Some(ControlFlow::Exit);
+        }
         None
     });
 
@@ -27,14 +76,82 @@ fn main() -> Result<()> {
 }
 
 struct App {
-    viewport_id: Option<TextureId>,
-    viewport_size: [f32; 2],
-    image_size: [f32; 2],
-    timer: Timer,
-    renderer: Renderer,
+    viewports: Vec<Viewport>,
+    /// The viewport that camera controls and viewport settings apply to.
+    active_viewport: usize,
+    next_viewport_id: usize,
     scene: Scene,
-    camera: Camera,
+    /// What the viewports render: a copy of `scene`, published after each
+    /// edit, so render threads never see one half done.
+    shared_scene: SceneHandle,
+    /// The hittable that F frames in the active viewport. With nothing
+    /// selected, it frames the whole scene.
+    selected: Option<usize>,
+    /// Problems found in the scene the last time it changed.
+    diagnostics: Vec<Diagnostic>,
+    /// The pixel to trace from the Debug window, or by ctrl clicking the
+    /// active viewport, and what tracing it found.
+    traced_pixel: [u32; 2],
+    pixel_trace: Option<String>,
+    /// The last path traced, drawn over the active viewport.
+    traced_path: Option<PixelTrace>,
+    show_traced_path: bool,
+    /// Draw each object's bounding box over the viewports.
+    show_bounding_boxes: bool,
+    /// Draw a grid on the ground and the directions of the axes over the
+    /// viewports, to help find the way around empty scenes.
+    show_grid: bool,
+    show_axis_gizmo: bool,
+    /// Each trace uses a new seed, so it follows a different path.
+    next_trace_seed: u64,
+    /// The file the scene was last loaded from or saved to, watched so
+    /// outside edits are reloaded.
+    scene_file: Option<FileWatcher>,
     frame_times: HashMap<String, VecDeque<f32>>,
+    presentation: Presentation,
+    final_render: FinalRender,
+    console: Console,
+    material_previews: MaterialPreviews,
+    node_editor: NodeEditor,
+    bindings: Bindings,
+    frame_limit: FrameLimit,
+    /// Physical pixels for each of the window's logical pixels.
+    scale_factor: f32,
+    /// Whether viewports render a pixel for each physical one, rather than
+    /// for each logical one.
+    render_native_dpi: bool,
+    export_status: Option<String>,
+    /// How the live render differs from the A/B comparison image, when last
+    /// measured.
+    comparison_status: Option<String>,
+    /// Where to save a screenshot of the whole window once this frame is drawn.
+    screenshot_request: Option<PathBuf>,
+    /// An error to show in a modal dialog.
+    error: Option<String>,
+    /// Set when the scene is edited, to catch up at the start of the next
+    /// frame.
+    scene_edited: Arc<AtomicBool>,
+    exit: Exit,
+}
+
+/// How far along quitting is, once the window has been asked to close.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Exit {
+    #[default]
+    No,
+    /// Asking what to do about the final render in progress.
+    Confirm,
+    /// Waiting for the final render to stop and save what it has.
+    Saving,
+    /// Ready to go, once this frame is done.
+    Now,
+}
+
+/// Fullscreen, panel-free display of the render.
+#[derive(Default)]
+struct Presentation {
+    enabled: bool,
+    show_sample_count: bool,
 }
 
 impl Default for App {
@@ -51,28 +168,55 @@ impl Default for App {
         scene.add_hittable(Sphere {
             center: Vec3::new(0., -10_000., 0.),
             radius: 10_000.,
-            material_index: ground_material,
+            material: ground_material,
         });
 
         scene.add_hittable(Sphere {
             center: Vec3::new(0., 0.5, 0.),
             radius: 0.5,
-            material_index: ball_material,
+            material: ball_material,
         });
 
         let mut camera = Camera::default();
         camera.set_position((0., 0.75, 4.).into());
 
-        Self {
-            viewport_id: None,
-            viewport_size: [400.0, 400.0],
-            image_size: [0.0, 0.0],
-            timer: Timer::new(),
-            renderer: Renderer::new(400, 400),
+        let mut app = Self {
+            viewports: vec![Viewport::new(0, camera)],
+            active_viewport: 0,
+            next_viewport_id: 1,
+            shared_scene: SceneHandle::new(scene.clone()),
             scene,
-            camera,
+            selected: None,
+            diagnostics: Vec::new(),
+            traced_pixel: [0, 0],
+            pixel_trace: None,
+            traced_path: None,
+            show_traced_path: true,
+            show_bounding_boxes: false,
+            show_grid: false,
+            show_axis_gizmo: false,
+            next_trace_seed: 0,
+            scene_file: None,
             frame_times: HashMap::new(),
-        }
+            presentation: Presentation::default(),
+            final_render: FinalRender::default(),
+            console: Console::default(),
+            material_previews: MaterialPreviews::default(),
+            node_editor: NodeEditor::default(),
+            bindings: Bindings::default(),
+            frame_limit: FrameLimit::default(),
+            scale_factor: 1.,
+            render_native_dpi: Settings::default().render_native_dpi,
+            export_status: None,
+            comparison_status: None,
+            screenshot_request: None,
+            error: None,
+            scene_edited: Arc::default(),
+            exit: Exit::No,
+        };
+        app.watch_scene();
+        app.restore_settings();
+        app
     }
 }
 
@@ -83,78 +227,269 @@ impl App {
         textures: &mut Textures<Texture>,
         gl_ctx: &F,
     ) {
-        let dt = ui.io().delta_time;
-        let mut camera_offset = Vec3::ZERO;
-        let mut camera_rotate = [0.0, 0.0];
+        if self.scene_file.as_mut().is_some_and(FileWatcher::poll) {
+            self.reload_scene();
+        }
+        if self.scene_edited.swap(false, Ordering::Relaxed) {
+            self.on_scene_changed();
+        }
 
-        if ui.is_mouse_down(MouseButton::Right) {
-            if ui.is_key_down(Key::D) {
-                camera_offset += Vec3::X;
-            }
-            if ui.is_key_down(Key::A) {
-                camera_offset += Vec3::NEG_X;
-            }
-            if ui.is_key_down(Key::E) {
-                camera_offset += Vec3::Y;
-            }
-            if ui.is_key_down(Key::Q) {
-                camera_offset += Vec3::NEG_Y;
-            }
-            if ui.is_key_down(Key::W) {
-                camera_offset += Vec3::Z;
-            }
-            if ui.is_key_down(Key::S) {
-                camera_offset += Vec3::NEG_Z;
-            }
+        self.viewports[self.active_viewport].handle_camera_input(ui, &self.bindings);
 
-            let drag = ui.mouse_drag_delta_with_button(MouseButton::Right);
-            ui.reset_mouse_drag_delta(MouseButton::Right);
-            if drag[0].abs() > 0. || drag[1].abs() > 0. {
-                camera_rotate = [-drag[1], -drag[0]];
-            }
+        if ui.is_key_pressed(Key::F11)
+            || (self.presentation.enabled && ui.is_key_pressed(Key::Escape))
+        {
+            self.presentation.enabled = !self.presentation.enabled;
+        }
 
-            if camera_offset != Vec3::ZERO {
-                camera_offset = camera_offset.normalize();
-                self.camera.relative_move(camera_offset, dt);
-                self.renderer.reset_accumulation();
-            }
-            if camera_rotate != [0.0, 0.0] {
-                self.camera.relative_turn(camera_rotate, dt);
-                self.renderer.reset_accumulation();
-            }
+        if ui.is_key_pressed(Key::F12) {
+            self.request_screenshot();
+        }
+
+        if ui.is_key_pressed(Key::F) && !ui.io().want_text_input {
+            self.frame_selection();
+        }
+
+        let render_scale = if self.render_native_dpi {
+            self.scale_factor
+        } else {
+            1.
+        };
+        for viewport in &mut self.viewports {
+            viewport.render_scale = render_scale;
         }
 
+        let shared_scene = self.shared_scene.snapshot();
         {
             // scope for style tokens
             let _padding_style = ui.push_style_var(imgui::StyleVar::WindowPadding([0.0, 0.0]));
-            ui.window("Viewport")
-                .size(self.viewport_size, Condition::FirstUseEver)
-                .scroll_bar(false)
-                .build(|| {
-                    self.render(textures, gl_ctx).ok();
-                    self.viewport_size = ui.content_region_avail();
-                    if let Some(viewport_id) = self.viewport_id {
-                        imgui::Image::new(viewport_id, self.image_size)
-                            // flip Y-coordinate
-                            .uv0([0., 1.])
-                            .uv1([1., 0.])
-                            .build(ui);
+            if self.presentation.enabled {
+                self.final_render.build(
+                    ui,
+                    &self.scene,
+                    &self.viewports[self.active_viewport].camera,
+                );
+
+                let viewport = &mut self.viewports[self.active_viewport];
+                // A separate window, so the regular viewport keeps its layout.
+                ui.window("##presentation")
+                    .position([0.0, 0.0], Condition::Always)
+                    .size(ui.io().display_size, Condition::Always)
+                    .no_decoration()
+                    .movable(false)
+                    .bring_to_front_on_focus(false)
+                    .build(|| {
+                        viewport.build(ui, &shared_scene, textures, gl_ctx);
+                        if self.presentation.show_sample_count {
+                            let [x, y] = ui.window_pos();
+                            let text = format!("{:.0} samples", viewport.renderer().frame_count());
+                            let draw_list = ui.get_window_draw_list();
+                            draw_list.add_text([x + 9.0, y + 9.0], [0.0, 0.0, 0.0, 0.8], &text);
+                            draw_list.add_text([x + 8.0, y + 8.0], [1.0, 1.0, 1.0, 0.9], &text);
+                        }
+                    });
+            } else {
+                let closable = self.viewports.len() > 1;
+                // the hittable and the material dropped onto it
+                let mut dropped = None;
+                for (idx, viewport) in self.viewports.iter_mut().enumerate() {
+                    let title = viewport.title();
+                    let mut open = viewport.open;
+                    let mut window = ui
+                        .window(title)
+                        .size(viewport.size, Condition::FirstUseEver)
+                        .scroll_bar(false);
+                    if closable {
+                        window = window.opened(&mut open);
                     }
+                    window.build(|| {
+                        if ui.is_window_hovered()
+                            && (ui.is_mouse_clicked(MouseButton::Left)
+                                || ui.is_mouse_clicked(MouseButton::Right))
+                        {
+                            self.active_viewport = idx;
+                        }
+                        viewport.build(ui, &shared_scene, textures, gl_ctx);
+                        if ui.is_item_clicked() && ui.io().key_ctrl {
+                            if let Some([x, y]) = viewport.pixel_at(ui.io().mouse_pos) {
+                                let seed = self.next_trace_seed;
+                                self.next_trace_seed += 1;
+                                let trace = viewport.renderer().trace_pixel(
+                                    &self.scene,
+                                    &viewport.camera,
+                                    x,
+                                    y,
+                                    seed,
+                                );
+                                self.traced_pixel = [x, y];
+                                self.pixel_trace = Some(trace.to_string());
+                                self.traced_path = Some(trace);
+                            }
+                        }
+                        if let Some(material) = dropped_material(ui) {
+                            let mouse_pos = ui.io().mouse_pos;
+                            if let Some(hittable) = viewport.hittable_at(&self.scene, mouse_pos) {
+                                dropped = Some((hittable, material));
+                            }
+                        }
+                        if self.show_grid {
+                            viewport.draw_grid(ui);
+                        }
+                        if self.show_axis_gizmo {
+                            viewport.draw_axis_gizmo(ui);
+                        }
+                        if self.show_bounding_boxes {
+                            viewport.draw_bounding_boxes(ui, &self.scene, self.selected);
+                        }
+                        if idx == self.active_viewport && self.show_traced_path {
+                            if let Some(trace) = &self.traced_path {
+                                viewport.draw_path(ui, trace);
+                            }
+                        }
+                    });
+                    viewport.open = open;
+                }
+                self.viewports.retain_mut(|viewport| {
+                    if !viewport.open {
+                        viewport.remove_textures(textures);
+                    }
+                    viewport.open
                 });
+                self.active_viewport = self.active_viewport.min(self.viewports.len() - 1);
+                if let Some((hittable, material)) = dropped {
+                    if let Some(handle) = self.scene.material_handle(material) {
+                        *self.scene.hittable_mut(hittable).material_mut() = handle;
+                    }
+                }
+            }
         }
 
+        self.build_exit_prompt(ui);
+        if self.presentation.enabled {
+            return;
+        }
+
+        if self.error.is_some() {
+            ui.open_popup("Error");
+        }
+        ui.modal_popup_config("Error")
+            .always_auto_resize(true)
+            .build(|| {
+                if let Some(error) = &self.error {
+                    ui.text(error);
+                }
+                if ui.button("OK") {
+                    self.error = None;
+                    ui.close_current_popup();
+                }
+            });
+
+        ui.main_menu_bar(|| {
+            ui.menu("Scene", || {
+                ui.menu("Load preset", || {
+                    for preset in Preset::ALL {
+                        if ui.menu_item(preset.name()) {
+                            self.load_preset(preset);
+                        }
+                    }
+                });
+                if ui.menu_item("Save to scene.ron") {
+                    match self.scene.save("scene.ron") {
+                        Ok(()) => self.scene_file = Some(FileWatcher::new("scene.ron".as_ref())),
+                        Err(err) => self.error = Some(format!("Couldn't save scene:\n{err:#}")),
+                    }
+                }
+                ui.separator();
+                if ui.menu_item(format!("Save materials to {MATERIALS_FILE}")) {
+                    if let Err(err) = self.scene.export_materials(MATERIALS_FILE) {
+                        self.error = Some(format!("Couldn't save materials:\n{err:#}"));
+                    }
+                }
+                if ui.menu_item(format!("Import materials from {MATERIALS_FILE}")) {
+                    if let Err(err) = self.load_file(MATERIALS_FILE.as_ref()) {
+                        self.error = Some(format!("Couldn't import materials:\n{err:#}"));
+                    }
+                }
+                ui.separator();
+                if ui.menu_item("Console...") {
+                    self.console.open = true;
+                }
+            });
+            ui.menu("Render", || {
+                if ui.menu_item("Render to file...") {
+                    self.final_render.open = true;
+                }
+            });
+            ui.menu("View", || {
+                if ui.menu_item("New viewport") {
+                    let active = &self.viewports[self.active_viewport];
+                    let viewport = Viewport::new(self.next_viewport_id, active.camera.clone());
+                    viewport
+                        .renderer()
+                        .set_thread_policy(active.renderer().thread_policy());
+                    viewport
+                        .renderer()
+                        .set_low_priority(active.renderer().low_priority());
+                    self.viewports.push(viewport);
+                    self.next_viewport_id += 1;
+                }
+                if ui.menu_item_config("Frame selection").shortcut("F").build() {
+                    self.frame_selection();
+                }
+                if ui
+                    .menu_item_config("Save screenshot")
+                    .shortcut("F12")
+                    .build()
+                {
+                    self.request_screenshot();
+                }
+                ui.separator();
+                if ui
+                    .menu_item_config("Bounding boxes")
+                    .selected(self.show_bounding_boxes)
+                    .build()
+                {
+                    self.show_bounding_boxes = !self.show_bounding_boxes;
+                }
+                if ui.menu_item_config("Grid").selected(self.show_grid).build() {
+                    self.show_grid = !self.show_grid;
+                }
+                if ui
+                    .menu_item_config("Axis gizmo")
+                    .selected(self.show_axis_gizmo)
+                    .build()
+                {
+                    self.show_axis_gizmo = !self.show_axis_gizmo;
+                }
+            });
+        });
+
+        self.final_render.build(
+            ui,
+            &self.scene,
+            &self.viewports[self.active_viewport].camera,
+        );
+        self.console.build(
+            ui,
+            &mut self.scene,
+            &mut self.viewports[self.active_viewport],
+        );
+
+        let viewport = &mut self.viewports[self.active_viewport];
+
         ui.window("Debug")
             .size([200.0, 100.0], Condition::FirstUseEver)
             .build(|| {
+                ui.text(format!("Active viewport: {}", viewport.title()));
                 ui.text(format!(
                     "Viewport size: {:.0}x{:.0} ({:.3}:1)",
-                    self.viewport_size[0],
-                    self.viewport_size[1],
-                    self.viewport_size[0] / self.viewport_size[1]
+                    viewport.size[0],
+                    viewport.size[1],
+                    viewport.size[0] / viewport.size[1]
                 ));
                 const MAX_FRAME_HISTORY: usize = 256;
                 ui.text("Last render:");
-                for (name, duration) in self.timer.get_durations() {
+                for (name, duration) in viewport.timer.get_durations() {
                     let times = self
                         .frame_times
                         .entry(name.to_string())
@@ -171,142 +506,1227 @@ impl App {
                     ui.same_line();
                     ui.plot_lines(name, times.make_contiguous()).build();
                 }
+
+                let stats = &viewport.render_stats;
+                ui.text("Renderer:");
+                for (name, duration) in &stats.stage_times {
+                    ui.text(format!(
+                        "  {name}: {:0>4.1}ms",
+                        duration.as_secs_f32() * 1000.0
+                    ));
+                }
+                ui.text(format!(
+                    "  {} rays ({} primary, {} secondary)",
+                    stats.rays_traced(),
+                    stats.primary_rays,
+                    stats.secondary_rays
+                ));
+                ui.text(format!("  Average bounces: {:.2}", stats.average_bounces()));
+                let paths = stats.bounce_histogram.iter().sum::<u64>().max(1) as f32;
+                let shares: Vec<f32> = stats
+                    .bounce_histogram
+                    .iter()
+                    .map(|count| *count as f32 / paths)
+                    .collect();
+                ui.plot_histogram("##bounces", &shares)
+                    .scale_min(0.)
+                    .scale_max(1.)
+                    .graph_size([0., 40.])
+                    .overlay_text("Paths by bounces")
+                    .build();
+                if ui.is_item_hovered() {
+                    let last = shares.len().saturating_sub(1);
+                    let lines: Vec<_> = shares
+                        .iter()
+                        .enumerate()
+                        .map(|(bounces, share)| {
+                            let more = if bounces == last { "+" } else { "" };
+                            format!("{bounces}{more}: {:.1}%", share * 100.)
+                        })
+                        .collect();
+                    ui.tooltip_text(lines.join("\n"));
+                }
+                ui.text(format!(
+                    "  {} intersection tests ({:.1} per ray)",
+                    stats.intersection_tests,
+                    stats.tests_per_ray()
+                ));
+                ui.text(format!(
+                    "  {:.2} Msamples/s, {:.2} Mrays/s",
+                    viewport.throughput.samples_per_second() / 1e6,
+                    viewport.throughput.rays_per_second() / 1e6
+                ));
+                ui.text(format!(
+                    "  {} NaN/Inf samples so far",
+                    viewport.renderer().total_stats().invalid_samples
+                ));
+
+                ui.separator();
+                let [width, height] = viewport.camera.size();
+                imgui::Drag::new("Pixel (from bottom left)")
+                    .range(0, width.max(height).saturating_sub(1))
+                    .build_array(ui, &mut self.traced_pixel);
+                let [x, y] = self.traced_pixel;
+                if x < width && y < height {
+                    let trace = |seed| {
+                        let renderer = viewport.renderer();
+                        renderer.trace_pixel(&self.scene, &viewport.camera, x, y, seed)
+                    };
+                    if ui.button("Trace pixel") {
+                        let path = trace(self.next_trace_seed);
+                        self.pixel_trace = Some(path.to_string());
+                        self.traced_path = Some(path);
+                        self.next_trace_seed += 1;
+                    }
+                    ui.same_line();
+                    if ui.button("Trace until NaN/Inf") {
+                        // most paths are fine, so look through a lot of them
+                        const TRIES: u64 = 10_000;
+                        let seeds = self.next_trace_seed..self.next_trace_seed + TRIES;
+                        self.next_trace_seed = seeds.end;
+                        let found = seeds.map(&trace).find(PixelTrace::has_invalid_values);
+                        self.pixel_trace = Some(found.as_ref().map_or_else(
+                            || format!("No NaN/Inf in {TRIES} paths through ({x}, {y})"),
+                            |trace| trace.to_string(),
+                        ));
+                        self.traced_path = found;
+                    }
+                }
+                ui.checkbox("Show path in viewport", &mut self.show_traced_path);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Ctrl+click the viewport to trace the pixel under the mouse");
+                }
+                if let Some(trace) = &self.pixel_trace {
+                    ui.text(trace);
+                }
             });
 
+        self.material_previews.update(self.scene.materials(), textures, gl_ctx);
+        let mut scene_changed = false;
         ui.window("Settings")
             .size([300., 300.], Condition::FirstUseEver)
             .build(|| {
-                ui.checkbox("Accumulation", &mut self.renderer.use_accumulation);
+                ui.text(format!("Active viewport: {}", viewport.title()));
+                ui.checkbox("Accumulation", &mut viewport.renderer().use_accumulation);
                 ui.same_line();
                 if ui.button("Reset") {
-                    self.renderer.reset_accumulation()
+                    viewport.renderer().reset_accumulation()
+                }
+                ui.same_line();
+                if ui.button("Export PNG") {
+                    self.export_status = Some(match Self::export_png(viewport) {
+                        Ok(path) => format!("Saved {}", path.display()),
+                        Err(err) => format!("Export failed: {err}"),
+                    });
+                }
+                if let Some(status) = &self.export_status {
+                    ui.text_wrapped(status);
                 }
 
-                let mut local_num_threads = self.renderer.num_threads();
-                if imgui::Drag::new("Thread count")
-                    .range(1, num_cpus::get() * 2)
-                    .speed(0.15)
-                    .build(ui, &mut local_num_threads)
-                {
-                    self.renderer.set_num_threads(local_num_threads);
+                ui.separator();
+                if ui.button("Capture A") {
+                    if let Err(err) = viewport.capture_comparison(textures, gl_ctx) {
+                        self.error = Some(format!("Couldn't capture the image:\n{err:#}"));
+                    }
+                    self.comparison_status = None;
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Keep the image as A, to compare against the live render");
+                }
+                if viewport.has_comparison() {
+                    ui.same_line();
+                    if ui.button("Clear A") {
+                        viewport.clear_comparison(textures);
+                        self.comparison_status = None;
+                    }
+                    ui.same_line();
+                    if ui.button("Measure difference") {
+                        self.comparison_status = Some(match viewport.comparison_difference() {
+                            Some(difference) => format!("RMS difference: {difference:.5}"),
+                            None => "A is a different size, so can't be measured".to_string(),
+                        });
+                    }
+                    ui.slider("A/B split", 0., 1., &mut viewport.comparison_split);
+                    if let Some(status) = &self.comparison_status {
+                        ui.text(status);
+                    }
                 }
 
-                let mut camera_position_ui: Vec3 = self.camera.position();
+                const VIEW_MODES: [(ViewMode, &str); 2] = [
+                    (ViewMode::Shaded, "Shaded"),
+                    (ViewMode::IntersectionHeatmap, "Intersection heatmap"),
+                ];
+                let mut view_idx = VIEW_MODES
+                    .iter()
+                    .position(|(mode, _)| *mode == viewport.renderer().view_mode)
+                    .unwrap_or_default();
+                if ui.combo("View", &mut view_idx, &VIEW_MODES, |(_, label)| {
+                    (*label).into()
+                }) {
+                    viewport.renderer().view_mode = VIEW_MODES[view_idx].0;
+                    viewport.renderer().reset_accumulation();
+                }
+                const INTERLEAVES: [(u32, &str); 3] =
+                    [(1, "Every pixel"), (2, "Checkerboard"), (4, "Quarter")];
+                let mut interleave_idx = INTERLEAVES
+                    .iter()
+                    .position(|(every, _)| *every == viewport.renderer().interleave())
+                    .unwrap_or_default();
+                if ui.combo("Interleave", &mut interleave_idx, &INTERLEAVES, |(_, label)| {
+                    (*label).into()
+                }) {
+                    viewport.renderer().set_interleave(INTERLEAVES[interleave_idx].0);
+                }
+                let mut frame_blend = viewport.renderer().frame_blend();
+                let mut blend_frames = frame_blend.is_some();
+                if ui.checkbox("Blend frames", &mut blend_frames) {
+                    viewport.renderer().set_frame_blend(blend_frames.then_some(0.1));
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "Give each new frame this weight, so the image catches up with changes \
+                        without a reset, instead of averaging every frame equally",
+                    );
+                }
+                if let Some(weight) = &mut frame_blend {
+                    ui.same_line();
+                    if imgui::Drag::new("##frame blend")
+                        .range(0.01, 1.)
+                        .speed(0.002)
+                        .build(ui, weight)
+                    {
+                        viewport.renderer().set_frame_blend(Some(*weight));
+                    }
+                }
+                if ui.checkbox(
+                    "Transparent background",
+                    &mut viewport.renderer().transparent_background,
+                ) {
+                    viewport.renderer().reset_accumulation();
+                }
+                if let Some(environment) = self.scene.environment() {
+                    let [width, height] = environment.size();
+                    ui.text(format!("Environment map: {width}x{height}"));
+                    ui.same_line();
+                    if ui.button("Clear##environment") {
+                        self.scene.set_environment(None);
+                    }
+                }
+                ui.checkbox(
+                    "Cache first bounce",
+                    &mut viewport.renderer().cache_first_bounce,
+                );
+                if ui.checkbox(
+                    "Mark NaN/Inf samples in magenta",
+                    &mut viewport.renderer().mark_invalid_samples,
+                ) {
+                    viewport.renderer().reset_accumulation();
+                }
+
+                ui.checkbox(
+                    "Show sample count when presenting (F11)",
+                    &mut self.presentation.show_sample_count,
+                );
+                ui.checkbox("Render at native DPI", &mut self.render_native_dpi);
+
+                let mut limit_fps = self.frame_limit.max_fps.is_some();
+                if ui.checkbox("Limit FPS", &mut limit_fps) {
+                    self.frame_limit.max_fps = limit_fps.then_some(30.);
+                }
+                if let Some(max_fps) = &mut self.frame_limit.max_fps {
+                    ui.same_line();
+                    imgui::Drag::new("##max fps")
+                        .range(1., 240.)
+                        .speed(0.5)
+                        .build(ui, max_fps);
+                }
+                imgui::Drag::new("Background FPS")
+                    .range(0.1, 60.)
+                    .speed(0.1)
+                    .build(ui, &mut self.frame_limit.background_fps);
+
+                const THREAD_POLICIES: [&str; 4] =
+                    ["All cores", "Physical cores", "All but one", "Fixed"];
+                let policy = viewport.renderer().thread_policy();
+                let mut policy_idx = match policy {
+                    ThreadPolicy::All => 0,
+                    ThreadPolicy::Physical => 1,
+                    ThreadPolicy::AllButOne => 2,
+                    ThreadPolicy::Fixed(_) => 3,
+                };
+                let mut new_policy = None;
+                if ui.combo_simple_string("Threads", &mut policy_idx, &THREAD_POLICIES) {
+                    new_policy = Some(match policy_idx {
+                        0 => ThreadPolicy::All,
+                        1 => ThreadPolicy::Physical,
+                        2 => ThreadPolicy::AllButOne,
+                        _ => ThreadPolicy::Fixed(policy.num_threads()),
+                    });
+                }
+                if let ThreadPolicy::Fixed(num_threads) = policy {
+                    let mut local_num_threads = num_threads;
+                    if imgui::Drag::new("Thread count")
+                        .range(1, num_cpus::get() * 2)
+                        .speed(0.15)
+                        .build(ui, &mut local_num_threads)
+                    {
+                        new_policy = Some(ThreadPolicy::Fixed(local_num_threads));
+                    }
+                }
+                if let Some(policy) = new_policy {
+                    // takes effect on the next frame, without waiting for this one
+                    if let Err(err) = viewport.renderer().try_set_thread_policy(policy) {
+                        self.error = Some(format!("{err}"));
+                    }
+                }
+                let mut low_priority = viewport.renderer().low_priority();
+                if ui.checkbox("Low priority threads", &mut low_priority) {
+                    if let Err(err) = viewport.renderer().try_set_low_priority(low_priority) {
+                        self.error = Some(format!("{err}"));
+                    }
+                }
+
+                let mut camera_position_ui: Vec3 = viewport.camera.position();
                 if imgui::Drag::new("Camera position")
                     .range(-10., 10.)
                     .speed(0.1)
                     .build_array(ui, camera_position_ui.as_mut())
                 {
-                    self.camera.set_position(camera_position_ui);
-                    self.renderer.reset_accumulation();
+                    let before = viewport.camera.clone();
+                    viewport.camera.set_position(camera_position_ui);
+                    viewport.renderer().reproject(&before, &viewport.camera);
                 }
 
-                let mut camera_direction_ui: Vec3 = self.camera.look_direction();
+                let mut camera_direction_ui: Vec3 = viewport.camera.look_direction();
                 if imgui::Drag::new("Camera direction")
                     .range(-1., 1.)
                     .speed(0.01)
                     .build_array(ui, camera_direction_ui.as_mut())
                 {
-                    self.camera.set_look_direction(camera_direction_ui);
-                    self.renderer.reset_accumulation();
+                    let before = viewport.camera.clone();
+                    viewport.camera.set_look_direction(camera_direction_ui);
+                    viewport.renderer().reproject(&before, &viewport.camera);
+                }
+
+                let mut roll = viewport.camera.roll();
+                if imgui::Drag::new("Roll")
+                    .range(-180., 180.)
+                    .speed(0.5)
+                    .build(ui, &mut roll)
+                {
+                    let before = viewport.camera.clone();
+                    viewport.camera.set_roll(roll);
+                    viewport.renderer().reproject(&before, &viewport.camera);
                 }
 
-                let mut local_fov = self.camera.vertical_fov();
+                let mut local_fov = viewport.camera.vertical_fov();
                 if imgui::Drag::new("FOV")
                     .range(1_f32, 90_f32)
                     .speed(0.3)
                     .build(ui, &mut local_fov)
                 {
-                    self.camera.set_vertical_fov(local_fov);
-                    self.renderer.reset_accumulation();
+                    let before = viewport.camera.clone();
+                    viewport.camera.set_vertical_fov(local_fov);
+                    viewport.renderer().reproject(&before, &viewport.camera);
+                }
+
+                let mut focal_length = viewport.camera.focal_length(LensPreset::FULL_FRAME);
+                if imgui::Drag::new("Focal length (mm, full frame)")
+                    .range(10., 300.)
+                    .speed(0.5)
+                    .build(ui, &mut focal_length)
+                {
+                    let before = viewport.camera.clone();
+                    viewport.camera.set_focal_length(focal_length, LensPreset::FULL_FRAME);
+                    viewport.renderer().reproject(&before, &viewport.camera);
+                }
+
+                let lens_names: Vec<&str> = std::iter::once("Custom")
+                    .chain(LensPreset::ALL.iter().map(|preset| preset.name))
+                    .collect();
+                let mut lens_idx = LensPreset::ALL
+                    .iter()
+                    .position(|preset| {
+                        (preset.vertical_fov() - viewport.camera.vertical_fov()).abs() < 0.01
+                    })
+                    .map_or(0, |idx| idx + 1);
+                if ui.combo_simple_string("Lens", &mut lens_idx, &lens_names) {
+                    if let Some(preset) = lens_idx.checked_sub(1).map(|idx| LensPreset::ALL[idx]) {
+                        let before = viewport.camera.clone();
+                        viewport.camera.set_focal_length(preset.focal_length, preset.sensor_height);
+                        viewport.renderer().reproject(&before, &viewport.camera);
+                    }
+                }
+
+                let mut turntable = viewport.camera.turntable();
+                let mut turntable_enabled = turntable.is_some();
+                if ui.checkbox("Turntable", &mut turntable_enabled) {
+                    turntable = turntable_enabled.then_some(Turntable {
+                        center: Vec3::ZERO,
+                        degrees_per_second: 30.,
+                    });
+                }
+                if let Some(turntable) = &mut turntable {
+                    imgui::Drag::new("Turntable center")
+                        .speed(0.05)
+                        .build_array(ui, turntable.center.as_mut());
+                    imgui::Drag::new("Turntable speed (deg/s)")
+                        .range(-360., 360.)
+                        .speed(0.5)
+                        .build(ui, &mut turntable.degrees_per_second);
+                }
+                viewport.camera.set_turntable(turntable);
+
+                if ui.collapsing_header("Bookmarks", imgui::TreeNodeFlags::empty()) {
+                    let mut recall = None;
+                    let mut remove = None;
+                    for (idx, bookmark) in self.scene.bookmarks_mut().iter_mut().enumerate() {
+                        let _id = ui.push_id_usize(idx);
+                        if ui.button("Go") {
+                            recall = Some(idx);
+                        }
+                        ui.same_line();
+                        if ui.button("X") {
+                            remove = Some(idx);
+                        }
+                        ui.same_line();
+                        ui.input_text("##name", &mut bookmark.name).build();
+                    }
+                    if let Some(idx) = recall {
+                        viewport.camera.animate_to(&self.scene.bookmarks()[idx], 1.);
+                    }
+                    if let Some(idx) = remove {
+                        self.scene.remove_bookmark(idx);
+                    }
+                    if ui.button("Save current view") {
+                        let name = format!("View {}", self.scene.bookmarks().len() + 1);
+                        self.scene.add_bookmark(viewport.camera.bookmark(name));
+                    }
                 }
 
+                if ui.collapsing_header("Controls", imgui::TreeNodeFlags::empty()) {
+                    Self::bindings_ui(ui, &mut self.bindings);
+                    if ui.button("Save controls") {
+                        if let Err(err) = settings::save(Bindings::FILE_NAME, &self.bindings) {
+                            self.error = Some(format!("Couldn't save controls:\n{err:#}"));
+                        }
+                    }
+                }
+
+                ui.separator();
+
+                if !self.diagnostics.is_empty() {
+                    // the count is left out of the id, so the header stays open as it changes
+                    let label = format!("Problems ({})###problems", self.diagnostics.len());
+                    if ui.collapsing_header(label, imgui::TreeNodeFlags::DEFAULT_OPEN) {
+                        for (idx, diagnostic) in self.diagnostics.iter().enumerate() {
+                            let _id = ui.push_id_usize(idx);
+                            if let Subject::Hittable(hittable) = diagnostic.subject {
+                                if ui.small_button("Select") {
+                                    self.selected = Some(hittable);
+                                }
+                                ui.same_line();
+                            }
+                            let color = match diagnostic.severity {
+                                Severity::Warning => [1.0, 0.8, 0.2, 1.0],
+                                Severity::Error => [1.0, 0.3, 0.3, 1.0],
+                            };
+                            ui.text_colored(color, diagnostic.to_string());
+                        }
+                    }
+                    ui.separator();
+                }
+
+                Self::node_ui(ui, &mut self.scene, NodeId::ROOT);
+
                 ui.separator();
 
                 let hittable_count = self.scene.hittables().len();
-                let material_count = self.scene.materials().len();
-                for (idx, hittable) in self.scene.hittables_mut().iter_mut().enumerate() {
+                let material_handles: Vec<_> = (0..self.scene.materials().len())
+                    .filter_map(|idx| self.scene.material_handle(idx))
+                    .collect();
+                let mut duplicate = None;
+                for idx in 0..hittable_count {
                     let _id = ui.push_id_usize(idx);
+                    // edit a copy, so the scene only hears about it when something changes
+                    let mut edited = self.scene.hittables()[idx].clone();
+                    let hittable = &mut edited;
+                    // set again below if this hittable changes
+                    let others_changed = std::mem::take(&mut scene_changed);
+                    let shape = match hittable {
+                        halide_raytracer::Hittable::Sphere(_) => "sphere",
+                        halide_raytracer::Hittable::Quad(_) => "quad",
+                        halide_raytracer::Hittable::Cylinder(_) => "cylinder",
+                        halide_raytracer::Hittable::Cone(_) => "cone",
+                        halide_raytracer::Hittable::Torus(_) => "torus",
+                        halide_raytracer::Hittable::Sdf(_) => "sdf",
+                        halide_raytracer::Hittable::Curve(_) => "curve",
+                        halide_raytracer::Hittable::Mesh(_) => "mesh",
+                        halide_raytracer::Hittable::Csg(_) => "csg",
+                        halide_raytracer::Hittable::Custom(custom) => custom.shape.kind(),
+                    };
+                    ui.text(format!("Obj #{idx}: {shape}"));
+                    if let Some(&handle) =
+                        dropped_material(ui).and_then(|material| material_handles.get(material))
+                    {
+                        *hittable.material_mut() = handle;
+                        scene_changed = true;
+                    }
+                    ui.same_line();
+                    if ui.small_button("Duplicate") {
+                        duplicate = Some(idx);
+                    }
+                    ui.same_line();
+                    let mut selected = self.selected == Some(idx);
+                    if ui.checkbox("Selected", &mut selected) {
+                        self.selected = selected.then_some(idx);
+                    }
                     match hittable {
                         halide_raytracer::Hittable::Sphere(sphere) => {
-                            ui.text(format!("Obj #{idx}: sphere"));
                             if imgui::Drag::new("Position")
                                 .range((-10.0..10.0).start, (-10.0..10.0).end)
                                 .speed(0.1)
                                 .build_array(ui, sphere.center.as_mut())
                             {
-                                self.renderer.reset_accumulation();
+                                scene_changed = true;
                             }
                             if imgui::Drag::new("Radius")
                                 .range(0.1, 3.0)
                                 .speed(0.03)
                                 .build(ui, &mut sphere.radius)
                             {
-                                self.renderer.reset_accumulation();
+                                scene_changed = true;
                             }
-                            if imgui::Drag::new("Material")
-                                .range(0, material_count - 1)
+                        }
+                        halide_raytracer::Hittable::Quad(quad) => {
+                            if imgui::Drag::new("Corner")
+                                .range(-10.0, 10.0)
                                 .speed(0.1)
-                                .build(ui, &mut sphere.material_index)
+                                .build_array(ui, quad.corner.as_mut())
                             {
-                                self.renderer.reset_accumulation();
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Side U")
+                                .range(-10.0, 10.0)
+                                .speed(0.05)
+                                .build_array(ui, quad.u.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Side V")
+                                .range(-10.0, 10.0)
+                                .speed(0.05)
+                                .build_array(ui, quad.v.as_mut())
+                            {
+                                scene_changed = true;
                             }
                         }
+                        halide_raytracer::Hittable::Cylinder(halide_raytracer::Cylinder {
+                            base,
+                            axis,
+                            radius,
+                            capped,
+                            ..
+                        })
+                        | halide_raytracer::Hittable::Cone(halide_raytracer::Cone {
+                            base,
+                            axis,
+                            radius,
+                            capped,
+                            ..
+                        }) => {
+                            if imgui::Drag::new("Base")
+                                .range(-10.0, 10.0)
+                                .speed(0.1)
+                                .build_array(ui, base.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Axis")
+                                .range(-10.0, 10.0)
+                                .speed(0.05)
+                                .build_array(ui, axis.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Radius")
+                                .range(0.01, 3.0)
+                                .speed(0.03)
+                                .build(ui, radius)
+                            {
+                                scene_changed = true;
+                            }
+                            if ui.checkbox("Capped", capped) {
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Torus(torus) => {
+                            if imgui::Drag::new("Center")
+                                .range(-10.0, 10.0)
+                                .speed(0.1)
+                                .build_array(ui, torus.center.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Axis")
+                                .range(-1.0, 1.0)
+                                .speed(0.01)
+                                .build_array(ui, torus.axis.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Major radius")
+                                .range(0.01, 5.0)
+                                .speed(0.03)
+                                .build(ui, &mut torus.major_radius)
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Minor radius")
+                                .range(0.01, 2.0)
+                                .speed(0.01)
+                                .build(ui, &mut torus.minor_radius)
+                            {
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Sdf(sdf) => {
+                            if imgui::Drag::new("Position")
+                                .range(-10.0, 10.0)
+                                .speed(0.1)
+                                .build_array(ui, sdf.transform.translation.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Scale")
+                                .range(0.1, 5.0)
+                                .speed(0.03)
+                                .build(ui, &mut sdf.transform.scale)
+                            {
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Curve(curve) => {
+                            for (point_idx, point) in curve.points.iter_mut().enumerate() {
+                                if imgui::Drag::new(format!("Point {point_idx}"))
+                                    .range(-10.0, 10.0)
+                                    .speed(0.05)
+                                    .build_array(ui, point.as_mut())
+                                {
+                                    scene_changed = true;
+                                }
+                            }
+                            if imgui::Drag::new("Widths")
+                                .range(0.0, 1.0)
+                                .speed(0.005)
+                                .build_array(ui, &mut curve.widths)
+                            {
+                                scene_changed = true;
+                            }
+                            let mut round = curve.shape == halide_raytracer::CurveShape::Round;
+                            if ui.checkbox("Round", &mut round) {
+                                curve.shape = if round {
+                                    halide_raytracer::CurveShape::Round
+                                } else {
+                                    halide_raytracer::CurveShape::Flat
+                                };
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Mesh(mesh) => {
+                            ui.text(format!(
+                                "{} triangles",
+                                mesh.geometry.triangles().len()
+                            ));
+                            if imgui::Drag::new("Position")
+                                .range(-10.0, 10.0)
+                                .speed(0.1)
+                                .build_array(ui, mesh.transform.translation.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Scale")
+                                .range(0.1, 5.0)
+                                .speed(0.03)
+                                .build(ui, &mut mesh.transform.scale)
+                            {
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Csg(csg) => {
+                            use halide_raytracer::CsgOperation;
+                            const OPERATIONS: [(CsgOperation, &str); 3] = [
+                                (CsgOperation::Union, "Union"),
+                                (CsgOperation::Intersection, "Intersection"),
+                                (CsgOperation::Difference, "Difference"),
+                            ];
+                            let mut operation_idx = OPERATIONS
+                                .iter()
+                                .position(|(operation, _)| *operation == csg.operation)
+                                .unwrap_or_default();
+                            if ui.combo("Operation", &mut operation_idx, &OPERATIONS, |(_, label)| {
+                                (*label).into()
+                            }) {
+                                csg.operation = OPERATIONS[operation_idx].0;
+                                scene_changed = true;
+                            }
+                        }
+                        halide_raytracer::Hittable::Custom(_) => (),
+                    }
+                    ui.text(format!("Material: Mat #{}", hittable.material().index()));
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(
+                            "Drag a material's preview onto the object here or in a viewport \
+                            to change it",
+                        );
                     }
+                    if scene_changed {
+                        *self.scene.hittable_mut(idx) = edited;
+                    }
+                    scene_changed |= others_changed;
+                }
+                if let Some(idx) = duplicate {
+                    self.scene.duplicate_hittable(idx);
+                    scene_changed = true;
                 }
 
                 ui.separator();
 
-                for (idx, material) in self.scene.materials_mut().iter_mut().enumerate() {
+                for idx in 0..self.scene.materials().len() {
                     let _id = ui.push_id_usize(idx);
+                    let mut edited = self.scene.materials()[idx].clone();
+                    let material = &mut edited;
+                    // set again below if this material changes
+                    let others_changed = std::mem::take(&mut scene_changed);
+                    self.material_previews.build(ui, idx);
                     match material {
                         Material::Null => (),
                         Material::Lambertian { albedo } => {
                             ui.text(format!("Mat #{idx}: Lambertian"));
                             if ui.color_edit3("Albedo", albedo.as_mut()) {
-                                self.renderer.reset_accumulation();
+                                scene_changed = true;
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Textured { texture } => {
+                            ui.text(format!("Mat #{idx}: Textured"));
+                            match texture {
+                                halide_raytracer::Texture::Solid(color) => {
+                                    if ui.color_edit3("Color", color.as_mut()) {
+                                        scene_changed = true;
+                                    }
+                                }
+                                halide_raytracer::Texture::Noise(noise) => {
+                                    if imgui::Drag::new("Frequency")
+                                        .range(0.01, 100.)
+                                        .speed(0.01)
+                                        .build(ui, &mut noise.frequency)
+                                    {
+                                        scene_changed = true;
+                                    }
+                                    if imgui::Drag::new("Octaves")
+                                        .range(1, 10)
+                                        .build(ui, &mut noise.octaves)
+                                    {
+                                        scene_changed = true;
+                                    }
+                                    if imgui::Drag::new("Seed").build(ui, &mut noise.seed) {
+                                        scene_changed = true;
+                                    }
+                                }
+                                halide_raytracer::Texture::Procedural(procedural) => {
+                                    ui.text(format!("Procedural: {}", procedural.name()));
+                                }
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Emissive { emission } => {
+                            ui.text(format!("Mat #{idx}: Emissive"));
+                            if imgui::Drag::new("Emission")
+                                .range(0., 100.)
+                                .speed(0.1)
+                                .build_array(ui, emission.as_mut())
+                            {
+                                scene_changed = true;
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Metal {
+                            albedo,
+                            fuzz,
+                            coating,
+                        } => {
+                            ui.text(format!("Mat #{idx}: Metal"));
+                            if ui.color_edit3("Albedo", albedo.as_mut()) {
+                                scene_changed = true;
+                            }
+                            if imgui::Drag::new("Fuzz")
+                                .range(0., 1.)
+                                .speed(0.01)
+                                .build(ui, fuzz)
+                            {
+                                scene_changed = true;
+                            }
+                            let mut coated = coating.is_some();
+                            if ui.checkbox("Thin film", &mut coated) {
+                                *coating = coated.then_some(ThinFilm {
+                                    thickness: 400.,
+                                    ior: 1.33,
+                                });
+                                scene_changed = true;
+                            }
+                            if let Some(film) = coating {
+                                if imgui::Drag::new("Thickness (nm)")
+                                    .range(0., 1500.)
+                                    .speed(1.)
+                                    .build(ui, &mut film.thickness)
+                                {
+                                    scene_changed = true;
+                                }
+                                if imgui::Drag::new("Film IOR")
+                                    .range(1., 2.5)
+                                    .speed(0.01)
+                                    .build(ui, &mut film.ior)
+                                {
+                                    scene_changed = true;
+                                }
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Bump { strength, .. } => {
+                            ui.text(format!("Mat #{idx}: Bump"));
+                            if imgui::Drag::new("Strength")
+                                .range(0., 10.)
+                                .speed(0.01)
+                                .build(ui, strength)
+                            {
+                                scene_changed = true;
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Sided { front, back } => {
+                            ui.text(format!("Mat #{idx}: Sided"));
+                            const BACKFACES: [&str; 3] = ["Shaded", "Culled", "Own material"];
+                            let mut backface_idx = match back {
+                                Backface::Shaded => 0,
+                                Backface::Culled => 1,
+                                Backface::Material(_) => 2,
+                            };
+                            if ui.combo_simple_string("Back", &mut backface_idx, &BACKFACES) {
+                                *back = match backface_idx {
+                                    0 => Backface::Shaded,
+                                    1 => Backface::Culled,
+                                    // start from the front, to be edited from there
+                                    _ => Backface::Material(front.clone()),
+                                };
+                                scene_changed = true;
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Mix { factor, .. } => {
+                            ui.text(format!("Mat #{idx}: Mix"));
+                            match factor {
+                                MixFactor::Constant(factor) => {
+                                    if imgui::Drag::new("Factor")
+                                        .range(0., 1.)
+                                        .speed(0.01)
+                                        .build(ui, factor)
+                                    {
+                                        scene_changed = true;
+                                    }
+                                }
+                                MixFactor::Texture(_) => ui.text("Factor from a texture"),
+                            }
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Custom { bsdf } => {
+                            ui.text(format!("Mat #{idx}: {}", bsdf.0.kind()));
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Procedural { shader } => {
+                            ui.text(format!("Mat #{idx}: Procedural ({})", shader.name()));
+                            if idx < hittable_count - 1 {
+                                ui.separator();
+                            }
+                        }
+                        Material::Graph { graph } => {
+                            ui.text(format!("Mat #{idx}: Graph"));
+                            ui.text(format!("{} nodes", graph.nodes.len()));
+                            if ui.button("Edit graph") {
+                                self.node_editor.open(idx);
                             }
                             if idx < hittable_count - 1 {
                                 ui.separator();
                             }
                         }
                     }
+                    if scene_changed {
+                        self.scene.materials_mut()[idx] = edited;
+                        self.material_previews.invalidate(idx);
+                    }
+                    scene_changed |= others_changed;
+                }
+                if ui.button("New graph material") {
+                    let material = self.scene.add_material(Material::Graph {
+                        graph: MaterialGraph::default(),
+                    });
+                    self.node_editor.open(material.index());
                 }
             });
+
+        if let Some(idx) = self.node_editor.build(ui, &mut self.scene) {
+            self.material_previews.invalidate(idx);
+        }
+    }
+
+    fn bindings_ui(ui: &imgui::Ui, bindings: &mut Bindings) {
+        let mut button_idx = MouseButton::VARIANTS
+            .iter()
+            .position(|button| *button == bindings.fly_button)
+            .unwrap_or_default();
+        if ui.combo(
+            "Fly button",
+            &mut button_idx,
+            &MouseButton::VARIANTS,
+            |button| format!("{button:?}").into(),
+        ) {
+            bindings.fly_button = MouseButton::VARIANTS[button_idx];
+        }
+        for (label, key) in bindings.movement_keys_mut() {
+            let mut key_idx = Key::VARIANTS
+                .iter()
+                .position(|k| k == key)
+                .unwrap_or_default();
+            if ui.combo(label, &mut key_idx, &Key::VARIANTS, |key| {
+                format!("{key:?}").into()
+            }) {
+                *key = Key::VARIANTS[key_idx];
+            }
+        }
+        imgui::Drag::new("Fast multiplier (shift)")
+            .range(1., 100.)
+            .speed(0.1)
+            .build(ui, &mut bindings.fast_multiplier);
+        imgui::Drag::new("Slow multiplier (ctrl)")
+            .range(0.01, 1.)
+            .speed(0.01)
+            .build(ui, &mut bindings.slow_multiplier);
+        imgui::Drag::new("Mouse sensitivity")
+            .range(0.05, 10.)
+            .speed(0.01)
+            .build(ui, &mut bindings.mouse_sensitivity);
+        ui.checkbox("Invert mouse Y", &mut bindings.invert_mouse_y);
     }
 
-    fn render<F: Facade>(&mut self, textures: &mut Textures<Texture>, gl_ctx: &F) -> Result<()> {
-        self.timer.reset();
-        let width = self.viewport_size[0] as u32;
-        let height = self.viewport_size[1] as u32;
+    /// Draw editors for a node and its descendants. Returns true if anything changed.
+    fn node_ui(ui: &imgui::Ui, scene: &mut Scene, id: NodeId) {
+        let _id = ui.push_id(format!("{id:?}"));
+        let node = scene.node(id);
+        let children = node.children().to_vec();
+        let label = format!("{} ({} objects)", node.name, node.hittables().len());
+        ui.tree_node_config(&label).default_open(true).build(|| {
+            let mut transform = scene.node(id).transform;
+            let mut edited = imgui::Drag::new("Translation")
+                .speed(0.05)
+                .build_array(ui, transform.translation.as_mut());
+            edited |= imgui::Drag::new("Scale")
+                .range(0.01, 100.)
+                .speed(0.01)
+                .build(ui, &mut transform.scale);
+            if edited {
+                scene.node_mut(id).transform = transform;
+            }
+            for child in children {
+                Self::node_ui(ui, scene, child);
+            }
+        });
+    }
 
-        self.renderer.resize(width, height);
-        self.camera.set_size(width, height);
-        let data = self.renderer.render(&self.scene, &self.camera);
+    /// Move the active viewport's camera to fit the selected hittable in
+    /// view, or the whole scene if nothing is selected.
+    fn frame_selection(&mut self) {
+        let bounds = match self.selected {
+            Some(idx) => match self.scene.world_hittables().get(idx) {
+                Some(hittable) => hittable.bounding_box(),
+                None => return,
+            },
+            None => self.scene.bounds(),
+        };
+        let camera = &mut self.viewports[self.active_viewport].camera;
+        let mut target = camera.clone();
+        target.frame(&bounds);
+        // the far clip might have grown to fit, and animations don't move it
+        camera.set_look_clip(target.look_clip().clone());
+        camera.animate_to(&target.bookmark("framed"), 0.5);
+    }
 
-        self.timer.stage_end("generate data");
+    /// Save the whole window, UI and all, to a timestamped file in the
+    /// working directory once the current frame is drawn.
+    fn request_screenshot(&mut self) {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(timestamp) => {
+                let path = format!("halide-screenshot-{}.png", timestamp.as_secs());
+                self.screenshot_request = Some(path.into());
+            }
+            Err(err) => self.export_status = Some(format!("Screenshot failed: {err}")),
+        }
+    }
 
-        let raw = RawImage2d {
-            data,
-            width,
-            height,
-            format: glium::texture::ClientFormat::U8U8U8U8,
+    /// Save the image accumulated so far to a timestamped file in the working directory.
+    fn export_png(viewport: &Viewport) -> Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = PathBuf::from(format!("halide-{timestamp}.png"));
+        viewport.renderer().snapshot().save_png(&path)?;
+        Ok(path)
+    }
+
+    fn on_file_dropped(&mut self, path: PathBuf) {
+        if let Err(err) = self.load_file(&path) {
+            self.error = Some(format!("Couldn't load {}:\n{err:#}", path.display()));
+        }
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<()> {
+        if is_material_library(path) {
+            self.scene.import_materials(path)?;
+            return Ok(());
+        }
+        match file_extension(path).as_deref() {
+            Some("ron" | "halide") => {
+                self.scene = read_scene(path)?;
+                self.selected = None;
+                self.scene_file = Some(FileWatcher::new(path));
+                self.on_scene_replaced();
+                Ok(())
+            }
+            Some("obj") => {
+                // added to the scene, with a grey material of its own to edit
+                let geometry = MeshGeometry::from_obj(&std::fs::read_to_string(path)?)?;
+                let material = self.scene.add_material(Material::Lambertian {
+                    albedo: Vec3::splat(0.5),
+                });
+                let mesh = Mesh::new(Arc::new(geometry), material);
+                self.selected = Some(self.scene.add_hittable(mesh));
+                Ok(())
+            }
+            Some("hdr") => {
+                // lights the scene from every direction, in place of the sky
+                let environment = EnvironmentMap::from_image(&image::open(path)?)?;
+                self.scene.set_environment(Some(Arc::new(environment)));
+                Ok(())
+            }
+            _ => anyhow::bail!("Unrecognized file type"),
+        }
+    }
+
+    /// Load the scene file again after it changed on disk, keeping the cameras.
+    fn reload_scene(&mut self) {
+        let Some(file) = &self.scene_file else {
+            return;
         };
-        let gl_texture =
-            glium::Texture2d::with_mipmaps(gl_ctx, raw, glium::texture::MipmapsOption::NoMipmap)?;
-        let texture = Texture {
-            texture: Rc::new(gl_texture),
-            sampler: SamplerBehavior {
-                magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
-                minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
-                ..Default::default()
+        match read_scene(file.path()) {
+            Ok(scene) => {
+                self.scene = scene;
+                self.on_scene_replaced();
+            }
+            Err(err) => {
+                self.error = Some(format!(
+                    "Couldn't reload {}:\n{err:#}",
+                    file.path().display()
+                ));
+            }
+        }
+    }
+
+    fn load_preset(&mut self, preset: Preset) {
+        self.scene = preset.scene();
+        self.selected = None;
+        self.scene_file = None;
+        for viewport in &mut self.viewports {
+            viewport.camera = preset.camera();
+        }
+        self.on_scene_replaced();
+    }
+
+    /// Apply settings and bindings saved by a previous session.
+    fn restore_settings(&mut self) {
+        match settings::load::<Bindings>(Bindings::FILE_NAME) {
+            Ok(bindings) => self.bindings = bindings.unwrap_or_default(),
+            Err(err) => self.error = Some(format!("Couldn't load controls:\n{err:#}")),
+        }
+
+        let settings = match settings::load::<Settings>(Settings::FILE_NAME) {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(err) => {
+                self.error = Some(format!("Couldn't load settings:\n{err:#}"));
+                return;
+            }
+        };
+        if let Some(num_threads) = settings.num_threads {
+            for viewport in &mut self.viewports {
+                if let Err(err) = viewport.renderer().try_set_num_threads(num_threads) {
+                    self.error = Some(format!("Couldn't restore thread count:\n{err}"));
+                }
+            }
+        }
+        self.frame_limit = settings.frame_limit;
+        self.presentation.show_sample_count = settings.show_sample_count;
+        self.render_native_dpi = settings.render_native_dpi;
+        if let Some(path) = settings.last_scene {
+            if let Err(err) = self.load_file(&path) {
+                self.error = Some(format!("Couldn't reopen {}:\n{err:#}", path.display()));
+            }
+        }
+    }
+
+    /// Start quitting, after the window has been asked to close. If a final
+    /// render is in progress, ask what to do with it first.
+    fn request_exit(&mut self) {
+        self.exit = if self.final_render.is_running() {
+            Exit::Confirm
+        } else {
+            Exit::Now
+        };
+    }
+
+    /// Ask whether to save or throw away the final render in progress
+    /// before quitting, and wait for it to save if asked to.
+    fn build_exit_prompt(&mut self, ui: &imgui::Ui) {
+        if matches!(self.exit, Exit::Confirm | Exit::Saving) && !self.final_render.is_running() {
+            if self.final_render.failed() {
+                // stay open, so the error can be seen and the render tried again
+                self.final_render.open = true;
+                self.exit = Exit::No;
+            } else {
+                self.exit = Exit::Now;
+            }
+        }
+
+        if matches!(self.exit, Exit::Confirm | Exit::Saving) {
+            ui.open_popup("Quit");
+        }
+        ui.modal_popup_config("Quit")
+            .always_auto_resize(true)
+            .build(|| {
+                let path = self.final_render.path().to_owned();
+                match self.exit {
+                    Exit::Confirm => {}
+                    Exit::Saving => {
+                        ui.text(format!("Saving {path}..."));
+                        return;
+                    }
+                    Exit::No | Exit::Now => {
+                        ui.close_current_popup();
+                        return;
+                    }
+                }
+                ui.text(format!("{path} is still rendering."));
+                if let Some(progress) = self.final_render.progress() {
+                    ui.text(progress);
+                }
+                if ui.button("Save what's done and quit") {
+                    self.final_render.stop(true);
+                    self.exit = Exit::Saving;
+                }
+                ui.same_line();
+                if ui.button("Discard and quit") {
+                    self.final_render.stop(false);
+                    self.exit = Exit::Now;
+                }
+                ui.same_line();
+                if ui.button("Keep rendering") {
+                    self.exit = Exit::No;
+                }
+            });
+    }
+
+    /// Save settings for the next session.
+    fn on_exit(&self) {
+        let policy = self.viewports[self.active_viewport].renderer().thread_policy();
+        let settings = Settings {
+            num_threads: match policy {
+                ThreadPolicy::Fixed(num_threads) => Some(num_threads),
+                _ => None,
             },
+            last_scene: self.scene_file.as_ref().map(|file| file.path().to_owned()),
+            frame_limit: self.frame_limit,
+            show_sample_count: self.presentation.show_sample_count,
+            render_native_dpi: self.render_native_dpi,
         };
-        self.timer.stage_end("update texture");
+        if let Err(err) = settings::save(Settings::FILE_NAME, &settings) {
+            eprintln!("Couldn't save settings: {err:#}");
+        }
+    }
+
+    /// Hand the scene to the viewports and start accumulating again in each
+    /// of them, and check it for problems, after it's been edited or
+    /// replaced.
+    fn on_scene_changed(&mut self) {
+        self.shared_scene.publish(&self.scene);
+        for viewport in &mut self.viewports {
+            viewport.renderer().reset_accumulation();
+        }
+        self.diagnostics = self.scene.validate();
+    }
 
-        self.viewport_id = Some(textures.insert(texture));
-        self.image_size = self.viewport_size;
+    /// Like [`App::on_scene_changed`], for a whole new scene, whose
+    /// materials all need new previews.
+    fn on_scene_replaced(&mut self) {
+        self.watch_scene();
+        self.material_previews.invalidate_all();
+        self.on_scene_changed();
+    }
+
+    /// Have edits to the scene that show in renders call
+    /// [`App::on_scene_changed`] at the start of the next frame. A scene
+    /// loaded in place of this one needs watching again.
+    fn watch_scene(&mut self) {
+        let edited = self.scene_edited.clone();
+        self.scene.on_change(move |change| {
+            if change.is_visible() {
+                edited.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+/// Where the Scene menu saves and imports material libraries.
+const MATERIALS_FILE: &str = "scene.materials.ron";
+
+/// Material libraries are RON like scenes, so they're told apart by name.
+fn is_material_library(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.to_lowercase().ends_with(".materials.ron"))
+}
+
+fn file_extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+}
 
-        Ok(())
+/// Read a scene saved as RON, or written in the `.halide` text format.
+fn read_scene(path: &Path) -> Result<Scene> {
+    if file_extension(path).as_deref() == Some("halide") {
+        Scene::from_dsl(&std::fs::read_to_string(path)?)
+    } else {
+        Scene::load(path)
     }
 }
@@ -0,0 +1,205 @@
+use crate::viewport::upload;
+use glium::backend::Facade;
+use halide_raytracer::{presets, Material, Renderer, Snapshot, ThreadPolicy};
+use imgui::{DragDropFlags, TextureId, Textures};
+use imgui_glium_renderer::Texture;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+/// The width and height of each preview, in pixels.
+const SIZE: u32 = 64;
+const SAMPLES: usize = 32;
+
+/// What dragged previews are called to drop targets. The payload is the
+/// material's index.
+const DRAG_DROP_NAME: &str = "material";
+
+/// Small pictures of each of the scene's materials on a sphere, for the
+/// Materials panel. They're rendered one at a time on a background thread
+/// with a renderer of its own, so they don't slow down the viewports.
+pub(crate) struct MaterialPreviews {
+    slots: Vec<Slot>,
+    requests: Sender<Request>,
+    results: Receiver<Rendered>,
+}
+
+#[derive(Default)]
+struct Slot {
+    texture_id: Option<TextureId>,
+    /// Bumped each time the material changes.
+    version: u64,
+    /// The version last sent to be rendered.
+    requested: Option<u64>,
+}
+
+struct Request {
+    idx: usize,
+    version: u64,
+    material: Material,
+}
+
+struct Rendered {
+    idx: usize,
+    version: u64,
+    snapshot: Snapshot,
+}
+
+impl Default for MaterialPreviews {
+    fn default() -> Self {
+        let (requests, requests_rx) = mpsc::channel();
+        let (results_tx, results) = mpsc::channel();
+        // the thread finishes once it's dropped the last sender
+        std::thread::spawn(move || render_previews(requests_rx, results_tx));
+        Self {
+            slots: Vec::new(),
+            requests,
+            results,
+        }
+    }
+}
+
+impl MaterialPreviews {
+    /// Show any previews that have finished, and ask for new ones for
+    /// materials that don't have one yet or have changed.
+    pub fn update<F: Facade>(
+        &mut self,
+        materials: &[Material],
+        textures: &mut Textures<Texture>,
+        gl_ctx: &F,
+    ) {
+        for slot in self.slots.drain(materials.len().min(self.slots.len())..) {
+            if let Some(texture_id) = slot.texture_id {
+                textures.remove(texture_id);
+            }
+        }
+        self.slots.resize_with(materials.len(), Slot::default);
+
+        for rendered in self.results.try_iter() {
+            let Some(slot) = self.slots.get_mut(rendered.idx) else {
+                continue;
+            };
+            // an older picture of the material is still better than none
+            if rendered.version != slot.version && slot.texture_id.is_some() {
+                continue;
+            }
+            let snapshot = rendered.snapshot;
+            let image = Cow::Owned(snapshot.image);
+            let Ok(texture) = upload(gl_ctx, image, snapshot.width, snapshot.height) else {
+                continue;
+            };
+            if let Some(old) = slot.texture_id.replace(textures.insert(texture)) {
+                textures.remove(old);
+            }
+        }
+
+        for (idx, (slot, material)) in self.slots.iter_mut().zip(materials).enumerate() {
+            if matches!(material, Material::Null) || slot.requested == Some(slot.version) {
+                continue;
+            }
+            let request = Request {
+                idx,
+                version: slot.version,
+                material: material.clone(),
+            };
+            if self.requests.send(request).is_ok() {
+                slot.requested = Some(slot.version);
+            }
+        }
+    }
+
+    /// Render the material at `idx` again, after it's been edited.
+    pub fn invalidate(&mut self, idx: usize) {
+        if let Some(slot) = self.slots.get_mut(idx) {
+            slot.version += 1;
+        }
+    }
+
+    /// Render every material again, such as after loading a new scene.
+    pub fn invalidate_all(&mut self) {
+        for slot in &mut self.slots {
+            slot.version += 1;
+        }
+    }
+
+    /// Draw the preview of the material at `idx`, if there is one yet,
+    /// followed by `same_line`. It can be dragged onto anything that takes
+    /// [`dropped_material`].
+    pub fn build(&self, ui: &imgui::Ui, idx: usize) {
+        let Some(texture_id) = self.slots.get(idx).and_then(|slot| slot.texture_id) else {
+            return;
+        };
+        image(ui, texture_id, SIZE as f32);
+        // images don't have ids of their own to drag from
+        if let Some(_tooltip) = ui
+            .drag_drop_source_config(DRAG_DROP_NAME)
+            .flags(DragDropFlags::SOURCE_ALLOW_NULL_ID)
+            .begin_payload(idx)
+        {
+            image(ui, texture_id, SIZE as f32 / 2.);
+            ui.same_line();
+            ui.text(format!("Mat #{idx}"));
+        }
+        ui.same_line();
+    }
+}
+
+/// The index of a material whose preview was just dropped on the last
+/// item drawn, if one was.
+pub(crate) fn dropped_material(ui: &imgui::Ui) -> Option<usize> {
+    let target = ui.drag_drop_target()?;
+    let payload = target.accept_payload::<usize, _>(DRAG_DROP_NAME, DragDropFlags::empty())?;
+    payload.ok().map(|payload| payload.data)
+}
+
+fn image(ui: &imgui::Ui, texture_id: TextureId, size: f32) {
+    imgui::Image::new(texture_id, [size, size])
+        // flip Y-coordinate
+        .uv0([0., 1.])
+        .uv1([1., 0.])
+        .build(ui);
+}
+
+/// Render previews as they're asked for, until the UI goes away. Requests
+/// are taken in order of material, and a newer request for a material
+/// replaces one that hasn't started yet, so dragging a color slider
+/// doesn't pile up work.
+fn render_previews(requests: Receiver<Request>, results: Sender<Rendered>) {
+    let Ok(mut renderer) = Renderer::try_new(SIZE, SIZE) else {
+        return;
+    };
+    renderer.set_thread_policy(ThreadPolicy::Fixed(1));
+    renderer.set_low_priority(true);
+    let mut camera = presets::material_preview_camera();
+    camera.set_size(SIZE, SIZE);
+
+    let mut queue = BTreeMap::new();
+    loop {
+        if queue.is_empty() {
+            let Ok(request) = requests.recv() else {
+                return;
+            };
+            queue.insert(request.idx, request);
+        }
+        for request in requests.try_iter() {
+            queue.insert(request.idx, request);
+        }
+        let Some((_, request)) = queue.pop_first() else {
+            continue;
+        };
+
+        let scene = presets::material_preview(request.material);
+        renderer.reset_accumulation();
+        renderer.render_accumulate(&scene, &camera, SAMPLES);
+        let rendered = Rendered {
+            idx: request.idx,
+            version: request.version,
+            snapshot: renderer.snapshot(),
+        };
+        if results.send(rendered).is_err() {
+            return;
+        }
+    }
+}
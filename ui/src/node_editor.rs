@@ -0,0 +1,366 @@
+use glam::{Vec2, Vec3};
+use halide_raytracer::{
+    GraphNode, Input, Material, MaterialGraph, MathOp, NodeKind, Scene, Texture,
+};
+use imgui::{Condition, DrawListMut, MouseButton};
+
+const NODE_WIDTH: f32 = 170.;
+const ROW_HEIGHT: f32 = 24.;
+const SOCKET_RADIUS: f32 = 5.;
+
+/// Inputs that are numbers rather than colors, edited with a slider.
+const NUMBER_INPUTS: [&str; 2] = ["Factor", "Fuzz"];
+
+/// The "Material graph" window, for editing the nodes of a
+/// [`Material::Graph`]. Nodes are moved by their titles, and linked by
+/// dragging from the output on the right of one to an input on the left of
+/// another.
+#[derive(Default)]
+pub(crate) struct NodeEditor {
+    /// The material being edited, by its index in [`Scene::materials`].
+    material: Option<usize>,
+    /// How far the canvas has been panned, with the middle mouse button.
+    scroll: Vec2,
+    /// The node whose output is being dragged to an input.
+    linking: Option<usize>,
+    /// Where on the canvas to put a node added from the context menu.
+    new_node_position: Vec2,
+}
+
+impl NodeEditor {
+    pub fn open(&mut self, material: usize) {
+        self.material = Some(material);
+        self.linking = None;
+    }
+
+    /// Draw the window, if it's open. Returns the index of the material
+    /// if its graph changed.
+    pub fn build(&mut self, ui: &imgui::Ui, scene: &mut Scene) -> Option<usize> {
+        let idx = self.material?;
+        let mut open = true;
+        let mut changed = false;
+        ui.window("Material graph")
+            .size([720., 420.], Condition::FirstUseEver)
+            .scrollable(false)
+            .opened(&mut open)
+            .build(|| {
+                // edit a copy, so the scene only hears about it when something changes
+                let Some(Material::Graph { graph }) = scene.materials().get(idx) else {
+                    ui.text("The material isn't a graph anymore");
+                    return;
+                };
+                let mut graph = graph.clone();
+                ui.text(format!("Mat #{idx}"));
+                ui.same_line();
+                ui.text_disabled(
+                    "Right click to add nodes, and drag with the middle mouse button to pan",
+                );
+                changed = self.canvas(ui, &mut graph);
+                if changed {
+                    scene.materials_mut()[idx] = Material::Graph { graph };
+                }
+            });
+        if !open {
+            self.material = None;
+        }
+        changed.then_some(idx)
+    }
+
+    fn canvas(&mut self, ui: &imgui::Ui, graph: &mut MaterialGraph) -> bool {
+        let origin = Vec2::from(ui.cursor_screen_pos()) + self.scroll;
+        let draw_list = ui.get_window_draw_list();
+        let mouse = Vec2::from(ui.io().mouse_pos);
+        let mut changed = false;
+
+        if ui.is_window_hovered() && ui.is_mouse_dragging(MouseButton::Middle) {
+            self.scroll += Vec2::from(ui.io().mouse_delta);
+        }
+
+        // links go under the nodes
+        let link_color = [0.8, 0.8, 0.8, 1.0];
+        for node in &graph.nodes {
+            for (row, (_, input)) in node.kind.inputs().into_iter().enumerate() {
+                if let Some(from) = input.link.and_then(|from| graph.nodes.get(from)) {
+                    let start = output_socket(origin, from);
+                    draw_link(
+                        &draw_list,
+                        start,
+                        input_socket(origin, node, row),
+                        link_color,
+                    );
+                }
+            }
+        }
+        if let Some(from) = self.linking.and_then(|from| graph.nodes.get(from)) {
+            draw_link(&draw_list, output_socket(origin, from), mouse, link_color);
+        }
+
+        // the input under the mouse, as the node and which of its inputs
+        let mut hovered_input = None;
+        let mut removed = None;
+        for idx in 0..graph.nodes.len() {
+            let _id = ui.push_id_usize(idx);
+            let is_output = graph.output == idx;
+            let node = &mut graph.nodes[idx];
+            let min = origin + node.position;
+            let max = min + Vec2::new(NODE_WIDTH, node_height(&node.kind));
+            let title_color = if node.kind.is_bsdf() {
+                [0.25, 0.45, 0.3, 1.0]
+            } else {
+                [0.3, 0.3, 0.5, 1.0]
+            };
+            draw_list
+                .add_rect(min.to_array(), max.to_array(), [0.16, 0.16, 0.18, 0.95])
+                .filled(true)
+                .rounding(4.)
+                .build();
+            draw_list
+                .add_rect(min.to_array(), [max.x, min.y + ROW_HEIGHT], title_color)
+                .filled(true)
+                .rounding(4.)
+                .build();
+            if is_output {
+                draw_list
+                    .add_rect(min.to_array(), max.to_array(), [1.0, 0.8, 0.2, 1.0])
+                    .rounding(4.)
+                    .thickness(2.)
+                    .build();
+            }
+            let title = if is_output {
+                format!("{} (output)", node.kind.name())
+            } else {
+                node.kind.name().to_string()
+            };
+            draw_list.add_text([min.x + 8., min.y + 4.], [1.0, 1.0, 1.0, 1.0], title);
+
+            // the title moves the node, and has the node's menu
+            ui.set_cursor_screen_pos(min.to_array());
+            ui.invisible_button("title", [NODE_WIDTH - 2. * SOCKET_RADIUS, ROW_HEIGHT]);
+            if ui.is_item_active() && ui.is_mouse_dragging(MouseButton::Left) {
+                node.position += Vec2::from(ui.io().mouse_delta);
+            }
+            if ui.is_item_clicked_with_button(MouseButton::Right) {
+                ui.open_popup("node");
+            }
+            ui.popup("node", || {
+                if node.kind.is_bsdf() && ui.menu_item("Use as output") {
+                    graph.output = idx;
+                    changed = true;
+                }
+                if ui.menu_item("Delete") {
+                    removed = Some(idx);
+                }
+            });
+            let node = &mut graph.nodes[idx];
+
+            // the output, to drag links from
+            let socket = output_socket(origin, node);
+            draw_list
+                .add_circle(socket.to_array(), SOCKET_RADIUS, [0.9, 0.8, 0.3, 1.0])
+                .filled(true)
+                .build();
+            ui.set_cursor_screen_pos((socket - Vec2::splat(SOCKET_RADIUS)).to_array());
+            ui.invisible_button("output", [2. * SOCKET_RADIUS; 2]);
+            if ui.is_item_active() && self.linking.is_none() {
+                self.linking = Some(idx);
+            }
+
+            changed |= node_body(ui, &mut node.kind, [min.x + 8., min.y + ROW_HEIGHT]);
+
+            // BSDF inputs only make sense linked, so they have no values
+            let takes_bsdfs = matches!(node.kind, NodeKind::MixBsdf { .. });
+            let sockets: Vec<_> = (0..node.kind.inputs().len())
+                .map(|row| input_socket(origin, node, row))
+                .collect();
+            for (row, (name, input)) in node.kind.inputs_mut().into_iter().enumerate() {
+                let _id = ui.push_id_usize(row);
+                let socket = sockets[row];
+                let color = if input.link.is_some() {
+                    [0.9, 0.8, 0.3, 1.0]
+                } else {
+                    [0.5, 0.5, 0.5, 1.0]
+                };
+                draw_list
+                    .add_circle(socket.to_array(), SOCKET_RADIUS, color)
+                    .filled(true)
+                    .build();
+                let reach = Vec2::splat(SOCKET_RADIUS * 2.);
+                if ui.is_mouse_hovering_rect(
+                    (socket - reach).to_array(),
+                    (socket + reach).to_array(),
+                ) {
+                    hovered_input = Some((idx, row));
+                    if ui.is_mouse_clicked(MouseButton::Right) && input.link.take().is_some() {
+                        changed = true;
+                    }
+                }
+
+                let left = min.x + 2. * SOCKET_RADIUS + 4.;
+                ui.set_cursor_screen_pos([left, socket.y - ROW_HEIGHT / 2. + 2.]);
+                ui.set_next_item_width(NODE_WIDTH - 90.);
+                if input.link.is_some() {
+                    ui.text(name);
+                } else if NUMBER_INPUTS.contains(&name) {
+                    let mut number = input.value.dot(Vec3::ONE) / 3.;
+                    if imgui::Drag::new(name)
+                        .range(0., 1.)
+                        .speed(0.01)
+                        .build(ui, &mut number)
+                    {
+                        input.value = Vec3::splat(number);
+                        changed = true;
+                    }
+                } else if takes_bsdfs {
+                    ui.text(name);
+                } else if ui
+                    .color_edit3_config(name, input.value.as_mut())
+                    .inputs(false)
+                    .build()
+                {
+                    changed = true;
+                }
+            }
+        }
+
+        if ui.is_mouse_released(MouseButton::Left) {
+            if let (Some(from), Some((to, row))) = (self.linking.take(), hovered_input) {
+                if from != to {
+                    if let Some((_, input)) = graph.nodes[to].kind.inputs_mut().into_iter().nth(row)
+                    {
+                        input.link = Some(from);
+                        changed = true;
+                    }
+                }
+            }
+            self.linking = None;
+        }
+
+        if let Some(idx) = removed {
+            graph.remove_node(idx);
+            changed = true;
+        }
+
+        if ui.is_window_hovered()
+            && !ui.is_any_item_hovered()
+            && hovered_input.is_none()
+            && ui.is_mouse_clicked(MouseButton::Right)
+        {
+            self.new_node_position = mouse - origin;
+            ui.open_popup("add node");
+        }
+        ui.popup("add node", || {
+            for kind in new_nodes() {
+                if ui.menu_item(kind.name()) {
+                    graph.nodes.push(GraphNode {
+                        kind,
+                        position: self.new_node_position,
+                    });
+                    changed = true;
+                }
+            }
+        });
+
+        changed
+    }
+}
+
+/// A fresh node of each kind, for the "add node" menu.
+fn new_nodes() -> Vec<NodeKind> {
+    let gray = Input::constant(Vec3::splat(0.8));
+    let half = Input::constant(Vec3::splat(0.5));
+    let unlinked = Input::constant(Vec3::ZERO);
+    vec![
+        NodeKind::Value(Vec3::splat(0.5)),
+        NodeKind::Texture(Texture::Solid(Vec3::splat(0.5))),
+        NodeKind::Position,
+        NodeKind::Normal,
+        NodeKind::Uv,
+        NodeKind::Math {
+            op: MathOp::Multiply,
+            a: gray,
+            b: gray,
+        },
+        NodeKind::MixColor {
+            a: gray,
+            b: gray,
+            factor: half,
+        },
+        NodeKind::Diffuse { color: gray },
+        NodeKind::Metal {
+            color: gray,
+            fuzz: Input::constant(Vec3::ZERO),
+        },
+        NodeKind::Emission {
+            color: Input::constant(Vec3::ONE),
+        },
+        NodeKind::MixBsdf {
+            a: unlinked,
+            b: unlinked,
+            factor: half,
+        },
+    ]
+}
+
+/// The settings of a node that aren't inputs, drawn at `pos`.
+fn node_body(ui: &imgui::Ui, kind: &mut NodeKind, pos: [f32; 2]) -> bool {
+    ui.set_cursor_screen_pos([pos[0], pos[1] + 2.]);
+    ui.set_next_item_width(NODE_WIDTH - 16.);
+    match kind {
+        NodeKind::Value(value) => ui.color_edit3("##value", value.as_mut()),
+        NodeKind::Texture(Texture::Solid(color)) => ui.color_edit3("##color", color.as_mut()),
+        NodeKind::Texture(Texture::Noise(_)) => {
+            ui.text("Noise");
+            false
+        }
+        NodeKind::Texture(Texture::Procedural(procedural)) => {
+            ui.text(procedural.name());
+            false
+        }
+        NodeKind::Math { op, .. } => {
+            let names = MathOp::ALL.map(|op| op.name());
+            let mut op_idx = MathOp::ALL
+                .iter()
+                .position(|other| other == op)
+                .unwrap_or(0);
+            let changed = ui.combo_simple_string("##op", &mut op_idx, &names);
+            *op = MathOp::ALL[op_idx];
+            changed
+        }
+        _ => false,
+    }
+}
+
+/// How many rows [`node_body`] takes up.
+fn body_rows(kind: &NodeKind) -> usize {
+    match kind {
+        NodeKind::Value(_) | NodeKind::Texture(_) | NodeKind::Math { .. } => 1,
+        _ => 0,
+    }
+}
+
+fn node_height(kind: &NodeKind) -> f32 {
+    (1 + body_rows(kind) + kind.inputs().len()) as f32 * ROW_HEIGHT + 4.
+}
+
+fn output_socket(origin: Vec2, node: &GraphNode) -> Vec2 {
+    origin + node.position + Vec2::new(NODE_WIDTH, ROW_HEIGHT / 2.)
+}
+
+fn input_socket(origin: Vec2, node: &GraphNode, row: usize) -> Vec2 {
+    let rows = 1 + body_rows(&node.kind) + row;
+    origin + node.position + Vec2::new(0., (rows as f32 + 0.5) * ROW_HEIGHT)
+}
+
+fn draw_link(draw_list: &DrawListMut<'_>, start: Vec2, end: Vec2, color: [f32; 4]) {
+    let bend = Vec2::new(((end.x - start.x).abs() / 2.).max(30.), 0.);
+    draw_list
+        .add_bezier_curve(
+            start.to_array(),
+            (start + bend).to_array(),
+            (end - bend).to_array(),
+            end.to_array(),
+            color,
+        )
+        .thickness(2.)
+        .build();
+}
@@ -0,0 +1,74 @@
+use glium::{
+    backend::Facade,
+    buffer::{Buffer, BufferMode, BufferType},
+    Rect, Texture2d,
+};
+
+type Pixel = (u8, u8, u8, u8);
+
+/// Pixel buffer objects to upload a viewport's frames through. Copying a
+/// frame into one is quick, and the driver moves it on to the texture in
+/// the background, where writing to the texture directly would hold up the
+/// UI thread until the copy is done. There are two, used in turn, so
+/// filling one doesn't wait for the GPU to finish reading the other.
+pub(crate) struct PixelBuffers {
+    buffers: [Buffer<[Pixel]>; 2],
+    /// The buffer last filled, to upload from.
+    current: usize,
+}
+
+impl PixelBuffers {
+    /// Buffers with room for `len` pixels, persistently mapped where the
+    /// driver allows it. `None` if it doesn't have pixel buffers at all.
+    pub fn new<F: Facade>(gl_ctx: &F, len: usize) -> Option<Self> {
+        let pair = |mode| {
+            let buffer = || Buffer::empty_array(gl_ctx, BufferType::PixelUnpackBuffer, len, mode);
+            Some([buffer().ok()?, buffer().ok()?])
+        };
+        let buffers = pair(BufferMode::Persistent).or_else(|| pair(BufferMode::Dynamic))?;
+        Some(Self {
+            buffers,
+            current: 0,
+        })
+    }
+
+    /// Copy the `tiles` of `image`, which is packed RGBA pixels `width`
+    /// wide, into the next buffer, one after another, ready to
+    /// [upload](PixelBuffers::upload).
+    pub fn fill(&mut self, image: &[u32], width: u32, tiles: &[Rect]) {
+        self.current = (self.current + 1) % self.buffers.len();
+        let mut mapping = self.buffers[self.current].map_write();
+        let mut idx = 0;
+        for tile in tiles {
+            for row in tile.bottom..tile.bottom + tile.height {
+                let start = row as usize * width as usize + tile.left as usize;
+                for pixel in &image[start..start + tile.width as usize] {
+                    // the bytes as they are in memory, as the texture reads them
+                    let [r, g, b, a] = pixel.to_ne_bytes();
+                    mapping.set(idx, (r, g, b, a));
+                    idx += 1;
+                }
+            }
+        }
+    }
+
+    /// Start copying the tiles last [filled](PixelBuffers::fill) to
+    /// `texture`. This returns without waiting for the copy.
+    pub fn upload(&self, texture: &Texture2d, tiles: &[Rect]) {
+        let buffer = &self.buffers[self.current];
+        let mut start = 0;
+        for tile in tiles {
+            let len = tile.width as usize * tile.height as usize;
+            let Some(pixels) = buffer.slice(start..start + len) else {
+                return;
+            };
+            texture.main_level().raw_upload_from_pixel_buffer(
+                pixels,
+                tile.left..tile.left + tile.width,
+                tile.bottom..tile.bottom + tile.height,
+                0..1,
+            );
+            start += len;
+        }
+    }
+}
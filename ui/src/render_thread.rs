@@ -0,0 +1,181 @@
+use glium::Rect;
+use halide_raytracer::{Camera, RenderStats, Renderer, Scene};
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::Duration,
+};
+
+/// How long the render thread works on each frame, so large viewports show
+/// their progress every so often instead of only after a whole pass.
+const RENDER_BUDGET: Duration = Duration::from_millis(16);
+
+/// The width and height of the tiles frames are compared in, to find the
+/// parts of the image that need uploading again.
+const TILE_SIZE: u32 = 64;
+
+/// A viewport's renderer, run on a thread of its own so that slow frames
+/// don't hold up the UI. The UI asks for a frame with
+/// [`RenderThread::request`] and picks it up with [`RenderThread::poll`]
+/// once it's done, drawing the last one it got in the meantime.
+pub(crate) struct RenderThread {
+    renderer: Arc<Mutex<Renderer>>,
+    requests: Sender<Request>,
+    frames: Receiver<Frame>,
+    /// Whether a frame has been asked for and not picked up yet. Only one is
+    /// asked for at a time, so requests can't pile up behind a slow frame.
+    pending: bool,
+}
+
+struct Request {
+    scene: Arc<Scene>,
+    camera: Camera,
+}
+
+/// A finished frame, as packed RGBA pixels with rows stored bottom to top.
+pub(crate) struct Frame {
+    pub image: Vec<u32>,
+    pub width: u32,
+    pub height: u32,
+    pub stats: RenderStats,
+    /// For everything rendered since the renderer was made, including this
+    /// frame.
+    pub total_stats: RenderStats,
+    /// The tiles that differ from the frame before, or `None` if it was a
+    /// different size, or this is the first.
+    pub changed: Option<Vec<Rect>>,
+}
+
+impl RenderThread {
+    pub fn new(renderer: Renderer) -> Self {
+        let renderer = Arc::new(Mutex::new(renderer));
+        let (requests, requests_rx) = mpsc::channel();
+        let (frames_tx, frames) = mpsc::channel();
+        // the thread finishes once it's dropped the last sender
+        std::thread::spawn({
+            let renderer = renderer.clone();
+            move || render_frames(&renderer, requests_rx, frames_tx)
+        });
+        Self {
+            renderer,
+            requests,
+            frames,
+            pending: false,
+        }
+    }
+
+    /// The renderer, to change its settings or look at what it's rendered.
+    /// If a frame is in progress, this waits for it to finish.
+    pub fn renderer(&self) -> MutexGuard<'_, Renderer> {
+        self.renderer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Ask for a frame of `scene` seen from `camera`, at the camera's size,
+    /// unless one is already on its way.
+    pub fn request(&mut self, scene: &Arc<Scene>, camera: &Camera) {
+        if self.pending {
+            return;
+        }
+        let request = Request {
+            scene: scene.clone(),
+            camera: camera.clone(),
+        };
+        self.pending = self.requests.send(request).is_ok();
+    }
+
+    /// The frame last asked for, if it's finished.
+    pub fn poll(&mut self) -> Option<Frame> {
+        let frame = self.frames.try_recv().ok()?;
+        self.pending = false;
+        Some(frame)
+    }
+}
+
+/// Render frames as they're asked for, until the viewport goes away. The
+/// renderer is only locked while rendering, so the UI can change it
+/// between frames.
+fn render_frames(renderer: &Mutex<Renderer>, requests: Receiver<Request>, frames: Sender<Frame>) {
+    let mut previous: Option<([u32; 2], Vec<u32>)> = None;
+    for request in requests {
+        let [width, height] = request.camera.size();
+        let mut renderer = renderer.lock().unwrap_or_else(PoisonError::into_inner);
+        renderer.resize(width, height);
+        let (image, stats) = renderer.render_for(&request.scene, &request.camera, RENDER_BUDGET);
+        let image = image.into_owned();
+        let total_stats = renderer.total_stats().clone();
+        drop(renderer);
+
+        let changed = match &previous {
+            Some((size, previous)) if *size == [width, height] => {
+                Some(changed_tiles(previous, &image, width, height))
+            }
+            _ => None,
+        };
+        previous = Some(([width, height], image.clone()));
+        let frame = Frame {
+            image,
+            width,
+            height,
+            stats,
+            total_stats,
+            changed,
+        };
+        if frames.send(frame).is_err() {
+            return;
+        }
+    }
+}
+
+/// The tiles where `image` differs from `previous`, counting rows from the
+/// bottom like the images do. Both must be `width` by `height`.
+fn changed_tiles(previous: &[u32], image: &[u32], width: u32, height: u32) -> Vec<Rect> {
+    let mut changed = Vec::new();
+    for bottom in (0..height).step_by(TILE_SIZE as usize) {
+        let rows = bottom..(bottom + TILE_SIZE).min(height);
+        for left in (0..width).step_by(TILE_SIZE as usize) {
+            let right = (left + TILE_SIZE).min(width);
+            let differs = rows.clone().any(|row| {
+                let start = row as usize * width as usize;
+                let pixels = start + left as usize..start + right as usize;
+                previous[pixels.clone()] != image[pixels]
+            });
+            if differs {
+                changed.push(Rect {
+                    left,
+                    bottom,
+                    width: right - left,
+                    height: rows.end - bottom,
+                });
+            }
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_tiles, TILE_SIZE};
+    use glium::Rect;
+
+    #[test]
+    fn finds_changed_tiles() {
+        let (width, height) = (TILE_SIZE + 10, TILE_SIZE * 2);
+        let previous = vec![0; (width * height) as usize];
+        assert!(changed_tiles(&previous, &previous, width, height).is_empty());
+
+        let mut image = previous.clone();
+        // in the narrow column of tiles on the right, in the top row
+        image[((TILE_SIZE + 3) * width + TILE_SIZE + 2) as usize] = 1;
+        assert_eq!(
+            changed_tiles(&previous, &image, width, height),
+            [Rect {
+                left: TILE_SIZE,
+                bottom: TILE_SIZE,
+                width: 10,
+                height: TILE_SIZE,
+            }]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! The Rhai scripts the console runs, and the scene, camera and renderer
+//! they can work on.
+
+use crate::viewport::Viewport;
+use anyhow::{anyhow, Result};
+use glam::Vec3;
+use halide_raytracer::{
+    AmbientOcclusion, Camera, DebugNormals, Material, MaterialHandle, PathTracer, PhotonMapper,
+    Quad, Renderer, Scene, Sphere,
+};
+use rand::Rng;
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Scope, FLOAT, INT};
+use std::{cell::RefCell, rc::Rc};
+
+/// How much work a script can do before it's stopped, since scripts run on
+/// the UI thread and an endless loop would hang it.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// The integrators scripts can pick with `renderer.use_integrator`.
+const INTEGRATORS: [&str; 4] = ["path", "photons", "ao", "normals"];
+
+type Shared<T> = Rc<RefCell<T>>;
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// `scene` in scripts.
+#[derive(Clone)]
+struct ScriptScene(Shared<Scene>);
+
+/// `camera` in scripts, the active viewport's.
+#[derive(Clone)]
+struct ScriptCamera(Shared<Camera>);
+
+/// `renderer` in scripts, the active viewport's.
+#[derive(Clone)]
+struct ScriptRenderer(Shared<RendererSettings>);
+
+/// The renderer settings scripts can see and change. Scripts work on a
+/// copy, applied once they finish, so the renderer isn't locked while they
+/// run.
+#[derive(Clone, Default)]
+struct RendererSettings {
+    transparent_background: bool,
+    cache_first_bounce: bool,
+    interleave: u32,
+    /// How many frames were accumulated when the script started.
+    frames: f32,
+    /// One of [`INTEGRATORS`], to switch to.
+    integrator: Option<&'static str>,
+    reset: bool,
+}
+
+impl RendererSettings {
+    fn read(renderer: &Renderer) -> Self {
+        Self {
+            transparent_background: renderer.transparent_background,
+            cache_first_bounce: renderer.cache_first_bounce,
+            interleave: renderer.interleave(),
+            frames: renderer.frame_count(),
+            integrator: None,
+            reset: false,
+        }
+    }
+
+    fn apply(&self, renderer: &mut Renderer) {
+        let reset = self.reset || renderer.transparent_background != self.transparent_background;
+        renderer.transparent_background = self.transparent_background;
+        renderer.cache_first_bounce = self.cache_first_bounce;
+        renderer.set_interleave(self.interleave);
+        match self.integrator {
+            Some("path") => renderer.set_integrator(PathTracer::default()),
+            Some("photons") => renderer.set_integrator(PhotonMapper::default()),
+            Some("ao") => renderer.set_integrator(AmbientOcclusion::default()),
+            Some("normals") => renderer.set_integrator(DebugNormals),
+            _ => {}
+        }
+        if reset {
+            renderer.reset_accumulation();
+        }
+    }
+}
+
+/// Runs the scripts typed into the console. Variables a script sets are
+/// kept for the ones run after it.
+pub(crate) struct Script {
+    engine: Engine,
+    scope: Scope<'static>,
+    scene: Shared<Scene>,
+    camera: Shared<Camera>,
+    renderer: Shared<RendererSettings>,
+    /// What the running script has printed.
+    printed: Shared<Vec<String>>,
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        let scene = Shared::default();
+        let camera = Shared::default();
+        let renderer = Shared::default();
+        let printed: Shared<Vec<String>> = Shared::default();
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print({
+            let printed = printed.clone();
+            move |text| printed.borrow_mut().push(text.to_string())
+        });
+        engine.register_fn("rand", |min: Dynamic, max: Dynamic| -> ScriptResult<FLOAT> {
+            let (min, max) = (number(&min)?, number(&max)?);
+            if min >= max || !min.is_finite() || !max.is_finite() {
+                return Err(format!("rand needs a min below its max, got {min} and {max}").into());
+            }
+            Ok(rand::thread_rng().gen_range(min..max).into())
+        });
+        register_vec3(&mut engine);
+        register_scene(&mut engine);
+        register_camera(&mut engine);
+        register_renderer(&mut engine);
+
+        let mut scope = Scope::new();
+        scope
+            .push_constant("scene", ScriptScene(Rc::clone(&scene)))
+            .push_constant("camera", ScriptCamera(Rc::clone(&camera)))
+            .push_constant("renderer", ScriptRenderer(Rc::clone(&renderer)));
+        Self {
+            engine,
+            scope,
+            scene,
+            camera,
+            renderer,
+            printed,
+        }
+    }
+}
+
+impl Script {
+    /// Run `source` on `scene` and `viewport`'s camera and renderer,
+    /// returning what it printed, and then the value it ended with, unless
+    /// there wasn't one.
+    pub fn run(
+        &mut self,
+        source: &str,
+        scene: &mut Scene,
+        viewport: &mut Viewport,
+    ) -> (Vec<String>, Result<Option<String>>) {
+        // lent to the script while it runs, and taken back after
+        std::mem::swap(scene, &mut *self.scene.borrow_mut());
+        *self.camera.borrow_mut() = viewport.camera.clone();
+        *self.renderer.borrow_mut() = RendererSettings::read(&viewport.renderer());
+        let result = self.engine.eval_with_scope::<Dynamic>(&mut self.scope, source);
+        std::mem::swap(scene, &mut *self.scene.borrow_mut());
+        let before = std::mem::replace(&mut viewport.camera, self.camera.borrow().clone());
+        let mut renderer = viewport.renderer();
+        renderer.reproject(&before, &viewport.camera);
+        self.renderer.borrow().apply(&mut renderer);
+
+        let printed = std::mem::take(&mut *self.printed.borrow_mut());
+        let result = result.map(describe).map_err(|err| anyhow!("{err}"));
+        (printed, result)
+    }
+}
+
+/// How a script's final value is shown in the console.
+fn describe(value: Dynamic) -> Option<String> {
+    if value.is_unit() {
+        None
+    } else if value.is::<Vec3>() {
+        Some(value.cast::<Vec3>().to_string())
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Scripts can use whole numbers anywhere they can use decimals.
+fn number(value: &Dynamic) -> ScriptResult<f32> {
+    if let Ok(int) = value.as_int() {
+        return Ok(int as f32);
+    }
+    value
+        .as_float()
+        .map(|float| float as f32)
+        .map_err(|found| format!("expected a number, found {found}").into())
+}
+
+fn register_vec3(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<Vec3>("vec3")
+        .register_fn("vec3", |x: Dynamic, y: Dynamic, z: Dynamic| -> ScriptResult<Vec3> {
+            Ok(Vec3::new(number(&x)?, number(&y)?, number(&z)?))
+        })
+        .register_get("x", |vec: &mut Vec3| FLOAT::from(vec.x))
+        .register_get("y", |vec: &mut Vec3| FLOAT::from(vec.y))
+        .register_get("z", |vec: &mut Vec3| FLOAT::from(vec.z))
+        .register_fn("+", |a: Vec3, b: Vec3| a + b)
+        .register_fn("-", |a: Vec3, b: Vec3| a - b)
+        .register_fn("-", |vec: Vec3| -vec)
+        .register_fn("*", |vec: Vec3, scale: FLOAT| vec * scale as f32)
+        .register_fn("*", |vec: Vec3, scale: INT| vec * scale as f32)
+        .register_fn("*", |scale: FLOAT, vec: Vec3| vec * scale as f32)
+        .register_fn("*", |scale: INT, vec: Vec3| vec * scale as f32)
+        .register_fn("/", |vec: Vec3, scale: FLOAT| vec / scale as f32)
+        .register_fn("/", |vec: Vec3, scale: INT| vec / scale as f32)
+        .register_fn("length", |vec: &mut Vec3| FLOAT::from(vec.length()))
+        .register_fn("normalize", |vec: &mut Vec3| vec.normalize_or_zero())
+        .register_fn("to_string", |vec: &mut Vec3| vec.to_string())
+        .register_fn("to_debug", |vec: &mut Vec3| vec.to_string());
+}
+
+fn register_scene(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptScene>("Scene")
+        .register_fn("lambertian", |scene: &mut ScriptScene, albedo: Vec3| {
+            let material = Material::Lambertian { albedo };
+            scene.0.borrow_mut().add_material(material).index() as INT
+        })
+        .register_fn("emissive", |scene: &mut ScriptScene, emission: Vec3| {
+            let material = Material::Emissive { emission };
+            scene.0.borrow_mut().add_material(material).index() as INT
+        })
+        .register_fn(
+            "sphere",
+            |scene: &mut ScriptScene,
+             center: Vec3,
+             radius: Dynamic,
+             material: INT|
+             -> ScriptResult<INT> {
+                let mut scene = scene.0.borrow_mut();
+                let sphere = Sphere {
+                    center,
+                    radius: number(&radius)?,
+                    material: material_handle(&scene, material)?,
+                };
+                Ok(scene.add_hittable(sphere) as INT)
+            },
+        )
+        .register_fn(
+            "quad",
+            |scene: &mut ScriptScene,
+             corner: Vec3,
+             u: Vec3,
+             v: Vec3,
+             material: INT|
+             -> ScriptResult<INT> {
+                let mut scene = scene.0.borrow_mut();
+                let material = material_handle(&scene, material)?;
+                Ok(scene.add_hittable(Quad { corner, u, v, material }) as INT)
+            },
+        )
+        .register_get("objects", |scene: &mut ScriptScene| {
+            scene.0.borrow().hittables().len() as INT
+        })
+        .register_get("materials", |scene: &mut ScriptScene| {
+            scene.0.borrow().materials().len() as INT
+        })
+        .register_fn(
+            "run",
+            |scene: &mut ScriptScene, statements: ImmutableString| -> ScriptResult<()> {
+                scene
+                    .0
+                    .borrow_mut()
+                    .run_dsl(statements.as_str())
+                    .map_err(|err| format!("{err:#}").into())
+            },
+        );
+}
+
+fn material_handle(scene: &Scene, material: INT) -> ScriptResult<MaterialHandle> {
+    usize::try_from(material)
+        .ok()
+        .and_then(|idx| scene.material_handle(idx))
+        .ok_or_else(|| format!("there's no material {material}").into())
+}
+
+fn register_camera(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptCamera>("Camera")
+        .register_get("position", |camera: &mut ScriptCamera| {
+            camera.0.borrow().position()
+        })
+        .register_set("position", |camera: &mut ScriptCamera, position: Vec3| {
+            camera.0.borrow_mut().set_position(position)
+        })
+        .register_get("look_direction", |camera: &mut ScriptCamera| {
+            camera.0.borrow().look_direction()
+        })
+        .register_fn(
+            "look_at",
+            |camera: &mut ScriptCamera, target: Vec3| -> ScriptResult<()> {
+                let mut camera = camera.0.borrow_mut();
+                let direction = (target - camera.position())
+                    .try_normalize()
+                    .ok_or_else(|| format!("the camera is already at {target}"))?;
+                camera.set_look_direction(direction);
+                Ok(())
+            },
+        )
+        .register_get("fov", |camera: &mut ScriptCamera| {
+            FLOAT::from(camera.0.borrow().vertical_fov())
+        })
+        .register_set("fov", |camera: &mut ScriptCamera, degrees: FLOAT| {
+            camera.0.borrow_mut().set_vertical_fov(degrees as f32)
+        })
+        .register_set("fov", |camera: &mut ScriptCamera, degrees: INT| {
+            camera.0.borrow_mut().set_vertical_fov(degrees as f32)
+        });
+}
+
+fn register_renderer(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<ScriptRenderer>("Renderer")
+        .register_get("transparent_background", |renderer: &mut ScriptRenderer| {
+            renderer.0.borrow().transparent_background
+        })
+        .register_set(
+            "transparent_background",
+            |renderer: &mut ScriptRenderer, transparent: bool| {
+                renderer.0.borrow_mut().transparent_background = transparent
+            },
+        )
+        .register_get("cache_first_bounce", |renderer: &mut ScriptRenderer| {
+            renderer.0.borrow().cache_first_bounce
+        })
+        .register_set(
+            "cache_first_bounce",
+            |renderer: &mut ScriptRenderer, cache: bool| {
+                renderer.0.borrow_mut().cache_first_bounce = cache
+            },
+        )
+        .register_get("interleave", |renderer: &mut ScriptRenderer| {
+            INT::from(renderer.0.borrow().interleave)
+        })
+        .register_set("interleave", |renderer: &mut ScriptRenderer, interleave: INT| {
+            renderer.0.borrow_mut().interleave = interleave.clamp(1, INT::from(u32::MAX)) as u32
+        })
+        .register_get("frames", |renderer: &mut ScriptRenderer| {
+            FLOAT::from(renderer.0.borrow().frames)
+        })
+        .register_fn(
+            "use_integrator",
+            |renderer: &mut ScriptRenderer, name: ImmutableString| -> ScriptResult<()> {
+                let integrator = INTEGRATORS
+                    .into_iter()
+                    .find(|integrator| *integrator == name.as_str())
+                    .ok_or_else(|| {
+                        format!(
+                            "there's no integrator {:?}, only {}",
+                            name.as_str(),
+                            INTEGRATORS.join(", ")
+                        )
+                    })?;
+                renderer.0.borrow_mut().integrator = Some(integrator);
+                Ok(())
+            },
+        )
+        .register_fn("reset", |renderer: &mut ScriptRenderer| {
+            renderer.0.borrow_mut().reset = true
+        });
+}
@@ -0,0 +1,93 @@
+use crate::system::FrameLimit;
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Where config files are kept, such as `~/.config/halide` on Linux.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("halide"))
+}
+
+/// The path to a file in the config directory.
+pub(crate) fn config_path(name: &str) -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join(name))
+}
+
+/// Read a RON config file. Missing files give `None`.
+pub(crate) fn load<T: DeserializeOwned>(name: &str) -> Result<Option<T>> {
+    let Some(path) = config_path(name) else {
+        return Ok(None);
+    };
+    if !path.exists() {
+        return Ok(None);
+    }
+    let source = std::fs::read_to_string(&path)?;
+    let value = ron::from_str(&source).with_context(|| format!("Parsing {}", path.display()))?;
+    Ok(Some(value))
+}
+
+/// Write a RON config file, creating the config directory if needed.
+pub(crate) fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    let dir = config_dir().context("No config directory on this platform")?;
+    std::fs::create_dir_all(&dir)?;
+    let source = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+    std::fs::write(dir.join(name), source)?;
+    Ok(())
+}
+
+/// App settings that are restored on the next launch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    /// Render threads for new viewports. `None` uses all cores but one.
+    pub num_threads: Option<usize>,
+    /// The scene file that was last loaded or saved, to reopen on startup.
+    pub last_scene: Option<PathBuf>,
+    pub frame_limit: FrameLimit,
+    pub show_sample_count: bool,
+    /// Render viewports at the display's resolution, rather than at the
+    /// window's logical size, which looks blurry on HiDPI displays.
+    pub render_native_dpi: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            num_threads: None,
+            last_scene: None,
+            frame_limit: FrameLimit::default(),
+            show_sample_count: false,
+            render_native_dpi: true,
+        }
+    }
+}
+
+impl Settings {
+    pub const FILE_NAME: &'static str = "settings.ron";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+    use crate::system::FrameLimit;
+
+    #[test]
+    fn ron_round_trip() {
+        let settings = Settings {
+            num_threads: Some(3),
+            last_scene: Some("scenes/demo.ron".into()),
+            frame_limit: FrameLimit {
+                max_fps: Some(30.),
+                background_fps: 1.,
+            },
+            show_sample_count: true,
+            render_native_dpi: false,
+        };
+        let source = ron::to_string(&settings).unwrap();
+        assert_eq!(ron::from_str::<Settings>(&source).unwrap(), settings);
+        assert_eq!(
+            ron::from_str::<Settings>("()").unwrap(),
+            Settings::default()
+        );
+    }
+}
@@ -1,5 +1,10 @@
-use std::{rc::Rc, time::Instant};
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
+use crate::settings;
 use anyhow::{Context, Result};
 use glium::{
     self,
@@ -10,11 +15,101 @@ use glium::{
         event_loop::{ControlFlow, EventLoop},
         window::WindowBuilder,
     },
+    texture::RawImage2d,
     Display, Surface,
 };
+use halide_raytracer::Snapshot;
 use imgui::{FontConfig, FontSource, Textures};
 use imgui_glium_renderer::{Renderer, Texture};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
+use serde::{Deserialize, Serialize};
+
+/// Window level state and events gathered since the last frame, for the app
+/// to act on.
+pub(crate) struct WindowState {
+    /// Files dropped onto the window. The app should drain this.
+    pub dropped_files: Vec<PathBuf>,
+    pub focused: bool,
+    pub minimized: bool,
+    /// Physical pixels for each of the window's logical pixels, such as 2
+    /// on HiDPI displays.
+    pub scale_factor: f64,
+    /// Set by the app to control how often frames are drawn.
+    pub frame_limit: FrameLimit,
+    /// Set by the app to show something in the window title, like the
+    /// progress of a long render, where it can be seen while the window is
+    /// minimized. winit has no way to show progress on the taskbar itself.
+    pub title_status: Option<String>,
+    /// Set by the app to save the next composited frame, UI and all, as a PNG
+    /// at this path.
+    pub screenshot: Option<PathBuf>,
+    /// The outcome of the last screenshot. The app should take this.
+    pub screenshot_result: Option<Result<PathBuf>>,
+    /// The user asked to close the window. The app should take this, and
+    /// return [`ControlFlow::Exit`] once it's ready, which might be after
+    /// asking what to do about work in progress.
+    pub close_requested: bool,
+}
+
+impl Default for WindowState {
+    fn default() -> Self {
+        Self {
+            dropped_files: Vec::new(),
+            focused: true,
+            minimized: false,
+            scale_factor: 1.,
+            frame_limit: FrameLimit::default(),
+            title_status: None,
+            screenshot: None,
+            screenshot_result: None,
+            close_requested: false,
+        }
+    }
+}
+
+/// Caps on how often the UI redraws, and so how often the scene is rendered.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct FrameLimit {
+    /// Cap while the window is focused. `None` draws as fast as vsync allows.
+    pub max_fps: Option<f32>,
+    /// Cap while the window is in the background. Minimized windows don't
+    /// draw at all, unless there's a [title
+    /// status](WindowState::title_status) to keep up to date.
+    pub background_fps: f32,
+}
+
+impl Default for FrameLimit {
+    fn default() -> Self {
+        Self {
+            max_fps: None,
+            background_fps: 2.,
+        }
+    }
+}
+
+impl WindowState {
+    /// How long to wait between frames, or `None` to not draw at all.
+    fn frame_interval(&self) -> Option<Duration> {
+        let fps = if self.close_requested {
+            // draw one more frame so the app can respond
+            None
+        } else if self.minimized {
+            // keep the title up to date, without drawing any more than that needs
+            self.title_status.as_ref()?;
+            Some(self.frame_limit.background_fps)
+        } else if self.focused {
+            self.frame_limit.max_fps
+        } else {
+            Some(self.frame_limit.background_fps)
+        };
+        Some(
+            fps.filter(|fps| *fps > 0.)
+                .map(|fps| Duration::from_secs_f32(1. / fps))
+                .unwrap_or_default(),
+        )
+    }
+}
 
 pub(crate) struct System {
     pub event_loop: EventLoop<()>,
@@ -22,6 +117,8 @@ pub(crate) struct System {
     pub imgui: imgui::Context,
     pub platform: WinitPlatform,
     pub renderer: Renderer,
+    /// The window title, without any status.
+    pub title: String,
 }
 
 impl System {
@@ -35,6 +132,8 @@ impl System {
             Display::new(window_builder, context, &event_loop).context("Creating display")?;
 
         let mut imgui = imgui::Context::create();
+        let ini_dir = settings::config_dir().filter(|dir| std::fs::create_dir_all(dir).is_ok());
+        imgui.set_ini_filename(ini_dir.map(|dir| dir.join("imgui.ini")));
 
         let mut platform = WinitPlatform::init(&mut imgui);
         {
@@ -65,6 +164,7 @@ impl System {
             imgui,
             platform,
             renderer,
+            title: title.to_string(),
         })
     }
 
@@ -74,19 +174,36 @@ impl System {
                 &mut imgui::Ui,
                 &mut Textures<Texture>,
                 &Rc<glium::backend::Context>,
+                &mut WindowState,
             ) -> Option<ControlFlow>
             + 'static,
     {
         let mut last_frame = Instant::now();
+        let mut window_state = WindowState::default();
+        let mut shown_status = None;
 
         self.event_loop
             .run(move |event, _, control_flow| match event {
-                Event::NewEvents(_) => {
+                Event::MainEventsCleared => {
+                    if *control_flow == ControlFlow::Exit {
+                        return;
+                    }
+                    let Some(interval) = window_state.frame_interval() else {
+                        // wait for the window to be restored
+                        *control_flow = ControlFlow::Wait;
+                        return;
+                    };
                     let now = Instant::now();
+                    let next_frame = last_frame + interval;
+                    if now < next_frame {
+                        *control_flow = ControlFlow::WaitUntil(next_frame);
+                        return;
+                    }
+                    *control_flow = ControlFlow::Poll;
+
                     self.imgui.io_mut().update_delta_time(now - last_frame);
                     last_frame = now;
-                }
-                Event::MainEventsCleared => {
+
                     let gl_window = self.display.gl_window();
                     self.platform
                         .prepare_frame(self.imgui.io_mut(), gl_window.window())
@@ -97,12 +214,21 @@ impl System {
                     let gl_ctx = self.display.get_context();
                     let textures = self.renderer.textures();
                     let ui = self.imgui.frame();
+                    window_state.scale_factor = self.platform.hidpi_factor();
 
-                    if let Some(cf) = run_ui(ui, textures, gl_ctx) {
+                    if let Some(cf) = run_ui(ui, textures, gl_ctx, &mut window_state) {
                         *control_flow = cf;
                     }
 
                     let gl_window = self.display.gl_window();
+                    if window_state.title_status != shown_status {
+                        let title = match &window_state.title_status {
+                            Some(status) => format!("{status} - {}", self.title),
+                            None => self.title.clone(),
+                        };
+                        gl_window.window().set_title(&title);
+                        shown_status = window_state.title_status.clone();
+                    }
                     let mut target = self.display.draw();
                     target.clear_color_srgb(0.015, 0.015, 0.02, 1.0);
                     self.platform.prepare_render(ui, gl_window.window());
@@ -111,12 +237,40 @@ impl System {
                         .render(&mut target, draw_data)
                         .expect("Rending failed");
                     target.finish().expect("Failed to swap buffers");
+
+                    if let Some(path) = window_state.screenshot.take() {
+                        window_state.screenshot_result =
+                            Some(save_screenshot(&self.display, &path).map(|_| path));
+                    }
                 }
                 Event::WindowEvent {
                     event: WindowEvent::CloseRequested,
                     ..
-                } => *control_flow = ControlFlow::Exit,
+                } => window_state.close_requested = true,
+                Event::LoopDestroyed => {
+                    // imgui only saves its layout periodically, so catch the last changes
+                    if let Some(path) = self.imgui.ini_filename() {
+                        let mut ini = String::new();
+                        self.imgui.save_ini_settings(&mut ini);
+                        if let Err(err) = std::fs::write(&path, ini) {
+                            eprintln!("Couldn't save {}: {err}", path.display());
+                        }
+                    }
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::DroppedFile(path),
+                    ..
+                } => window_state.dropped_files.push(path),
                 event => {
+                    if let Event::WindowEvent { event, .. } = &event {
+                        match event {
+                            WindowEvent::Focused(focused) => window_state.focused = *focused,
+                            WindowEvent::Resized(size) => {
+                                window_state.minimized = size.width == 0 || size.height == 0
+                            }
+                            _ => {}
+                        }
+                    }
                     let gl_window = self.display.gl_window();
                     self.platform
                         .handle_event(self.imgui.io_mut(), gl_window.window(), &event);
@@ -124,3 +278,47 @@ impl System {
             });
     }
 }
+
+/// Write the frame that was just presented to a PNG.
+fn save_screenshot(display: &Display, path: &Path) -> Result<()> {
+    let raw: RawImage2d<u8> = display.read_front_buffer()?;
+    let image = raw
+        .data
+        .chunks_exact(4)
+        // the window isn't transparent, whatever the framebuffer's alpha says
+        .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], 255]))
+        .collect();
+    // Both GL and snapshots store rows bottom to top.
+    let snapshot = Snapshot {
+        width: raw.width,
+        height: raw.height,
+        frame_count: 0.,
+        image,
+        hdr: Vec::new(),
+    };
+    snapshot.save_png(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameLimit, WindowState};
+    use std::time::Duration;
+
+    #[test]
+    fn frame_interval() {
+        let mut state = WindowState {
+            frame_limit: FrameLimit {
+                max_fps: None,
+                background_fps: 4.,
+            },
+            ..Default::default()
+        };
+        assert_eq!(state.frame_interval(), Some(Duration::ZERO));
+        state.frame_limit.max_fps = Some(50.);
+        assert_eq!(state.frame_interval(), Some(Duration::from_millis(20)));
+        state.focused = false;
+        assert_eq!(state.frame_interval(), Some(Duration::from_millis(250)));
+        state.minimized = true;
+        assert_eq!(state.frame_interval(), None);
+    }
+}
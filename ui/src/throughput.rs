@@ -0,0 +1,65 @@
+use halide_raytracer::RenderStats;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How far back the rates look.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Samples and rays per second over about the last second, worked out from
+/// a renderer's running totals, so the readout holds steady while frames
+/// take several UI frames to finish.
+#[derive(Default)]
+pub(crate) struct Throughput {
+    /// Totals as they were seen, oldest first.
+    history: VecDeque<(Instant, Totals)>,
+}
+
+#[derive(Clone, Copy)]
+struct Totals {
+    samples: u64,
+    rays: u64,
+    render_time: Duration,
+}
+
+impl Throughput {
+    pub fn record(&mut self, totals: &RenderStats) {
+        let now = Instant::now();
+        self.history.push_back((
+            now,
+            Totals {
+                samples: totals.primary_rays,
+                rays: totals.rays_traced(),
+                render_time: totals.total_time(),
+            },
+        ));
+        // keep one entry from before the window to measure from
+        while self.history.len() > 2 && now.duration_since(self.history[1].0) > WINDOW {
+            self.history.pop_front();
+        }
+    }
+
+    /// Camera samples per second spent rendering.
+    pub fn samples_per_second(&self) -> f64 {
+        self.rate(|totals| totals.samples)
+    }
+
+    /// Rays per second spent rendering.
+    pub fn rays_per_second(&self) -> f64 {
+        self.rate(|totals| totals.rays)
+    }
+
+    fn rate(&self, count: impl Fn(&Totals) -> u64) -> f64 {
+        let (Some((_, first)), Some((_, last))) = (self.history.front(), self.history.back())
+        else {
+            return 0.0;
+        };
+        let secs = last.render_time.saturating_sub(first.render_time).as_secs_f64();
+        if secs > 0.0 {
+            count(last).saturating_sub(count(first)) as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
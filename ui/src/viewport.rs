@@ -0,0 +1,502 @@
+use crate::{
+    input::Bindings, pixel_buffers::PixelBuffers, render_thread::RenderThread,
+    throughput::Throughput, timer::Timer,
+};
+use anyhow::Result;
+use glium::{
+    backend::Facade,
+    texture::{MipmapsOption, RawImage2d, UncompressedFloatFormat},
+    uniforms::SamplerBehavior,
+    Rect, Texture2d,
+};
+use glam::{Vec2, Vec3};
+use halide_raytracer::{
+    BounceHit, Camera, PixelTrace, RenderStats, Renderer, Scene, Snapshot, ThreadPolicy,
+};
+use imgui::{MouseButton, TextureId, Textures};
+use imgui_glium_renderer::Texture;
+use std::{
+    borrow::Cow,
+    rc::Rc,
+    sync::{Arc, MutexGuard},
+};
+
+/// The format of the textures the renderer's images are shown from. They're
+/// already encoded as sRGB, like the PNGs they're saved as, and imgui draws
+/// textures without converting them, so the bytes are stored as they are. An
+/// sRGB format would have them decoded back to linear when they're drawn,
+/// making them look too dark.
+const IMAGE_FORMAT: UncompressedFloatFormat = UncompressedFloatFormat::U8U8U8U8;
+
+/// A view of the scene with its own camera and renderer.
+pub(crate) struct Viewport {
+    id: usize,
+    pub open: bool,
+    texture_id: Option<TextureId>,
+    /// What frames are uploaded to `texture_id` through, if the driver has
+    /// them. Made again whenever the texture is.
+    pixel_buffers: Option<PixelBuffers>,
+    pub size: [f32; 2],
+    /// How many pixels the image has for each of the window's, so it can
+    /// be rendered at the display's native resolution. Set by the app.
+    pub render_scale: f32,
+    /// The size the image is drawn at, in the window's pixels.
+    image_size: [f32; 2],
+    /// The screen position of the top left of the image, as last drawn.
+    image_pos: [f32; 2],
+    pub timer: Timer,
+    render_thread: RenderThread,
+    pub camera: Camera,
+    pub render_stats: RenderStats,
+    pub throughput: Throughput,
+    /// A still image shown over the left part of the live one, to compare
+    /// them.
+    comparison: Option<Comparison>,
+    /// How much of the image's width the comparison covers, from 0 to 1.
+    pub comparison_split: f32,
+}
+
+/// An image captured with [`Viewport::capture_comparison`].
+struct Comparison {
+    texture_id: TextureId,
+    snapshot: Snapshot,
+}
+
+impl Viewport {
+    /// `id` must be unique among open viewports, since it identifies the window.
+    pub fn new(id: usize, camera: Camera) -> Self {
+        let mut renderer = Renderer::new(400, 400);
+        // leave a core for the UI thread, and let it go first on the others
+        renderer.set_thread_policy(ThreadPolicy::AllButOne);
+        renderer.set_low_priority(true);
+        Self {
+            id,
+            open: true,
+            texture_id: None,
+            pixel_buffers: None,
+            size: [400.0, 400.0],
+            render_scale: 1.0,
+            image_size: [0.0, 0.0],
+            image_pos: [0.0, 0.0],
+            timer: Timer::new(),
+            render_thread: RenderThread::new(renderer),
+            camera,
+            render_stats: RenderStats::default(),
+            throughput: Throughput::default(),
+            comparison: None,
+            comparison_split: 0.5,
+        }
+    }
+
+    /// The imgui window name.
+    pub fn title(&self) -> String {
+        if self.id == 0 {
+            // keeps the layout of the original single viewport
+            "Viewport".to_string()
+        } else {
+            format!("Viewport {}##viewport{}", self.id + 1, self.id)
+        }
+    }
+
+    /// The renderer, to change its settings or look at what it's rendered.
+    /// If a frame is in progress, this waits for it to finish.
+    pub fn renderer(&self) -> MutexGuard<'_, Renderer> {
+        self.render_thread.renderer()
+    }
+
+    /// Fly the camera around with `bindings`, and advance any camera
+    /// animation.
+    pub fn handle_camera_input(&mut self, ui: &imgui::Ui, bindings: &Bindings) {
+        let before = self.camera.clone();
+        let animated = self.camera.update(ui.io().delta_time);
+        let flown = bindings.fly_camera(ui, &mut self.camera);
+        if animated || flown {
+            self.renderer().reproject(&before, &self.camera);
+        }
+    }
+
+    /// Draw the last frame rendered, and ask for a new one at the size of
+    /// the current window's content region.
+    pub fn build<F: Facade>(
+        &mut self,
+        ui: &imgui::Ui,
+        scene: &Arc<Scene>,
+        textures: &mut Textures<Texture>,
+        gl_ctx: &F,
+    ) {
+        self.render(scene, textures, gl_ctx).ok();
+        self.size = ui.content_region_avail();
+        if let Some(texture_id) = self.texture_id {
+            self.image_pos = ui.cursor_screen_pos();
+            imgui::Image::new(texture_id, self.image_size)
+                // flip Y-coordinate
+                .uv0([0., 1.])
+                .uv1([1., 0.])
+                .build(ui);
+            self.draw_comparison(ui);
+        }
+    }
+
+    /// Keep the image as it is now, as "A" to compare the live render, "B",
+    /// against. Settings can then be changed and judged side by side.
+    pub fn capture_comparison<F: Facade>(
+        &mut self,
+        textures: &mut Textures<Texture>,
+        gl_ctx: &F,
+    ) -> Result<()> {
+        let snapshot = self.renderer().snapshot();
+        let image = Cow::Borrowed(snapshot.image.as_slice());
+        let texture = upload(gl_ctx, image, snapshot.width, snapshot.height)?;
+        if let Some(old) = self.comparison.take() {
+            textures.remove(old.texture_id);
+        }
+        self.comparison = Some(Comparison {
+            texture_id: textures.insert(texture),
+            snapshot,
+        });
+        Ok(())
+    }
+
+    pub fn clear_comparison(&mut self, textures: &mut Textures<Texture>) {
+        if let Some(old) = self.comparison.take() {
+            textures.remove(old.texture_id);
+        }
+    }
+
+    pub fn has_comparison(&self) -> bool {
+        self.comparison.is_some()
+    }
+
+    /// How different the live render is from the comparison image, as
+    /// from [`Snapshot::rms_difference`].
+    pub fn comparison_difference(&self) -> Option<f32> {
+        let comparison = self.comparison.as_ref()?;
+        self.renderer().snapshot().rms_difference(&comparison.snapshot)
+    }
+
+    /// Draw the comparison image over the left of the live one, up to a
+    /// line that can be dragged across to wipe between them.
+    fn draw_comparison(&mut self, ui: &imgui::Ui) {
+        let Some(comparison) = &self.comparison else {
+            return;
+        };
+        let [left, top] = self.image_pos;
+        let [width, height] = self.image_size;
+        if ui.is_item_hovered() && ui.is_mouse_down(MouseButton::Left) && !ui.io().key_ctrl {
+            self.comparison_split = ((ui.io().mouse_pos[0] - left) / width).clamp(0., 1.);
+        }
+        let split = left + width * self.comparison_split;
+        let draw_list = ui.get_window_draw_list();
+        // rows are stored bottom to top, like the live image
+        draw_list
+            .add_image(comparison.texture_id, [left, top], [split, top + height])
+            .uv_min([0., 1.])
+            .uv_max([self.comparison_split, 0.])
+            .build();
+        draw_list
+            .add_line([split, top], [split, top + height], [1.0, 1.0, 1.0, 0.9])
+            .thickness(2.0)
+            .build();
+        let white = [1.0, 1.0, 1.0, 0.9];
+        draw_list.add_text([split - 16.0, top + 6.0], white, "A");
+        draw_list.add_text([split + 8.0, top + 6.0], white, "B");
+    }
+
+    /// The pixel under a screen position, counting from the bottom left
+    /// like [`Camera::ray_for_pixel`], if it's on the image.
+    pub fn pixel_at(&self, [x, y]: [f32; 2]) -> Option<[u32; 2]> {
+        let [width, height] = self.camera.size();
+        let x = ((x - self.image_pos[0]) * self.render_scale).floor();
+        let y = ((self.image_pos[1] + self.image_size[1] - y) * self.render_scale).floor();
+        (x >= 0. && y >= 0. && (x as u32) < width && (y as u32) < height)
+            .then_some([x as u32, y as u32])
+    }
+
+    /// The index of the hittable seen at a screen position, if the position
+    /// is on the image and there's anything there.
+    pub fn hittable_at(&self, scene: &Scene, position: [f32; 2]) -> Option<usize> {
+        let [x, y] = self.pixel_at(position)?;
+        let ray = self.camera.ray_for_pixel(x, y, Vec2::splat(0.5));
+        let hit = scene.raycast(&ray, self.camera.look_clip())?;
+        Some(hit.hittable)
+    }
+
+    /// Draw `trace` over the image as lines from bounce to bounce, with a
+    /// dot where each one hit a surface, so it's clear where a pixel's
+    /// light came from. The camera ray is yellow, and the bounces after it
+    /// orange.
+    pub fn draw_path(&self, ui: &imgui::Ui, trace: &PixelTrace) {
+        /// How far rays that see the sky are drawn, in scene units.
+        const SKY_LENGTH: f32 = 2.;
+        let draw_list = ui.get_window_draw_list();
+        let segments = trace.segments(SKY_LENGTH);
+        for (idx, (bounce, [start, end])) in trace.bounces.iter().zip(segments).enumerate() {
+            let color = if idx == 0 {
+                [1.0, 0.9, 0.2, 1.0]
+            } else {
+                [1.0, 0.5, 0.1, 1.0]
+            };
+            let Some([_, end]) = self.draw_line(&draw_list, [start, end], color, 2.0) else {
+                continue;
+            };
+            if matches!(bounce.hit, BounceHit::Surface { .. }) {
+                draw_list.add_circle(end, 3.0, color).filled(true).build();
+            }
+        }
+    }
+
+    /// Draw a wireframe of each hittable's bounding box over the image, so
+    /// objects can be picked out and placed before the render has
+    /// converged. The selected one is drawn brighter, on top.
+    pub fn draw_bounding_boxes(&self, ui: &imgui::Ui, scene: &Scene, selected: Option<usize>) {
+        let draw_list = ui.get_window_draw_list();
+        let hittables = scene.world_hittables();
+        let others = (0..hittables.len()).filter(|idx| Some(*idx) != selected);
+        for idx in others.chain(selected) {
+            let Some(hittable) = hittables.get(idx) else {
+                continue;
+            };
+            let bounds = hittable.bounding_box();
+            // infinite shapes have no box worth drawing
+            if bounds.is_empty() || !(bounds.min.is_finite() && bounds.max.is_finite()) {
+                continue;
+            }
+            let (color, thickness) = if Some(idx) == selected {
+                ([1.0, 0.8, 0.2, 1.0], 2.0)
+            } else {
+                ([0.4, 0.8, 1.0, 0.6], 1.0)
+            };
+            for edge in bounds.edges() {
+                self.draw_line(&draw_list, edge, color, thickness);
+            }
+        }
+    }
+
+    /// Draw a grid on the ground plane over the image, one unit to a square,
+    /// fading out away from the camera so it seems to go on forever. The X
+    /// and Z axes are drawn through it in red and blue.
+    pub fn draw_grid(&self, ui: &imgui::Ui) {
+        /// How many squares the grid reaches from the camera.
+        const EXTENT: i32 = 30;
+        let draw_list = ui.get_window_draw_list();
+        let position = self.camera.position();
+        // the grid moves with the camera, a whole square at a time
+        let center = [position.x.round() as i32, position.z.round() as i32];
+        let fade = |point: Vec3| {
+            let distance = Vec2::new(point.x - position.x, point.z - position.z).length();
+            (1. - distance / EXTENT as f32).max(0.)
+        };
+        for axis in 0..2 {
+            for line in -EXTENT..=EXTENT {
+                let across = center[axis] + line;
+                let color = match (axis, across) {
+                    // along Z, at x = 0
+                    (0, 0) => [0.3, 0.5, 1.0],
+                    // along X, at z = 0
+                    (1, 0) => [1.0, 0.3, 0.3],
+                    _ => [0.7, 0.7, 0.7],
+                };
+                // in pieces, so each can fade by its distance
+                for step in -EXTENT..EXTENT {
+                    let along = [step, step + 1].map(|step| (center[1 - axis] + step) as f32);
+                    let ends = along.map(|along| match axis {
+                        0 => Vec3::new(across as f32, 0., along),
+                        _ => Vec3::new(along, 0., across as f32),
+                    });
+                    let alpha = fade((ends[0] + ends[1]) / 2.) * 0.6;
+                    if alpha > 0. {
+                        let [r, g, b] = color;
+                        self.draw_line(&draw_list, ends, [r, g, b, alpha], 1.0);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the world's axes in the bottom left of the image, turned the way
+    /// the camera sees them: X in red, Y in green, and Z in blue.
+    pub fn draw_axis_gizmo(&self, ui: &imgui::Ui) {
+        const LENGTH: f32 = 30.;
+        const MARGIN: f32 = 45.;
+        let draw_list = ui.get_window_draw_list();
+        let origin = [
+            self.image_pos[0] + MARGIN,
+            self.image_pos[1] + self.image_size[1] - MARGIN,
+        ];
+        let look = self.camera.look_direction();
+        let up = self.camera.up_direction();
+        let right = look.cross(up);
+        let mut axes = [
+            (Vec3::X, "X", [1.0, 0.3, 0.3, 1.0]),
+            (Vec3::Y, "Y", [0.3, 1.0, 0.3, 1.0]),
+            (Vec3::Z, "Z", [0.3, 0.5, 1.0, 1.0]),
+        ];
+        // draw the axes pointing away from the camera first, so the nearer
+        // ones are on top
+        axes.sort_by(|(a, ..), (b, ..)| b.dot(look).total_cmp(&a.dot(look)));
+        for (axis, label, color) in axes {
+            let tip = [
+                origin[0] + axis.dot(right) * LENGTH,
+                origin[1] - axis.dot(up) * LENGTH,
+            ];
+            draw_list.add_line(origin, tip, color).thickness(2.0).build();
+            draw_list.add_text([tip[0] - 3.0, tip[1] - 7.0], color, label);
+        }
+    }
+
+    /// Draw the line between two points in the scene over the image, as the
+    /// camera sees it. Returns where its ends were drawn on the screen, if
+    /// any of it is in front of the camera.
+    fn draw_line(
+        &self,
+        draw_list: &imgui::DrawListMut,
+        [start, end]: [Vec3; 2],
+        color: [f32; 4],
+        thickness: f32,
+    ) -> Option<[[f32; 2]; 2]> {
+        let [left, top] = self.image_pos;
+        let bottom = top + self.image_size[1];
+        let to_screen = |pixel: Vec2| {
+            let pixel = pixel / self.render_scale;
+            [left + pixel.x, bottom - pixel.y]
+        };
+        let [start, end] = self.camera.project_segment(start, end)?.map(to_screen);
+        draw_list
+            .add_line(start, end, color)
+            .thickness(thickness)
+            .build();
+        Some([start, end])
+    }
+
+    /// Show the frame the render thread has finished, if it has, and ask
+    /// it for the next one.
+    fn render<F: Facade>(
+        &mut self,
+        scene: &Arc<Scene>,
+        textures: &mut Textures<Texture>,
+        gl_ctx: &F,
+    ) -> Result<()> {
+        let width = (self.size[0] * self.render_scale) as u32;
+        let height = (self.size[1] * self.render_scale) as u32;
+
+        self.camera.set_size(width, height);
+        let frame = self.render_thread.poll();
+        self.render_thread.request(scene, &self.camera);
+        let Some(frame) = frame else {
+            return Ok(());
+        };
+        self.timer.reset();
+        // keep showing the last frame's numbers while a big one is in progress
+        if frame.stats.frames > 0 {
+            self.render_stats = frame.stats;
+        }
+        self.throughput.record(&frame.total_stats);
+        if frame.image.is_empty() {
+            return Ok(());
+        }
+
+        let size = (frame.width, frame.height);
+        let texture = self
+            .texture_id
+            .and_then(|texture_id| textures.get(texture_id))
+            .map(|texture| texture.texture.clone())
+            .filter(|texture| texture.dimensions() == size);
+        let (texture, tiles) = match (texture, frame.changed) {
+            (Some(texture), Some(tiles)) => (texture, tiles),
+            _ => {
+                // the size changed, so start over with a new texture
+                let texture = Rc::new(Texture2d::empty_with_format(
+                    gl_ctx,
+                    IMAGE_FORMAT,
+                    MipmapsOption::NoMipmap,
+                    frame.width,
+                    frame.height,
+                )?);
+                let entry = imgui_texture(texture.clone());
+                match self.texture_id {
+                    Some(texture_id) => {
+                        textures.replace(texture_id, entry);
+                    }
+                    None => self.texture_id = Some(textures.insert(entry)),
+                }
+                self.pixel_buffers = PixelBuffers::new(gl_ctx, frame.image.len());
+                let whole = Rect {
+                    left: 0,
+                    bottom: 0,
+                    width: frame.width,
+                    height: frame.height,
+                };
+                (texture, vec![whole])
+            }
+        };
+        self.timer.stage_end("prepare texture");
+
+        if let Some(pixel_buffers) = &mut self.pixel_buffers {
+            pixel_buffers.fill(&frame.image, frame.width, &tiles);
+            self.timer.stage_end("copy to pixel buffer");
+            pixel_buffers.upload(&texture, &tiles);
+        } else {
+            for tile in tiles {
+                texture.write(tile, sub_image(&frame.image, frame.width, tile));
+            }
+        }
+        self.timer.stage_end("upload");
+        self.image_size = [frame.width, frame.height].map(|len| len as f32 / self.render_scale);
+
+        Ok(())
+    }
+
+    /// Free the viewport's textures, once it's been closed.
+    pub fn remove_textures(&mut self, textures: &mut Textures<Texture>) {
+        if let Some(texture_id) = self.texture_id.take() {
+            textures.remove(texture_id);
+        }
+        self.pixel_buffers = None;
+        self.clear_comparison(textures);
+    }
+}
+
+/// The pixels of `image`, which is `width` pixels wide, in `rect`.
+fn sub_image(image: &[u32], width: u32, rect: Rect) -> RawImage2d<'static, u32> {
+    let data = (rect.bottom..rect.bottom + rect.height)
+        .flat_map(|row| {
+            let start = row as usize * width as usize + rect.left as usize;
+            image[start..start + rect.width as usize].iter().copied()
+        })
+        .collect::<Vec<_>>();
+    RawImage2d {
+        data: Cow::Owned(data),
+        width: rect.width,
+        height: rect.height,
+        format: glium::texture::ClientFormat::U8U8U8U8,
+    }
+}
+
+/// Make a texture from packed RGBA pixels, with rows stored bottom to top.
+pub(crate) fn upload<F: Facade>(
+    gl_ctx: &F,
+    data: Cow<'_, [u32]>,
+    width: u32,
+    height: u32,
+) -> Result<Texture> {
+    let raw = RawImage2d {
+        data,
+        width,
+        height,
+        format: glium::texture::ClientFormat::U8U8U8U8,
+    };
+    let gl_texture = Texture2d::with_format(gl_ctx, raw, IMAGE_FORMAT, MipmapsOption::NoMipmap)?;
+    Ok(imgui_texture(Rc::new(gl_texture)))
+}
+
+/// `texture`, to draw with imgui.
+fn imgui_texture(texture: Rc<Texture2d>) -> Texture {
+    Texture {
+        texture,
+        sampler: SamplerBehavior {
+            magnify_filter: glium::uniforms::MagnifySamplerFilter::Linear,
+            minify_filter: glium::uniforms::MinifySamplerFilter::Linear,
+            ..Default::default()
+        },
+    }
+}
@@ -0,0 +1,27 @@
+// Draws the rendered image over the whole surface.
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // one triangle that covers the screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    // the image's rows are stored bottom to top, like GL textures
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0)
+var image: texture_2d<f32>;
+@group(0) @binding(1)
+var image_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(textureSample(image, image_sampler, in.uv).rgb, 1.0);
+}
@@ -0,0 +1,142 @@
+use glam::Vec3;
+use halide_raytracer::Camera;
+use std::collections::HashSet;
+use winit::event::{ElementState, ModifiersState, VirtualKeyCode};
+
+/// Fly camera controls for the canvas. While the pointer is locked, moving
+/// the mouse turns the camera and WASD, E, and Q move it, matching the
+/// desktop viewer's default bindings.
+#[derive(Default)]
+pub(crate) struct FlyControls {
+    /// Whether the canvas has pointer lock. Input is ignored without it, so
+    /// typing elsewhere on the page doesn't move the camera.
+    locked: bool,
+    held: HashSet<VirtualKeyCode>,
+    modifiers: ModifiersState,
+    mouse_delta: [f32; 2],
+}
+
+impl FlyControls {
+    const FAST_MULTIPLIER: f32 = 4.;
+    const SLOW_MULTIPLIER: f32 = 0.25;
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn set_locked(&mut self, locked: bool) {
+        self.locked = locked;
+        if !locked {
+            // keys released while unlocked would otherwise stay held
+            self.held.clear();
+            self.mouse_delta = [0., 0.];
+        }
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode, state: ElementState) {
+        match state {
+            ElementState::Pressed if self.locked => {
+                self.held.insert(key);
+            }
+            ElementState::Pressed => {}
+            ElementState::Released => {
+                self.held.remove(&key);
+            }
+        }
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn mouse_motion(&mut self, (dx, dy): (f64, f64)) {
+        if self.locked {
+            self.mouse_delta[0] += dx as f32;
+            self.mouse_delta[1] += dy as f32;
+        }
+    }
+
+    /// Apply the input gathered since the last update to `camera`. Returns
+    /// true if the camera moved.
+    pub fn update(&mut self, camera: &mut Camera, dt: f32) -> bool {
+        let mut moved = false;
+
+        let mut camera_offset = Vec3::ZERO;
+        for (key, direction) in [
+            (VirtualKeyCode::D, Vec3::X),
+            (VirtualKeyCode::A, Vec3::NEG_X),
+            (VirtualKeyCode::E, Vec3::Y),
+            (VirtualKeyCode::Q, Vec3::NEG_Y),
+            (VirtualKeyCode::W, Vec3::Z),
+            (VirtualKeyCode::S, Vec3::NEG_Z),
+        ] {
+            if self.held.contains(&key) {
+                camera_offset += direction;
+            }
+        }
+        if camera_offset != Vec3::ZERO {
+            let mut speed = 1.;
+            if self.modifiers.shift() {
+                speed *= Self::FAST_MULTIPLIER;
+            }
+            if self.modifiers.ctrl() {
+                speed *= Self::SLOW_MULTIPLIER;
+            }
+            camera.cancel_animation();
+            camera.relative_move(camera_offset.normalize(), dt * speed);
+            moved = true;
+        }
+
+        let [dx, dy] = std::mem::take(&mut self.mouse_delta);
+        if dx != 0. || dy != 0. {
+            camera.cancel_animation();
+            camera.relative_turn([-dy, -dx], dt);
+            moved = true;
+        }
+
+        moved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FlyControls;
+    use halide_raytracer::Camera;
+    use winit::event::{ElementState, VirtualKeyCode};
+
+    #[test]
+    fn input_needs_pointer_lock() {
+        let mut controls = FlyControls::default();
+        let mut camera = Camera::default();
+        let start = camera.position();
+
+        controls.key(VirtualKeyCode::W, ElementState::Pressed);
+        controls.mouse_motion((10., 0.));
+        assert!(!controls.update(&mut camera, 0.1));
+
+        controls.set_locked(true);
+        controls.key(VirtualKeyCode::W, ElementState::Pressed);
+        assert!(controls.update(&mut camera, 0.1));
+        assert!(camera.position().distance(start) > 0.);
+
+        // losing the lock releases held keys
+        controls.set_locked(false);
+        let stopped = camera.position();
+        assert!(!controls.update(&mut camera, 0.1));
+        assert_eq!(camera.position(), stopped);
+    }
+
+    #[test]
+    fn mouse_motion_turns_once() {
+        let mut controls = FlyControls::default();
+        let mut camera = Camera::default();
+        let start = camera.look_direction();
+
+        controls.set_locked(true);
+        controls.mouse_motion((5., 0.));
+        controls.mouse_motion((5., 0.));
+        assert!(controls.update(&mut camera, 0.1));
+        assert_ne!(camera.look_direction(), start);
+        assert!(!controls.update(&mut camera, 0.1));
+    }
+}
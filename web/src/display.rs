@@ -0,0 +1,223 @@
+use anyhow::{Context, Result};
+use std::num::NonZeroU32;
+use winit::window::Window;
+
+/// Shows rendered images on the window, scaled to fill it. Uses WebGL in
+/// the browser.
+pub(crate) struct Display {
+    surface: wgpu::Surface,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    image: Option<Image>,
+}
+
+/// The last image uploaded, and the bind group to draw it with.
+struct Image {
+    texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    size: wgpu::Extent3d,
+}
+
+impl Display {
+    pub async fn new(window: &Window) -> Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        // SAFETY: the window is owned by the app, which also owns the display
+        // and drops it first.
+        let surface =
+            unsafe { instance.create_surface(window) }.context("Creating surface")?;
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                compatible_surface: Some(&surface),
+                ..Default::default()
+            })
+            .await
+            .context("No graphics adapter")?;
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::downlevel_webgl2_defaults()
+                        .using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await
+            .context("Creating device")?;
+
+        let capabilities = surface.get_capabilities(&adapter);
+        // Show the renderer's colors as they are, like the desktop viewer.
+        let format = capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|format| !format.describe().srgb)
+            .unwrap_or(capabilities.formats[0]);
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: wgpu::PresentMode::AutoVsync,
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: Vec::new(),
+        };
+        surface.configure(&device, &config);
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("blit.wgsl"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("blit"),
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            surface,
+            device,
+            queue,
+            config,
+            pipeline,
+            sampler,
+            image: None,
+        })
+    }
+
+    /// Match the surface to the window's size, if it changed.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let changed = (self.config.width, self.config.height) != (width, height);
+        if changed && width > 0 && height > 0 {
+            self.config.width = width;
+            self.config.height = height;
+            self.surface.configure(&self.device, &self.config);
+        }
+    }
+
+    /// Replace the displayed image with one from the renderer.
+    pub fn upload(&mut self, width: u32, height: u32, pixels: &[u32]) {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        if self.image.as_ref().map(|image| image.size) != Some(size) {
+            self.image = Some(self.create_image(size));
+        }
+        let Some(image) = &self.image else {
+            return;
+        };
+        let bytes = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_le_bytes())
+            .collect::<Vec<_>>();
+        self.queue.write_texture(
+            image.texture.as_image_copy(),
+            &bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(4 * width),
+                rows_per_image: None,
+            },
+            size,
+        );
+    }
+
+    pub fn draw(&mut self) -> Result<()> {
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                // try again next frame
+                self.surface.configure(&self.device, &self.config);
+                return Ok(());
+            }
+            Err(err) => return Err(err).context("Getting next frame"),
+        };
+        let view = frame
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: 0.015,
+                            g: 0.015,
+                            b: 0.02,
+                            a: 1.0,
+                        }),
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            if let Some(image) = &self.image {
+                pass.set_pipeline(&self.pipeline);
+                pass.set_bind_group(0, &image.bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+        self.queue.submit([encoder.finish()]);
+        frame.present();
+        Ok(())
+    }
+
+    fn create_image(&self, size: wgpu::Extent3d) -> Image {
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("image"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("image"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        Image {
+            texture,
+            bind_group,
+            size,
+        }
+    }
+}
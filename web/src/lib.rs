@@ -0,0 +1,229 @@
+//! The interactive viewer, in the browser. Renders a preset scene into a
+//! canvas a slice at a time, so the page stays responsive. Click the canvas
+//! to lock the pointer and fly the camera around.
+
+use anyhow::{Context, Result};
+use controls::FlyControls;
+use display::Display;
+use halide_raytracer::{presets::Preset, Camera, Renderer, Scene};
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+use winit::{
+    event::{
+        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode,
+        WindowEvent,
+    },
+    event_loop::{ControlFlow, EventLoop},
+    window::{CursorGrabMode, Window, WindowBuilder},
+};
+
+mod controls;
+mod display;
+
+/// How much time each frame spends rendering. Rendering shares the thread
+/// with the event loop, so this leaves room for input and drawing.
+const FRAME_BUDGET: Duration = Duration::from_millis(12);
+
+/// Pixels are rendered at this fraction of the canvas resolution, since
+/// there is only one thread to render with.
+const RENDER_SCALE: u32 = 2;
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(start)]
+pub fn start() {
+    console_error_panic_hook::set_once();
+    wasm_bindgen_futures::spawn_local(async {
+        if let Err(err) = run().await {
+            web_sys::console::error_1(&format!("{err:?}").into());
+        }
+    });
+}
+
+/// Open the viewer and run it until the window closes. In the browser, the
+/// canvas is added to the page's body.
+pub async fn run() -> Result<()> {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("Halide")
+        .build(&event_loop)
+        .context("Creating window")?;
+    #[cfg(target_arch = "wasm32")]
+    web::attach_canvas(&window)?;
+
+    let mut app = App::new(window).await?;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        match event {
+            Event::WindowEvent { event, .. } => app.on_window_event(event, control_flow),
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => app.controls.mouse_motion(delta),
+            Event::MainEventsCleared => {
+                app.update();
+                app.window.request_redraw();
+            }
+            Event::RedrawRequested(_) => {
+                if let Err(err) = app.display.draw() {
+                    eprintln!("Drawing failed: {err:?}");
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+struct App {
+    // Dropped before the window, since it draws to it.
+    display: Display,
+    window: Window,
+    controls: FlyControls,
+    renderer: Renderer,
+    scene: Scene,
+    camera: Camera,
+    last_frame: Instant,
+}
+
+impl App {
+    async fn new(window: Window) -> Result<Self> {
+        let preset = Preset::Demo;
+        Ok(Self {
+            display: Display::new(&window).await?,
+            window,
+            controls: FlyControls::default(),
+            renderer: Renderer::try_new(1, 1)?,
+            scene: preset.scene(),
+            camera: preset.camera(),
+            last_frame: Instant::now(),
+        })
+    }
+
+    fn on_window_event(&mut self, event: WindowEvent, control_flow: &mut ControlFlow) {
+        match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Focused(false) => self.release_pointer(),
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !self.controls.locked() => self.lock_pointer(),
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                if key == VirtualKeyCode::Escape {
+                    self.release_pointer();
+                }
+                self.controls.key(key, state);
+            }
+            WindowEvent::ModifiersChanged(modifiers) => self.controls.set_modifiers(modifiers),
+            _ => {}
+        }
+    }
+
+    fn lock_pointer(&mut self) {
+        let grabbed = self
+            .window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined))
+            .is_ok();
+        // In the browser the lock is granted later, and `update` picks it up.
+        if grabbed && cfg!(not(target_arch = "wasm32")) {
+            self.window.set_cursor_visible(false);
+            self.controls.set_locked(true);
+        }
+    }
+
+    fn release_pointer(&mut self) {
+        if self.controls.locked() {
+            self.window.set_cursor_grab(CursorGrabMode::None).ok();
+            self.window.set_cursor_visible(true);
+            self.controls.set_locked(false);
+        }
+    }
+
+    /// Apply input, and render as much of the next frame as fits in the
+    /// frame budget.
+    fn update(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        {
+            web::fit_to_page(&self.window);
+            // winit doesn't report when the browser grants or releases the lock
+            let locked = web::pointer_locked();
+            if locked != self.controls.locked() {
+                self.controls.set_locked(locked);
+            }
+        }
+
+        let now = Instant::now();
+        let dt = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+        let animated = self.camera.update(dt);
+        let flown = self.controls.update(&mut self.camera, dt);
+        if animated || flown {
+            self.renderer.reset_accumulation();
+        }
+
+        let size = self.window.inner_size();
+        self.display.resize(size.width, size.height);
+        let width = (size.width / RENDER_SCALE).max(1);
+        let height = (size.height / RENDER_SCALE).max(1);
+        self.renderer.resize(width, height);
+        self.camera.set_size(width, height);
+        if let Some((image, _)) = self
+            .renderer
+            .render_step(&self.scene, &self.camera, FRAME_BUDGET)
+        {
+            self.display.upload(width, height, &image);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use anyhow::{anyhow, Context, Result};
+    use winit::{dpi::LogicalSize, platform::web::WindowExtWebSys, window::Window};
+
+    pub fn attach_canvas(window: &Window) -> Result<()> {
+        let body = web_sys::window()
+            .and_then(|page| page.document())
+            .and_then(|document| document.body())
+            .context("No document body")?;
+        body.append_child(&window.canvas())
+            .map_err(|err| anyhow!("Adding canvas to the page: {err:?}"))?;
+        fit_to_page(window);
+        Ok(())
+    }
+
+    /// Size the canvas to fill the page. winit doesn't follow the canvas's
+    /// CSS size on its own.
+    pub fn fit_to_page(window: &Window) {
+        let Some(page) = web_sys::window() else {
+            return;
+        };
+        let width = page.inner_width().ok().and_then(|w| w.as_f64());
+        let height = page.inner_height().ok().and_then(|h| h.as_f64());
+        if let (Some(width), Some(height)) = (width, height) {
+            let size = LogicalSize::new(width, height);
+            if window.inner_size() != size.to_physical(window.scale_factor()) {
+                window.set_inner_size(size);
+            }
+        }
+    }
+
+    pub fn pointer_locked() -> bool {
+        web_sys::window()
+            .and_then(|page| page.document())
+            .and_then(|document| document.pointer_lock_element())
+            .is_some()
+    }
+}